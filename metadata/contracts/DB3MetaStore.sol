@@ -12,6 +12,8 @@ contract DB3MetaStore is IDB3MetaStore {
     // A map to store collection information
     mapping(uint256 => mapping(address => mapping(bytes32 => Types.Collection)))
         private _collections;
+    // A map to store the mutation root of every rollup arweave tx
+    mapping(uint256 => mapping(bytes32 => bytes32)) private _mutationRoots;
     // Counter to keep track of number of data networks
     uint256 private _networkCounter;
     // Counter to keep track of number of database
@@ -108,7 +110,8 @@ contract DB3MetaStore is IDB3MetaStore {
     // Update network information for a specific network ID
     function updateRollupSteps(
         uint256 networkId,
-        bytes32 latestArweaveTx
+        bytes32 latestArweaveTx,
+        bytes32 mutationRoot
     ) public {
         require(networkId != 0, "invalid data network");
         // Check if network is registered
@@ -125,8 +128,25 @@ contract DB3MetaStore is IDB3MetaStore {
         // Update latest Arweave transaction in registration struct
         dataNetwork.latestArweaveTx = latestArweaveTx;
         dataNetwork.latestRollupTime = block.timestamp;
+        // Anchor the mutation root of the rollup batch
+        _mutationRoots[networkId][latestArweaveTx] = mutationRoot;
         // emit an event
-        emit Events.UpdateRollupStep(msg.sender, networkId, latestArweaveTx);
+        emit Events.UpdateRollupStep(
+            msg.sender,
+            networkId,
+            latestArweaveTx,
+            mutationRoot
+        );
+    }
+
+    function getMutationRoot(
+        uint256 networkId,
+        bytes32 arweaveTx
+    ) external view returns (bytes32) {
+        require(networkId != 0, "invalid data network");
+        // Check the data network must be registered
+        require(networkId <= _networkCounter, "Data Network is not registered");
+        return _mutationRoots[networkId][arweaveTx];
     }
 
     function createDocDatabase(uint256 networkId, bytes32 description) public {
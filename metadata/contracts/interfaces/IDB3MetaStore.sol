@@ -62,8 +62,23 @@ interface IDB3MetaStore {
      * update the data rollup steps
      * @param id                  The id of your data network
      * @param latestArweaveTx     The latest arweave transaction id
+     * @param mutationRoot        The merkle root over the ids of the rolled up mutations
      */
-    function updateRollupSteps(uint256 id, bytes32 latestArweaveTx) external;
+    function updateRollupSteps(
+        uint256 id,
+        bytes32 latestArweaveTx,
+        bytes32 mutationRoot
+    ) external;
+
+    /**
+     * get the mutation root anchored with the rollup arweave tx
+     * @param id                  The id of your data network
+     * @param arweaveTx           The arweave transaction id of the rollup
+     */
+    function getMutationRoot(
+        uint256 id,
+        bytes32 arweaveTx
+    ) external view returns (bytes32);
 
     /**
      * create a document database
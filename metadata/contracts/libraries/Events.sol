@@ -57,11 +57,13 @@ library Events {
      * @param sender          The sender of transaction
      * @param networkId       The id of the network that the database belongs to
      * @param arweaveTx       The transaction id of arweave
+     * @param mutationRoot    The merkle root over the ids of the rolled up mutations
      */
     event UpdateRollupStep(
         address sender,
         uint256 networkId,
-        bytes32 arweaveTx
+        bytes32 arweaveTx,
+        bytes32 mutationRoot
     );
 
     /**
@@ -18,8 +18,9 @@ describe("DB3MetaStore", function () {
         "TY5SMaPPRk_TMvSDROaQWyc_WHyJrEL760-UhiNnHG4"
       );
       const arTx = ethers.utils.hexZeroPad(binaryData, 32);
+      const mutationRoot = ethers.utils.formatBytes32String("root");
       await expect(
-        metaStore.connect(deployer).updateRollupSteps(1, arTx)
+        metaStore.connect(deployer).updateRollupSteps(1, arTx, mutationRoot)
       ).to.revertedWith("Data Network is not registered");
     });
 
@@ -44,8 +45,9 @@ describe("DB3MetaStore", function () {
         .to.emit(eventLibABI, "CreateNetwork")
         .withArgs(deployer.address, 1);
       const arTx = ethers.utils.formatBytes32String("");
+      const mutationRoot = ethers.utils.formatBytes32String("root");
       await expect(
-        metaStore.connect(deployer).updateRollupSteps(1, arTx)
+        metaStore.connect(deployer).updateRollupSteps(1, arTx, mutationRoot)
       ).to.revertedWith("Invalid arweave tx");
     });
     it("update rollup steps smoke test", async function () {
@@ -72,9 +74,13 @@ describe("DB3MetaStore", function () {
         "TY5SMaPPRk_TMvSDROaQWyc_WHyJrEL760-UhiNnHG4"
       );
       const arTx = ethers.utils.hexZeroPad(binaryData, 32);
-      await expect(metaStore.connect(deployer).updateRollupSteps(1, arTx))
+      const mutationRoot = ethers.utils.formatBytes32String("root");
+      await expect(
+        metaStore.connect(deployer).updateRollupSteps(1, arTx, mutationRoot)
+      )
         .to.emit(eventLibABI, "UpdateRollupStep")
-        .withArgs(deployer.address, 1, arTx);
+        .withArgs(deployer.address, 1, arTx, mutationRoot);
+      expect(await metaStore.getMutationRoot(1, arTx)).to.equal(mutationRoot);
     });
   });
 });
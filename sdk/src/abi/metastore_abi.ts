@@ -232,6 +232,30 @@ export const db3MetaStoreContractConfig = {
             stateMutability: 'view',
             type: 'function',
         },
+        {
+            inputs: [
+                {
+                    internalType: 'uint256',
+                    name: 'networkId',
+                    type: 'uint256',
+                },
+                {
+                    internalType: 'bytes32',
+                    name: 'arweaveTx',
+                    type: 'bytes32',
+                },
+            ],
+            name: 'getMutationRoot',
+            outputs: [
+                {
+                    internalType: 'bytes32',
+                    name: '',
+                    type: 'bytes32',
+                },
+            ],
+            stateMutability: 'view',
+            type: 'function',
+        },
         {
             inputs: [
                 {
@@ -364,6 +388,11 @@ export const db3MetaStoreContractConfig = {
                     name: 'latestArweaveTx',
                     type: 'bytes32',
                 },
+                {
+                    internalType: 'bytes32',
+                    name: 'mutationRoot',
+                    type: 'bytes32',
+                },
             ],
             name: 'updateRollupSteps',
             outputs: [],
@@ -615,6 +644,12 @@ export const db3MetaStoreContractConfig = {
                     name: 'arweaveTx',
                     type: 'bytes32',
                 },
+                {
+                    indexed: false,
+                    internalType: 'bytes32',
+                    name: 'mutationRoot',
+                    type: 'bytes32',
+                },
             ],
             name: 'UpdateRollupStep',
             type: 'event',
@@ -23,4 +23,8 @@ export interface DocumentEntry<T> {
     id: string
     owner: string
     doc: T
+    // the revision starts from 1 and increases on every update
+    revision: string
+    // the key chosen by the client, it's empty if the document has no key
+    key: string
 }
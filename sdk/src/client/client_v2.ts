@@ -26,7 +26,7 @@ import {
 } from '../proto/db3_mutation_v2'
 import type { DocumentData, DocumentEntry } from './base'
 import type { DB3Account } from '../account/types'
-import type { Client, ReadClient } from './types'
+import type { Client, ReadClient, MutationFilter } from './types'
 import { Index } from '../proto/db3_database_v2'
 import { SystemConfig } from '../proto/db3_base'
 import { StorageProviderV2 } from '../provider/storage_provider_v2'
@@ -84,7 +84,7 @@ export function createClient(
     account: DB3Account
 ) {
     const provider = new StorageProviderV2(rollupNodeUrl, account)
-    const indexer = new IndexerProvider(indexNodeUrl)
+    const indexer = new IndexerProvider(indexNodeUrl, account)
     return {
         provider,
         indexer,
@@ -161,6 +161,23 @@ export async function getMutationState(client: Client) {
     return response.view
 }
 
+/**
+ *
+ * Get the delivery stats of the subscribers of the storage node
+ *
+ * ```ts
+ *  const stats = getSubscriberStats(client)
+ * ```
+ *
+ * @param client     - the client of db3 network
+ * @returns the subscriber count and the lag of the subscribers
+ *
+ **/
+export async function getSubscriberStats(client: Client) {
+    const response = await client.provider.getMutationState()
+    return response.subscriberStats
+}
+
 /**
  *
  * Get the system status of index node
@@ -199,6 +216,27 @@ export async function getMutationBody(client: Client, id: string) {
     throw new Error('mutation not found')
 }
 
+/**
+ *
+ * Get the merkle proof of the rolled up mutation by the id
+ *
+ * ```ts
+ * const proof = getMutationProof(client, '0x....')
+ * ```
+ *
+ * @param client    - the instance of client
+ * @param id        - the id of mutation
+ * @returns the path from the mutation id to the mutation root of the rollup record
+ *
+ **/
+export async function getMutationProof(client: Client, id: string) {
+    const response = await client.provider.getMutationProof(id)
+    if (response.proof) {
+        return response.proof
+    }
+    throw new Error('mutation has not been rolled up')
+}
+
 /**
  *
  * Sync the nonce of account
@@ -240,6 +278,24 @@ export async function getMutationHeader(
     return response
 }
 
+/**
+ *
+ * Get the mutation header by the id
+ *
+ * ```ts
+ * const header = getMutationHeaderById(client, '0x....')
+ * ```
+ *
+ * @param client    - the instance of client
+ * @param id        - the id of mutation
+ * @returns the mutation header
+ *
+ **/
+export async function getMutationHeaderById(client: Client, id: string) {
+    const response = await client.provider.getMutationHeaderById(id)
+    return response
+}
+
 export async function scanMutationHeaders(
     client: Client,
     start: number,
@@ -249,6 +305,57 @@ export async function scanMutationHeaders(
     return response.headers
 }
 
+/**
+ *
+ * Scan the mutation headers by the sender, the database and the actions
+ *
+ * ```ts
+ * const result = scanMutationHeadersByFilter(client, {
+ *     sender: '0x....',
+ *     dbAddr: '0x....',
+ * })
+ * if (result.hasMore) {
+ *     const next = scanMutationHeadersByFilter(client, filter,
+ *         result.nextBlock, result.nextOrder)
+ * }
+ * ```
+ *
+ * The scanned index keys are capped in a request, so the headers may be fewer than
+ * the limit while `hasMore` is true. The mutations of the blocks before
+ * `indexStartBlock` were added before the indexes and are not matched
+ *
+ * @param client     - the instance of client
+ * @param filter     - at least one of the sender, the database address and the actions should be set
+ * @param startBlock - the start block
+ * @param startOrder - the start order in the start block
+ * @param limit      - the headers limit
+ * @returns the headers ordered by block and order with the position to resume from
+ *
+ **/
+export async function scanMutationHeadersByFilter(
+    client: Client,
+    filter: MutationFilter,
+    startBlock: string = '0',
+    startOrder: number = 0,
+    limit: number = 50
+) {
+    const response = await client.provider.scanMutationHeadersByFilter(
+        filter.sender || '',
+        filter.dbAddr || '',
+        filter.actions || [],
+        startBlock,
+        startOrder,
+        limit
+    )
+    return {
+        headers: response.headers,
+        hasMore: response.hasMore,
+        nextBlock: response.nextBlock,
+        nextOrder: response.nextOrder,
+        indexStartBlock: response.indexStartBlock,
+    }
+}
+
 /**
  *
  * Scan the rollup records
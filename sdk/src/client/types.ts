@@ -16,6 +16,7 @@
 //
 
 import type { DB3Account } from '../account/types'
+import type { MutationAction } from '../proto/db3_mutation_v2'
 
 import { StorageProviderV2 } from '../provider/storage_provider_v2'
 import { IndexerProvider } from '../provider/indexer_provider'
@@ -32,3 +33,11 @@ export type ReadClient = {
     indexer: IndexerProvider
     nonce: number
 }
+
+export type MutationFilter = {
+    // a hex format sender address
+    sender?: string
+    // a hex format database address
+    dbAddr?: string
+    actions?: MutationAction[]
+}
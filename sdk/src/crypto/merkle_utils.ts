@@ -0,0 +1,113 @@
+//
+// merkle_utils.ts
+// Copyright (C) 2023 db3.network Author imotai <codego.me@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+import { sha3_256 } from '@noble/hashes/sha3'
+import type { PublicClient } from 'viem'
+import type { MutationProof } from '../proto/db3_rollup'
+import { db3MetaStoreContractConfig } from '../abi/metastore_abi'
+import { fromB64, fromHEX, toHEX } from './crypto_utils'
+
+// the prefixes keep the leaf hashes and the node hashes apart
+const LEAF_PREFIX = 0
+const NODE_PREFIX = 1
+const HASH_LENGTH = 32
+
+function hashWithPrefix(prefix: number, ...parts: Uint8Array[]) {
+    const hasher = sha3_256.create()
+    hasher.update(Uint8Array.from([prefix]))
+    parts.forEach((part) => hasher.update(part))
+    return hasher.digest()
+}
+
+function isEqual(left: Uint8Array, right: Uint8Array) {
+    return (
+        left.length === right.length &&
+        left.every((value, index) => value === right[index])
+    )
+}
+
+/**
+ *
+ * Get the mutation root anchored on the evm chain with the arweave tx of the rollup batch
+ *
+ * ```ts
+ * const root = await getAnchoredMutationRoot(publicClient, 1n, proof.record.arweaveTx)
+ * ```
+ *
+ * @param client    - the viem public client of the evm chain
+ * @param networkId - the id of the data network
+ * @param arweaveTx - the base64url format arweave tx id of the rollup batch
+ * @returns the anchored mutation root which is all zeros if it's not found
+ *
+ **/
+export async function getAnchoredMutationRoot(
+    client: PublicClient,
+    networkId: bigint,
+    arweaveTx: string
+) {
+    const tx = fromB64(arweaveTx.replace(/-/g, '+').replace(/_/g, '/'))
+    if (tx.length !== HASH_LENGTH) {
+        throw new Error('invalid arweave tx')
+    }
+    const root = await client.readContract({
+        ...db3MetaStoreContractConfig,
+        functionName: 'getMutationRoot',
+        args: [networkId, `0x${toHEX(tx)}`],
+    })
+    return fromHEX(root)
+}
+
+/**
+ *
+ * Verify the mutation is included in the rollup batch with the trusted root
+ *
+ * ```ts
+ * const proof = await getMutationProof(client, '0x....')
+ * const root = await getAnchoredMutationRoot(publicClient, 1n, proof.record.arweaveTx)
+ * const ok = verifyMutationProof(proof, root)
+ * ```
+ *
+ * The root returned by the node is not trusted, so the trusted root should be the
+ * mutation root anchored on the evm chain with the arweave tx of the rollup batch
+ *
+ * @param proof       - the proof of the mutation
+ * @param trustedRoot - the mutation root of the rollup batch
+ * @returns true if the path leads the mutation id to the trusted root
+ *
+ **/
+export function verifyMutationProof(
+    proof: MutationProof,
+    trustedRoot: Uint8Array
+) {
+    if (trustedRoot.length !== HASH_LENGTH) {
+        return false
+    }
+    const id = fromHEX(proof.id)
+    if (id.length !== HASH_LENGTH) {
+        return false
+    }
+    let computed = hashWithPrefix(LEAF_PREFIX, id)
+    for (const node of proof.path) {
+        if (node.hash.length !== HASH_LENGTH) {
+            return false
+        }
+        computed = node.isLeft
+            ? hashWithPrefix(NODE_PREFIX, node.hash, computed)
+            : hashWithPrefix(NODE_PREFIX, computed, node.hash)
+    }
+    return isEqual(computed, trustedRoot)
+}
@@ -21,7 +21,7 @@ export {
     signTypedData,
     createFromExternal,
 } from './account/db3_account'
-export type { Client, ReadClient } from './client/types'
+export type { Client, ReadClient, MutationFilter } from './client/types'
 export type { DocumentData, DocumentEntry } from './client/base'
 export type {
     Database,
@@ -35,9 +35,25 @@ export {
     addDoc,
     updateDoc,
     deleteDoc,
+    deleteDocsByQuery,
+    updateDocsByQuery,
     queryDoc,
     getDoc,
+    getDocByKey,
+    getDocAsOf,
+    queryDocAsOf,
+    upsertDoc,
+    revisionPrecondition,
+    existsPrecondition,
+    transformDoc,
+    increment,
+    maximum,
+    minimum,
+    arrayUnion,
+    arrayRemove,
+    serverTimestamp,
 } from './store/document_v2'
+export { Precondition, FieldTransform } from './proto/db3_mutation_v2'
 
 export { SystemConfig, SystemStatus, Version } from './proto/db3_base'
 export {
@@ -45,8 +61,10 @@ export {
     createReadonlyClient,
     syncAccountNonce,
     getMutationHeader,
+    getMutationHeaderById,
     getMutationBody,
     scanMutationHeaders,
+    scanMutationHeadersByFilter,
     scanGcRecords,
     scanRollupRecords,
     getStorageNodeStatus,
@@ -54,7 +72,13 @@ export {
     setup,
     getContractSyncStatus,
     getMutationState,
+    getSubscriberStats,
+    getMutationProof,
 } from './client/client_v2'
+export {
+    verifyMutationProof,
+    getAnchoredMutationRoot,
+} from './crypto/merkle_utils'
 
 export {
     createDocumentDatabase,
@@ -66,14 +90,32 @@ export {
     getDatabase,
     getCollection,
     addIndex,
+    dropIndex,
+    updateCollectionSchema,
+    updateDatabaseAccessControl,
+    updateCollectionAccessControl,
+    listIndexes,
     deleteEventDatabase,
+    deleteDocumentDatabase,
+    dropCollection,
+    renameCollection,
+    transferDatabase,
 } from './store/database_v2'
 
-export { Index, IndexType } from './proto/db3_database_v2'
+export {
+    Index,
+    IndexField,
+    IndexType,
+    CollectionSchema,
+    FieldSchema,
+    FieldType,
+    AccessControl,
+} from './proto/db3_database_v2'
 export {
     MutationAction,
     MutationHeader,
     MutationBody,
 } from './proto/db3_mutation_v2'
 export { MutationStateView } from './proto/db3_storage'
+export { MutationProof, RollupRecord } from './proto/db3_rollup'
 export { db3MetaStoreContractConfig } from './abi/metastore_abi'
@@ -23,19 +23,33 @@ import { IndexerNodeClient } from '../proto/db3_indexer.client'
 import { SystemClient } from '../proto/db3_system.client'
 import {
     RunQueryRequest,
+    RunStructuredQueryRequest,
+    RunAggregationQueryRequest,
     GetContractSyncStatusRequest,
     GetCollectionOfDatabaseRequest,
     GetDocRequest,
+    ReadAuth,
 } from '../proto/db3_indexer'
 import { SetupRequest, GetSystemStatusRequest } from '../proto/db3_system'
-import { Query } from '../proto/db3_database_v2'
+import {
+    Query,
+    StructuredQuery,
+    Aggregation,
+} from '../proto/db3_database_v2'
+import { DB3Account } from '../account/types'
+import { signTypedData } from '../account/db3_account'
 import { DB3Error } from './error'
 import { RpcError } from '@protobuf-ts/runtime-rpc'
 
 export class IndexerProvider {
     readonly client: IndexerNodeClient
     readonly system: SystemClient
-    constructor(url: string) {
+    readonly account: DB3Account | undefined
+    /**
+     * new an indexer provider and the account is used to sign the read auth
+     * for the collections which are not public read
+     */
+    constructor(url: string, account?: DB3Account) {
         const goptions: GrpcWebOptions = {
             baseUrl: url,
             // simple example for how to add auth headers to each request
@@ -47,13 +61,48 @@ export class IndexerProvider {
         const transport = new GrpcWebFetchTransport(goptions)
         this.client = new IndexerNodeClient(transport)
         this.system = new SystemClient(transport)
+        this.account = account
     }
 
-    async runQuery(db: string, colName: string, query: Query) {
+    private async readAuth(db: string): Promise<ReadAuth | undefined> {
+        if (!this.account) {
+            return undefined
+        }
+        const message = {
+            types: {
+                EIP712Domain: [],
+                Message: [
+                    { name: 'db', type: 'string' },
+                    { name: 'time', type: 'string' },
+                ],
+            },
+            domain: {},
+            primaryType: 'Message',
+            message: {
+                db: db.toLowerCase(),
+                time: Math.floor(Date.now() / 1000).toString(),
+            },
+        }
+        const signature = await signTypedData(this.account, message)
+        const msgParams = JSON.stringify(message)
+        return {
+            payload: new TextEncoder().encode(msgParams),
+            signature,
+        }
+    }
+
+    async runQuery(
+        db: string,
+        colName: string,
+        query: Query,
+        asOfBlock: string = '0'
+    ) {
         const request: RunQueryRequest = {
             db,
             colName,
             query,
+            auth: await this.readAuth(db),
+            asOfBlock,
         }
         try {
             const { response } = await this.client.runQuery(request)
@@ -63,11 +112,60 @@ export class IndexerProvider {
         }
     }
 
-    async getDoc(db: string, colName: string, id: string) {
+    async runStructuredQuery(
+        db: string,
+        query: StructuredQuery,
+        cursor: string = ''
+    ) {
+        const request: RunStructuredQueryRequest = {
+            db,
+            query,
+            cursor,
+            auth: await this.readAuth(db),
+        }
+        try {
+            const { response } = await this.client.runStructuredQuery(request)
+            return response
+        } catch (e) {
+            throw new DB3Error(e as RpcError)
+        }
+    }
+
+    async runAggregationQuery(
+        db: string,
+        query: StructuredQuery,
+        aggregations: Aggregation[],
+        groupBy: string = ''
+    ) {
+        const request: RunAggregationQueryRequest = {
+            db,
+            query,
+            aggregations,
+            groupBy,
+            auth: await this.readAuth(db),
+        }
+        try {
+            const { response } = await this.client.runAggregationQuery(request)
+            return response
+        } catch (e) {
+            throw new DB3Error(e as RpcError)
+        }
+    }
+
+    async getDoc(
+        db: string,
+        colName: string,
+        id: string,
+        key?: string,
+        asOfBlock: string = '0'
+    ) {
         const request: GetDocRequest = {
             dbAddr: db,
             colName,
             id,
+            key: key ? key : '',
+            auth: await this.readAuth(db),
+            asOfBlock,
         }
         try {
             const { response } = await this.client.getDoc(request)
@@ -22,7 +22,11 @@ import {
 } from '@protobuf-ts/grpcweb-transport'
 import { StorageNodeClient } from '../proto/db3_storage.client'
 import { SystemClient } from '../proto/db3_system.client'
-import { MutationBody, Mutation } from '../proto/db3_mutation_v2'
+import {
+    MutationBody,
+    Mutation,
+    MutationAction,
+} from '../proto/db3_mutation_v2'
 import {
     SendMutationRequest,
     GetNonceRequest,
@@ -35,6 +39,8 @@ import {
     ScanGcRecordRequest,
     GetDatabaseRequest,
     GetMutationStateRequest,
+    GetMutationProofRequest,
+    ScanMutationHeaderByFilterRequest,
 } from '../proto/db3_storage'
 import { SetupRequest, GetSystemStatusRequest } from '../proto/db3_system'
 import { fromHEX, toHEX } from '../crypto/crypto_utils'
@@ -130,6 +136,21 @@ export class StorageProviderV2 {
         const request: GetMutationHeaderRequest = {
             blockId: block,
             orderId: order,
+            id: '',
+        }
+        try {
+            const { response } = await this.client.getMutationHeader(request)
+            return response
+        } catch (e) {
+            throw new DB3Error(e)
+        }
+    }
+
+    async getMutationHeaderById(id: string) {
+        const request: GetMutationHeaderRequest = {
+            blockId: '0',
+            orderId: 0,
+            id,
         }
         try {
             const { response } = await this.client.getMutationHeader(request)
@@ -167,6 +188,31 @@ export class StorageProviderV2 {
         }
     }
 
+    async scanMutationHeadersByFilter(
+        sender: string,
+        dbAddr: string,
+        actions: MutationAction[],
+        startBlock: string,
+        startOrder: number,
+        limit: number
+    ) {
+        const request: ScanMutationHeaderByFilterRequest = {
+            sender,
+            dbAddr,
+            actions,
+            startBlock,
+            startOrder,
+            limit,
+        }
+        try {
+            const { response } =
+                await this.client.scanMutationHeaderByFilter(request)
+            return response
+        } catch (e) {
+            throw new DB3Error(e)
+        }
+    }
+
     async scanRollupRecords(start: number, limit: number) {
         const request: ScanRollupRecordRequest = {
             start,
@@ -256,6 +302,18 @@ export class StorageProviderV2 {
         }
     }
 
+    async getMutationProof(id: string) {
+        const request: GetMutationProofRequest = {
+            id,
+        }
+        try {
+            const { response } = await this.client.getMutationProof(request)
+            return response
+        } catch (e) {
+            throw new DB3Error(e)
+        }
+    }
+
     async getDatabase(addr: string) {
         const request: GetDatabaseRequest = {
             addr,
@@ -33,12 +33,23 @@ import {
     DocumentDatabaseMutation,
     EventDatabaseMutation,
     AddIndexMutation,
+    DropIndexMutation,
     DeleteEventDatabaseMutation,
+    UpdateCollectionSchemaMutation,
+    AccessControlMutation,
+    DropCollectionMutation,
+    DeleteDocumentDatabaseMutation,
+    RenameCollectionMutation,
+    TransferDatabaseMutation,
 } from '../proto/db3_mutation_v2'
 
 import { Client, ReadClient } from '../client/types'
 import { toHEX, fromHEX } from '../crypto/crypto_utils'
-import { Index } from '../proto/db3_database_v2'
+import {
+    Index,
+    CollectionSchema,
+    AccessControl,
+} from '../proto/db3_database_v2'
 
 /**
  *
@@ -85,6 +96,211 @@ export async function deleteEventDatabase(client: Client, dbAddress: string) {
     }
 }
 
+/**
+ *
+ * Delete the document database with all its collections and documents
+ *
+ * ```ts
+ * const result = await deleteDocumentDatabase(db)
+ * ```
+ * @param db - the instance of database
+ * @returns the {@link MutationResult}
+ * @note only the owner of document database can delete the document database
+ *
+ **/
+export async function deleteDocumentDatabase(db: Database) {
+    const deleteDocumentDatabaseMutation: DeleteDocumentDatabaseMutation = {}
+    const body: Mutation_BodyWrapper = {
+        body: {
+            oneofKind: 'deleteDocumentDatabaseMutation',
+            deleteDocumentDatabaseMutation,
+        },
+        dbAddress: fromHEX(db.addr),
+    }
+
+    const dm: Mutation = {
+        action: MutationAction.DeleteDocumentDB,
+        bodies: [body],
+    }
+    const payload = Mutation.toBinary(dm)
+    try {
+        const response = await db.client.provider.sendMutation(
+            payload,
+            db.client.nonce.toString()
+        )
+        if (response.code == 0) {
+            return {
+                id: response.id,
+                block: response.block,
+                order: response.order,
+            } as MutationResult
+        } else {
+            throw new Error('fail to delete database with err ' + response.msg)
+        }
+    } catch (e) {
+        throw e
+    } finally {
+        db.client.nonce += 1
+    }
+}
+
+/**
+ *
+ * Drop the collection with its documents and indexes
+ *
+ * ```ts
+ * const result = await dropCollection(collection)
+ * ```
+ * @param collection - the instance of collection
+ * @returns the {@link MutationResult}
+ * @note only the owner of the database or the collection can drop the collection
+ *
+ **/
+export async function dropCollection(collection: Collection) {
+    const dropCollectionMutation: DropCollectionMutation = {
+        collectionName: collection.name,
+    }
+    const body: Mutation_BodyWrapper = {
+        body: { oneofKind: 'dropCollectionMutation', dropCollectionMutation },
+        dbAddress: fromHEX(collection.db.addr),
+    }
+
+    const dm: Mutation = {
+        action: MutationAction.DropCollection,
+        bodies: [body],
+    }
+    const payload = Mutation.toBinary(dm)
+    try {
+        const response = await collection.db.client.provider.sendMutation(
+            payload,
+            collection.db.client.nonce.toString()
+        )
+        if (response.code == 0) {
+            return {
+                id: response.id,
+                block: response.block,
+                order: response.order,
+            } as MutationResult
+        } else {
+            throw new Error('fail to drop collection with err ' + response.msg)
+        }
+    } catch (e) {
+        throw e
+    } finally {
+        collection.db.client.nonce += 1
+    }
+}
+
+/**
+ *
+ * Rename the collection and its documents and indexes are kept
+ *
+ * ```ts
+ * const result = await renameCollection(collection, 'col2')
+ * ```
+ * @param collection - the instance of collection
+ * @param newName    - the new name of the collection
+ * @returns the {@link MutationResult}
+ * @note only the owner of the database or the collection can rename the collection
+ *
+ **/
+export async function renameCollection(
+    collection: Collection,
+    newName: string
+) {
+    const renameCollectionMutation: RenameCollectionMutation = {
+        collectionName: collection.name,
+        newName,
+    }
+    const body: Mutation_BodyWrapper = {
+        body: {
+            oneofKind: 'renameCollectionMutation',
+            renameCollectionMutation,
+        },
+        dbAddress: fromHEX(collection.db.addr),
+    }
+
+    const dm: Mutation = {
+        action: MutationAction.RenameCollection,
+        bodies: [body],
+    }
+    const payload = Mutation.toBinary(dm)
+    try {
+        const response = await collection.db.client.provider.sendMutation(
+            payload,
+            collection.db.client.nonce.toString()
+        )
+        if (response.code == 0) {
+            return {
+                id: response.id,
+                block: response.block,
+                order: response.order,
+            } as MutationResult
+        } else {
+            throw new Error(
+                'fail to rename collection with err ' + response.msg
+            )
+        }
+    } catch (e) {
+        throw e
+    } finally {
+        collection.db.client.nonce += 1
+    }
+}
+
+/**
+ *
+ * Transfer the database and the collections created by the current owner to the new owner
+ *
+ * ```ts
+ * const result = await transferDatabase(db, '0x....')
+ * ```
+ * @param db       - the instance of database
+ * @param newOwner - the hex format address of the new owner
+ * @returns the {@link MutationResult}
+ * @note only the owner of the database can transfer the database
+ *
+ **/
+export async function transferDatabase(db: Database, newOwner: string) {
+    const transferDatabaseMutation: TransferDatabaseMutation = {
+        newOwner: fromHEX(newOwner),
+    }
+    const body: Mutation_BodyWrapper = {
+        body: {
+            oneofKind: 'transferDatabaseMutation',
+            transferDatabaseMutation,
+        },
+        dbAddress: fromHEX(db.addr),
+    }
+
+    const dm: Mutation = {
+        action: MutationAction.TransferDatabase,
+        bodies: [body],
+    }
+    const payload = Mutation.toBinary(dm)
+    try {
+        const response = await db.client.provider.sendMutation(
+            payload,
+            db.client.nonce.toString()
+        )
+        if (response.code == 0) {
+            return {
+                id: response.id,
+                block: response.block,
+                order: response.order,
+            } as MutationResult
+        } else {
+            throw new Error(
+                'fail to transfer database with err ' + response.msg
+            )
+        }
+    } catch (e) {
+        throw e
+    } finally {
+        db.client.nonce += 1
+    }
+}
+
 /**
  *
  * Create an event database to store contract events
@@ -106,6 +322,7 @@ export async function deleteEventDatabase(client: Client, dbAddress: string) {
  * @param abi               - the json abi of contract
  * @param evmNodeUrl        - the websocket url of evm node
  * @param startBlock        - the start block to subscribe, 0 start from the latest block
+ * @param ttl               - the events expire after the ttl in seconds, never expire by default
  * @returns the {@link CreateDBResult}
  *
  **/
@@ -116,19 +333,22 @@ export async function createEventDatabase(
     tables: string[],
     abi: string,
     evmNodeUrl: string,
-    startBlock: string
+    startBlock: string,
+    ttl?: number
 ) {
     const collections = tables.map((name) => {
         const collection: CollectionMutation = {
             indexFields: [],
             collectionName: name,
+            ttl: '0',
+            keepHistory: false,
         }
         return collection
     })
 
     const mutation: EventDatabaseMutation = {
         contractAddress,
-        ttl: '0',
+        ttl: ttl ? ttl.toString() : '0',
         desc,
         tables: collections,
         eventsJsonAbi: abi,
@@ -178,9 +398,20 @@ export async function createEventDatabase(
  *
  *  const index:Index = {
  *    path:'/city', // a top level field name 'city' and the path will be '/city'
- *    indexType: IndexType.StringKey
+ *    indexType: IndexType.StringKey,
+ *    fields: []
  *  }
- *  const result = await addIndex(collection, [index])
+ *  // a compound index is named by the path and covers all the fields. a query
+ *  // like city = 'a' and age > 10 or city = 'a' order by age requires it
+ *  const compoundIndex:Index = {
+ *    path:'city_age',
+ *    indexType: IndexType.UniqueKey,
+ *    fields: [
+ *      { path: '/city', indexType: IndexType.StringKey },
+ *      { path: '/age', indexType: IndexType.Int64Key },
+ *    ]
+ *  }
+ *  const result = await addIndex(collection, [index, compoundIndex])
  * ```
  * @param client    - the db3 client instance
  * @param indexes   - the index list of {@link Index}
@@ -188,7 +419,12 @@ export async function createEventDatabase(
  *
  **/
 export async function addIndex(collection: Collection, indexes: Index[]) {
-    if (indexes.filter((item) => !item.path.startsWith('/')).length > 0) {
+    const paths = indexes.flatMap((item) =>
+        item.fields.length > 0
+            ? item.fields.map((field) => field.path)
+            : [item.path]
+    )
+    if (paths.filter((path) => !path.startsWith('/')).length > 0) {
         throw new Error('the index path must start with /')
     }
     const addIndexMutation: AddIndexMutation = {
@@ -229,6 +465,146 @@ export async function addIndex(collection: Collection, indexes: Index[]) {
     }
 }
 
+/**
+ *
+ * Drop the indexes from the existing Collection
+ *
+ * ```ts
+ *  // drop the single field index '/city' and the compound index 'city_age'
+ *  const result = await dropIndex(collection, ['/city', 'city_age'])
+ * ```
+ * @param collection - the instance of collection
+ * @param paths      - the paths of single field indexes or the names of compound indexes
+ * @returns the {@link MutationResult}
+ *
+ **/
+export async function dropIndex(collection: Collection, paths: string[]) {
+    const dropIndexMutation: DropIndexMutation = {
+        collectionName: collection.name,
+        paths,
+    }
+
+    const body: Mutation_BodyWrapper = {
+        body: { oneofKind: 'dropIndexMutation', dropIndexMutation },
+        dbAddress: fromHEX(collection.db.addr),
+    }
+
+    const dm: Mutation = {
+        action: MutationAction.DropIndex,
+        bodies: [body],
+    }
+    const payload = Mutation.toBinary(dm)
+    try {
+        const response = await collection.db.client.provider.sendMutation(
+            payload,
+            collection.db.client.nonce.toString()
+        )
+        if (response.code == 0) {
+            return {
+                result: {
+                    id: response.id,
+                    block: response.block,
+                    order: response.order,
+                } as MutationResult,
+            }
+        } else {
+            throw new Error('fail to drop index with err ' + response.msg)
+        }
+    } catch (e) {
+        throw e
+    } finally {
+        collection.db.client.nonce += 1
+    }
+}
+
+/**
+ *
+ * Replace the schema of the existing Collection and the existing documents are not validated again
+ *
+ * ```ts
+ *  const schema:CollectionSchema = {
+ *    fields: [
+ *      { path: 'name', fieldType: FieldType.FieldTypeString, required: true },
+ *      { path: 'profile.age', fieldType: FieldType.FieldTypeInt64, required: false },
+ *    ],
+ *    strict: false,
+ *  }
+ *  const result = await updateCollectionSchema(collection, schema)
+ *  // remove the schema of the collection
+ *  await updateCollectionSchema(collection)
+ * ```
+ * @param collection - the instance of collection
+ * @param schema     - the new {@link CollectionSchema} and the collection has no schema if it's undefined
+ * @returns the {@link MutationResult}
+ *
+ **/
+export async function updateCollectionSchema(
+    collection: Collection,
+    schema?: CollectionSchema
+) {
+    const updateCollectionSchemaMutation: UpdateCollectionSchemaMutation = {
+        collectionName: collection.name,
+        schema,
+    }
+
+    const body: Mutation_BodyWrapper = {
+        body: {
+            oneofKind: 'updateCollectionSchemaMutation',
+            updateCollectionSchemaMutation,
+        },
+        dbAddress: fromHEX(collection.db.addr),
+    }
+
+    const dm: Mutation = {
+        action: MutationAction.UpdateCollectionSchema,
+        bodies: [body],
+    }
+    const payload = Mutation.toBinary(dm)
+    try {
+        const response = await collection.db.client.provider.sendMutation(
+            payload,
+            collection.db.client.nonce.toString()
+        )
+        if (response.code == 0) {
+            return {
+                result: {
+                    id: response.id,
+                    block: response.block,
+                    order: response.order,
+                } as MutationResult,
+            }
+        } else {
+            throw new Error(
+                'fail to update collection schema with err ' + response.msg
+            )
+        }
+    } catch (e) {
+        throw e
+    } finally {
+        collection.db.client.nonce += 1
+    }
+}
+
+/**
+ *
+ * List the single field and compound indexes of the Collection
+ *
+ * ```ts
+ * const indexes = await listIndexes(collection)
+ * ```
+ * @param collection - the instance of collection
+ * @returns the {@link Index}[]
+ *
+ **/
+export async function listIndexes(collection: Collection) {
+    const latest = await getCollection(
+        collection.db.addr,
+        collection.name,
+        collection.db.client
+    )
+    return latest.indexFields
+}
+
 /**
  *
  * Create a document database to group the collections
@@ -382,7 +758,8 @@ export async function showDatabase(owner: string, client: Client | ReadClient) {
  * ```ts
  * const index1:Index = {
  *    path:'/city', // a top level field name 'city' and the path will be '/city'
- *    indexType: IndexType.StringKey
+ *    indexType: IndexType.StringKey,
+ *    fields: []
  * }
  * const {collection, result} = await createCollection(db, "test_collection", [index1])
  * ```
@@ -391,17 +768,26 @@ export async function showDatabase(owner: string, client: Client | ReadClient) {
  * @param db          - the instance of database
  * @param name        - the name of collection
  * @param indexFields - the fields for {@link Index}
+ * @param schema      - an optional {@link CollectionSchema} to validate the documents
+ * @param ttl         - the documents expire after the ttl in seconds, never expire by default
+ * @param keepHistory - keep the previous versions of the documents to read them as of a block
  * @returns the {@link CreateCollectionResult}
  *
  **/
 export async function createCollection(
     db: Database,
     name: string,
-    indexFields?: Index[]
+    indexFields?: Index[],
+    schema?: CollectionSchema,
+    ttl?: number,
+    keepHistory?: boolean
 ) {
     const collection: CollectionMutation = {
         indexFields: indexFields ? indexFields : [],
         collectionName: name,
+        schema,
+        ttl: ttl ? ttl.toString() : '0',
+        keepHistory: keepHistory ? keepHistory : false,
     }
     const body: Mutation_BodyWrapper = {
         body: {
@@ -496,3 +882,98 @@ export async function showCollectionFromIndex(db: Database) {
     })
     return collectionList
 }
+
+async function sendAccessControlMutation(
+    db: Database,
+    collectionName: string,
+    acl?: AccessControl
+) {
+    const accessControlMutation: AccessControlMutation = {
+        collectionName,
+        acl,
+    }
+
+    const body: Mutation_BodyWrapper = {
+        body: { oneofKind: 'accessControlMutation', accessControlMutation },
+        dbAddress: fromHEX(db.addr),
+    }
+
+    const dm: Mutation = {
+        action: MutationAction.UpdateAccessControl,
+        bodies: [body],
+    }
+    const payload = Mutation.toBinary(dm)
+    try {
+        const response = await db.client.provider.sendMutation(
+            payload,
+            db.client.nonce.toString()
+        )
+        if (response.code == 0) {
+            return {
+                result: {
+                    id: response.id,
+                    block: response.block,
+                    order: response.order,
+                } as MutationResult,
+            }
+        } else {
+            throw new Error(
+                'fail to update access control with err ' + response.msg
+            )
+        }
+    } catch (e) {
+        throw e
+    } finally {
+        db.client.nonce += 1
+    }
+}
+
+/**
+ *
+ * Replace the access control list of the database and only the owner of the database can do it.
+ * The collections without their own access control list follow it
+ *
+ * ```ts
+ *  const acl:AccessControl = {
+ *    writers: [fromHEX(bob.address)],
+ *    readers: [],
+ *    publicRead: true,
+ *  }
+ *  const result = await updateDatabaseAccessControl(db, acl)
+ * ```
+ * @param db  - the instance of database
+ * @param acl - the new {@link AccessControl} and everyone can write the database if it's undefined
+ * @returns the {@link MutationResult}
+ *
+ **/
+export async function updateDatabaseAccessControl(
+    db: Database,
+    acl?: AccessControl
+) {
+    return sendAccessControlMutation(db, '', acl)
+}
+
+/**
+ *
+ * Replace the access control list of the collection which overrides the one of the database.
+ * Only the owner of the database or the collection can do it
+ *
+ * ```ts
+ *  const acl:AccessControl = {
+ *    writers: [fromHEX(bob.address)],
+ *    readers: [fromHEX(carol.address)],
+ *    publicRead: false,
+ *  }
+ *  const result = await updateCollectionAccessControl(collection, acl)
+ * ```
+ * @param collection - the instance of collection
+ * @param acl        - the new {@link AccessControl} and the collection follows the database if it's undefined
+ * @returns the {@link MutationResult}
+ *
+ **/
+export async function updateCollectionAccessControl(
+    collection: Collection,
+    acl?: AccessControl
+) {
+    return sendAccessControlMutation(collection.db, collection.name, acl)
+}
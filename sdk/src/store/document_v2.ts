@@ -21,23 +21,41 @@ import {
     DocumentMask,
     Mutation_BodyWrapper,
     MutationAction,
+    Precondition,
+    DocumentTransformMutation,
+    DocumentQueryMutation,
+    FieldTransform,
+    ServerValue,
 } from '../proto/db3_mutation_v2'
 import { BSON } from 'db3-bson'
 import { fromHEX } from '../crypto/crypto_utils'
 import type { Collection, QueryResult } from './types'
-import type { Query, QueryParameter } from '../proto/db3_database_v2'
+import type {
+    Query,
+    QueryParameter,
+    StructuredQuery,
+    StructuredQuery_Value,
+} from '../proto/db3_database_v2'
+import { StructuredQuery_NullValue } from '../proto/db3_database_v2'
 import type { DocumentData, DocumentEntry } from '../client/base'
 
-async function runQueryInternal<T>(col: Collection, query: Query) {
+async function runQueryInternal<T>(
+    col: Collection,
+    query: Query,
+    asOfBlock: string = '0'
+) {
     const response = await col.db.client.indexer.runQuery(
         col.db.addr,
         col.name,
-        query
+        query,
+        asOfBlock
     )
     const entries = response.documents.map((doc) => {
         return {
             doc: JSON.parse(doc.doc) as T,
             id: doc.id,
+            revision: doc.revision,
+            key: doc.key,
         } as DocumentEntry<T>
     })
 
@@ -139,18 +157,140 @@ export async function getDoc<T = DocumentData>(col: Collection, id: string) {
         return {
             doc: JSON.parse(response.document.doc) as T,
             id: response.document.id,
+            revision: response.document.revision,
+            key: response.document.key,
         } as DocumentEntry<T>
     } else {
         throw new Error('no document was found with id ' + id)
     }
 }
 
-export async function deleteDoc(col: Collection, ids: string[]) {
+/**
+ *
+ * Query the versions of the documents as of a block. The collection must be
+ * created with `keepHistory`
+ *
+ * ```ts
+ * const resultSet = await queryDocAsOf<Profile>(collection, '/* | limit 1', '100')
+ * ```
+ *
+ * @param col        - the instance of collection
+ * @param queryStr   - a document query string
+ * @param block      - the block to read the documents as of
+ * @param parameters - an optional query parameters
+ * @returns the {@link QueryResult}
+ *
+ **/
+export async function queryDocAsOf<T = DocumentData>(
+    col: Collection,
+    queryStr: string,
+    block: string,
+    parameters?: QueryParameter[]
+) {
+    const query: Query = {
+        queryStr,
+        parameters: parameters ? parameters : [],
+    }
+    return runQueryInternal<T>(col, query, block)
+}
+
+/**
+ *
+ * This function gets the version of a document as of a block. The collection
+ * must be created with `keepHistory`
+ *
+ * ```ts
+ * const doc = await getDocAsOf(collection, "10", "100")
+ * ```
+ * @param col    - the instance of collection
+ * @param id     - the id of document
+ * @param block  - the block to read the document as of
+ * @returns the {@link DocumentEntry} if the document existed at the block. Otherwise, raises an error.
+ **/
+export async function getDocAsOf<T = DocumentData>(
+    col: Collection,
+    id: string,
+    block: string
+) {
+    const response = await col.db.client.indexer.getDoc(
+        col.db.addr,
+        col.name,
+        id,
+        undefined,
+        block
+    )
+    if (response.document) {
+        return {
+            doc: JSON.parse(response.document.doc) as T,
+            id: response.document.id,
+            revision: response.document.revision,
+            key: response.document.key,
+        } as DocumentEntry<T>
+    } else {
+        throw new Error(
+            'no document was found with id ' + id + ' as of block ' + block
+        )
+    }
+}
+
+/**
+ *
+ * This function gets a document from the database by its key.
+ *
+ * ```ts
+ * const doc = await getDocByKey(collection, "alice")
+ * ```
+ * @param col    - the instance of collection
+ * @param key    - the key of document
+ * @returns the {@link DocumentEntry} if the document was found. Otherwise, raises an error.
+ **/
+export async function getDocByKey<T = DocumentData>(
+    col: Collection,
+    key: string
+) {
+    const response = await col.db.client.indexer.getDoc(
+        col.db.addr,
+        col.name,
+        '0',
+        key
+    )
+    if (response.document) {
+        return {
+            doc: JSON.parse(response.document.doc) as T,
+            id: response.document.id,
+            revision: response.document.revision,
+            key: response.document.key,
+        } as DocumentEntry<T>
+    } else {
+        throw new Error('no document was found with key ' + key)
+    }
+}
+
+/**
+ * Delete the documents from the collection.
+ *
+ * ```ts
+ * // delete the document only if nobody has changed it since it was read
+ * const doc = await getDoc(collection, "10")
+ * await deleteDoc(collection, ["10"], [revisionPrecondition(doc.revision)])
+ * ```
+ * @param col           - the instance of collection
+ * @param ids           - the ids of documents
+ * @param preconditions - an optional precondition for every document
+ * @returns the {@link MutationResult}
+ **/
+export async function deleteDoc(
+    col: Collection,
+    ids: string[],
+    preconditions?: Precondition[]
+) {
     const documentMutation: DocumentMutation = {
         collectionName: col.name,
         documents: [],
         ids,
         masks: [],
+        preconditions: preconditions ? preconditions : [],
+        keys: [],
     }
     const body: Mutation_BodyWrapper = {
         body: {
@@ -178,24 +318,49 @@ export async function deleteDoc(col: Collection, ids: string[]) {
             block: response.block,
             order: response.order,
         }
+    } else if (response.code == 2) {
+        col.db.client.nonce += 1
+        throw new Error('precondition failed with err ' + response.msg)
     } else {
         throw new Error('fail to delete doc')
     }
 }
 
+/**
+ * Update a document in the collection.
+ *
+ * ```ts
+ * // update the document only if nobody has changed it since it was read
+ * const doc = await getDoc(collection, "10")
+ * await updateDoc(collection, "10", {name: "John"}, revisionPrecondition(doc.revision))
+ * // only update the name and delete the age of the document
+ * await updateDoc(collection, "10", {name: "John"}, undefined, ["name", "age"])
+ * ```
+ * @param col          - the instance of collection
+ * @param id           - the id of document
+ * @param doc          - the new document
+ * @param precondition - an optional precondition of the document
+ * @param updateMask   - an optional list of field paths to update, the fields in the mask but
+ *                       not in the document are deleted. The whole document is merged if it's empty
+ * @returns the {@link MutationResult}
+ **/
 export async function updateDoc(
     col: Collection,
     id: string,
-    doc: DocumentData
+    doc: DocumentData,
+    precondition?: Precondition,
+    updateMask?: string[]
 ) {
     const documentMask: DocumentMask = {
-        fields: [],
+        fields: updateMask ? updateMask : [],
     }
     const documentMutation: DocumentMutation = {
         collectionName: col.name,
         documents: [BSON.serialize(doc)],
         ids: [id],
         masks: [documentMask],
+        preconditions: precondition ? [precondition] : [],
+        keys: [],
     }
     const body: Mutation_BodyWrapper = {
         body: {
@@ -220,24 +385,374 @@ export async function updateDoc(
             block: response.block,
             order: response.order,
         }
+    } else if (response.code == 2) {
+        col.db.client.nonce += 1
+        throw new Error('precondition failed with err ' + response.msg)
     } else {
         throw new Error('fail to update doc')
     }
 }
 
+async function sendQueryMutation(
+    col: Collection,
+    action: MutationAction,
+    query: StructuredQuery,
+    doc?: DocumentData,
+    updateMask?: string[]
+) {
+    const documentQueryMutation: DocumentQueryMutation = {
+        query: { ...query, collectionName: col.name },
+        document: doc ? BSON.serialize(doc) : new Uint8Array(),
+        mask: { fields: updateMask ? updateMask : [] },
+    }
+    const body: Mutation_BodyWrapper = {
+        body: {
+            oneofKind: 'documentQueryMutation',
+            documentQueryMutation,
+        },
+        dbAddress: fromHEX(col.db.addr),
+    }
+    const dm: Mutation = {
+        action,
+        bodies: [body],
+    }
+    const payload = Mutation.toBinary(dm)
+    const response = await col.db.client.provider.sendMutation(
+        payload,
+        col.db.client.nonce.toString()
+    )
+    if (response.code == 0) {
+        col.db.client.nonce += 1
+        // the storage node returns the ids of the matched documents
+        const matched = response.items.find((item) => item.key == 'matched_0')
+        return {
+            mid: response.id,
+            block: response.block,
+            order: response.order,
+            ids: matched && matched.value ? matched.value.split(',') : [],
+        }
+    } else {
+        throw new Error('fail to apply the mutation by query')
+    }
+}
+
+/**
+ * Delete the documents matched by the query and owned by the sender.
+ * The storage node resolves the matched documents with its doc store, so it
+ * rejects the mutation if it is started without `--enable-doc-store`.
+ *
+ * ```ts
+ * const { ids } = await deleteDocsByQuery(collection, {
+ *     collectionName: collection.name,
+ *     where: nameFilter,
+ *     orderBy: [],
+ * })
+ * ```
+ * @param col   - the instance of collection
+ * @param query - the structured query to match the documents
+ * @returns the {@link MutationResult} with the ids of the deleted documents
+ **/
+export async function deleteDocsByQuery(
+    col: Collection,
+    query: StructuredQuery
+) {
+    return sendQueryMutation(col, MutationAction.DeleteDocumentByQuery, query)
+}
+
+/**
+ * Update the documents matched by the query and owned by the sender.
+ * The storage node resolves the matched documents with its doc store, so it
+ * rejects the mutation if it is started without `--enable-doc-store`.
+ *
+ * ```ts
+ * // set the status of all the matched documents
+ * const { ids } = await updateDocsByQuery(collection, query, {status: "done"})
+ * ```
+ * @param col        - the instance of collection
+ * @param query      - the structured query to match the documents
+ * @param doc        - the document merged into every matched document
+ * @param updateMask - an optional field mask of the update
+ * @returns the {@link MutationResult} with the ids of the updated documents
+ **/
+export async function updateDocsByQuery(
+    col: Collection,
+    query: StructuredQuery,
+    doc: DocumentData,
+    updateMask?: string[]
+) {
+    return sendQueryMutation(
+        col,
+        MutationAction.UpdateDocumentByQuery,
+        query,
+        doc,
+        updateMask
+    )
+}
+
+/**
+ * Apply the field transforms to a document atomically on the server.
+ *
+ * ```ts
+ * await transformDoc(collection, "10", [
+ *     increment("count", 1),
+ *     arrayUnion("tags", ["db3"]),
+ *     serverTimestamp("updatedAt"),
+ * ])
+ * ```
+ * @param col          - the instance of collection
+ * @param id           - the id of document
+ * @param transforms   - the field transforms which are applied in order
+ * @param precondition - an optional precondition of the document
+ * @returns the {@link MutationResult}
+ **/
+export async function transformDoc(
+    col: Collection,
+    id: string,
+    transforms: FieldTransform[],
+    precondition?: Precondition
+) {
+    const documentTransformMutation: DocumentTransformMutation = {
+        collectionName: col.name,
+        ids: [id],
+        transforms: [{ fieldTransforms: transforms }],
+        preconditions: precondition ? [precondition] : [],
+    }
+    const body: Mutation_BodyWrapper = {
+        body: {
+            oneofKind: 'documentTransformMutation',
+            documentTransformMutation,
+        },
+        dbAddress: fromHEX(col.db.addr),
+    }
+    const dm: Mutation = {
+        action: MutationAction.TransformDocument,
+        bodies: [body],
+    }
+    const payload = Mutation.toBinary(dm)
+    const response = await col.db.client.provider.sendMutation(
+        payload,
+        col.db.client.nonce.toString()
+    )
+    if (response.code == 0) {
+        col.db.client.nonce += 1
+        return {
+            mid: response.id,
+            block: response.block,
+            order: response.order,
+        }
+    } else if (response.code == 2) {
+        col.db.client.nonce += 1
+        throw new Error('precondition failed with err ' + response.msg)
+    } else {
+        throw new Error('fail to transform doc')
+    }
+}
+
+type TransformElement = string | number | boolean | null
+
+function toValue(value: TransformElement): StructuredQuery_Value {
+    if (value === null) {
+        return {
+            valueType: {
+                oneofKind: 'nullValue',
+                nullValue: StructuredQuery_NullValue.NULL_VALUE,
+            },
+        }
+    } else if (typeof value === 'number') {
+        return Number.isInteger(value)
+            ? {
+                  valueType: {
+                      oneofKind: 'integerValue',
+                      integerValue: value.toString(),
+                  },
+              }
+            : { valueType: { oneofKind: 'doubleValue', doubleValue: value } }
+    } else if (typeof value === 'boolean') {
+        return { valueType: { oneofKind: 'booleanValue', booleanValue: value } }
+    } else {
+        return { valueType: { oneofKind: 'stringValue', stringValue: value } }
+    }
+}
+
+/**
+ * Add the number to the field, the field which is not a number is set to the given one
+ **/
+export function increment(fieldPath: string, value: number): FieldTransform {
+    return {
+        fieldPath,
+        transformType: { oneofKind: 'increment', increment: toValue(value) },
+    }
+}
+
+/**
+ * Set the field to the larger one of the field and the given number
+ **/
+export function maximum(fieldPath: string, value: number): FieldTransform {
+    return {
+        fieldPath,
+        transformType: { oneofKind: 'maximum', maximum: toValue(value) },
+    }
+}
+
+/**
+ * Set the field to the smaller one of the field and the given number
+ **/
+export function minimum(fieldPath: string, value: number): FieldTransform {
+    return {
+        fieldPath,
+        transformType: { oneofKind: 'minimum', minimum: toValue(value) },
+    }
+}
+
+/**
+ * Append the elements which are not in the array field
+ **/
+export function arrayUnion(
+    fieldPath: string,
+    elements: TransformElement[]
+): FieldTransform {
+    return {
+        fieldPath,
+        transformType: {
+            oneofKind: 'appendMissingElements',
+            appendMissingElements: { values: elements.map(toValue) },
+        },
+    }
+}
+
+/**
+ * Remove all the given elements from the array field
+ **/
+export function arrayRemove(
+    fieldPath: string,
+    elements: TransformElement[]
+): FieldTransform {
+    return {
+        fieldPath,
+        transformType: {
+            oneofKind: 'removeAllFromArray',
+            removeAllFromArray: { values: elements.map(toValue) },
+        },
+    }
+}
+
+/**
+ * Set the field to the time of the mutation in milliseconds
+ **/
+export function serverTimestamp(fieldPath: string): FieldTransform {
+    return {
+        fieldPath,
+        transformType: {
+            oneofKind: 'setToServerValue',
+            setToServerValue: ServerValue.RequestTime,
+        },
+    }
+}
+
+/**
+ * Build a precondition which requires the document to be at the given revision
+ *
+ * @param revision - the revision of document returned by {@link getDoc} or {@link queryDoc}
+ * @returns the {@link Precondition}
+ **/
+export function revisionPrecondition(revision: string): Precondition {
+    return {
+        conditionType: {
+            oneofKind: 'revision',
+            revision,
+        },
+    }
+}
+
+/**
+ * Build a precondition which requires the document to exist or not
+ *
+ * @param exists - whether the document should exist
+ * @returns the {@link Precondition}
+ **/
+export function existsPrecondition(exists: boolean): Precondition {
+    return {
+        conditionType: {
+            oneofKind: 'exists',
+            exists,
+        },
+    }
+}
+
+/**
+ * Insert a document with the key or replace the document which has the key.
+ *
+ * ```ts
+ * await upsertDoc(collection, "alice", {name: "alice", age: 10})
+ * ```
+ * @param col          - the instance of collection
+ * @param key          - the key of document which is unique in the collection
+ * @param doc          - the document
+ * @param precondition - an optional precondition of the document
+ * @returns the {@link MutationResult} and the id of document if it's inserted
+ **/
+export async function upsertDoc(
+    col: Collection,
+    key: string,
+    doc: DocumentData,
+    precondition?: Precondition
+) {
+    const documentMutation: DocumentMutation = {
+        collectionName: col.name,
+        documents: [BSON.serialize(doc)],
+        ids: [],
+        masks: [],
+        preconditions: precondition ? [precondition] : [],
+        keys: [key],
+    }
+    const body: Mutation_BodyWrapper = {
+        body: {
+            oneofKind: 'documentMutation',
+            documentMutation,
+        },
+        dbAddress: fromHEX(col.db.addr),
+    }
+    const dm: Mutation = {
+        action: MutationAction.UpsertDocument,
+        bodies: [body],
+    }
+    const payload = Mutation.toBinary(dm)
+    const response = await col.db.client.provider.sendMutation(
+        payload,
+        col.db.client.nonce.toString()
+    )
+    if (response.code == 0) {
+        col.db.client.nonce += 1
+        return {
+            mid: response.id,
+            block: response.block,
+            order: response.order,
+            id: response.items.length > 0 ? response.items[0].value : undefined,
+        }
+    } else if (response.code == 2) {
+        col.db.client.nonce += 1
+        throw new Error('precondition failed with err ' + response.msg)
+    } else {
+        throw new Error('fail to upsert doc')
+    }
+}
+
 /**
  * Add a document to the collection.
  *
  * @param col The collection to add the document to.
  * @param doc The document to add.
+ * @param key The optional key of the document which is unique in the collection.
  * @returns The ID of the newly added document.
  */
-export async function addDoc(col: Collection, doc: DocumentData) {
+export async function addDoc(col: Collection, doc: DocumentData, key?: string) {
     const documentMutation: DocumentMutation = {
         collectionName: col.name,
         documents: [BSON.serialize(doc)],
         ids: [],
         masks: [],
+        preconditions: [],
+        keys: key ? [key] : [],
     }
     const body: Mutation_BodyWrapper = {
         body: {
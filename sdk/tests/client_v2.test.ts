@@ -189,6 +189,7 @@ describe('test db3.js client module', () => {
             const index: Index = {
                 path: '/city',
                 indexType: IndexType.StringKey,
+                fields: [],
             }
             {
                 const { collection, result } = await createCollection(
@@ -285,6 +286,7 @@ describe('test db3.js client module', () => {
             const index: Index = {
                 path: '/city',
                 indexType: IndexType.StringKey,
+                fields: [],
             }
             const { collection } = await createCollection(db, 'col', [index])
             expect(collection).toBeDefined()
@@ -298,6 +300,7 @@ describe('test db3.js client module', () => {
             const index2: Index = {
                 path: '/name',
                 indexType: IndexType.StringKey,
+                fields: [],
             }
             try {
                 const result = await addIndex(collection, [index2])
@@ -308,6 +311,7 @@ describe('test db3.js client module', () => {
             const badIndex: Index = {
                 path: 'name',
                 indexType: IndexType.StringKey,
+                fields: [],
             }
             try {
                 const result = await addIndex(collection, [badIndex])
@@ -351,6 +355,7 @@ describe('test db3.js client module', () => {
             const index: Index = {
                 path: '/city',
                 indexType: IndexType.StringKey,
+                fields: [],
             }
             {
                 const { collection, result } = await createCollection(
@@ -467,6 +472,7 @@ describe('test db3.js client module', () => {
             const index: Index = {
                 path: '/city',
                 indexType: IndexType.StringKey,
+                fields: [],
             }
             {
                 const { collection, result } = await createCollection(
@@ -529,6 +535,7 @@ describe('test db3.js client module', () => {
             const index: Index = {
                 path: '/city',
                 indexType: IndexType.StringKey,
+                fields: [],
             }
             {
                 const { collection, result } = await createCollection(
@@ -600,6 +607,7 @@ describe('test db3.js client module', () => {
             const index: Index = {
                 path: '/city',
                 indexType: IndexType.StringKey,
+                fields: [],
             }
             {
                 const { collection, result } = await createCollection(
@@ -751,6 +759,7 @@ describe('test db3.js client module', () => {
             const index: Index = {
                 path: '/city',
                 indexType: IndexType.StringKey,
+                fields: [],
             }
             const { collection } = await createCollection(db, 'col', [index])
             for (var i = 0; i < 1; i++) {
@@ -68,6 +68,7 @@ describe('test db3.js sdk database', () => {
         const index: Index = {
             path: '/city',
             indexType: IndexType.StringKey,
+            fields: [],
         }
         {
             const { collection, result } = await createCollection(db, 'col1', [
@@ -6,7 +6,11 @@ use db3_proto::db3_database_v2_proto::structured_query::composite_filter::Operat
 use db3_proto::db3_database_v2_proto::structured_query::field_filter::Operator;
 use db3_proto::db3_database_v2_proto::structured_query::filter::FilterType;
 use db3_proto::db3_database_v2_proto::structured_query::value::ValueType;
+use db3_proto::db3_database_v2_proto::structured_query::ArrayValue;
 use db3_proto::db3_database_v2_proto::structured_query::Filter;
+use db3_proto::db3_database_v2_proto::structured_query::MapValue;
+use db3_proto::db3_database_v2_proto::structured_query::NullValue;
+use db3_proto::db3_database_v2_proto::structured_query::Timestamp;
 use db3_proto::db3_database_v2_proto::structured_query::Value;
 use db3_proto::db3_database_v2_proto::structured_query::{CompositeFilter, FieldFilter};
 use serde_json::Value as JsonValue;
@@ -44,15 +48,158 @@ pub fn bson_document_into_bytes(doc: &Document) -> Vec<u8> {
     row_doc.into_bytes()
 }
 
+/// convert Bson::Document into the json string stored in the doc store. the datetime is
+/// stored as the milliseconds since epoch and the binary is stored as the hex string, so
+/// they can be compared in the query.
+///
+/// the documents written before by `Document::to_string` keep the same json value, the
+/// datetime and the binary were rendered as `DateTime(..)` and `Binary(..)` there, which
+/// is not json and was rejected by the doc store, so no stored document has to be migrated
+pub fn bson_document_into_json_str(doc: &Document) -> String {
+    bson_into_json_value(Bson::Document(doc.clone())).to_string()
+}
+
+/// convert the proto value into the json value with the same format of the doc store
+pub fn json_value_from_proto_value(value: &Value) -> std::result::Result<JsonValue, DB3Error> {
+    Ok(bson_into_json_value(bson_value_from_proto_value(value)?))
+}
+
+fn bson_into_json_value(value: Bson) -> JsonValue {
+    match value {
+        Bson::DateTime(dt) => JsonValue::from(dt.timestamp_millis()),
+        Bson::Binary(b) => JsonValue::String(hex::encode(b.bytes)),
+        Bson::Int32(n) => JsonValue::from(n),
+        Bson::Int64(n) => JsonValue::from(n),
+        Bson::Document(doc) => JsonValue::Object(
+            doc.into_iter()
+                .map(|(k, v)| (k, bson_into_json_value(v)))
+                .collect(),
+        ),
+        Bson::Array(a) => JsonValue::Array(a.into_iter().map(bson_into_json_value).collect()),
+        other => other.into_relaxed_extjson(),
+    }
+}
+
+/// convert the timestamp to the milliseconds since epoch and the additional precision is rounded down
+pub fn timestamp_to_millis(ts: &Timestamp) -> i64 {
+    ts.seconds
+        .saturating_mul(1000)
+        .saturating_add((ts.nanos / 1_000_000) as i64)
+}
+
+pub fn timestamp_from_millis(millis: i64) -> Timestamp {
+    Timestamp {
+        seconds: millis.div_euclid(1000),
+        nanos: (millis.rem_euclid(1000) * 1_000_000) as i32,
+    }
+}
+
+/// check the dot separated field path. the empty segment and the segment wrapped by
+/// double underscores like `__name__` are reserved
+pub fn check_field_path(path: &str) -> std::result::Result<(), DB3Error> {
+    let reserved = path.split('.').any(|seg| {
+        seg.is_empty() || (seg.len() >= 4 && seg.starts_with("__") && seg.ends_with("__"))
+    });
+    if reserved {
+        return Err(DB3Error::InvalidKeyPathError(format!(
+            "the field path {path} is empty or contains a reserved name"
+        )));
+    }
+    Ok(())
+}
+
+fn get_field_by_path<'a>(doc: &'a Document, segments: &[&str]) -> Option<&'a Bson> {
+    let (first, rest) = segments.split_first()?;
+    let value = doc.get(*first)?;
+    if rest.is_empty() {
+        return Some(value);
+    }
+    match value {
+        Bson::Document(d) => get_field_by_path(d, rest),
+        _ => None,
+    }
+}
+
+fn set_field_by_path(doc: &mut Document, segments: &[&str], value: Bson) {
+    if let Some((first, rest)) = segments.split_first() {
+        if rest.is_empty() {
+            doc.insert(*first, value);
+            return;
+        }
+        if !matches!(doc.get(*first), Some(Bson::Document(_))) {
+            doc.insert(*first, Document::new());
+        }
+        if let Some(Bson::Document(d)) = doc.get_mut(*first) {
+            set_field_by_path(d, rest, value);
+        }
+    }
+}
+
+/// build the merge patch of the document with the field mask. the masked fields in the
+/// document are copied into the patch and the missing ones are set to null, which deletes
+/// them from the stored document. the fields out of the mask are ignored
+pub fn bson_document_with_mask(
+    doc: &Document,
+    fields: &[String],
+) -> std::result::Result<Document, DB3Error> {
+    for (i, path) in fields.iter().enumerate() {
+        check_field_path(path)?;
+        let overlapped = fields.iter().enumerate().any(|(j, other)| {
+            i != j && (path == other || other.starts_with(format!("{path}.").as_str()))
+        });
+        if overlapped {
+            return Err(DB3Error::InvalidKeyPathError(format!(
+                "the field path {path} overlaps with others in the mask"
+            )));
+        }
+    }
+    let mut patch = Document::new();
+    for path in fields {
+        let segments: Vec<&str> = path.split('.').collect();
+        let value = get_field_by_path(doc, &segments)
+            .cloned()
+            .unwrap_or(Bson::Null);
+        set_field_by_path(&mut patch, &segments, value);
+    }
+    Ok(patch)
+}
+
 pub fn bson_value_from_proto_value(value: &Value) -> std::result::Result<Bson, DB3Error> {
     if let Some(value_type) = &value.value_type {
         match value_type {
             ValueType::BooleanValue(b) => Ok(Bson::Boolean(*b)),
             ValueType::IntegerValue(n) => Ok(Bson::Int64(*n)),
             ValueType::StringValue(s) => Ok(Bson::String(s.to_string())),
-            _ => Err(DB3Error::InvalidFilterValue(
-                "value is not support".to_string(),
-            )),
+            ValueType::DoubleValue(d) => Ok(Bson::Double(*d)),
+            ValueType::NullValue(_) => Ok(Bson::Null),
+            ValueType::TimestampValue(ts) => Ok(Bson::DateTime(bson::DateTime::from_millis(
+                timestamp_to_millis(ts),
+            ))),
+            ValueType::BytesValue(b) => Ok(Bson::Binary(bson::Binary {
+                subtype: bson::spec::BinarySubtype::Generic,
+                bytes: b.to_vec(),
+            })),
+            ValueType::ArrayValue(a) => {
+                let values = a
+                    .values
+                    .iter()
+                    .map(bson_value_from_proto_value)
+                    .collect::<std::result::Result<Vec<Bson>, DB3Error>>()?;
+                Ok(Bson::Array(values))
+            }
+            ValueType::MapValue(m) => {
+                // sort the keys to keep the document stable
+                let mut keys: Vec<&String> = m.fields.keys().collect();
+                keys.sort();
+                let mut doc = Document::new();
+                for key in keys {
+                    doc.insert(
+                        key.to_string(),
+                        bson_value_from_proto_value(&m.fields[key])?,
+                    );
+                }
+                Ok(Bson::Document(doc))
+            }
         }
     } else {
         Err(DB3Error::InvalidFilterValue("value is none".to_string()))
@@ -83,6 +230,10 @@ fn field_filter_from_json_value(
         "<" => Operator::LessThan,
         ">=" => Operator::GreaterThanOrEqual,
         "<=" => Operator::LessThanOrEqual,
+        "array-contains" => Operator::ArrayContains,
+        "in" => Operator::In,
+        "not-in" => Operator::NotIn,
+        "array-contains-any" => Operator::ArrayContainsAny,
         "!=" => {
             return Err(DB3Error::InvalidFilterOp(format!(
                 "OP {} un-support currently",
@@ -93,6 +244,25 @@ fn field_filter_from_json_value(
             return Err(DB3Error::InvalidFilterOp(format!("Invalid OP {}", op_str)));
         }
     };
+    let is_array_value = matches!(value.value_type, Some(ValueType::ArrayValue(_)));
+    match op {
+        Operator::In | Operator::NotIn | Operator::ArrayContainsAny => match &value.value_type {
+            Some(ValueType::ArrayValue(a)) if !a.values.is_empty() && a.values.len() <= 10 => {}
+            _ => {
+                return Err(DB3Error::InvalidFilterValue(format!(
+                    "OP {} requires a non-empty array with at most 10 values",
+                    op_str
+                )));
+            }
+        },
+        _ if is_array_value => {
+            return Err(DB3Error::InvalidFilterValue(format!(
+                "array value is not support for OP {}",
+                op_str
+            )));
+        }
+        _ => {}
+    }
 
     Ok(Some(Filter {
         filter_type: Some(FilterType::FieldFilter(FieldFilter {
@@ -117,8 +287,8 @@ fn composite_filter_from_json_value(
                 DB3Error::InvalidFilterJson("op is required in filter json".to_string())
             })?;
 
-            // only support == in composite filter
-            if op_str != "==" {
+            // only support == and in in composite filter
+            if op_str != "==" && op_str != "in" {
                 return Err(DB3Error::InvalidFilterJson(format!(
                     "{} is not support in composite filter",
                     op_str
@@ -148,8 +318,18 @@ pub fn filter_from_json_value(json_str: &str) -> std::result::Result<Option<Filt
     if json_str.is_empty() {
         Ok(None)
     } else {
-        let filter_doc = json_str_to_bson_document(json_str)
+        // parse the filter as the extended json to support the value like {"$date": ...}
+        let value: JsonValue = serde_json::from_str(json_str)
             .map_err(|e| DB3Error::InvalidFilterValue(format!("{:?}", e)))?;
+        let filter_doc = match Bson::try_from(value) {
+            Ok(Bson::Document(doc)) => doc,
+            Ok(_) => {
+                return Err(DB3Error::InvalidFilterJson(
+                    "filter json is invalid".to_string(),
+                ));
+            }
+            Err(e) => return Err(DB3Error::InvalidFilterValue(format!("{:?}", e))),
+        };
 
         if filter_doc.contains_key("field") {
             field_filter_from_json_value(&filter_doc)
@@ -169,6 +349,22 @@ pub fn filter_from_json_value(json_str: &str) -> std::result::Result<Option<Filt
                     "filter json is invalid".to_string(),
                 ))
             }
+        } else if filter_doc.contains_key("OR") {
+            if let Ok(filters) = filter_doc.get_array("OR") {
+                composite_filter_from_json_value(filters, CompositeOp::Or)
+            } else {
+                Err(DB3Error::InvalidFilterJson(
+                    "filter json is invalid".to_string(),
+                ))
+            }
+        } else if filter_doc.contains_key("or") {
+            if let Ok(filters) = filter_doc.get_array("or") {
+                composite_filter_from_json_value(filters, CompositeOp::Or)
+            } else {
+                Err(DB3Error::InvalidFilterJson(
+                    "filter json is invalid".to_string(),
+                ))
+            }
         } else {
             Err(DB3Error::InvalidFilterJson(
                 "filter json is invalid".to_string(),
@@ -191,6 +387,43 @@ pub fn filter_value_from_bson_value(value: &Bson) -> std::result::Result<Value,
         Bson::String(s) => Ok(Value {
             value_type: Some(ValueType::StringValue(s.to_string())),
         }),
+        Bson::Double(d) => Ok(Value {
+            value_type: Some(ValueType::DoubleValue(*d)),
+        }),
+        Bson::Null => Ok(Value {
+            value_type: Some(ValueType::NullValue(NullValue::NullValue.into())),
+        }),
+        Bson::DateTime(dt) => Ok(Value {
+            value_type: Some(ValueType::TimestampValue(timestamp_from_millis(
+                dt.timestamp_millis(),
+            ))),
+        }),
+        Bson::Binary(b) => Ok(Value {
+            value_type: Some(ValueType::BytesValue(b.bytes.to_vec())),
+        }),
+        Bson::Document(doc) => {
+            let mut fields = std::collections::HashMap::new();
+            for (k, v) in doc.iter() {
+                fields.insert(k.to_string(), filter_value_from_bson_value(v)?);
+            }
+            Ok(Value {
+                value_type: Some(ValueType::MapValue(MapValue { fields })),
+            })
+        }
+        Bson::Array(a) => {
+            let mut values = vec![];
+            for item in a {
+                if let Bson::Array(_) = item {
+                    return Err(DB3Error::InvalidFilterValue(
+                        "nested array un-support for filter value".to_string(),
+                    ));
+                }
+                values.push(filter_value_from_bson_value(item)?);
+            }
+            Ok(Value {
+                value_type: Some(ValueType::ArrayValue(ArrayValue { values })),
+            })
+        }
         _ => Err(DB3Error::InvalidFilterValue(format!(
             "type {:?} un-support for filter value",
             value.element_type()
@@ -205,6 +438,41 @@ mod tests {
         bson_document_into_bytes, bytes_to_bson_document, json_str_to_bson_document,
     };
     use bson::Bson;
+    #[test]
+    fn bson_document_with_mask_ut() {
+        let doc = json_str_to_bson_document(
+            r#"{"name": "a", "age": 1, "address": {"city": "b", "zip": "c"}}"#,
+        )
+        .unwrap();
+        let fields = vec![
+            "name".to_string(),
+            "score".to_string(),
+            "address.city".to_string(),
+            "address.street".to_string(),
+        ];
+        let patch = bson_document_with_mask(&doc, &fields).unwrap();
+        let expected: JsonValue = serde_json::from_str(
+            r#"{"name": "a", "score": null, "address": {"city": "b", "street": null}}"#,
+        )
+        .unwrap();
+        let patch_json: JsonValue =
+            serde_json::from_str(bson_document_into_json_str(&patch).as_str()).unwrap();
+        assert_eq!(expected, patch_json);
+        for fields in [
+            vec!["__name__".to_string()],
+            vec!["address..city".to_string()],
+            vec!["".to_string()],
+            vec!["address".to_string(), "address.city".to_string()],
+            vec!["name".to_string(), "name".to_string()],
+        ] {
+            assert!(matches!(
+                bson_document_with_mask(&doc, &fields),
+                Err(DB3Error::InvalidKeyPathError(_))
+            ));
+        }
+        assert!(check_field_path("_name_").is_ok());
+    }
+
     #[test]
     fn json_str_to_bson_document_ut() {
         let data = r#"
@@ -303,6 +571,50 @@ mod tests {
         assert!(filter_from_json_value(r#"{"field": "name"}"#).is_err());
     }
 
+    #[test]
+    fn array_filter_from_json_value_ut() {
+        let filter =
+            filter_from_json_value(r#"{"field": "name", "value": ["Bill", "Tom"], "op": "in"}"#)
+                .unwrap()
+                .unwrap();
+        assert_eq!(
+            r#"{"filter_type":{"FieldFilter":{"field":"name","op":8,"value":{"value_type":{"ArrayValue":{"values":[{"value_type":{"StringValue":"Bill"}},{"value_type":{"StringValue":"Tom"}}]}}}}}}"#,
+            serde_json::to_string(&filter).unwrap()
+        );
+        let filter = filter_from_json_value(r#"{"field": "age", "value": [1], "op": "not-in"}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            r#"{"filter_type":{"FieldFilter":{"field":"age","op":10,"value":{"value_type":{"ArrayValue":{"values":[{"value_type":{"IntegerValue":1}}]}}}}}}"#,
+            serde_json::to_string(&filter).unwrap()
+        );
+        let filter = filter_from_json_value(
+            r#"{"field": "tags", "value": ["a", "b"], "op": "array-contains-any"}"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            r#"{"filter_type":{"FieldFilter":{"field":"tags","op":9,"value":{"value_type":{"ArrayValue":{"values":[{"value_type":{"StringValue":"a"}},{"value_type":{"StringValue":"b"}}]}}}}}}"#,
+            serde_json::to_string(&filter).unwrap()
+        );
+        let filter =
+            filter_from_json_value(r#"{"field": "tags", "value": "a", "op": "array-contains"}"#)
+                .unwrap()
+                .unwrap();
+        assert_eq!(
+            r#"{"filter_type":{"FieldFilter":{"field":"tags","op":7,"value":{"value_type":{"StringValue":"a"}}}}}"#,
+            serde_json::to_string(&filter).unwrap()
+        );
+        assert!(filter_from_json_value(r#"{"field": "age", "value": [], "op": "in"}"#).is_err());
+        assert!(filter_from_json_value(r#"{"field": "age", "value": [[1]], "op": "in"}"#).is_err());
+        assert!(filter_from_json_value(r#"{"field": "age", "value": 1, "op": "in"}"#).is_err());
+        assert!(filter_from_json_value(r#"{"field": "age", "value": [1], "op": "=="}"#).is_err());
+        assert!(filter_from_json_value(
+            r#"{"field": "age", "value": [1,2,3,4,5,6,7,8,9,10,11], "op": "in"}"#
+        )
+        .is_err());
+    }
+
     #[test]
     fn composite_filter_from_json_value_ut() {
         let filter = filter_from_json_value(
@@ -326,15 +638,20 @@ mod tests {
         }"#
         )
         .is_err());
-        assert!(filter_from_json_value(
+        let filter = filter_from_json_value(
             r#"{
             "or": [
                 {"field": "name", "value": "Bill", "op": "=="},
-                {"field": "age", "value": 44, "op": "=="}
+                {"field": "age", "value": [44, 45], "op": "in"}
             ]
-        }"#
+        }"#,
         )
-        .is_err());
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            r#"{"filter_type":{"CompositeFilter":{"op":2,"filters":[{"filter_type":{"FieldFilter":{"field":"name","op":5,"value":{"value_type":{"StringValue":"Bill"}}}}},{"filter_type":{"FieldFilter":{"field":"age","op":8,"value":{"value_type":{"ArrayValue":{"values":[{"value_type":{"IntegerValue":44}},{"value_type":{"IntegerValue":45}}]}}}}}}]}}}"#,
+            serde_json::to_string(&filter).unwrap()
+        );
         assert!(filter_from_json_value(
             r#"{
             "and": [
@@ -404,5 +721,130 @@ mod tests {
             })
             .unwrap()
         );
+
+        assert_eq!(
+            (Bson::Array(vec![Bson::Int64(1), Bson::String("a".to_string())])),
+            bson_value_from_proto_value(&Value {
+                value_type: Some(ValueType::ArrayValue(ArrayValue {
+                    values: vec![
+                        Value {
+                            value_type: Some(ValueType::IntegerValue(1))
+                        },
+                        Value {
+                            value_type: Some(ValueType::StringValue("a".to_string()))
+                        },
+                    ]
+                }))
+            })
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn proto_value_round_trip_ut() {
+        let mut fields = std::collections::HashMap::new();
+        fields.insert(
+            "b".to_string(),
+            Value {
+                value_type: Some(ValueType::DoubleValue(1.5)),
+            },
+        );
+        fields.insert(
+            "a".to_string(),
+            Value {
+                value_type: Some(ValueType::NullValue(NullValue::NullValue.into())),
+            },
+        );
+        let values = vec![
+            ValueType::DoubleValue(-0.5),
+            ValueType::NullValue(NullValue::NullValue.into()),
+            ValueType::TimestampValue(Timestamp {
+                seconds: -2,
+                nanos: 999_000_000,
+            }),
+            ValueType::BytesValue(vec![0, 1, 255]),
+            ValueType::MapValue(MapValue { fields }),
+        ];
+        for value_type in values {
+            let value = Value {
+                value_type: Some(value_type),
+            };
+            let bson_value = bson_value_from_proto_value(&value).unwrap();
+            assert_eq!(value, filter_value_from_bson_value(&bson_value).unwrap());
+        }
+        // the timestamp is only precise to milliseconds
+        let bson_value = bson_value_from_proto_value(&Value {
+            value_type: Some(ValueType::TimestampValue(Timestamp {
+                seconds: 1,
+                nanos: 1_999_999,
+            })),
+        })
+        .unwrap();
+        assert_eq!(
+            Bson::DateTime(bson::DateTime::from_millis(1001)),
+            bson_value
+        );
+    }
+
+    #[test]
+    fn extended_filter_from_json_value_ut() {
+        let filter = filter_from_json_value(
+            r#"{"field": "ts", "value": {"$date": {"$numberLong": "1001"}}, "op": ">"}"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            r#"{"filter_type":{"FieldFilter":{"field":"ts","op":3,"value":{"value_type":{"TimestampValue":{"seconds":1,"nanos":1000000}}}}}}"#,
+            serde_json::to_string(&filter).unwrap()
+        );
+        let filter = filter_from_json_value(r#"{"field": "name", "value": null, "op": "=="}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            r#"{"filter_type":{"FieldFilter":{"field":"name","op":5,"value":{"value_type":{"NullValue":0}}}}}"#,
+            serde_json::to_string(&filter).unwrap()
+        );
+    }
+
+    #[test]
+    fn bson_document_into_json_str_ut() {
+        let mut doc = Document::new();
+        doc.insert("name", "a\"b");
+        doc.insert("ts", bson::DateTime::from_millis(1001));
+        doc.insert(
+            "data",
+            bson::Binary {
+                subtype: bson::spec::BinarySubtype::Generic,
+                bytes: vec![0, 255],
+            },
+        );
+        doc.insert("n", Bson::Null);
+        doc.insert("nested", bson::doc! {"count": 1_i32, "price": 1.5});
+        assert_eq!(
+            r#"{"name":"a\"b","ts":1001,"data":"00ff","n":null,"nested":{"count":1,"price":1.5}}"#,
+            bson_document_into_json_str(&doc)
+        );
+    }
+
+    #[test]
+    fn bson_document_into_json_str_legacy_ut() {
+        // the documents stored by the previous format are still read as the same json
+        let doc = bson::doc! {
+            "name": "a",
+            "count": 1_i32,
+            "total": 2_i64,
+            "price": 1.5,
+            "ok": true,
+            "n": Bson::Null,
+            "tags": ["x", "y"],
+            "nested": {"count": 1_i32},
+        };
+        let legacy: serde_json::Value = serde_json::from_str(doc.to_string().as_str()).unwrap();
+        let current: serde_json::Value =
+            serde_json::from_str(bson_document_into_json_str(&doc).as_str()).unwrap();
+        assert_eq!(legacy, current);
+        // the legacy datetime and binary were never valid json for the doc store
+        let doc = bson::doc! {"ts": bson::DateTime::from_millis(1001)};
+        assert!(serde_json::from_str::<serde_json::Value>(doc.to_string().as_str()).is_err());
     }
 }
@@ -23,3 +23,10 @@ pub fn get_current_time_in_secs() -> u64 {
         Err(_) => 0,
     }
 }
+
+pub fn get_current_time_in_millis() -> u64 {
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(n) => n.as_millis() as u64,
+        Err(_) => 0,
+    }
+}
@@ -19,6 +19,7 @@ use db3_error::DB3Error;
 use fastcrypto::encoding::{decode_bytes_hex, Encoding, Hex};
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
+use std::fmt;
 
 pub const DB3_ADDRESS_LENGTH: usize = 20;
 #[serde_as]
@@ -110,6 +111,13 @@ impl TryFrom<&str> for DB3Address {
     }
 }
 
+/// Debug DB3Address as the hex string
+impl fmt::Debug for DB3Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
 impl AsRef<[u8]> for DB3Address {
     fn as_ref(&self) -> &[u8] {
         &self.0[..]
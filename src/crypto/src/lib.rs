@@ -19,3 +19,4 @@
 pub mod db3_address;
 pub mod id;
 pub mod id_v2;
+pub mod merkle;
@@ -0,0 +1,163 @@
+//
+// merkle.rs
+// Copyright (C) 2023 db3.network Author imotai <codego.me@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use fastcrypto::hash::{HashFunction, Sha3_256};
+
+pub const HASH_LENGTH: usize = 32;
+pub type Hash = [u8; HASH_LENGTH];
+// the prefixes keep the leaf hashes and the node hashes apart
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// the hash of the data
+pub fn hash(data: &[u8]) -> Hash {
+    let mut hasher = Sha3_256::default();
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/// the hash of a leaf in the merkle tree
+pub fn hash_leaf(data: &[u8]) -> Hash {
+    let mut hasher = Sha3_256::default();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/// the hash of a node in the merkle tree over its children
+pub fn hash_node(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha3_256::default();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_node(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+///
+/// the merkle root over the leaf hashes. the last node of a level with odd nodes is
+/// moved to the next level as it is and the root of no leaf is zero
+///
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return [0; HASH_LENGTH];
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+/// a sibling on the path from a leaf to the merkle root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofNode {
+    pub hash: Hash,
+    // the sibling is the left child of its parent
+    pub is_left: bool,
+}
+
+///
+/// the path from the leaf at the index to the merkle root. the levels where the node
+/// has no sibling are skipped as the node is moved to the next level as it is
+///
+pub fn merkle_proof(leaves: &[Hash], index: usize) -> Option<Vec<ProofNode>> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut proof: Vec<ProofNode> = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut index = index;
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            proof.push(ProofNode {
+                hash: level[sibling],
+                is_left: sibling < index,
+            });
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+    Some(proof)
+}
+
+/// verify the leaf is included in the merkle tree of the root
+pub fn verify_proof(leaf: &Hash, proof: &[ProofNode], root: &Hash) -> bool {
+    let computed = proof.iter().fold(*leaf, |acc, node| {
+        if node.is_left {
+            hash_node(&node.hash, &acc)
+        } else {
+            hash_node(&acc, &node.hash)
+        }
+    });
+    &computed == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merkle_root() {
+        assert_eq!([0; HASH_LENGTH], merkle_root(&[]));
+        let leaves: Vec<Hash> = (0..3u8).map(|i| hash_leaf(&[i])).collect();
+        assert_eq!(leaves[0], merkle_root(&leaves[0..1]));
+        let root = merkle_root(&leaves);
+        let expected = hash_node(&hash_node(&leaves[0], &leaves[1]), &leaves[2]);
+        assert_eq!(expected, root);
+        // the order of the leaves matters
+        let reversed: Vec<Hash> = leaves.iter().rev().cloned().collect();
+        assert_ne!(root, merkle_root(&reversed));
+        assert_ne!(hash(&[0]), hash_leaf(&[0]));
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        assert_eq!(None, merkle_proof(&[], 0));
+        for count in 1..10u8 {
+            let leaves: Vec<Hash> = (0..count).map(|i| hash_leaf(&[i])).collect();
+            let root = merkle_root(&leaves);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = merkle_proof(&leaves, index).unwrap();
+                assert!(verify_proof(leaf, &proof, &root));
+                // the proof is bound to the leaf
+                assert!(!verify_proof(&hash_leaf(&[count]), &proof, &root));
+            }
+            assert_eq!(None, merkle_proof(&leaves, count as usize));
+        }
+        let leaves: Vec<Hash> = (0..5u8).map(|i| hash_leaf(&[i])).collect();
+        let root = merkle_root(&leaves);
+        // the last leaf is only paired at the top level
+        let proof = merkle_proof(&leaves, 4).unwrap();
+        assert_eq!(1, proof.len());
+        assert!(proof[0].is_left);
+        let mut proof = merkle_proof(&leaves, 1).unwrap();
+        proof[0].is_left = !proof[0].is_left;
+        assert!(!verify_proof(&leaves[1], &proof, &root));
+    }
+}
@@ -147,6 +147,22 @@ pub enum DB3Error {
     CollectionAlreadyExist(String, String),
     #[error("You have no permission to modify the collection")]
     CollectionPermissionDenied(),
+    #[error("the value of unique key {0} conflicts with document {1}")]
+    DuplicateUniqueKey(String, i64),
+    #[error("precondition failed for error {0}")]
+    PreconditionFailed(String),
+    #[error("the document key {0} already exists in collection {1}")]
+    DocumentKeyAlreadyExist(String, String),
+    #[error("the document does not match the schema for error {0}")]
+    DocumentSchemaMismatch(String),
+    #[error("You have no permission to read the collection {0}")]
+    ReadPermissionDenied(String),
+    #[error("invalid read auth for error {0}")]
+    InvalidReadAuth(String),
+    #[error("the collection {0} does not keep the history of documents")]
+    HistoryNotKept(String),
+    #[error("invalid block for error {0}")]
+    InvalidBlockError(String),
 }
 
 pub type Result<T> = std::result::Result<T, DB3Error>;
@@ -164,12 +164,16 @@ impl MetaStoreEventProcessor {
         network: u64,
         sender_addr: &DB3Address,
     ) -> Result<()> {
+        // the block is not produced until the mutation is added
+        let _guard = storage
+            .hold_block()
+            .map_err(|e| DB3Error::StoreEventError(format!("fail to hold the block for {e}")))?;
         let (_, block, order) = storage
             .generate_mutation_block_and_order(payload, signature)
             .map_err(|e| DB3Error::StoreEventError(format!("fail to generate tx for {e}")))?;
-        if let Ok(_) = db_store.apply_mutation(
+        if let Ok(items) = db_store.apply_mutation(
             action,
-            mutation,
+            mutation.clone(),
             sender_addr,
             network,
             nonce,
@@ -177,6 +181,7 @@ impl MetaStoreEventProcessor {
             order,
             &HashMap::new(),
         ) {
+            let db_addrs = DBStoreV2::get_db_addrs_of_mutation(&mutation, &items);
             match storage.add_mutation(
                 &payload,
                 signature,
@@ -187,6 +192,7 @@ impl MetaStoreEventProcessor {
                 order,
                 network,
                 action,
+                &db_addrs,
             ) {
                 Ok(_) => {
                     info!("mint event with from sender {} done", sender_addr.to_hex());
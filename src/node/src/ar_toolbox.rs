@@ -170,6 +170,7 @@ impl ArToolBox {
         current_block: u64,
         recordbatch: &RecordBatch,
         network_id: u64,
+        mutation_root: &str,
     ) -> Result<(String, u64, u64, u64)> {
         let tmp_dir = TempDir::new_in(&self.temp_data_path, "compression")
             .map_err(|e| DB3Error::RollupError(format!("{e}")))?;
@@ -188,6 +189,7 @@ impl ArToolBox {
                 current_block,
                 network_id,
                 filename.as_str(),
+                mutation_root,
             )
             .await?;
         Ok((id, reward, num_rows, size))
@@ -485,6 +487,7 @@ mod tests {
                 current_block,
                 &record_batch,
                 network_id,
+                "",
             )
             .await;
         assert_eq!(true, result.is_ok());
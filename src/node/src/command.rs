@@ -19,6 +19,7 @@ use crate::indexer_impl::IndexerNodeImpl;
 use crate::recover::{Recover, RecoverConfig, RecoverType};
 use crate::rollup_executor::RollupExecutorConfig;
 use crate::storage_node_light_impl::{StorageNodeV2Config, StorageNodeV2Impl};
+use crate::subscriber::{LagPolicy, SubscriberConfig};
 use crate::system_impl::SystemImpl;
 use clap::Parser;
 use db3_crypto::db3_address::DB3Address;
@@ -107,6 +108,27 @@ pub enum DB3Command {
         /// use the legacy transaction format
         #[clap(long, default_value = "false")]
         use_legacy_tx: bool,
+        /// enable the doc store to resolve the documents matched by the query mutations,
+        /// it should be enabled since the first block of the node. the query mutations
+        /// are rejected without it
+        #[clap(long, default_value = "false")]
+        enable_doc_store: bool,
+        /// The root path of the doc store
+        #[clap(long, default_value = "./rollup_doc_store")]
+        doc_store_root_path: String,
+        /// The interval in millis to delete the expired documents
+        #[clap(long, default_value = "60000")]
+        doc_expire_interval: u64,
+        /// The max count of the buffered events for every subscriber
+        #[clap(long, default_value = "1024")]
+        subscriber_buffer_size: usize,
+        /// The max count of the subscribers
+        #[clap(long, default_value = "1024")]
+        max_subscriber_count: usize,
+        /// What to do when the buffer of a subscriber is full, drop-oldest, disconnect or coalesce.
+        /// the block events are never dropped and the subscriber is disconnected instead
+        #[clap(long, default_value = "disconnect")]
+        subscriber_lag_policy: LagPolicy,
     },
 
     /// Start the data index node
@@ -274,6 +296,12 @@ impl DB3Command {
                 admin_addr,
                 doc_id_start,
                 use_legacy_tx,
+                enable_doc_store,
+                doc_store_root_path,
+                doc_expire_interval,
+                subscriber_buffer_size,
+                max_subscriber_count,
+                subscriber_lag_policy,
             } => {
                 let log_level = if verbose {
                     LevelFilter::DEBUG
@@ -295,6 +323,14 @@ impl DB3Command {
                     admin_addr.as_str(),
                     doc_id_start,
                     use_legacy_tx,
+                    enable_doc_store,
+                    doc_store_root_path.as_str(),
+                    doc_expire_interval,
+                    SubscriberConfig {
+                        buffer_size: subscriber_buffer_size,
+                        max_subscriber_count,
+                        lag_policy: subscriber_lag_policy,
+                    },
                 )
                 .await;
                 let running = Arc::new(AtomicBool::new(true));
@@ -573,6 +609,10 @@ impl DB3Command {
         admin_addr: &str,
         doc_start_id: i64,
         use_legacy_tx: bool,
+        enable_doc_store: bool,
+        doc_store_root_path: &str,
+        doc_expire_interval: u64,
+        subscriber_config: SubscriberConfig,
     ) {
         let listen_addr = format!("{bind_host}:{listening_port}");
         let rollup_config = RollupExecutorConfig {
@@ -618,10 +658,16 @@ impl DB3Command {
             doc_owner_store_cf_name: "doc_owner_store_cf".to_string(),
             db_owner_store_cf_name: "db_owner_cf".to_string(),
             scan_max_limit: 1000,
-            enable_doc_store: false,
-            doc_store_conf: DocStoreConfig::default(),
+            enable_doc_store,
+            doc_store_conf: DocStoreConfig {
+                db_root_path: doc_store_root_path.to_string(),
+                in_memory_db_handle_limit: 16,
+            },
             doc_start_id,
         };
+        if !enable_doc_store {
+            info!("the doc store is disabled and the mutations by query will be rejected");
+        }
         let (update_sender, update_receiver) = tokio::sync::mpsc::channel::<()>(8);
         let (sender, receiver) = tokio::sync::mpsc::channel::<(
             DB3Address,
@@ -634,6 +680,8 @@ impl DB3Command {
             rollup_config,
             db_store_config,
             block_interval,
+            doc_expire_interval,
+            subscriber_config,
         };
         let storage_node =
             StorageNodeV2Impl::new(config, system_store.clone(), state_store.clone(), sender)
@@ -16,6 +16,7 @@
 //
 
 use crate::mutation_utils::MutationUtil;
+use db3_base::times;
 use db3_crypto::db3_address::DB3Address;
 use db3_error::{DB3Error, Result};
 use db3_event::event_processor::EventProcessor;
@@ -24,12 +25,14 @@ use db3_proto::db3_indexer_proto::indexer_node_server::IndexerNode;
 use db3_proto::db3_indexer_proto::{
     ContractSyncStatus, GetCollectionOfDatabaseRequest, GetCollectionOfDatabaseResponse,
     GetContractSyncStatusRequest, GetContractSyncStatusResponse, GetDocRequest, GetDocResponse,
-    RunQueryRequest, RunQueryResponse,
+    ReadAuth, RunAggregationQueryRequest, RunAggregationQueryResponse, RunQueryRequest,
+    RunQueryResponse, RunStructuredQueryRequest,
 };
 use db3_proto::db3_mutation_v2_proto::MutationAction;
 use db3_proto::db3_storage_proto::block_response::MutationWrapper;
 use db3_proto::db3_storage_proto::event_message;
 use db3_proto::db3_storage_proto::EventMessage as EventMessageV2;
+use db3_proto::db3_storage_proto::ExtraItem;
 use db3_sdk::store_sdk_v2::StoreSDKV2;
 use db3_storage::db_store_v2::DBStoreV2;
 use db3_storage::system_store::{SystemRole, SystemStore};
@@ -41,6 +44,21 @@ use tokio::time::{sleep, Duration};
 use tonic::{Request, Response, Status};
 use tracing::{debug, info, warn};
 
+fn query_error_to_status(e: DB3Error) -> Status {
+    match e {
+        DB3Error::IndexNotFoundForFiledFilter(_)
+        | DB3Error::InvalidFilterValue(_)
+        | DB3Error::InvalidFilterOp(_)
+        | DB3Error::InvalidFilterType(_)
+        | DB3Error::InvalidKeyPathError(_)
+        | DB3Error::InvalidCollectionNameError(_) => Status::invalid_argument(format!("{e}")),
+        DB3Error::ReadPermissionDenied(_) => Status::permission_denied(format!("{e}")),
+        DB3Error::InvalidReadAuth(_) => Status::unauthenticated(format!("{e}")),
+        DB3Error::HistoryNotKept(_) => Status::failed_precondition(format!("{e}")),
+        _ => Status::internal(format!("{e}")),
+    }
+}
+
 #[derive(Clone)]
 pub struct IndexerNodeImpl {
     db_store: DBStoreV2,
@@ -57,6 +75,33 @@ impl IndexerNodeImpl {
         })
     }
 
+    ///
+    /// verify the optional read auth and check the reader has the permission to read
+    /// the collection
+    ///
+    fn check_read_permission(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        auth: Option<&ReadAuth>,
+    ) -> std::result::Result<(), Status> {
+        let reader = match auth {
+            Some(auth) => Some(
+                MutationUtil::verify_read_auth(
+                    auth.payload.as_ref(),
+                    auth.signature.as_str(),
+                    db_addr,
+                    times::get_current_time_in_secs(),
+                )
+                .map_err(query_error_to_status)?,
+            ),
+            None => None,
+        };
+        self.db_store
+            .check_read_permission(db_addr, col_name, reader.as_ref())
+            .map_err(query_error_to_status)
+    }
+
     pub async fn subscribe_update(&self, mut update_receiver: Receiver<()>) {
         let local_system_store = self.system_store.clone();
         tokio::spawn(async move {
@@ -75,6 +120,7 @@ impl IndexerNodeImpl {
             }
         });
     }
+
     pub async fn recover(&self, store_sdk: &StoreSDKV2) -> Result<()> {
         self.recover_state().await?;
         self.recover_from_fetched_blocks(store_sdk).await?;
@@ -134,6 +180,10 @@ impl IndexerNodeImpl {
                 info!("Stop fetching blocks, no more blocks to fetch");
                 break;
             }
+            // the mutations of the blocks which have been rolled up may be removed by gc
+            if let Err(e) = MutationUtil::verify_blocks(&response.headers, &mutations) {
+                warn!("fail to verify the fetched blocks for {e}");
+            }
             self.parse_and_apply_mutations(&mutations).await?;
             start_block += 100;
         }
@@ -142,12 +192,24 @@ impl IndexerNodeImpl {
     }
 
     /// start standalone indexer block syncer
-    /// 1. subscribe db3 event
+    /// 1. subscribe db3 event from the block of the last applied mutation
     /// 2. handle event to sync db3 node block
     pub async fn start(&self, store_sdk: StoreSDKV2) -> Result<()> {
         info!("start subscribe...");
         loop {
-            match store_sdk.subscribe_event_message().await {
+            // the block state is stored with every applied mutation, so the blocks
+            // produced during the reconnection are replayed by the storage node
+            let from_block = match self.db_store.recover_block_state() {
+                Ok(Some(block_state)) => block_state.block,
+                Ok(None) => 0,
+                Err(e) => {
+                    warn!("fail to recover the block state for {e} and retry in 5 seconds");
+                    sleep(Duration::from_millis(1000 * 5)).await;
+                    continue;
+                }
+            };
+            info!("subscribe block event from block {}", from_block);
+            match store_sdk.subscribe_event_message(Some(from_block)).await {
                 Ok(handle) => {
                     info!("listen and handle event message");
                     let mut stream = handle.into_inner();
@@ -188,6 +250,7 @@ impl IndexerNodeImpl {
                     .into_inner();
                 let mutations = response.mutations;
                 debug!("Block mutations size: {:?}", mutations.len());
+                MutationUtil::verify_blocks(&response.headers, &mutations)?;
                 self.parse_and_apply_mutations(&mutations).await?;
             }
             _ => {}
@@ -256,36 +319,77 @@ impl IndexerNodeImpl {
         Ok(())
     }
 
+    fn apply_mutation_wrapper(
+        &self,
+        mutation: &MutationWrapper,
+    ) -> Result<(MutationAction, Vec<ExtraItem>)> {
+        let body = mutation.body.as_ref().ok_or(DB3Error::WriteStoreError(
+            "invalid mutation body".to_string(),
+        ))?;
+        // validate the signature
+        let (dm, address, nonce) =
+            MutationUtil::unwrap_and_light_verify(&body.payload, body.signature.as_str())
+                .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        let action = MutationAction::from_i32(dm.action).ok_or(DB3Error::WriteStoreError(
+            "fail to convert action type".to_string(),
+        ))?;
+
+        let header = match &mutation.header {
+            Some(header) => Ok(header),
+            _ => Err(DB3Error::WriteStoreError(
+                "invalid mutation header".to_string(),
+            )),
+        }?;
+
+        let doc_ids_map = MutationUtil::convert_doc_ids_map_to_vec(&header.doc_ids_map)?;
+        let extra_items = self.db_store.apply_mutation(
+            action,
+            dm,
+            &address,
+            header.network,
+            nonce,
+            header.block_id,
+            header.order_id,
+            &doc_ids_map,
+        )?;
+        Ok((action, extra_items))
+    }
+
     async fn parse_and_apply_mutations(&self, mutations: &Vec<MutationWrapper>) -> Result<()> {
+        let last_applied = self
+            .db_store
+            .recover_block_state()?
+            .map(|block_state| (block_state.block, block_state.order));
         for mutation in mutations.iter() {
             let header = mutation.header.as_ref().unwrap();
-            let body = mutation.body.as_ref().unwrap();
-            // validate the signature
-            let (dm, address, nonce) =
-                MutationUtil::unwrap_and_light_verify(&body.payload, body.signature.as_str())
-                    .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
-            let action = MutationAction::from_i32(dm.action).ok_or(DB3Error::WriteStoreError(
-                "fail to convert action type".to_string(),
-            ))?;
-
-            let (block, order, doc_ids_map_str) = match &mutation.header {
-                Some(header) => Ok((header.block_id, header.order_id, &header.doc_ids_map)),
-                _ => Err(DB3Error::WriteStoreError(
-                    "invalid mutation header".to_string(),
-                )),
-            }?;
-
-            let doc_ids_map = MutationUtil::convert_doc_ids_map_to_vec(doc_ids_map_str)?;
-            let extra_items = self.db_store.apply_mutation(
-                action,
-                dm,
-                &address,
-                header.network,
-                nonce,
-                block,
-                order,
-                &doc_ids_map,
-            )?;
+            // the replayed block may contain the mutations applied before
+            if let Some(last_applied) = last_applied {
+                if (header.block_id, header.order_id) <= last_applied {
+                    debug!(
+                        "skip the applied mutation with block {} and order {}",
+                        header.block_id, header.order_id
+                    );
+                    continue;
+                }
+            }
+            let (action, extra_items) = match self.apply_mutation_wrapper(mutation) {
+                Ok(result) => result,
+                Err(e) if DBStoreV2::is_apply_mutation_error(&e) => {
+                    // the mutation fails on every node, so retrying it only blocks the
+                    // following ones. it's recorded and skipped
+                    warn!(
+                        "skip the mutation with block {} and order {} for error {e}",
+                        header.block_id, header.order_id
+                    );
+                    self.db_store.skip_mutation(
+                        header.block_id,
+                        header.order_id,
+                        format!("{e}").as_str(),
+                    )?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
             match action {
                 MutationAction::CreateEventDb => {
                     if extra_items.len() > 0 && extra_items[0].key.as_str() == "db_addr" {
@@ -376,10 +480,23 @@ impl IndexerNode for IndexerNodeImpl {
         let addr = DB3Address::from_hex(r.db_addr.as_str()).map_err(|e| {
             Status::invalid_argument(format!("fail to parse the db address for {e}"))
         })?;
-        let document = self
-            .db_store
-            .get_doc(&addr, r.col_name.as_str(), r.id)
-            .map_err(|e| Status::internal(format!("{e}")))?;
+        self.check_read_permission(&addr, r.col_name.as_str(), r.auth.as_ref())?;
+        let document = match (r.key.is_empty(), r.as_of_block) {
+            (true, 0) => self.db_store.get_doc(&addr, r.col_name.as_str(), r.id),
+            (false, 0) => self
+                .db_store
+                .get_doc_by_key(&addr, r.col_name.as_str(), r.key.as_str()),
+            (true, block) => self
+                .db_store
+                .get_doc_as_of(&addr, r.col_name.as_str(), r.id, block),
+            (false, block) => self.db_store.get_doc_by_key_as_of(
+                &addr,
+                r.col_name.as_str(),
+                r.key.as_str(),
+                block,
+            ),
+        }
+        .map_err(query_error_to_status)?;
         Ok(Response::new(GetDocResponse { document }))
     }
 
@@ -391,11 +508,15 @@ impl IndexerNode for IndexerNodeImpl {
         let addr = DB3Address::from_hex(r.db.as_str()).map_err(|e| {
             Status::invalid_argument(format!("fail to parse the db address for {e}"))
         })?;
+        self.check_read_permission(&addr, r.col_name.as_str(), r.auth.as_ref())?;
         if let Some(q) = &r.query {
-            let (documents, count) = self
-                .db_store
-                .query_docs(&addr, r.col_name.as_str(), q)
-                .map_err(|e| Status::internal(format!("{e}")))?;
+            let (documents, count) = if r.as_of_block > 0 {
+                self.db_store
+                    .query_docs_as_of(&addr, r.col_name.as_str(), q, r.as_of_block)
+            } else {
+                self.db_store.query_docs(&addr, r.col_name.as_str(), q)
+            }
+            .map_err(query_error_to_status)?;
             info!(
                 "query str {} from collection {} in db {} with result len {}, parameters len {}",
                 q.query_str,
@@ -404,7 +525,68 @@ impl IndexerNode for IndexerNodeImpl {
                 documents.len(),
                 q.parameters.len()
             );
-            Ok(Response::new(RunQueryResponse { documents, count }))
+            Ok(Response::new(RunQueryResponse {
+                documents,
+                count,
+                next_cursor: "".to_string(),
+            }))
+        } else {
+            Err(Status::invalid_argument("no query provided".to_string()))
+        }
+    }
+
+    async fn run_structured_query(
+        &self,
+        request: Request<RunStructuredQueryRequest>,
+    ) -> std::result::Result<Response<RunQueryResponse>, Status> {
+        let r = request.into_inner();
+        let addr = DB3Address::from_hex(r.db.as_str()).map_err(|e| {
+            Status::invalid_argument(format!("fail to parse the db address for {e}"))
+        })?;
+        if let Some(q) = &r.query {
+            self.check_read_permission(&addr, q.collection_name.as_str(), r.auth.as_ref())?;
+            let (documents, count, next_cursor) = self
+                .db_store
+                .query_docs_by_structured_query(&addr, q, r.cursor.as_str())
+                .map_err(query_error_to_status)?;
+            info!(
+                "structured query from collection {} in db {} with result len {}",
+                q.collection_name.as_str(),
+                r.db.as_str(),
+                documents.len()
+            );
+            Ok(Response::new(RunQueryResponse {
+                documents,
+                count,
+                next_cursor,
+            }))
+        } else {
+            Err(Status::invalid_argument("no query provided".to_string()))
+        }
+    }
+
+    async fn run_aggregation_query(
+        &self,
+        request: Request<RunAggregationQueryRequest>,
+    ) -> std::result::Result<Response<RunAggregationQueryResponse>, Status> {
+        let r = request.into_inner();
+        let addr = DB3Address::from_hex(r.db.as_str()).map_err(|e| {
+            Status::invalid_argument(format!("fail to parse the db address for {e}"))
+        })?;
+        if let Some(q) = &r.query {
+            self.check_read_permission(&addr, q.collection_name.as_str(), r.auth.as_ref())?;
+            let results = self
+                .db_store
+                .aggregate_docs(&addr, q, &r.aggregations, r.group_by.as_str())
+                .map_err(query_error_to_status)?;
+            info!(
+                "aggregation query from collection {} in db {} with group by {} and result len {}",
+                q.collection_name.as_str(),
+                r.db.as_str(),
+                r.group_by.as_str(),
+                results.len()
+            );
+            Ok(Response::new(RunAggregationQueryResponse { results }))
         } else {
             Err(Status::invalid_argument("no query provided".to_string()))
         }
@@ -23,5 +23,6 @@ mod node_test_base;
 pub mod recover;
 pub mod rollup_executor;
 pub mod storage_node_light_impl;
+pub mod subscriber;
 pub mod system_impl;
 pub mod version_util;
@@ -15,16 +15,37 @@ use std::collections::HashMap;
 // See the License for the specific language governing permissions and
 // limitations under the License.
 //
+use db3_base::bson_util::{
+    bson_document_into_json_str, bson_document_with_mask, bytes_to_bson_document,
+};
 use db3_crypto::db3_address::DB3Address;
+use db3_crypto::id::TxId;
 use db3_error::DB3Error;
-use db3_proto::db3_mutation_v2_proto::Mutation as MutationV2;
-use db3_proto::db3_storage_proto::ExtraItem;
+use db3_proto::db3_mutation_v2_proto::mutation::body_wrapper::Body;
+use db3_proto::db3_mutation_v2_proto::{
+    BlockHeader, DocumentMask, Mutation as MutationV2, MutationAction,
+};
+use db3_proto::db3_storage_proto::block_response::MutationWrapper;
+use db3_proto::db3_storage_proto::document_change::ChangeType;
+use db3_proto::db3_storage_proto::{DocumentChange, ExtraItem, MutationEvent};
+use db3_storage::db_store_v2::{
+    EXPIRED_DOCS_KEY_PREFIX, MATCHED_DOCS_KEY_PREFIX, NEW_DOCS_KEY_PREFIX, SERVER_TIME_KEY,
+};
+use db3_storage::mutation_store::MutationStore;
 use ethers::core::types::Bytes as EthersBytes;
-use ethers::types::{transaction::eip712::TypedData, Address, Signature};
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::{
+    transaction::eip712::{EIP712Domain, TypedData, Types},
+    Address, Signature,
+};
 use prost::Message;
 use serde_json::json;
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
+/// the read auth is valid within the window around the time it is signed
+pub const READ_AUTH_EXPIRY_SECS: u64 = 300;
+
 pub struct MutationUtil {}
 
 impl MutationUtil {
@@ -89,6 +110,87 @@ impl MutationUtil {
         }
     }
 
+    ///
+    /// verify the read auth signed by the reader and return the address of the reader.
+    /// the auth is bound to the database and expires after READ_AUTH_EXPIRY_SECS
+    ///
+    pub fn verify_read_auth(
+        payload: &[u8],
+        sig: &str,
+        db_addr: &DB3Address,
+        now_secs: u64,
+    ) -> Result<DB3Address, DB3Error> {
+        let data = serde_json::from_slice::<TypedData>(payload)
+            .map_err(|e| DB3Error::InvalidReadAuth(format!("bad typed data for err {e}")))?;
+        let db = Self::get_str_field(&data, "db", "");
+        let expected_db = db_addr.to_hex();
+        if db.to_lowercase() != expected_db {
+            return Err(DB3Error::InvalidReadAuth(format!(
+                "the auth is signed for db {db} but the db {expected_db} is read"
+            )));
+        }
+        let time = Self::get_u64_field(&data, "time", 0);
+        if time.abs_diff(now_secs) > READ_AUTH_EXPIRY_SECS {
+            return Err(DB3Error::InvalidReadAuth(format!(
+                "the auth signed at {time} has expired"
+            )));
+        }
+        let signature = Signature::from_str(sig)
+            .map_err(|e| DB3Error::InvalidReadAuth(format!("invalid signature for err {e}")))?;
+        let address = signature
+            .recover_typed_data(&data)
+            .map_err(|e| DB3Error::InvalidReadAuth(format!("invalid typed data for err {e}")))?;
+        Ok(DB3Address::from(address.as_fixed_bytes()))
+    }
+
+    ///
+    /// sign the mutation with the wallet of the node and return the payload and the
+    /// signature which are verified by unwrap_and_light_verify
+    ///
+    pub async fn sign_mutation(
+        wallet: &LocalWallet,
+        mutation: &MutationV2,
+        nonce: u64,
+    ) -> Result<(Vec<u8>, String), DB3Error> {
+        let mutation_type: Types = serde_json::from_value(json!({
+            "EIP712Domain": [],
+            "Message": [
+                {"name": "payload", "type": "bytes"},
+                {"name": "nonce", "type": "string"}
+            ]
+        }))
+        .map_err(|e| DB3Error::ApplyMutationError(format!("invalid mutation type for err {e}")))?;
+        let mbuf = EthersBytes::from(mutation.encode_to_vec());
+        let mut message: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        message.insert(
+            "payload".to_string(),
+            serde_json::Value::from(format!("{mbuf}")),
+        );
+        message.insert(
+            "nonce".to_string(),
+            serde_json::Value::from(nonce.to_string()),
+        );
+        let typed_data = TypedData {
+            domain: EIP712Domain {
+                name: None,
+                version: None,
+                chain_id: None,
+                verifying_contract: None,
+                salt: None,
+            },
+            types: mutation_type,
+            primary_type: "Message".to_string(),
+            message,
+        };
+        let signature = wallet.sign_typed_data(&typed_data).await.map_err(|e| {
+            DB3Error::ApplyMutationError(format!("fail to sign mutation for err {e}"))
+        })?;
+        let payload = serde_json::to_vec(&typed_data).map_err(|e| {
+            DB3Error::ApplyMutationError(format!("fail to convert typed data for err {e}"))
+        })?;
+        Ok((payload, format!("0x{signature}")))
+    }
+
     /// unwrap and verify write request
     pub fn unwrap_and_light_verify(
         payload: &[u8],
@@ -136,16 +238,35 @@ impl MutationUtil {
     }
 
     pub fn get_create_doc_ids_map(items: &Vec<ExtraItem>) -> String {
-        let doc_ids = items
+        // the created ids are replayed by the index of their body
+        let mut new_doc_ids: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for item in items.iter() {
+            if let Some(idx) = item.key.strip_prefix(NEW_DOCS_KEY_PREFIX) {
+                new_doc_ids
+                    .entry(idx)
+                    .or_default()
+                    .push(item.value.as_str());
+            }
+        }
+        let mut doc_ids_map = serde_json::Map::new();
+        for (idx, doc_ids) in new_doc_ids {
+            doc_ids_map.insert(idx.to_string(), json!(doc_ids.join(",")));
+        }
+        // the server time of the transform is replayed by the indexer and the recover
+        if let Some(item) = items.iter().find(|item| item.key == SERVER_TIME_KEY) {
+            doc_ids_map.insert(SERVER_TIME_KEY.to_string(), json!(item.value));
+        }
+        // the ids matched by the query bodies are recorded even if nothing is matched
+        for item in items
             .iter()
-            .filter(|item| item.key == "document")
-            .map(|item| item.value.clone())
-            .collect::<Vec<String>>()
-            .join(",");
-        if doc_ids.is_empty() {
-            return "".to_string();
+            .filter(|item| item.key.starts_with(MATCHED_DOCS_KEY_PREFIX))
+        {
+            doc_ids_map.insert(item.key.to_string(), json!(item.value));
+        }
+        if doc_ids_map.is_empty() {
+            "".to_string()
         } else {
-            json!({ "0": doc_ids }).to_string()
+            serde_json::Value::Object(doc_ids_map).to_string()
         }
     }
 
@@ -158,7 +279,7 @@ impl MutationUtil {
                 for (k, v) in map.iter() {
                     if let Some(v) = v.as_str() {
                         let mut doc_ids = vec![];
-                        let ids = v.split(",").collect::<Vec<&str>>();
+                        let ids = v.split(",").filter(|id| !id.is_empty());
                         for id in ids {
                             if let Ok(doc_id) = i64::from_str(id) {
                                 doc_ids.push(doc_id)
@@ -177,10 +298,331 @@ impl MutationUtil {
 
         Ok(res)
     }
+
+    fn parse_item_ids(items: &[ExtraItem], key: &str) -> Vec<i64> {
+        items
+            .iter()
+            .find(|item| item.key == key)
+            .map(|item| {
+                item.value
+                    .split(',')
+                    .filter_map(|id| i64::from_str(id).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// the json of the bson document with the mask, and it's empty if the document is invalid
+    fn document_to_json(buf: &[u8], mask: Option<&DocumentMask>) -> String {
+        let document = match bytes_to_bson_document(buf.to_vec()) {
+            Ok(document) => document,
+            Err(_) => return "".to_string(),
+        };
+        match mask {
+            Some(mask) if !mask.fields.is_empty() => {
+                bson_document_with_mask(&document, &mask.fields)
+                    .map(|document| bson_document_into_json_str(&document))
+                    .unwrap_or_default()
+            }
+            _ => bson_document_into_json_str(&document),
+        }
+    }
+
+    ///
+    /// decode the document changes of the applied mutation into one event for every body.
+    /// the ids of the added documents, the matched documents and the expired documents
+    /// are taken from the items of the mutation, and the ids of the upserted documents are
+    /// resolved by their keys with `get_doc_id`
+    ///
+    pub fn get_mutation_events<F>(
+        action: MutationAction,
+        dm: &MutationV2,
+        items: &[ExtraItem],
+        sender: &DB3Address,
+        block: u64,
+        order: u32,
+        get_doc_id: F,
+    ) -> Vec<MutationEvent>
+    where
+        F: Fn(&DB3Address, &str, &str) -> Option<i64>,
+    {
+        let new_ids: Vec<i64> = items
+            .iter()
+            .filter(|item| item.key.starts_with(NEW_DOCS_KEY_PREFIX))
+            .filter_map(|item| i64::from_str(item.value.as_str()).ok())
+            .collect();
+        let mut new_id_iter = new_ids.iter();
+        let change = |change_type: ChangeType, id: i64, doc: String| DocumentChange {
+            change_type: change_type.into(),
+            id,
+            doc,
+        };
+        let mut events = vec![];
+        for (i, body) in dm.bodies.iter().enumerate() {
+            let db_address_ref: &[u8] = body.db_address.as_ref();
+            let db_addr = match DB3Address::try_from(db_address_ref) {
+                Ok(db_addr) => db_addr,
+                Err(_) => continue,
+            };
+            let (collection_name, changes): (&str, Vec<DocumentChange>) = match (action, &body.body)
+            {
+                (MutationAction::AddDocument, Some(Body::DocumentMutation(m))) => (
+                    m.collection_name.as_str(),
+                    m.documents
+                        .iter()
+                        .zip(new_id_iter.by_ref())
+                        .map(|(doc, id)| {
+                            change(ChangeType::Insert, *id, Self::document_to_json(doc, None))
+                        })
+                        .collect(),
+                ),
+                (MutationAction::UpsertDocument, Some(Body::DocumentMutation(m))) => (
+                    m.collection_name.as_str(),
+                    m.documents
+                        .iter()
+                        .zip(m.keys.iter())
+                        .filter_map(|(doc, key)| {
+                            get_doc_id(&db_addr, m.collection_name.as_str(), key.as_str()).map(
+                                |id| {
+                                    let change_type = if new_ids.contains(&id) {
+                                        ChangeType::Insert
+                                    } else {
+                                        ChangeType::Update
+                                    };
+                                    change(change_type, id, Self::document_to_json(doc, None))
+                                },
+                            )
+                        })
+                        .collect(),
+                ),
+                (MutationAction::UpdateDocument, Some(Body::DocumentMutation(m))) => (
+                    m.collection_name.as_str(),
+                    m.ids
+                        .iter()
+                        .zip(m.documents.iter())
+                        .enumerate()
+                        .map(|(j, (id, doc))| {
+                            change(
+                                ChangeType::Update,
+                                *id,
+                                Self::document_to_json(doc, m.masks.get(j)),
+                            )
+                        })
+                        .collect(),
+                ),
+                (MutationAction::DeleteDocument, Some(Body::DocumentMutation(m))) => (
+                    m.collection_name.as_str(),
+                    m.ids
+                        .iter()
+                        .map(|id| change(ChangeType::Delete, *id, "".to_string()))
+                        .collect(),
+                ),
+                (MutationAction::TransformDocument, Some(Body::DocumentTransformMutation(m))) => (
+                    m.collection_name.as_str(),
+                    m.ids
+                        .iter()
+                        .map(|id| change(ChangeType::Update, *id, "".to_string()))
+                        .collect(),
+                ),
+                (
+                    MutationAction::DeleteDocumentByQuery | MutationAction::UpdateDocumentByQuery,
+                    Some(Body::DocumentQueryMutation(m)),
+                ) => {
+                    let collection_name = match &m.query {
+                        Some(query) => query.collection_name.as_str(),
+                        None => continue,
+                    };
+                    let ids = Self::parse_item_ids(
+                        items,
+                        format!("{MATCHED_DOCS_KEY_PREFIX}{i}").as_str(),
+                    );
+                    let (change_type, doc) = if action == MutationAction::DeleteDocumentByQuery {
+                        (ChangeType::Delete, "".to_string())
+                    } else {
+                        (
+                            ChangeType::Update,
+                            Self::document_to_json(&m.document, m.mask.as_ref()),
+                        )
+                    };
+                    (
+                        collection_name,
+                        ids.into_iter()
+                            .map(|id| change(change_type, id, doc.to_string()))
+                            .collect(),
+                    )
+                }
+                (MutationAction::ExpireDocument, Some(Body::ExpireDocumentMutation(m))) => (
+                    m.collection_name.as_str(),
+                    Self::parse_item_ids(items, format!("{EXPIRED_DOCS_KEY_PREFIX}{i}").as_str())
+                        .into_iter()
+                        .map(|id| change(ChangeType::Delete, id, "".to_string()))
+                        .collect(),
+                ),
+                _ => continue,
+            };
+            if changes.is_empty() {
+                continue;
+            }
+            events.push(MutationEvent {
+                block_id: block,
+                order_id: order,
+                sender: sender.as_ref().to_vec(),
+                db_addr: db_addr.as_ref().to_vec(),
+                collection_name: collection_name.to_string(),
+                changes,
+            });
+        }
+        events
+    }
+
+    ///
+    /// verify the hash of the block headers and the mutations of the blocks with the
+    /// mutation roots, the blocks without header are skipped
+    ///
+    pub fn verify_blocks(
+        headers: &[BlockHeader],
+        mutations: &[MutationWrapper],
+    ) -> Result<(), DB3Error> {
+        for header in headers {
+            if MutationStore::compute_block_hash(header).as_ref() != header.hash.as_slice() {
+                return Err(DB3Error::InvalidBlockError(format!(
+                    "the hash of block {} mismatches",
+                    header.block_id
+                )));
+            }
+            let ids = mutations
+                .iter()
+                .filter_map(|m| m.header.as_ref())
+                .filter(|h| h.block_id == header.block_id)
+                .map(|h| TxId::try_from_hex(h.id.as_str()))
+                .collect::<Result<Vec<TxId>, DB3Error>>()?;
+            if MutationStore::compute_mutation_root(&ids).as_ref()
+                != header.mutation_root.as_slice()
+            {
+                return Err(DB3Error::InvalidBlockError(format!(
+                    "the mutations of block {} mismatch the mutation root",
+                    header.block_id
+                )));
+            }
+        }
+        Ok(())
+    }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
+    use db3_proto::db3_mutation_v2_proto::mutation::BodyWrapper;
+    use db3_proto::db3_mutation_v2_proto::{
+        CollectionMutation, DocumentDatabaseMutation, DocumentMutation, ExpireDocumentMutation,
+        MutationHeader,
+    };
+    use db3_storage::db_store_v2::{DBStoreV2, DBStoreV2Config};
+    use db3_storage::doc_store::DocStoreConfig;
+    use tempdir::TempDir;
+
+    fn new_db_store(tmp_dir: &TempDir) -> DBStoreV2 {
+        let config = DBStoreV2Config {
+            db_path: tmp_dir.path().to_str().unwrap().to_string(),
+            db_store_cf_name: "db_store_cf".to_string(),
+            doc_store_cf_name: "doc_store_cf".to_string(),
+            collection_store_cf_name: "col_store_cf".to_string(),
+            index_store_cf_name: "idx_store_cf".to_string(),
+            doc_owner_store_cf_name: "doc_owner_store_cf".to_string(),
+            db_owner_store_cf_name: "db_owner_cf".to_string(),
+            scan_max_limit: 1000,
+            enable_doc_store: false,
+            doc_store_conf: DocStoreConfig::default(),
+            doc_start_id: 0,
+        };
+        DBStoreV2::new(config).unwrap()
+    }
+
+    #[test]
+    fn replay_multi_body_upsert_ut() {
+        let tmp_dir = TempDir::new("replay_upsert").unwrap();
+        let replica_tmp_dir = TempDir::new("replay_upsert_replica").unwrap();
+        let store = new_db_store(&tmp_dir);
+        let replica = new_db_store(&replica_tmp_dir);
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let collection = CollectionMutation {
+            index_fields: vec![],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let mut db_addr = DB3Address::ZERO;
+        for db_store in [&store, &replica] {
+            let db_id = db_store
+                .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+                .unwrap();
+            assert!(db_store
+                .create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1)
+                .is_ok());
+            db_addr = *db_id.address();
+        }
+        // the replica allocates other ids without the ids recorded in the mutation
+        let docs = vec![r#"{"name":"x"}"#.to_string()];
+        assert!(store
+            .add_docs(&db_addr, &DB3Address::ZERO, "col1", &docs, None)
+            .is_ok());
+        let doc = db3_base::bson_util::json_str_to_bson_bytes(r#"{"name":"a"}"#).unwrap();
+        let body = |key: &str| BodyWrapper {
+            body: Some(Body::DocumentMutation(DocumentMutation {
+                collection_name: "col1".to_string(),
+                documents: vec![doc.clone()],
+                ids: vec![],
+                masks: vec![],
+                preconditions: vec![],
+                keys: vec![key.to_string()],
+            })),
+            db_address: db_addr.as_ref().to_vec(),
+        };
+        let dm = MutationV2 {
+            action: MutationAction::UpsertDocument.into(),
+            bodies: vec![body("k1"), body("k2")],
+        };
+        let items = store
+            .apply_mutation(
+                MutationAction::UpsertDocument,
+                dm.clone(),
+                &DB3Address::ZERO,
+                1,
+                2,
+                2,
+                1,
+                &HashMap::new(),
+            )
+            .unwrap();
+        let doc_ids_map =
+            MutationUtil::convert_doc_ids_map_to_vec(&MutationUtil::get_create_doc_ids_map(&items))
+                .unwrap();
+        assert!(doc_ids_map.contains_key("0"));
+        assert!(doc_ids_map.contains_key("1"));
+        let replayed_items = replica
+            .apply_mutation(
+                MutationAction::UpsertDocument,
+                dm,
+                &DB3Address::ZERO,
+                1,
+                2,
+                2,
+                1,
+                &doc_ids_map,
+            )
+            .unwrap();
+        assert_eq!(items, replayed_items);
+        for key in ["k1", "k2"] {
+            let id = store.get_doc_id_by_key(&db_addr, "col1", key).unwrap();
+            assert!(id.is_some());
+            assert_eq!(
+                id,
+                replica.get_doc_id_by_key(&db_addr, "col1", key).unwrap()
+            );
+        }
+    }
 
     #[test]
     pub fn convert_doc_ids_map_to_vec_ut() {
@@ -198,15 +640,22 @@ mod tests {
     pub fn get_create_doc_ids_map_ut() {
         let mut items = Vec::new();
         items.push(ExtraItem {
-            key: "document".to_string(),
+            key: format!("{NEW_DOCS_KEY_PREFIX}0"),
             value: "1".to_string(),
         });
         items.push(ExtraItem {
-            key: "document".to_string(),
+            key: format!("{NEW_DOCS_KEY_PREFIX}0"),
             value: "2".to_string(),
         });
         let doc_ids = MutationUtil::get_create_doc_ids_map(&items);
         assert_eq!(doc_ids, json!({"0": "1,2"}).to_string());
+        // the ids of every body are kept apart
+        items.push(ExtraItem {
+            key: format!("{NEW_DOCS_KEY_PREFIX}1"),
+            value: "3".to_string(),
+        });
+        let doc_ids = MutationUtil::get_create_doc_ids_map(&items);
+        assert_eq!(doc_ids, json!({"0": "1,2", "1": "3"}).to_string());
 
         let mut items = Vec::new();
         items.push(ExtraItem {
@@ -219,6 +668,37 @@ mod tests {
         });
         let doc_ids = MutationUtil::get_create_doc_ids_map(&items);
         assert_eq!(doc_ids, "");
+
+        let items = vec![ExtraItem {
+            key: SERVER_TIME_KEY.to_string(),
+            value: "1000".to_string(),
+        }];
+        let doc_ids_map_str = MutationUtil::get_create_doc_ids_map(&items);
+        let doc_ids_map = MutationUtil::convert_doc_ids_map_to_vec(&doc_ids_map_str).unwrap();
+        assert_eq!(
+            doc_ids_map,
+            HashMap::from_iter(vec![(SERVER_TIME_KEY.to_string(), vec![1000])])
+        );
+
+        let items = vec![
+            ExtraItem {
+                key: format!("{MATCHED_DOCS_KEY_PREFIX}0"),
+                value: "3,4".to_string(),
+            },
+            ExtraItem {
+                key: format!("{MATCHED_DOCS_KEY_PREFIX}1"),
+                value: "".to_string(),
+            },
+        ];
+        let doc_ids_map_str = MutationUtil::get_create_doc_ids_map(&items);
+        let doc_ids_map = MutationUtil::convert_doc_ids_map_to_vec(&doc_ids_map_str).unwrap();
+        assert_eq!(
+            doc_ids_map,
+            HashMap::from_iter(vec![
+                (format!("{MATCHED_DOCS_KEY_PREFIX}0"), vec![3, 4]),
+                (format!("{MATCHED_DOCS_KEY_PREFIX}1"), vec![]),
+            ])
+        );
     }
     #[test]
     pub fn test_java_sdk_verfiy_ut() {
@@ -230,4 +710,195 @@ mod tests {
         let (addr, _typed_obj) = MutationUtil::verify_setup(typed_data, signature).unwrap();
         println!("{}", addr)
     }
+
+    #[tokio::test]
+    async fn verify_read_auth_ut() {
+        let wallet = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let db_addr = DB3Address::from(&[1u8; 20]);
+        let typed_data = |db: &str, time: u64| -> TypedData {
+            serde_json::from_value(json!({
+                "types": {
+                    "EIP712Domain": [],
+                    "Message": [
+                        {"name": "db", "type": "string"},
+                        {"name": "time", "type": "string"}
+                    ]
+                },
+                "domain": {},
+                "primaryType": "Message",
+                "message": {"db": db, "time": time.to_string()}
+            }))
+            .unwrap()
+        };
+        let data = typed_data(db_addr.to_hex().as_str(), 1000);
+        let signature = wallet.sign_typed_data(&data).await.unwrap();
+        let sig = format!("0x{}", signature);
+        let payload = serde_json::to_vec(&data).unwrap();
+        let reader =
+            MutationUtil::verify_read_auth(&payload, sig.as_str(), &db_addr, 1100).unwrap();
+        assert_eq!(DB3Address::from(wallet.address().as_fixed_bytes()), reader);
+        // the auth has expired
+        assert!(matches!(
+            MutationUtil::verify_read_auth(&payload, sig.as_str(), &db_addr, 2000),
+            Err(DB3Error::InvalidReadAuth(_))
+        ));
+        // the auth is signed for another db
+        let other_db = DB3Address::from(&[2u8; 20]);
+        assert!(matches!(
+            MutationUtil::verify_read_auth(&payload, sig.as_str(), &other_db, 1100),
+            Err(DB3Error::InvalidReadAuth(_))
+        ));
+        assert!(MutationUtil::verify_read_auth(b"{}", sig.as_str(), &db_addr, 1100).is_err());
+    }
+
+    #[tokio::test]
+    async fn sign_mutation_ut() {
+        let wallet = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let mutation = MutationV2 {
+            action: MutationAction::ExpireDocument.into(),
+            bodies: vec![BodyWrapper {
+                body: Some(Body::ExpireDocumentMutation(ExpireDocumentMutation {
+                    collection_name: "col1".to_string(),
+                    ids: vec![1, 2],
+                })),
+                db_address: vec![1u8; 20],
+            }],
+        };
+        let (payload, signature) = MutationUtil::sign_mutation(&wallet, &mutation, 3)
+            .await
+            .unwrap();
+        let (dm, address, nonce) =
+            MutationUtil::unwrap_and_light_verify(&payload, signature.as_str()).unwrap();
+        assert_eq!(mutation, dm);
+        assert_eq!(DB3Address::from(wallet.address().as_fixed_bytes()), address);
+        assert_eq!(3, nonce);
+    }
+
+    #[test]
+    fn get_mutation_events_ut() {
+        let doc = db3_base::bson_util::json_str_to_bson_bytes(r#"{"name":"a"}"#).unwrap();
+        let mutation = MutationV2 {
+            action: MutationAction::AddDocument.into(),
+            bodies: vec![BodyWrapper {
+                body: Some(Body::DocumentMutation(DocumentMutation {
+                    collection_name: "col1".to_string(),
+                    documents: vec![doc.clone(), doc],
+                    ids: vec![],
+                    masks: vec![],
+                    preconditions: vec![],
+                    keys: vec![],
+                })),
+                db_address: vec![1u8; 20],
+            }],
+        };
+        let items = vec![
+            ExtraItem {
+                key: format!("{NEW_DOCS_KEY_PREFIX}0"),
+                value: "1000".to_string(),
+            },
+            ExtraItem {
+                key: format!("{NEW_DOCS_KEY_PREFIX}0"),
+                value: "1001".to_string(),
+            },
+        ];
+        let events = MutationUtil::get_mutation_events(
+            MutationAction::AddDocument,
+            &mutation,
+            &items,
+            &DB3Address::ZERO,
+            2,
+            1,
+            |_, _, _| None,
+        );
+        assert_eq!(1, events.len());
+        assert_eq!("col1", events[0].collection_name.as_str());
+        assert_eq!(vec![1u8; 20], events[0].db_addr);
+        let ids: Vec<i64> = events[0].changes.iter().map(|c| c.id).collect();
+        assert_eq!(vec![1000, 1001], ids);
+        assert_eq!(ChangeType::Insert as i32, events[0].changes[0].change_type);
+        let value: serde_json::Value =
+            serde_json::from_str(events[0].changes[0].doc.as_str()).unwrap();
+        assert_eq!("a", value["name"]);
+
+        let mutation = MutationV2 {
+            action: MutationAction::ExpireDocument.into(),
+            bodies: vec![BodyWrapper {
+                body: Some(Body::ExpireDocumentMutation(ExpireDocumentMutation {
+                    collection_name: "col1".to_string(),
+                    ids: vec![1000, 1001],
+                })),
+                db_address: vec![1u8; 20],
+            }],
+        };
+        // only the expired documents in the items are deleted
+        let items = vec![ExtraItem {
+            key: format!("{EXPIRED_DOCS_KEY_PREFIX}0"),
+            value: "1001".to_string(),
+        }];
+        let events = MutationUtil::get_mutation_events(
+            MutationAction::ExpireDocument,
+            &mutation,
+            &items,
+            &DB3Address::ZERO,
+            3,
+            1,
+            |_, _, _| None,
+        );
+        assert_eq!(1, events[0].changes.len());
+        assert_eq!(1001, events[0].changes[0].id);
+        assert_eq!(ChangeType::Delete as i32, events[0].changes[0].change_type);
+        let items = vec![ExtraItem {
+            key: format!("{EXPIRED_DOCS_KEY_PREFIX}0"),
+            value: "".to_string(),
+        }];
+        assert!(MutationUtil::get_mutation_events(
+            MutationAction::ExpireDocument,
+            &mutation,
+            &items,
+            &DB3Address::ZERO,
+            3,
+            1,
+            |_, _, _| None,
+        )
+        .is_empty());
+    }
+    #[test]
+    fn verify_blocks_ut() {
+        let ids: Vec<TxId> = (0..2u8).map(|i| TxId::from(&[i][..])).collect();
+        let mutations: Vec<MutationWrapper> = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| MutationWrapper {
+                header: Some(MutationHeader {
+                    block_id: 1,
+                    order_id: i as u32 + 1,
+                    id: id.to_hex(),
+                    ..Default::default()
+                }),
+                body: None,
+            })
+            .collect();
+        let mut header = BlockHeader {
+            block_id: 1,
+            parent_hash: vec![],
+            mutation_root: MutationStore::compute_mutation_root(&ids).to_vec(),
+            state_root: vec![],
+            time: 1,
+            mutation_count: 2,
+            hash: vec![],
+        };
+        header.hash = MutationStore::compute_block_hash(&header).to_vec();
+        assert!(MutationUtil::verify_blocks(&[header.clone()], &mutations).is_ok());
+        // a mutation of the block is missing
+        assert!(MutationUtil::verify_blocks(&[header.clone()], &mutations[0..1]).is_err());
+        let mut tampered = header.clone();
+        tampered.time = 2;
+        assert!(MutationUtil::verify_blocks(&[tampered], &mutations).is_err());
+        // the block without header is skipped
+        assert!(MutationUtil::verify_blocks(&[], &mutations).is_ok());
+    }
 }
@@ -164,6 +164,7 @@ pub mod tests {
                     order,
                     1,
                     MutationAction::CreateDocumentDb,
+                    &[],
                 );
                 assert_eq!(true, result.is_ok());
             }
@@ -18,6 +18,7 @@
 use crate::ar_toolbox::ArToolBox;
 use arc_swap::ArcSwapOption;
 use db3_base::times;
+use db3_crypto::id::TxId;
 use db3_error::{DB3Error, Result};
 use db3_proto::db3_rollup_proto::{GcRecord, RollupRecord};
 use db3_storage::ar_fs::{ArFileSystem, ArFileSystemConfig};
@@ -250,6 +251,7 @@ impl RollupExecutor {
             cost: 0,
             evm_tx: "".to_string(),
             evm_cost: 0,
+            mutation_root: vec![],
         }
     }
 
@@ -309,6 +311,11 @@ impl RollupExecutor {
                 self.pending_mutations.store(0, Ordering::Relaxed);
             }
 
+            let mutation_ids = mutations
+                .iter()
+                .map(|(header, _)| TxId::try_from_hex(header.id.as_str()))
+                .collect::<std::result::Result<Vec<TxId>, DB3Error>>()?;
+            let mutation_root = MutationStore::compute_mutation_root(&mutation_ids);
             let (id, reward, num_rows, size) = ar_toolbox
                 .compress_and_upload_record_batch(
                     tx,
@@ -316,11 +323,12 @@ impl RollupExecutor {
                     current_block,
                     &recordbatch,
                     network_id,
+                    format!("0x{}", hex::encode(mutation_root)).as_str(),
                 )
                 .await?;
 
             let (evm_cost, tx_hash) = meta_store
-                .update_rollup_step(id.as_str(), network_id)
+                .update_rollup_step(id.as_str(), &mutation_root, network_id)
                 .await?;
 
             let tx_str = format!("0x{}", hex::encode(tx_hash.as_bytes()));
@@ -343,10 +351,11 @@ impl RollupExecutor {
                 start_block: last_end_block,
                 evm_tx: tx_str,
                 evm_cost: evm_cost.as_u64(),
+                mutation_root: mutation_root.to_vec(),
             };
 
             self.storage
-                .add_rollup_record(&record)
+                .add_rollup_record(&record, &mutation_ids)
                 .map_err(|e| DB3Error::RollupError(format!("{e}")))?;
             self.gc_mutation()?;
         } else {
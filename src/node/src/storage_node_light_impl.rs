@@ -17,36 +17,44 @@
 
 use crate::mutation_utils::MutationUtil;
 use crate::rollup_executor::{RollupExecutor, RollupExecutorConfig};
-use db3_base::strings;
+use crate::subscriber::{SubscriberConfig, SubscriberManager, SubscriberMetrics};
+use db3_base::{strings, times};
 use db3_crypto::db3_address::DB3Address;
 use db3_crypto::id::TxId;
 use db3_error::{DB3Error, Result};
 use db3_event::meta_store_event_processor::MetaStoreEventProcessor;
-use db3_proto::db3_mutation_v2_proto::{MutationAction, MutationRollupStatus};
+use db3_proto::db3_mutation_v2_proto::mutation::body_wrapper::Body;
+use db3_proto::db3_mutation_v2_proto::mutation::BodyWrapper;
+use db3_proto::db3_mutation_v2_proto::{
+    ExpireDocumentMutation, Mutation, MutationAction, MutationRollupStatus,
+};
 use db3_proto::db3_storage_proto::block_response;
 use db3_proto::db3_storage_proto::event_message::Event as EventV2;
+use db3_proto::db3_storage_proto::ExtraItem;
 use db3_proto::db3_storage_proto::{
     storage_node_server::StorageNode, BlockRequest, BlockResponse, GetCollectionOfDatabaseRequest,
     GetCollectionOfDatabaseResponse, GetDatabaseOfOwnerRequest, GetDatabaseOfOwnerResponse,
     GetDatabaseRequest, GetDatabaseResponse, GetMutationBodyRequest, GetMutationBodyResponse,
-    GetMutationHeaderRequest, GetMutationHeaderResponse, GetMutationStateRequest,
-    GetMutationStateResponse, GetNonceRequest, GetNonceResponse, MutationStateView,
-    ScanGcRecordRequest, ScanGcRecordResponse, ScanMutationHeaderRequest,
-    ScanMutationHeaderResponse, ScanRollupRecordRequest, ScanRollupRecordResponse,
-    SendMutationRequest, SendMutationResponse, SubscribeRequest,
+    GetMutationHeaderRequest, GetMutationHeaderResponse, GetMutationProofRequest,
+    GetMutationProofResponse, GetMutationStateRequest, GetMutationStateResponse, GetNonceRequest,
+    GetNonceResponse, MutationStateView, ScanGcRecordRequest, ScanGcRecordResponse,
+    ScanMutationHeaderByFilterRequest, ScanMutationHeaderByFilterResponse,
+    ScanMutationHeaderRequest, ScanMutationHeaderResponse, ScanRollupRecordRequest,
+    ScanRollupRecordResponse, SendMutationRequest, SendMutationResponse, SubscribeRequest,
 };
 use db3_proto::db3_storage_proto::{
     BlockEvent as BlockEventV2, EventMessage as EventMessageV2, EventType as EventTypeV2,
-    Subscription as SubscriptionV2,
+    MutationEvent as MutationEventV2, Subscription as SubscriptionV2,
 };
 use db3_storage::db_store_v2::{DBStoreV2, DBStoreV2Config};
-use db3_storage::mutation_store::{MutationStore, MutationStoreConfig};
+use db3_storage::mutation_store::{MutationFilter, MutationStore, MutationStoreConfig};
 use db3_storage::state_store::StateStore;
 use db3_storage::system_store::{SystemRole, SystemStore};
 use ethers::core::types::Bytes as EthersBytes;
+use ethers::signers::Signer;
 use ethers::types::U256;
 use prost::Message;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::HashMap;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
@@ -59,12 +67,20 @@ use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 use tracing::{debug, info, warn};
 
+// the max count of the documents deleted by one expire document mutation
+const EXPIRE_DOCS_BATCH_SIZE: usize = 1000;
+// the interval in millis to send the buffered events to the subscribers
+const SUBSCRIBER_FLUSH_INTERVAL: u64 = 100;
+
 #[derive(Clone)]
 pub struct StorageNodeV2Config {
     pub store_config: MutationStoreConfig,
     pub rollup_config: RollupExecutorConfig,
     pub db_store_config: DBStoreV2Config,
     pub block_interval: u64,
+    // the interval in millis to delete the expired documents
+    pub doc_expire_interval: u64,
+    pub subscriber_config: SubscriberConfig,
 }
 
 pub struct StorageNodeV2Impl {
@@ -78,12 +94,16 @@ pub struct StorageNodeV2Impl {
         SubscriptionV2,
         Sender<std::result::Result<EventMessageV2, Status>>,
     )>,
-    broadcast_sender: BroadcastSender<EventMessageV2>,
+    // the block events are sent by another channel, so they are not dropped for
+    // the lag of the mutation events
+    block_event_sender: BroadcastSender<EventMessageV2>,
+    mutation_event_sender: BroadcastSender<EventMessageV2>,
     rollup_executor: Arc<RollupExecutor>,
     rollup_interval: Arc<AtomicU64>,
     network_id: Arc<AtomicU64>,
     system_store: Arc<SystemStore>,
     event_processor: Arc<MetaStoreEventProcessor>,
+    subscriber_metrics: Arc<SubscriberMetrics>,
 }
 
 impl StorageNodeV2Impl {
@@ -102,7 +122,8 @@ impl StorageNodeV2Impl {
         let storage = MutationStore::new(config.store_config.clone())?;
         storage.recover()?;
         let db_store = DBStoreV2::new(config.db_store_config.clone())?;
-        let (broadcast_sender, _) = broadcast::channel(1024);
+        let (block_event_sender, _) = broadcast::channel(1024);
+        let (mutation_event_sender, _) = broadcast::channel(1024);
         let event_processor = Arc::new(MetaStoreEventProcessor::new(
             state_store.clone(),
             db_store.clone(),
@@ -137,12 +158,14 @@ impl StorageNodeV2Impl {
                 running: Arc::new(AtomicBool::new(true)),
                 db_store,
                 sender,
-                broadcast_sender,
+                block_event_sender,
+                mutation_event_sender,
                 rollup_executor,
                 rollup_interval: Arc::new(AtomicU64::new(rollup_interval)),
                 network_id,
                 system_store,
                 event_processor,
+                subscriber_metrics: Arc::new(SubscriberMetrics::default()),
             })
         } else {
             info!("please setup the node first");
@@ -162,12 +185,14 @@ impl StorageNodeV2Impl {
                 running: Arc::new(AtomicBool::new(true)),
                 db_store,
                 sender,
-                broadcast_sender,
+                block_event_sender,
+                mutation_event_sender,
                 rollup_executor,
                 rollup_interval: Arc::new(AtomicU64::new(1000 * 10 * 60)),
                 network_id,
                 system_store,
                 event_processor,
+                subscriber_metrics: Arc::new(SubscriberMetrics::default()),
             })
         }
     }
@@ -181,6 +206,330 @@ impl StorageNodeV2Impl {
         self.start_to_produce_block().await;
         self.start_to_rollup().await;
         self.start_flush_state().await;
+        self.start_to_expire_docs().await;
+    }
+
+    ///
+    /// broadcast the document changes of the applied mutation to the subscribers
+    ///
+    fn broadcast_mutation_events(
+        event_sender: &BroadcastSender<EventMessageV2>,
+        db_store: &DBStoreV2,
+        action: MutationAction,
+        dm: &Mutation,
+        items: &[ExtraItem],
+        sender: &DB3Address,
+        block: u64,
+        order: u32,
+    ) {
+        let events = MutationUtil::get_mutation_events(
+            action,
+            dm,
+            items,
+            sender,
+            block,
+            order,
+            |db_addr, col_name, key| {
+                db_store
+                    .get_doc_id_by_key(db_addr, col_name, key)
+                    .ok()
+                    .flatten()
+            },
+        );
+        for e in events {
+            let msg = EventMessageV2 {
+                r#type: EventTypeV2::Mutation as i32,
+                event: Some(EventV2::MutationEvent(e)),
+            };
+            if let Err(e) = event_sender.send(msg) {
+                debug!("no subscriber for the mutation event {:?}", e);
+            }
+        }
+    }
+
+    ///
+    /// the subscriber receives the mutation event if it sends the mutation or it can
+    /// read the collection of the event with the acl of the collection or the database
+    ///
+    fn can_read_mutation_event(
+        db_store: &DBStoreV2,
+        reader: &DB3Address,
+        event: &MutationEventV2,
+    ) -> bool {
+        if event.sender.as_slice() == reader.as_ref() {
+            return true;
+        }
+        match DB3Address::try_from(event.db_addr.as_slice()) {
+            Ok(db_addr) => db_store
+                .check_read_permission(&db_addr, event.collection_name.as_str(), Some(reader))
+                .is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    ///
+    /// get the block events of the blocks with mutation in the range
+    ///
+    fn get_block_events(
+        storage: &MutationStore,
+        block_start: u64,
+        block_end: u64,
+    ) -> Result<Vec<EventMessageV2>> {
+        if block_start >= block_end {
+            return Ok(vec![]);
+        }
+        let blocks = storage.get_range_block_mutation_count(block_start, block_end)?;
+        Ok(blocks
+            .into_iter()
+            .map(|(block_id, mutation_count)| EventMessageV2 {
+                r#type: EventTypeV2::Block as i32,
+                event: Some(EventV2::BlockEvent(BlockEventV2 {
+                    block_id,
+                    mutation_count,
+                })),
+            })
+            .collect())
+    }
+
+    ///
+    /// the block events before the gc watermark can not be replayed because their
+    /// mutations have been removed
+    ///
+    fn check_replay_block(storage: &MutationStore, from_block: u64) -> Result<()> {
+        let watermark = storage.get_gc_watermark()?;
+        if from_block < watermark {
+            return Err(DB3Error::ReadStoreError(format!(
+                "the blocks before {watermark} have been removed by the gc and can not be replayed from block {from_block}"
+            )));
+        }
+        Ok(())
+    }
+
+    ///
+    /// replay the block events from the block to the subscriber until it catches up
+    /// with the current block and return the block to receive the live events from
+    ///
+    async fn replay_block_events(
+        storage: &MutationStore,
+        from_block: u64,
+        sender: &Sender<std::result::Result<EventMessageV2, Status>>,
+    ) -> Result<u64> {
+        let mut next_block = from_block;
+        loop {
+            // the gc may run during the replay
+            Self::check_replay_block(storage, next_block)?;
+            let current_block = storage.get_current_block()?;
+            if next_block >= current_block {
+                return Ok(next_block);
+            }
+            for e in Self::get_block_events(storage, next_block, current_block)? {
+                sender.send(Ok(e)).await.map_err(|e| {
+                    DB3Error::WriteStoreError(format!("fail to replay the block event {e}"))
+                })?;
+            }
+            next_block = current_block;
+        }
+    }
+
+    ///
+    /// get the blocks produced between the replay and the registration of the
+    /// subscriber, then the subscriber only receives the live block events after them
+    ///
+    fn catch_up_block_events(
+        storage: &MutationStore,
+        sub: &mut SubscriptionV2,
+    ) -> Result<Vec<EventMessageV2>> {
+        Self::check_replay_block(storage, sub.from_block)?;
+        let current_block = storage.get_current_block()?;
+        let events = Self::get_block_events(storage, sub.from_block, current_block)?;
+        sub.from_block = sub.from_block.max(current_block);
+        Ok(events)
+    }
+
+    ///
+    /// apply the mutation and add it to the block which is held until the mutation is
+    /// added. the nonce is committed after the mutation is applied, so the nonce is not
+    /// used by the mutation failed to apply
+    ///
+    fn apply_and_add_mutation<F>(
+        storage: &MutationStore,
+        db_store: &DBStoreV2,
+        event_sender: &BroadcastSender<EventMessageV2>,
+        payload: &[u8],
+        signature: &str,
+        dm: &Mutation,
+        address: &DB3Address,
+        nonce: u64,
+        network: u64,
+        commit_nonce: F,
+    ) -> Result<(String, u64, u32, Vec<ExtraItem>)>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let action = MutationAction::from_i32(dm.action).ok_or(DB3Error::ApplyMutationError(
+            "bad mutation action".to_string(),
+        ))?;
+        // the block is not produced until the mutation is added
+        let _guard = storage.hold_block()?;
+        let (id, block, order) = storage.generate_mutation_block_and_order(payload, signature)?;
+        let items = db_store.apply_mutation(
+            action,
+            dm.clone(),
+            address,
+            network,
+            nonce,
+            block,
+            order,
+            &HashMap::new(),
+        )?;
+        commit_nonce()?;
+        let doc_ids_map = MutationUtil::get_create_doc_ids_map(&items);
+        let db_addrs = DBStoreV2::get_db_addrs_of_mutation(dm, &items);
+        storage.add_mutation(
+            payload,
+            signature,
+            doc_ids_map.as_str(),
+            address,
+            nonce,
+            block,
+            order,
+            network,
+            action,
+            &db_addrs,
+        )?;
+        Self::broadcast_mutation_events(
+            event_sender,
+            db_store,
+            action,
+            dm,
+            &items,
+            address,
+            block,
+            order,
+        );
+        Ok((id, block, order, items))
+    }
+
+    ///
+    /// delete the expired documents with the expire document mutation signed by the node,
+    /// so the indexer and the recover delete the same documents by replaying the mutation.
+    /// the events are added by the indexer, so their collections are expired by the
+    /// empty ids. the node does not know when the events expire, so an event collection
+    /// is expired at most once in its ttl with the next expiry time in the map
+    ///
+    async fn expire_docs(
+        storage: &MutationStore,
+        db_store: &DBStoreV2,
+        state_store: &StateStore,
+        system_store: &SystemStore,
+        network: u64,
+        event_sender: &BroadcastSender<EventMessageV2>,
+        next_event_expiry: &mut HashMap<(DB3Address, String), u64>,
+    ) -> Result<usize> {
+        let now = times::get_current_time_in_millis();
+        let expired_docs = db_store.get_expired_docs(now, EXPIRE_DOCS_BATCH_SIZE)?;
+        let event_collections: Vec<(DB3Address, String, u64)> = db_store
+            .get_expiring_event_collections()?
+            .into_iter()
+            .filter(|(db_addr, col_name, _)| {
+                next_event_expiry
+                    .get(&(*db_addr, col_name.to_string()))
+                    .map_or(true, |next| *next <= now)
+            })
+            .collect();
+        if expired_docs.is_empty() && event_collections.is_empty() {
+            return Ok(0);
+        }
+        let chain_id = system_store
+            .get_config(&SystemRole::DataRollupNode)?
+            .map(|c| c.chain_id)
+            .ok_or(DB3Error::ApplyMutationError(
+                "the system has not been setup".to_string(),
+            ))?;
+        let wallet = system_store.get_evm_wallet(chain_id)?;
+        let address = DB3Address::from(wallet.address().as_fixed_bytes());
+        let count = expired_docs.iter().map(|(_, _, ids)| ids.len()).sum();
+        let dm = Mutation {
+            action: MutationAction::ExpireDocument.into(),
+            bodies: expired_docs
+                .into_iter()
+                .chain(
+                    event_collections
+                        .iter()
+                        .map(|(db_addr, collection_name, _)| {
+                            (*db_addr, collection_name.to_string(), vec![])
+                        }),
+                )
+                .map(|(db_addr, collection_name, ids)| BodyWrapper {
+                    body: Some(Body::ExpireDocumentMutation(ExpireDocumentMutation {
+                        collection_name,
+                        ids,
+                    })),
+                    db_address: db_addr.as_ref().to_vec(),
+                })
+                .collect(),
+        };
+        let nonce = state_store.get_nonce(&address)? + 1;
+        let (payload, signature) = MutationUtil::sign_mutation(&wallet, &dm, nonce).await?;
+        Self::apply_and_add_mutation(
+            storage,
+            db_store,
+            event_sender,
+            &payload,
+            signature.as_str(),
+            &dm,
+            &address,
+            nonce,
+            network,
+            || state_store.incr_nonce(&address, nonce).map(|_| ()),
+        )?;
+        for (db_addr, col_name, ttl) in event_collections {
+            next_event_expiry.insert((db_addr, col_name), now + ttl * 1000);
+        }
+        Ok(count)
+    }
+
+    async fn start_to_expire_docs(&self) {
+        let local_running = self.running.clone();
+        let local_storage = self.storage.clone();
+        let local_db_store = self.db_store.clone();
+        let local_state_store = self.state_store.clone();
+        let local_system_store = self.system_store.clone();
+        let local_network_id = self.network_id.clone();
+        let local_expire_interval = self.config.doc_expire_interval;
+        let local_event_sender = self.mutation_event_sender.clone();
+        task::spawn(async move {
+            info!("start the document expiry thread");
+            let mut next_event_expiry: HashMap<(DB3Address, String), u64> = HashMap::new();
+            while local_running.load(Ordering::Relaxed) {
+                sleep(TokioDuration::from_millis(local_expire_interval)).await;
+                let network = local_network_id.load(Ordering::Relaxed);
+                if network == 0 {
+                    // the node has not been setup
+                    continue;
+                }
+                match Self::expire_docs(
+                    &local_storage,
+                    &local_db_store,
+                    &local_state_store,
+                    &local_system_store,
+                    network,
+                    &local_event_sender,
+                    &mut next_event_expiry,
+                )
+                .await
+                {
+                    Ok(count) if count > 0 => {
+                        info!("expire {} documents done", count);
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("fail to expire documents for error {e}");
+                    }
+                }
+            }
+            info!("exit the document expiry thread");
+        });
     }
 
     async fn start_flush_state(&self) {
@@ -206,8 +555,9 @@ impl StorageNodeV2Impl {
     async fn start_to_produce_block(&self) {
         let local_running = self.running.clone();
         let local_storage = self.storage.clone();
+        let local_db_store = self.db_store.clone();
         let local_block_interval = self.config.block_interval;
-        let local_event_sender = self.broadcast_sender.clone();
+        let local_event_sender = self.block_event_sender.clone();
         task::spawn(async move {
             info!("start the block producer thread");
             while local_running.load(Ordering::Relaxed) {
@@ -216,8 +566,15 @@ impl StorageNodeV2Impl {
                     "produce block {}",
                     local_storage.get_current_block().unwrap_or(0)
                 );
-                match local_storage.increase_block_return_last_state() {
-                    Ok((block_id, mutation_count)) => {
+                match local_storage.produce_block(|| local_db_store.get_state_root()) {
+                    Ok(header) => {
+                        debug!(
+                            "the block {} is produced with hash 0x{}",
+                            header.block_id,
+                            hex::encode(&header.hash)
+                        );
+                        let block_id = header.block_id;
+                        let mutation_count = header.mutation_count;
                         // sender block event
                         let e = BlockEventV2 {
                             block_id,
@@ -278,25 +635,29 @@ impl StorageNodeV2Impl {
     ) -> std::result::Result<(), Status> {
         info!("start to keep subscription");
         let local_running = self.running.clone();
-        let local_broadcast_sender = self.broadcast_sender.clone();
+        let local_block_event_sender = self.block_event_sender.clone();
+        let local_mutation_event_sender = self.mutation_event_sender.clone();
         let local_rollup_interval = self.rollup_interval.clone();
         let local_rollup_executor = self.rollup_executor.clone();
         let local_system_store = self.system_store.clone();
         let local_network_id = self.network_id.clone();
         let local_event_processor = self.event_processor.clone();
+        let local_storage = self.storage.clone();
+        let local_subscriber_config = self.config.subscriber_config.clone();
+        let local_subscriber_metrics = self.subscriber_metrics.clone();
+        let local_db_store = self.db_store.clone();
         tokio::spawn(async move {
             info!("listen to subscription update event and event message broadcaster");
             while local_running.load(Ordering::Relaxed) {
                 info!("keep subscription loop");
-                let mut subscribers: BTreeMap<
-                    DB3Address,
-                    (
-                        Sender<std::result::Result<EventMessageV2, Status>>,
-                        SubscriptionV2,
-                    ),
-                > = BTreeMap::new();
-                let mut to_be_removed: HashSet<DB3Address> = HashSet::new();
-                let mut event_sub = local_broadcast_sender.subscribe();
+                let mut subscribers = SubscriberManager::new(local_subscriber_config.clone());
+                let mut flush_interval =
+                    tokio::time::interval(TokioDuration::from_millis(SUBSCRIBER_FLUSH_INTERVAL));
+                let mut block_event_sub = local_block_event_sender.subscribe();
+                let mut mutation_event_sub = local_mutation_event_sender.subscribe();
+                // the next block event to dispatch, the lagged block events are caught up
+                // from the mutation store
+                let mut next_block = local_storage.get_current_block().unwrap_or(0);
                 while local_running.load(Ordering::Relaxed) {
                     tokio::select! {
                         Some(()) = update_receiver.recv() => {
@@ -314,45 +675,92 @@ impl StorageNodeV2Impl {
                                 warn!("fail update rollup executor config for {e}");
                             }
                         }
-                        Some((addr, sub, sender)) = receiver.recv() => {
-                            info!("add or update the subscriber with addr 0x{}", hex::encode(addr.as_ref()));
-                            //TODO limit the max address count
-                            subscribers.insert(addr, (sender, sub));
-                            info!("subscribers len : {}", subscribers.len());
-                        }
-
-                        Ok(event) = event_sub.recv() => {
-                            debug!("receive event {:?}", event);
-                            for (key , (sender, sub)) in subscribers.iter() {
-                                if sender.is_closed() {
-                                    to_be_removed.insert(key.clone());
-                                    warn!("the channel has been closed by client for addr 0x{}", hex::encode(key.as_ref()));
-                                    continue;
+                        Some((addr, mut sub, sender)) = receiver.recv() => {
+                            info!("add the subscriber with addr 0x{}", hex::encode(addr.as_ref()));
+                            let events = if sub.resume {
+                                Self::catch_up_block_events(&local_storage, &mut sub)
+                            } else {
+                                Ok(vec![])
+                            };
+                            match events {
+                                Ok(events) => {
+                                    subscribers.add(addr, sub, sender, events, &local_subscriber_metrics);
                                 }
-                                for idx in 0..sub.topics.len() {
-                                    if sub.topics[idx] != EventTypeV2::Block as i32 {
-                                        continue;
+                                // the subscriber is not added with a gap in the block events
+                                Err(e) => {
+                                    warn!("fail to catch up the block events for addr 0x{} with error {e}", hex::encode(addr.as_ref()));
+                                    if let Err(e) = sender.try_send(Err(Status::aborted(format!("fail to catch up the block events for {e}")))) {
+                                        debug!("fail to notify the subscriber for {e}");
                                     }
-                                    match sender.try_send(Ok(event.clone())) {
-                                        Ok(_) => {
-                                            debug!("send event to addr 0x{}", hex::encode(key.as_ref()));
-                                            break;
+                                }
+                            }
+                        }
+                        result = block_event_sub.recv() => {
+                            match result {
+                                Ok(event) => {
+                                    debug!("receive event {:?}", event);
+                                    if let Some(EventV2::BlockEvent(e)) = &event.event {
+                                        // the block event has been caught up
+                                        if e.block_id < next_block {
+                                            continue;
                                         }
-                                        Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => {
-                                            // retry?
-                                            // TODO
-                                            warn!("the channel is full for addr 0x{}", hex::encode(key.as_ref()));
+                                        next_block = e.block_id + 1;
+                                    }
+                                    subscribers.dispatch(&event, &local_subscriber_metrics, |reader, e| {
+                                        Self::can_read_mutation_event(&local_db_store, reader, e)
+                                    });
+                                }
+                                Err(broadcast::error::RecvError::Lagged(count)) => {
+                                    warn!("the subscription thread lags behind {} block events", count);
+                                    local_subscriber_metrics.add_lagged_events(count);
+                                    let events = local_storage.get_current_block().and_then(|current_block| {
+                                        let events = Self::get_block_events(&local_storage, next_block, current_block)?;
+                                        next_block = next_block.max(current_block);
+                                        Ok(events)
+                                    });
+                                    match events {
+                                        Ok(events) => {
+                                            for event in events.iter() {
+                                                subscribers.dispatch(event, &local_subscriber_metrics, |_, _| true);
+                                            }
                                         }
-                                        Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => {
-                                            // remove the address
-                                            to_be_removed.insert(key.clone());
-                                            warn!("the channel has been closed by client for addr 0x{}", hex::encode(key.as_ref()));
+                                        Err(e) => {
+                                            warn!("fail to catch up the block events for error {e}");
+                                            subscribers.disconnect_topic(EventTypeV2::Block as i32, &local_subscriber_metrics);
                                         }
-
                                     }
                                 }
+                                Err(broadcast::error::RecvError::Closed) => {
+                                    info!("the event broadcaster has been closed");
+                                    sleep(TokioDuration::from_millis(1000 * 5)).await;
+                                    break;
+                                }
                             }
-                        },
+                        }
+                        result = mutation_event_sub.recv() => {
+                            match result {
+                                Ok(event) => {
+                                    debug!("receive event {:?}", event);
+                                    subscribers.dispatch(&event, &local_subscriber_metrics, |reader, e| {
+                                        Self::can_read_mutation_event(&local_db_store, reader, e)
+                                    });
+                                }
+                                Err(broadcast::error::RecvError::Lagged(count)) => {
+                                    // the mutation events can not be replayed
+                                    warn!("the subscription thread lags behind {} mutation events", count);
+                                    local_subscriber_metrics.add_lagged_events(count);
+                                    subscribers.disconnect_topic(EventTypeV2::Mutation as i32, &local_subscriber_metrics);
+                                }
+                                Err(broadcast::error::RecvError::Closed) => {
+                                    info!("the event broadcaster has been closed");
+                                    sleep(TokioDuration::from_millis(1000 * 5)).await;
+                                    break;
+                                }
+                            }
+                        }
+                        _ = flush_interval.tick() => {
+                            subscribers.flush(&local_subscriber_metrics);
+                        }
                         else => {
                             info!("unexpected channel update");
                             // reconnect in 5 seconds
@@ -361,10 +769,6 @@ impl StorageNodeV2Impl {
                         }
 
                     }
-                    for k in to_be_removed.iter() {
-                        subscribers.remove(k);
-                    }
-                    to_be_removed.clear();
                 }
             }
             info!("exit the keep subscription thread");
@@ -398,7 +802,10 @@ impl StorageNode for StorageNodeV2Impl {
             total_storage_cost,
             total_evm_cost,
         };
-        Ok(Response::new(GetMutationStateResponse { view: Some(view) }))
+        Ok(Response::new(GetMutationStateResponse {
+            view: Some(view),
+            subscriber_stats: Some(self.subscriber_metrics.get_stats_view()),
+        }))
     }
 
     async fn scan_gc_record(
@@ -434,15 +841,67 @@ impl StorageNode for StorageNodeV2Impl {
             let subscription = SubscriptionV2::decode(data.as_ref()).map_err(|e| {
                 Status::invalid_argument(format!("decode the data to object failed for error {e}"))
             })?;
+            // the signer of the subscription must be able to read the subscribed collections
+            if subscription
+                .topics
+                .contains(&(EventTypeV2::Mutation as i32))
+                && !subscription.db_addr.is_empty()
+            {
+                let db_addr = DB3Address::try_from(subscription.db_addr.as_slice())
+                    .map_err(|e| Status::invalid_argument(format!("invalid db address {e}")))?;
+                for col_name in subscription.collection_names.iter() {
+                    self.db_store
+                        .check_read_permission(&db_addr, col_name.as_str(), Some(&db3_address))
+                        .map_err(|e| match e {
+                            DB3Error::ReadPermissionDenied(_) => {
+                                Status::permission_denied(format!("{e}"))
+                            }
+                            _ => Status::invalid_argument(format!("{e}")),
+                        })?;
+                }
+            }
             info!(
                 "add subscriber for addr 0x{}",
                 hex::encode(address.as_ref())
             );
             let (msg_sender, msg_receiver) =
                 tokio::sync::mpsc::channel::<std::result::Result<EventMessageV2, Status>>(10);
-            sender
-                .try_send((db3_address, subscription, msg_sender))
-                .map_err(|e| Status::internal(format!("fail to add subscriber for {e}")))?;
+            if subscription.resume && subscription.topics.contains(&(EventTypeV2::Block as i32)) {
+                Self::check_replay_block(&self.storage, subscription.from_block)
+                    .map_err(|e| Status::out_of_range(format!("{e}")))?;
+                // replay the stored blocks before the subscriber receives the live events
+                let storage = self.storage.clone();
+                task::spawn(async move {
+                    let mut subscription = subscription;
+                    match Self::replay_block_events(&storage, subscription.from_block, &msg_sender)
+                        .await
+                    {
+                        Ok(next_block) => {
+                            subscription.from_block = next_block;
+                            if let Err(e) =
+                                sender.send((db3_address, subscription, msg_sender)).await
+                            {
+                                warn!("fail to add subscriber for {e}");
+                            }
+                        }
+                        Err(e) => {
+                            warn!("fail to replay the block events for error {e}");
+                            if let Err(e) = msg_sender
+                                .send(Err(Status::aborted(format!(
+                                    "fail to replay the block events for {e}"
+                                ))))
+                                .await
+                            {
+                                debug!("fail to notify the subscriber for {e}");
+                            }
+                        }
+                    }
+                });
+            } else {
+                sender
+                    .try_send((db3_address, subscription, msg_sender))
+                    .map_err(|e| Status::internal(format!("fail to add subscriber for {e}")))?;
+            }
             Ok(Response::new(ReceiverStream::new(msg_receiver)))
         } else {
             Err(Status::invalid_argument(
@@ -467,7 +926,11 @@ impl StorageNode for StorageNodeV2Impl {
                 body: Some(b.to_owned()),
             })
             .collect();
-        Ok(Response::new(BlockResponse { mutations }))
+        let headers = self
+            .storage
+            .get_range_block_headers(r.block_start, r.block_end)
+            .map_err(|e| Status::internal(format!("{e}")))?;
+        Ok(Response::new(BlockResponse { mutations, headers }))
     }
 
     async fn get_database(
@@ -543,6 +1006,21 @@ impl StorageNode for StorageNodeV2Impl {
         Ok(Response::new(GetMutationBodyResponse { body }))
     }
 
+    async fn get_mutation_proof(
+        &self,
+        request: Request<GetMutationProofRequest>,
+    ) -> std::result::Result<Response<GetMutationProofResponse>, Status> {
+        let r = request.into_inner();
+        let tx_id = TxId::try_from_hex(r.id.as_str())
+            .map_err(|e| Status::invalid_argument(format!("invalid mutation id {e}")))?;
+        // the proof is none if the mutation has not been rolled up
+        let proof = self
+            .storage
+            .get_mutation_proof(&tx_id)
+            .map_err(|e| Status::internal(format!("{e}")))?;
+        Ok(Response::new(GetMutationProofResponse { proof }))
+    }
+
     async fn scan_rollup_record(
         &self,
         request: Request<ScanRollupRecordRequest>,
@@ -577,15 +1055,76 @@ impl StorageNode for StorageNodeV2Impl {
         Ok(Response::new(ScanMutationHeaderResponse { headers }))
     }
 
+    async fn scan_mutation_header_by_filter(
+        &self,
+        request: Request<ScanMutationHeaderByFilterRequest>,
+    ) -> std::result::Result<Response<ScanMutationHeaderByFilterResponse>, Status> {
+        let r = request.into_inner();
+        let sender = if r.sender.is_empty() {
+            None
+        } else {
+            Some(
+                DB3Address::try_from(r.sender.as_str())
+                    .map_err(|e| Status::invalid_argument(format!("invalid sender {e}")))?,
+            )
+        };
+        let db_addr =
+            if r.db_addr.is_empty() {
+                None
+            } else {
+                Some(DB3Address::try_from(r.db_addr.as_str()).map_err(|e| {
+                    Status::invalid_argument(format!("invalid database address {e}"))
+                })?)
+            };
+        let actions = r
+            .actions
+            .iter()
+            .map(|action| {
+                MutationAction::from_i32(*action)
+                    .ok_or(Status::invalid_argument("bad mutation action".to_string()))
+            })
+            .collect::<std::result::Result<Vec<MutationAction>, Status>>()?;
+        if sender.is_none() && db_addr.is_none() && actions.is_empty() {
+            return Err(Status::invalid_argument(
+                "the sender, the database address or the actions should be set".to_string(),
+            ));
+        }
+        let filter = MutationFilter {
+            sender,
+            db_addr,
+            actions,
+        };
+        let (headers, next_position) = self
+            .storage
+            .scan_mutation_headers_by_filter(&filter, r.start_block, r.start_order, r.limit)
+            .map_err(|e| Status::internal(format!("{e}")))?;
+        let index_start_block = self
+            .storage
+            .get_index_start_block()
+            .map_err(|e| Status::internal(format!("{e}")))?;
+        let (next_block, next_order) = next_position.unwrap_or_default();
+        Ok(Response::new(ScanMutationHeaderByFilterResponse {
+            headers,
+            has_more: next_position.is_some(),
+            next_block,
+            next_order,
+            index_start_block,
+        }))
+    }
+
     async fn get_mutation_header(
         &self,
         request: Request<GetMutationHeaderRequest>,
     ) -> std::result::Result<Response<GetMutationHeaderResponse>, Status> {
         let r = request.into_inner();
-        let header = self
-            .storage
-            .get_mutation_header(r.block_id, r.order_id)
-            .map_err(|e| Status::internal(format!("{e}")))?;
+        let header = if r.id.is_empty() {
+            self.storage.get_mutation_header(r.block_id, r.order_id)
+        } else {
+            let tx_id = TxId::try_from_hex(r.id.as_str())
+                .map_err(|e| Status::invalid_argument(format!("invalid mutation id {e}")))?;
+            self.storage.get_mutation_header_by_id(&tx_id)
+        }
+        .map_err(|e| Status::internal(format!("{e}")))?;
         Ok(Response::new(GetMutationHeaderResponse {
             header,
             status: MutationRollupStatus::Pending.into(),
@@ -632,51 +1171,49 @@ impl StorageNode for StorageNodeV2Impl {
         })?;
         let action = MutationAction::from_i32(dm.action)
             .ok_or(Status::invalid_argument("bad mutation action".to_string()))?;
+        // the documents matched by the query are resolved with the doc store
+        if matches!(
+            action,
+            MutationAction::DeleteDocumentByQuery | MutationAction::UpdateDocumentByQuery
+        ) && !self.db_store.is_doc_store_enabled()
+        {
+            return Err(Status::failed_precondition(
+                "the mutations by query require the node to start with --enable-doc-store"
+                    .to_string(),
+            ));
+        }
         match self.state_store.incr_nonce(&address, nonce) {
             Ok(_) => {
-                // mutation id
-                let (id, block, order) = self
-                    .storage
-                    .generate_mutation_block_and_order(&r.payload, r.signature.as_str())
-                    .map_err(|e| {
-                        warn!("fail to generate the block and order for {e}");
-                        Status::internal(format!("{e}"))
-                    })?;
-                let response = match self.db_store.apply_mutation(
-                    action,
-                    dm,
+                let response = match Self::apply_and_add_mutation(
+                    &self.storage,
+                    &self.db_store,
+                    &self.mutation_event_sender,
+                    &r.payload,
+                    r.signature.as_str(),
+                    &dm,
                     &address,
-                    network,
                     nonce,
-                    block,
-                    order,
-                    &HashMap::new(),
+                    network,
+                    // the nonce has been committed before the mutation is applied
+                    || Ok(()),
                 ) {
-                    Ok(items) => {
-                        let doc_ids_map = MutationUtil::get_create_doc_ids_map(&items);
-                        self.storage
-                            .add_mutation(
-                                &r.payload,
-                                r.signature.as_str(),
-                                doc_ids_map.as_str(),
-                                &address,
-                                nonce,
-                                block,
-                                order,
-                                network,
-                                action,
-                            )
-                            .map_err(|e| {
-                                warn!("fail to add mutation for error {e}");
-                                Status::internal(format!("{e}"))
-                            })?;
+                    Ok((id, block, order, items)) => Response::new(SendMutationResponse {
+                        id,
+                        code: 0,
+                        msg: "ok".to_string(),
+                        items,
+                        block,
+                        order,
+                    }),
+                    Err(DB3Error::PreconditionFailed(msg)) => {
+                        // the nonce has been used and the client can retry with a new one
                         Response::new(SendMutationResponse {
-                            id,
-                            code: 0,
-                            msg: "ok".to_string(),
-                            items,
-                            block,
-                            order,
+                            id: "".to_string(),
+                            code: 2,
+                            msg,
+                            items: vec![],
+                            block: 0,
+                            order: 0,
                         })
                     }
                     Err(e) => {
@@ -702,6 +1239,7 @@ impl StorageNode for StorageNodeV2Impl {
 mod tests {
 
     use super::*;
+    use crate::subscriber::LagPolicy;
     use crate::system_impl::SystemImpl;
     use db3_proto::db3_system_proto::system_server::System;
     use db3_proto::db3_system_proto::SetupRequest;
@@ -766,6 +1304,12 @@ mod tests {
                 rollup_config,
                 db_store_config,
                 block_interval: 10000,
+                doc_expire_interval: 10000,
+                subscriber_config: SubscriberConfig {
+                    buffer_size: 1024,
+                    max_subscriber_count: 1024,
+                    lag_policy: LagPolicy::Disconnect,
+                },
             },
         )
     }
@@ -0,0 +1,656 @@
+//
+// subscriber.rs
+// Copyright (C) 2023 db3.network Author imotai <codego.me@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use db3_crypto::db3_address::DB3Address;
+use db3_proto::db3_storage_proto::event_message::Event as EventV2;
+use db3_proto::db3_storage_proto::{
+    EventMessage as EventMessageV2, MutationEvent as MutationEventV2, SubscriberStatsView,
+    Subscription as SubscriptionV2,
+};
+use std::collections::{BTreeMap, VecDeque};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Sender;
+use tonic::Status;
+use tracing::{debug, info, warn};
+
+///
+/// what to do with the events of a subscriber when its buffer is full. the block events
+/// are never dropped or merged, so the subscriber is disconnected instead if the oldest
+/// buffered event is a block event and it can resume from the last received block
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// drop the oldest buffered event
+    DropOldest,
+    /// disconnect the subscriber and it can resume from the last received block
+    Disconnect,
+    /// merge the mutation event into the last buffered one and drop the oldest if they
+    /// can't be merged
+    Coalesce,
+}
+
+impl FromStr for LagPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(LagPolicy::DropOldest),
+            "disconnect" => Ok(LagPolicy::Disconnect),
+            "coalesce" => Ok(LagPolicy::Coalesce),
+            _ => Err(format!(
+                "invalid lag policy {s}, it should be one of drop-oldest, disconnect and coalesce"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubscriberConfig {
+    // the max count of the buffered events for every subscriber
+    pub buffer_size: usize,
+    pub max_subscriber_count: usize,
+    pub lag_policy: LagPolicy,
+}
+
+#[derive(Default)]
+pub struct SubscriberMetrics {
+    subscriber_count: AtomicU64,
+    pending_event_count: AtomicU64,
+    lagged_event_count: AtomicU64,
+    coalesced_event_count: AtomicU64,
+    disconnected_subscriber_count: AtomicU64,
+    rejected_subscriber_count: AtomicU64,
+}
+
+impl SubscriberMetrics {
+    pub fn get_stats_view(&self) -> SubscriberStatsView {
+        SubscriberStatsView {
+            subscriber_count: self.subscriber_count.load(Ordering::Relaxed),
+            pending_event_count: self.pending_event_count.load(Ordering::Relaxed),
+            lagged_event_count: self.lagged_event_count.load(Ordering::Relaxed),
+            coalesced_event_count: self.coalesced_event_count.load(Ordering::Relaxed),
+            disconnected_subscriber_count: self
+                .disconnected_subscriber_count
+                .load(Ordering::Relaxed),
+            rejected_subscriber_count: self.rejected_subscriber_count.load(Ordering::Relaxed),
+        }
+    }
+
+    /// the events missed by all the subscribers
+    pub fn add_lagged_events(&self, count: u64) {
+        self.lagged_event_count.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+///
+/// check whether the event matches the topics and the filters of the subscription
+///
+pub fn is_subscribed(sub: &SubscriptionV2, event: &EventMessageV2) -> bool {
+    if !sub.topics.contains(&event.r#type) {
+        return false;
+    }
+    match &event.event {
+        Some(EventV2::MutationEvent(e)) => {
+            (sub.db_addr.is_empty() || sub.db_addr == e.db_addr)
+                && (sub.collection_names.is_empty()
+                    || sub.collection_names.contains(&e.collection_name))
+        }
+        // the blocks before the from block have been replayed to the subscriber
+        Some(EventV2::BlockEvent(e)) => !sub.resume || e.block_id >= sub.from_block,
+        _ => true,
+    }
+}
+
+///
+/// merge the mutation event into the buffered one of the same sender and collection and
+/// keep all the document changes. the block events are not merged because the subscriber
+/// resumes from the block id
+///
+fn coalesce(last: &mut EventMessageV2, event: &EventMessageV2) -> bool {
+    match (&mut last.event, &event.event) {
+        (Some(EventV2::MutationEvent(l)), Some(EventV2::MutationEvent(e)))
+            if l.sender == e.sender
+                && l.db_addr == e.db_addr
+                && l.collection_name == e.collection_name =>
+        {
+            l.block_id = e.block_id;
+            l.order_id = e.order_id;
+            l.changes.extend_from_slice(&e.changes);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// the subscriber of a connection
+struct Subscriber {
+    addr: DB3Address,
+    sub: SubscriptionV2,
+    sender: Sender<std::result::Result<EventMessageV2, Status>>,
+    // the events which can't be sent for the full channel
+    pending: VecDeque<EventMessageV2>,
+}
+
+impl Subscriber {
+    ///
+    /// send the buffered events until the channel is full and return false if the
+    /// channel has been closed by client
+    ///
+    fn flush(&mut self) -> bool {
+        while let Some(e) = self.pending.pop_front() {
+            match self.sender.try_send(Ok(e)) {
+                Ok(_) => {}
+                Err(TrySendError::Full(e)) => {
+                    if let Ok(e) = e {
+                        self.pending.push_front(e);
+                    }
+                    break;
+                }
+                Err(TrySendError::Closed(_)) => {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    ///
+    /// send the event or buffer it if the channel is full and return false if the
+    /// subscriber should be removed
+    ///
+    fn deliver(
+        &mut self,
+        event: &EventMessageV2,
+        config: &SubscriberConfig,
+        metrics: &SubscriberMetrics,
+    ) -> bool {
+        if !self.flush() {
+            return false;
+        }
+        if self.pending.is_empty() {
+            match self.sender.try_send(Ok(event.clone())) {
+                Ok(_) => {
+                    return true;
+                }
+                Err(TrySendError::Full(_)) => {}
+                Err(TrySendError::Closed(_)) => {
+                    return false;
+                }
+            }
+        }
+        if self.pending.len() < config.buffer_size {
+            self.pending.push_back(event.clone());
+            return true;
+        }
+        match config.lag_policy {
+            LagPolicy::DropOldest => {}
+            LagPolicy::Disconnect => {
+                return self.disconnect(metrics);
+            }
+            LagPolicy::Coalesce => {
+                if let Some(last) = self.pending.back_mut() {
+                    if coalesce(last, event) {
+                        metrics
+                            .coalesced_event_count
+                            .fetch_add(1, Ordering::Relaxed);
+                        return true;
+                    }
+                }
+            }
+        }
+        if let Some(EventV2::BlockEvent(_)) = self.pending.front().and_then(|e| e.event.as_ref()) {
+            return self.disconnect(metrics);
+        }
+        self.pending.pop_front();
+        self.pending.push_back(event.clone());
+        metrics.lagged_event_count.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// return false to remove the subscriber which lags behind
+    fn disconnect(&self, metrics: &SubscriberMetrics) -> bool {
+        metrics
+            .disconnected_subscriber_count
+            .fetch_add(1, Ordering::Relaxed);
+        warn!(
+            "disconnect the subscriber with addr 0x{} for lagging behind",
+            hex::encode(self.addr.as_ref())
+        );
+        false
+    }
+}
+
+///
+/// the subscribers keyed by the connection id, so a wallet can subscribe with many
+/// connections
+///
+pub struct SubscriberManager {
+    config: SubscriberConfig,
+    subscribers: BTreeMap<u64, Subscriber>,
+    next_id: u64,
+}
+
+impl SubscriberManager {
+    pub fn new(config: SubscriberConfig) -> Self {
+        Self {
+            config,
+            subscribers: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    ///
+    /// add the subscriber of a connection with the events to send first and return its
+    /// id. the subscriber is rejected if the max subscriber count has been reached. the
+    /// events to send first are replayed ones, so they are buffered without the lag policy
+    ///
+    pub fn add(
+        &mut self,
+        addr: DB3Address,
+        sub: SubscriptionV2,
+        sender: Sender<std::result::Result<EventMessageV2, Status>>,
+        events: Vec<EventMessageV2>,
+        metrics: &SubscriberMetrics,
+    ) -> Option<u64> {
+        if self.subscribers.len() >= self.config.max_subscriber_count {
+            metrics
+                .rejected_subscriber_count
+                .fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "reject the subscriber with addr 0x{} for reaching the max subscriber count {}",
+                hex::encode(addr.as_ref()),
+                self.config.max_subscriber_count
+            );
+            if let Err(e) = sender.try_send(Err(Status::resource_exhausted(
+                "the max subscriber count has been reached",
+            ))) {
+                debug!("fail to notify the rejected subscriber for {e}");
+            }
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut subscriber = Subscriber {
+            addr,
+            sub,
+            sender,
+            pending: VecDeque::new(),
+        };
+        subscriber.pending.extend(events);
+        if !subscriber.flush() {
+            return None;
+        }
+        info!(
+            "add the subscriber {} with addr 0x{}",
+            id,
+            hex::encode(subscriber.addr.as_ref())
+        );
+        self.subscribers.insert(id, subscriber);
+        self.update_metrics(metrics);
+        Some(id)
+    }
+
+    ///
+    /// send the event to the subscribers which subscribe it. the mutation event is only
+    /// sent to the subscribers which can read it
+    ///
+    pub fn dispatch<F>(&mut self, event: &EventMessageV2, metrics: &SubscriberMetrics, can_read: F)
+    where
+        F: Fn(&DB3Address, &MutationEventV2) -> bool,
+    {
+        let config = &self.config;
+        self.subscribers.retain(|id, subscriber| {
+            if subscriber.sender.is_closed() {
+                warn!(
+                    "the channel has been closed by client for subscriber {} with addr 0x{}",
+                    id,
+                    hex::encode(subscriber.addr.as_ref())
+                );
+                return false;
+            }
+            if !is_subscribed(&subscriber.sub, event) {
+                return true;
+            }
+            if let Some(EventV2::MutationEvent(e)) = &event.event {
+                if !can_read(&subscriber.addr, e) {
+                    return true;
+                }
+            }
+            subscriber.deliver(event, config, metrics)
+        });
+        self.update_metrics(metrics);
+    }
+
+    ///
+    /// disconnect the subscribers of the topic whose events have been missed, so they
+    /// can subscribe again instead of receiving the events with a gap
+    ///
+    pub fn disconnect_topic(&mut self, topic: i32, metrics: &SubscriberMetrics) {
+        self.subscribers.retain(|id, subscriber| {
+            if !subscriber.sub.topics.contains(&topic) {
+                return true;
+            }
+            if let Err(e) = subscriber.sender.try_send(Err(Status::aborted(
+                "the events have been missed and please subscribe again",
+            ))) {
+                debug!("fail to notify the subscriber {} for {e}", id);
+            }
+            subscriber.disconnect(metrics)
+        });
+        self.update_metrics(metrics);
+    }
+
+    /// send the buffered events of the subscribers
+    pub fn flush(&mut self, metrics: &SubscriberMetrics) {
+        self.subscribers.retain(|id, subscriber| {
+            if subscriber.pending.is_empty() {
+                return true;
+            }
+            let open = subscriber.flush();
+            if !open {
+                warn!(
+                    "the channel has been closed by client for subscriber {} with addr 0x{}",
+                    id,
+                    hex::encode(subscriber.addr.as_ref())
+                );
+            }
+            open
+        });
+        self.update_metrics(metrics);
+    }
+
+    fn update_metrics(&self, metrics: &SubscriberMetrics) {
+        metrics
+            .subscriber_count
+            .store(self.subscribers.len() as u64, Ordering::Relaxed);
+        let pending: usize = self.subscribers.values().map(|s| s.pending.len()).sum();
+        metrics
+            .pending_event_count
+            .store(pending as u64, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db3_proto::db3_storage_proto::{BlockEvent, DocumentChange, EventType};
+
+    fn block_event(block_id: u64) -> EventMessageV2 {
+        EventMessageV2 {
+            r#type: EventType::Block as i32,
+            event: Some(EventV2::BlockEvent(BlockEvent {
+                block_id,
+                mutation_count: 1,
+            })),
+        }
+    }
+
+    fn mutation_event(block_id: u64) -> EventMessageV2 {
+        EventMessageV2 {
+            r#type: EventType::Mutation as i32,
+            event: Some(EventV2::MutationEvent(MutationEventV2 {
+                block_id,
+                order_id: 1,
+                sender: DB3Address::ZERO.as_ref().to_vec(),
+                db_addr: DB3Address::ZERO.as_ref().to_vec(),
+                collection_name: "col1".to_string(),
+                changes: vec![DocumentChange::default()],
+            })),
+        }
+    }
+
+    fn block_subscription() -> SubscriptionV2 {
+        SubscriptionV2 {
+            topics: vec![EventType::Block as i32],
+            db_addr: vec![],
+            collection_names: vec![],
+            resume: false,
+            from_block: 0,
+        }
+    }
+
+    /// the block id with the mutation count of the block events or the change count of
+    /// the mutation events
+    fn received_blocks(
+        receiver: &mut tokio::sync::mpsc::Receiver<std::result::Result<EventMessageV2, Status>>,
+    ) -> Vec<(u64, u32)> {
+        let mut blocks = vec![];
+        while let Ok(Ok(e)) = receiver.try_recv() {
+            match e.event {
+                Some(EventV2::BlockEvent(be)) => blocks.push((be.block_id, be.mutation_count)),
+                Some(EventV2::MutationEvent(me)) => {
+                    blocks.push((me.block_id, me.changes.len() as u32))
+                }
+                None => {}
+            }
+        }
+        blocks
+    }
+
+    fn lag_flow(
+        lag_policy: LagPolicy,
+        event: fn(u64) -> EventMessageV2,
+    ) -> (Vec<(u64, u32)>, SubscriberStatsView, bool) {
+        let config = SubscriberConfig {
+            buffer_size: 2,
+            max_subscriber_count: 1,
+            lag_policy,
+        };
+        let metrics = SubscriberMetrics::default();
+        let mut manager = SubscriberManager::new(config);
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        let sub = SubscriptionV2 {
+            topics: vec![EventType::Block as i32, EventType::Mutation as i32],
+            ..block_subscription()
+        };
+        let id = manager.add(DB3Address::ZERO, sub, sender, vec![], &metrics);
+        assert_eq!(Some(1), id);
+        // the channel holds one event and the buffer holds two
+        for block_id in 0..5 {
+            manager.dispatch(&event(block_id), &metrics, |_, _| true);
+        }
+        let view = metrics.get_stats_view();
+        let mut blocks = received_blocks(&mut receiver);
+        manager.flush(&metrics);
+        blocks.extend(received_blocks(&mut receiver));
+        manager.flush(&metrics);
+        blocks.extend(received_blocks(&mut receiver));
+        (blocks, view, manager.subscribers.is_empty())
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let (blocks, view, removed) = lag_flow(LagPolicy::DropOldest, mutation_event);
+        assert_eq!(vec![(0, 1), (3, 1), (4, 1)], blocks);
+        assert_eq!(2, view.lagged_event_count);
+        assert_eq!(2, view.pending_event_count);
+        assert!(!removed);
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (blocks, view, removed) = lag_flow(LagPolicy::Disconnect, mutation_event);
+        assert_eq!(vec![(0, 1)], blocks);
+        assert_eq!(1, view.disconnected_subscriber_count);
+        assert_eq!(0, view.subscriber_count);
+        assert!(removed);
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let (blocks, view, removed) = lag_flow(LagPolicy::Coalesce, mutation_event);
+        assert_eq!(vec![(0, 1), (1, 1), (4, 3)], blocks);
+        assert_eq!(2, view.coalesced_event_count);
+        assert_eq!(0, view.lagged_event_count);
+        assert!(!removed);
+    }
+
+    #[test]
+    fn test_block_events_never_dropped() {
+        for lag_policy in [
+            LagPolicy::DropOldest,
+            LagPolicy::Disconnect,
+            LagPolicy::Coalesce,
+        ] {
+            let (blocks, view, removed) = lag_flow(lag_policy, block_event);
+            // the subscriber resumes from the block 0 instead of missing the blocks
+            assert_eq!(vec![(0, 1)], blocks);
+            assert_eq!(1, view.disconnected_subscriber_count);
+            assert_eq!(0, view.lagged_event_count);
+            assert_eq!(0, view.coalesced_event_count);
+            assert!(removed);
+        }
+    }
+
+    #[test]
+    fn test_replayed_events_without_lag_policy() {
+        let config = SubscriberConfig {
+            buffer_size: 2,
+            max_subscriber_count: 1,
+            lag_policy: LagPolicy::DropOldest,
+        };
+        let metrics = SubscriberMetrics::default();
+        let mut manager = SubscriberManager::new(config);
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        let events: Vec<EventMessageV2> = (0..5).map(block_event).collect();
+        let id = manager.add(
+            DB3Address::ZERO,
+            block_subscription(),
+            sender,
+            events,
+            &metrics,
+        );
+        assert_eq!(Some(1), id);
+        let mut blocks = vec![];
+        for _ in 0..5 {
+            blocks.extend(received_blocks(&mut receiver));
+            manager.flush(&metrics);
+        }
+        assert_eq!(vec![(0, 1), (1, 1), (2, 1), (3, 1), (4, 1)], blocks);
+        assert_eq!(0, metrics.get_stats_view().lagged_event_count);
+    }
+
+    #[test]
+    fn test_dispatch_readable_mutation_event() {
+        let config = SubscriberConfig {
+            buffer_size: 2,
+            max_subscriber_count: 2,
+            lag_policy: LagPolicy::DropOldest,
+        };
+        let metrics = SubscriberMetrics::default();
+        let mut manager = SubscriberManager::new(config);
+        let bob = DB3Address::try_from("0x2000000000000000000000000000000000000000").unwrap();
+        let sub = SubscriptionV2 {
+            topics: vec![EventType::Mutation as i32],
+            db_addr: vec![],
+            collection_names: vec![],
+            resume: false,
+            from_block: 0,
+        };
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        manager.add(DB3Address::ZERO, sub.clone(), sender, vec![], &metrics);
+        let (bob_sender, mut bob_receiver) = tokio::sync::mpsc::channel(1);
+        manager.add(bob.clone(), sub, bob_sender, vec![], &metrics);
+        let event = EventMessageV2 {
+            r#type: EventType::Mutation as i32,
+            event: Some(EventV2::MutationEvent(MutationEventV2 {
+                block_id: 1,
+                order_id: 1,
+                sender: DB3Address::ZERO.as_ref().to_vec(),
+                db_addr: DB3Address::ZERO.as_ref().to_vec(),
+                collection_name: "col1".to_string(),
+                changes: vec![],
+            })),
+        };
+        // only the sender of the mutation can read the collection
+        manager.dispatch(&event, &metrics, |reader, e| {
+            reader.as_ref() == e.sender.as_slice()
+        });
+        assert!(matches!(receiver.try_recv(), Ok(Ok(_))));
+        assert!(bob_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_disconnect_topic() {
+        let config = SubscriberConfig {
+            buffer_size: 2,
+            max_subscriber_count: 2,
+            lag_policy: LagPolicy::DropOldest,
+        };
+        let metrics = SubscriberMetrics::default();
+        let mut manager = SubscriberManager::new(config);
+        let (sender, mut block_receiver) = tokio::sync::mpsc::channel(1);
+        manager.add(
+            DB3Address::ZERO,
+            block_subscription(),
+            sender,
+            vec![],
+            &metrics,
+        );
+        let sub = SubscriptionV2 {
+            topics: vec![EventType::Mutation as i32],
+            ..block_subscription()
+        };
+        let (sender, mut mutation_receiver) = tokio::sync::mpsc::channel(1);
+        manager.add(DB3Address::ZERO, sub, sender, vec![], &metrics);
+        // only the subscribers of the lagged topic are disconnected
+        manager.disconnect_topic(EventType::Mutation as i32, &metrics);
+        assert!(matches!(mutation_receiver.try_recv(), Ok(Err(_))));
+        assert!(block_receiver.try_recv().is_err());
+        let view = metrics.get_stats_view();
+        assert_eq!(1, view.subscriber_count);
+        assert_eq!(1, view.disconnected_subscriber_count);
+    }
+
+    #[test]
+    fn test_max_subscriber_count() {
+        let config = SubscriberConfig {
+            buffer_size: 2,
+            max_subscriber_count: 1,
+            lag_policy: LagPolicy::DropOldest,
+        };
+        let metrics = SubscriberMetrics::default();
+        let mut manager = SubscriberManager::new(config);
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        assert_eq!(
+            Some(1),
+            manager.add(
+                DB3Address::ZERO,
+                block_subscription(),
+                sender,
+                vec![],
+                &metrics
+            )
+        );
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        assert_eq!(
+            None,
+            manager.add(
+                DB3Address::ZERO,
+                block_subscription(),
+                sender,
+                vec![],
+                &metrics
+            )
+        );
+        assert!(matches!(receiver.try_recv(), Ok(Err(_))));
+        assert_eq!(1, metrics.get_stats_view().rejected_subscriber_count);
+        assert!("coalesce".parse::<LagPolicy>().is_ok());
+        assert!("retry".parse::<LagPolicy>().is_err());
+    }
+}
@@ -16,10 +16,14 @@
 //
 
 use bytes::BytesMut;
+use db3_crypto::db3_address::DB3Address;
+use db3_crypto::id::TxId;
+use db3_crypto::merkle::{self, Hash, ProofNode};
+use db3_proto::db3_rollup_proto::MutationProof;
 use db3_proto::db3_storage_proto::{
     storage_node_client::StorageNodeClient as StorageNodeV2Client, BlockRequest as BlockRequestV2,
     BlockResponse as BlockResponseV2, EventMessage as EventMessageV2, EventType as EventTypeV2,
-    SubscribeRequest, Subscription as SubscriptionV2,
+    GetMutationProofRequest, SubscribeRequest, Subscription as SubscriptionV2,
 };
 
 use ethers::core::types::{
@@ -58,13 +62,47 @@ impl StoreSDKV2 {
         }
     }
 
+    ///
+    /// subscribe the block events. the block events from the from block are replayed
+    /// before the live ones if it's set
+    ///
     pub async fn subscribe_event_message(
         &self,
+        from_block: Option<u64>,
     ) -> Result<tonic::Response<Streaming<EventMessageV2>>, Status> {
         let sub = SubscriptionV2 {
             topics: vec![EventTypeV2::Block.into()],
+            db_addr: vec![],
+            collection_names: vec![],
+            resume: from_block.is_some(),
+            from_block: from_block.unwrap_or(0),
         };
+        self.subscribe(&sub).await
+    }
+
+    ///
+    /// subscribe the document changes of the database. the changes of all the collections
+    /// are received if the collection names are empty
+    ///
+    pub async fn subscribe_mutation_event(
+        &self,
+        db_addr: &DB3Address,
+        collection_names: &[String],
+    ) -> Result<tonic::Response<Streaming<EventMessageV2>>, Status> {
+        let sub = SubscriptionV2 {
+            topics: vec![EventTypeV2::Mutation.into()],
+            db_addr: db_addr.as_ref().to_vec(),
+            collection_names: collection_names.to_vec(),
+            resume: false,
+            from_block: 0,
+        };
+        self.subscribe(&sub).await
+    }
 
+    async fn subscribe(
+        &self,
+        sub: &SubscriptionV2,
+    ) -> Result<tonic::Response<Streaming<EventMessageV2>>, Status> {
         let mut buf = BytesMut::with_capacity(1024 * 4);
         sub.encode(&mut buf)
             .map_err(|e| Status::internal(format!("Fail to encode subscription {e}")))?;
@@ -123,11 +161,52 @@ impl StoreSDKV2 {
     ) -> Result<tonic::Response<BlockResponseV2>, Status> {
         self.get_blocks(height, height + 1).await
     }
+
+    ///
+    /// get the merkle proof of the mutation which is none if the mutation has not been
+    /// rolled up. use `verify_mutation_proof` with the trusted root to check the proof
+    ///
+    pub async fn get_mutation_proof(&self, id: &str) -> Result<Option<MutationProof>, Status> {
+        let req = GetMutationProofRequest { id: id.to_string() };
+        let mut client = self.client.as_ref().clone();
+        let response = client.get_mutation_proof(req).await?;
+        Ok(response.into_inner().proof)
+    }
+}
+
+///
+/// verify the mutation is included in the rollup batch with the trusted root. the root
+/// returned by the node is not trusted, so the trusted root should be the mutation root
+/// anchored on the evm chain with the arweave tx of the rollup record, which is returned
+/// by `getMutationRoot` of the meta store contract
+///
+pub fn verify_mutation_proof(proof: &MutationProof, trusted_root: &[u8]) -> bool {
+    let tx_id = match TxId::try_from_hex(proof.id.as_str()) {
+        Ok(tx_id) => tx_id,
+        _ => return false,
+    };
+    let root: Hash = match trusted_root.try_into() {
+        Ok(root) => root,
+        _ => return false,
+    };
+    let mut path: Vec<ProofNode> = Vec::new();
+    for node in proof.path.iter() {
+        match node.hash.as_slice().try_into() {
+            Ok(hash) => path.push(ProofNode {
+                hash,
+                is_left: node.is_left,
+            }),
+            _ => return false,
+        }
+    }
+    let leaf = merkle::hash_leaf(tx_id.as_ref());
+    merkle::verify_proof(&leaf, &path, &root)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use db3_proto::db3_rollup_proto::{MerkleProofNode, RollupRecord};
     use std::time::Duration;
     use tonic::transport::Endpoint;
 
@@ -138,7 +217,7 @@ mod tests {
         let wallet = LocalWallet::new(&mut rng);
         let sdk = StoreSDKV2::new(client, wallet);
         let res: Result<tonic::Response<Streaming<EventMessageV2>>, Status> =
-            sdk.subscribe_event_message().await;
+            sdk.subscribe_event_message(None).await;
         println!("res {:?}", res);
         assert!(res.is_ok(), "{:?}", res);
         let two_second = Duration::from_millis(2000);
@@ -166,6 +245,46 @@ mod tests {
         assert!(res.is_ok(), "{:?}", res);
     }
 
+    #[test]
+    fn verify_mutation_proof_ut() {
+        let ids: Vec<TxId> = (0..3u8).map(|i| TxId::from(&[i][..])).collect();
+        let leaves: Vec<Hash> = ids
+            .iter()
+            .map(|id| merkle::hash_leaf(id.as_ref()))
+            .collect();
+        let path: Vec<MerkleProofNode> = merkle::merkle_proof(&leaves, 2)
+            .unwrap()
+            .into_iter()
+            .map(|node| MerkleProofNode {
+                hash: node.hash.to_vec(),
+                is_left: node.is_left,
+            })
+            .collect();
+        let root = merkle::merkle_root(&leaves);
+        let mut proof = MutationProof {
+            id: ids[2].to_hex(),
+            index: 2,
+            path,
+            record: Some(RollupRecord {
+                mutation_root: root.to_vec(),
+                ..Default::default()
+            }),
+        };
+        assert!(verify_mutation_proof(&proof, &root));
+        proof.id = ids[1].to_hex();
+        assert!(!verify_mutation_proof(&proof, &root));
+        proof.id = ids[2].to_hex();
+        // the root in the record returned by the node is not trusted
+        let other_root = merkle::merkle_root(&leaves[0..2]);
+        proof.record = Some(RollupRecord {
+            mutation_root: other_root.to_vec(),
+            ..Default::default()
+        });
+        assert!(!verify_mutation_proof(&proof, &other_root));
+        assert!(verify_mutation_proof(&proof, &root));
+        assert!(!verify_mutation_proof(&proof, &root[0..31]));
+    }
+
     #[tokio::test]
     async fn subscribe_event_message_ut() {
         let ep = "http://127.0.0.1:26619";
@@ -138,6 +138,7 @@ impl ArFileSystem {
         end_block: u64,
         network_id: u64,
         filename: &str,
+        mutation_root: &str,
     ) -> Result<(String, u64)> {
         let mut tags: Vec<Tag<Base64>> = {
             let app_tag: Tag<Base64> = Tag::from_utf8_strs("App-Name", "DB3 Network")
@@ -174,6 +175,14 @@ impl ArFileSystem {
             tags.push(last_rollup_tx);
         }
 
+        // the merkle root over the ids of the rolled up mutations
+        if !mutation_root.is_empty() {
+            let mutation_root_tag: Tag<Base64> =
+                Tag::from_utf8_strs("Mutation-Root", mutation_root)
+                    .map_err(|e| DB3Error::ArwareOpError(format!("{e}")))?;
+            tags.push(mutation_root_tag);
+        }
+
         let metadata =
             std::fs::metadata(path).map_err(|e| DB3Error::ArwareOpError(format!("{e}")))?;
         let fee = self
@@ -309,6 +318,7 @@ mod tests {
                 37968,
                 10,
                 "37829_37968.gz.parquet",
+                "",
             )
             .await;
         let balance = ar_filesystem.get_balance().await;
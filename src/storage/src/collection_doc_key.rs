@@ -0,0 +1,95 @@
+//
+// collection_doc_key.rs
+// Copyright (C) 2023 db3.network Author imotai <codego.me@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use db3_crypto::db3_address::DB3Address;
+use db3_error::{DB3Error, Result};
+use std::fmt;
+const COLLECTION_DOC_PREFIX: &str = "/collection_doc/";
+
+/// CollectionDocKey with db address, collection name and doc id which lists the
+/// documents of the collection without the doc store
+pub struct CollectionDocKey<'a>(pub &'a DB3Address, pub &'a str, pub i64);
+impl<'a> CollectionDocKey<'a> {
+    ///
+    /// encode the collection doc key. the collection name is prefixed with its length
+    /// to keep the keys of different collections apart
+    ///
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut encoded_key = Self::build_prefix(self.0, Some(self.1))?;
+        encoded_key.extend_from_slice(self.2.to_be_bytes().as_ref());
+        Ok(encoded_key)
+    }
+
+    /// the prefix of the keys in the collection or in the database if the name is none
+    pub fn build_prefix(db_addr: &DB3Address, col_name: Option<&str>) -> Result<Vec<u8>> {
+        let mut encoded_key = COLLECTION_DOC_PREFIX.as_bytes().to_vec();
+        encoded_key.extend_from_slice(db_addr.as_ref());
+        if let Some(col_name) = col_name {
+            let col_len = u16::try_from(col_name.len())
+                .map_err(|e| DB3Error::KeyCodecError(format!("invalid collection name {e}")))?;
+            encoded_key.extend_from_slice(col_len.to_be_bytes().as_ref());
+            encoded_key.extend_from_slice(col_name.as_bytes());
+        }
+        Ok(encoded_key)
+    }
+
+    /// decode the doc id from the key with the given collection prefix
+    pub fn decode_id(key: &[u8], col_prefix: &[u8]) -> Result<i64> {
+        if key.len() != col_prefix.len() + 8 || !key.starts_with(col_prefix) {
+            return Err(DB3Error::KeyCodecError(
+                "invalid collection doc key".to_string(),
+            ));
+        }
+        Ok(i64::from_be_bytes(
+            <[u8; 8]>::try_from(&key[col_prefix.len()..])
+                .map_err(|e| DB3Error::KeyCodecError(format!("get doc id err {e}")))?,
+        ))
+    }
+}
+
+impl fmt::Display for CollectionDocKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}/{}",
+            COLLECTION_DOC_PREFIX,
+            self.0.to_hex(),
+            self.1,
+            self.2
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collection_doc_key() {
+        let db_addr = DB3Address::ZERO;
+        let encoded_key = CollectionDocKey(&db_addr, "col", 1000).encode().unwrap();
+        let col_prefix = CollectionDocKey::build_prefix(&db_addr, Some("col")).unwrap();
+        assert_eq!(
+            1000,
+            CollectionDocKey::decode_id(&encoded_key, &col_prefix).unwrap()
+        );
+        let other_col_prefix = CollectionDocKey::build_prefix(&db_addr, Some("cola")).unwrap();
+        assert!(CollectionDocKey::decode_id(&encoded_key, &other_col_prefix).is_err());
+        let db_prefix = CollectionDocKey::build_prefix(&db_addr, None).unwrap();
+        assert!(encoded_key.starts_with(&db_prefix));
+    }
+}
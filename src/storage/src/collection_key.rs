@@ -20,6 +20,24 @@ use db3_error::{DB3Error, Result};
 
 const MAX_COLLECTION_NAME: usize = 20;
 
+///
+/// check the name of the new collection. the empty name stands for the database in the
+/// acl, so it's not a valid collection name
+///
+pub fn check_collection_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(DB3Error::InvalidCollectionNameError(
+            "name is empty".to_string(),
+        ));
+    }
+    if name.len() > MAX_COLLECTION_NAME {
+        return Err(DB3Error::InvalidCollectionNameError(
+            "name exceeds the max lens limit".to_string(),
+        ));
+    }
+    Ok(())
+}
+
 pub fn build_collection_key(db_addr: &DB3Address, name: &str) -> Result<Vec<u8>> {
     if name.len() > MAX_COLLECTION_NAME {
         return Err(DB3Error::InvalidCollectionNameError(
@@ -0,0 +1,102 @@
+//
+// db_doc_name_key_v2.rs
+// Copyright (C) 2023 db3.network Author imotai <codego.me@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use db3_crypto::db3_address::DB3Address;
+use db3_error::{DB3Error, Result};
+use std::fmt;
+const DOC_NAME_PREFIX: &str = "/doc_name/";
+pub const MAX_DOC_NAME_LENGTH: usize = 256;
+
+/// DbDocNameKeyV2 with db address, collection name and the document key chosen by the client
+pub struct DbDocNameKeyV2<'a>(pub &'a DB3Address, pub &'a str, pub &'a str);
+impl<'a> DbDocNameKeyV2<'a> {
+    ///
+    /// encode the document name key. the collection name is prefixed with its length
+    /// to keep the keys of different collections apart
+    ///
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        if self.2.is_empty() || self.2.len() > MAX_DOC_NAME_LENGTH {
+            return Err(DB3Error::KeyCodecError(format!(
+                "the length of document key {} should be in [1, {}]",
+                self.2, MAX_DOC_NAME_LENGTH
+            )));
+        }
+        let col_len = u16::try_from(self.1.len())
+            .map_err(|e| DB3Error::KeyCodecError(format!("invalid collection name {e}")))?;
+        let mut encoded_key = DOC_NAME_PREFIX.as_bytes().to_vec();
+        encoded_key.extend_from_slice(self.0.as_ref());
+        encoded_key.extend_from_slice(col_len.to_be_bytes().as_ref());
+        encoded_key.extend_from_slice(self.1.as_bytes());
+        encoded_key.extend_from_slice(self.2.as_bytes());
+        Ok(encoded_key)
+    }
+
+    /// the prefix of the keys in the collection or in the database if the name is none
+    pub fn build_prefix(db_addr: &DB3Address, col_name: Option<&str>) -> Result<Vec<u8>> {
+        let mut encoded_key = DOC_NAME_PREFIX.as_bytes().to_vec();
+        encoded_key.extend_from_slice(db_addr.as_ref());
+        if let Some(col_name) = col_name {
+            let col_len = u16::try_from(col_name.len())
+                .map_err(|e| DB3Error::KeyCodecError(format!("invalid collection name {e}")))?;
+            encoded_key.extend_from_slice(col_len.to_be_bytes().as_ref());
+            encoded_key.extend_from_slice(col_name.as_bytes());
+        }
+        Ok(encoded_key)
+    }
+}
+
+impl fmt::Display for DbDocNameKeyV2<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}/{}",
+            DOC_NAME_PREFIX,
+            self.0.to_hex(),
+            self.1,
+            self.2
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db3_crypto::db3_address::DB3_ADDRESS_LENGTH;
+
+    #[test]
+    fn test_db_doc_name_key_v2() {
+        let db_addr = DB3Address::ZERO;
+        let encoded_key = DbDocNameKeyV2(&db_addr, "col", "alice").encode().unwrap();
+        assert_eq!(
+            encoded_key.len(),
+            DOC_NAME_PREFIX.len() + DB3_ADDRESS_LENGTH + 2 + 3 + 5
+        );
+        // the same bytes of collection and key in different split are different keys
+        let other_key = DbDocNameKeyV2(&db_addr, "cola", "lice").encode().unwrap();
+        assert_ne!(encoded_key, other_key);
+        let col_prefix = DbDocNameKeyV2::build_prefix(&db_addr, Some("col")).unwrap();
+        assert!(encoded_key.starts_with(&col_prefix));
+        assert!(!other_key.starts_with(&col_prefix));
+        let db_prefix = DbDocNameKeyV2::build_prefix(&db_addr, None).unwrap();
+        assert!(other_key.starts_with(&db_prefix));
+        assert!(DbDocNameKeyV2(&db_addr, "col", "").encode().is_err());
+        let long_name = "a".repeat(MAX_DOC_NAME_LENGTH + 1);
+        assert!(DbDocNameKeyV2(&db_addr, "col", long_name.as_str())
+            .encode()
+            .is_err());
+    }
+}
@@ -15,33 +15,52 @@
 // limitations under the License.
 //
 //
+use crate::collection_doc_key::CollectionDocKey;
 use crate::collection_key;
 use crate::db_doc_key_v2::DbDocKeyV2;
+use crate::db_doc_name_key_v2::DbDocNameKeyV2;
 use crate::db_owner_key_v2::DbOwnerKey;
-use crate::doc_store::{DocStore, DocStoreConfig};
+use crate::doc_aggregator;
+use crate::doc_expiry_key::{DocExpiryIndexKey, DocExpiryKey, EXPIRY_PREFIX};
+use crate::doc_history_key::DocHistoryKey;
+use crate::doc_schema;
+use crate::doc_store::{self, DocStore, DocStoreConfig};
+use crate::doc_transform;
+use crate::doc_unique_key::{DocUniqueFieldKey, DocUniqueValueKey};
+use crate::query_plan::{self, QueryPlanner};
+use crate::state_tree_key::{StateLeafKey, StateNodeKey};
 use bytes::BytesMut;
 use chashmap::CHashMap;
-use db3_base::bson_util::bytes_to_bson_document;
-use db3_crypto::db3_address::DB3Address;
+use db3_base::bson_util::{
+    bson_document_into_json_str, bson_document_with_mask, bytes_to_bson_document,
+};
+use db3_base::times;
+use db3_crypto::db3_address::{DB3Address, DB3_ADDRESS_LENGTH};
 use db3_crypto::id::DbId;
+use db3_crypto::merkle;
 use db3_error::{DB3Error, Result};
+use db3_proto::db3_database_v2_proto::aggregation::Operator as AggregationOp;
+use db3_proto::db3_database_v2_proto::structured_query::{Limit, Order, Value};
 use db3_proto::db3_database_v2_proto::{
-    database_message, BlockState, Collection, CollectionState as CollectionStateProto,
-    DatabaseMessage, DatabaseState as DatabaseStateProto, DatabaseStatePersistence, Document,
-    DocumentDatabase, EventDatabase, Index, Query,
+    database_message, AccessControl, Aggregation, AggregationResult, BlockState, Collection,
+    CollectionSchema, CollectionState as CollectionStateProto, DatabaseMessage,
+    DatabaseState as DatabaseStateProto, DatabaseStatePersistence, Document, DocumentDatabase,
+    EventDatabase, Index, IndexField, IndexType, PageCursor, Query, StructuredQuery,
 };
 use db3_proto::db3_mutation_v2_proto::mutation::body_wrapper::Body;
+use db3_proto::db3_mutation_v2_proto::precondition::ConditionType;
 use db3_proto::db3_mutation_v2_proto::{
-    CollectionMutation, DocumentDatabaseMutation, EventDatabaseMutation, Mutation, MutationAction,
+    CollectionMutation, DocumentDatabaseMutation, DocumentTransform, EventDatabaseMutation,
+    Mutation, MutationAction, Precondition,
 };
 use db3_proto::db3_storage_proto::ExtraItem;
 use prost::Message;
 use rocksdb::{DBRawIteratorWithThreadMode, DBWithThreadMode, MultiThreaded, Options, WriteBatch};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ops::Deref;
 use std::ops::DerefMut;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tracing::{debug, info, warn};
 type StorageEngine = DBWithThreadMode<MultiThreaded>;
 type DBRawIterator<'a> = DBRawIteratorWithThreadMode<'a, StorageEngine>;
@@ -49,6 +68,31 @@ type DBRawIterator<'a> = DBRawIteratorWithThreadMode<'a, StorageEngine>;
 const STATE_CF: &str = "DB_STATE_CF";
 const BLOCK_STATE_CF: &str = "BLOCK_STATE_CF";
 const BLOCK_STATE_KEY: &str = "BLOCK_STATE_KEY";
+// the mapping between the key and the id of the document
+const DOC_NAME_CF: &str = "DOC_NAME_CF";
+// the expiry time of the documents ordered by time and the mapping between the document
+// and its expiry time
+const DOC_EXPIRY_CF: &str = "DOC_EXPIRY_CF";
+// the versions of the documents in the collections which keep the history
+const DOC_HISTORY_CF: &str = "DOC_HISTORY_CF";
+// the mapping between the values of the unique fields and the ids of the documents
+const DOC_UNIQUE_CF: &str = "DOC_UNIQUE_CF";
+// the ids of the documents in the collections to clean them up without the doc store
+const COLLECTION_DOC_CF: &str = "COLLECTION_DOC_CF";
+// the mutations skipped by the indexer for failing to be applied
+const SKIPPED_MUTATION_CF: &str = "SKIPPED_MUTATION_CF";
+// the hashes of the documents and the nodes of the state tree over them
+const STATE_TREE_CF: &str = "STATE_TREE_CF";
+// the depth of the state tree whose leaves are the buckets of the documents
+const STATE_TREE_DEPTH: u8 = 16;
+/// the key of the server time in the doc ids map of the mutation header
+pub const SERVER_TIME_KEY: &str = "server_time";
+/// the prefix of the key of the ids matched by the query body in the doc ids map
+pub const MATCHED_DOCS_KEY_PREFIX: &str = "matched_";
+/// the prefix of the key of the ids deleted by the expire body in the items
+pub const EXPIRED_DOCS_KEY_PREFIX: &str = "expired_";
+/// the prefix of the key of the ids created by the add or upsert body in the items
+pub const NEW_DOCS_KEY_PREFIX: &str = "document_";
 
 #[derive(Clone)]
 pub struct DBStoreV2Config {
@@ -78,12 +122,192 @@ struct DatabaseState {
     pub total_doc_count: u64,
 }
 
+///
+/// the inverse operation of a doc store write. the doc store can not join the batch of
+/// the storage engine, so its writes are compensated in the reverse order when one of
+/// the bodies of a mutation fails
+///
+enum UndoRecord {
+    AddCollection {
+        db_addr: DB3Address,
+        col_name: String,
+    },
+    AddDocs {
+        db_addr: DB3Address,
+        col_name: String,
+        doc_ids: Vec<i64>,
+    },
+    UpdateDocs {
+        db_addr: DB3Address,
+        col_name: String,
+        // the docs before the update
+        docs: Vec<(i64, String)>,
+    },
+    DeleteDocs {
+        db_addr: DB3Address,
+        col_name: String,
+        docs: Vec<(i64, String)>,
+    },
+}
+
+impl UndoRecord {
+    /// the database, the collection and the ids of the documents changed by the body
+    fn changed_docs(&self) -> Option<(DB3Address, String, Vec<i64>)> {
+        match self {
+            UndoRecord::AddCollection { .. } => None,
+            UndoRecord::AddDocs {
+                db_addr,
+                col_name,
+                doc_ids,
+            } => Some((db_addr.clone(), col_name.to_string(), doc_ids.to_vec())),
+            UndoRecord::UpdateDocs {
+                db_addr,
+                col_name,
+                docs,
+            }
+            | UndoRecord::DeleteDocs {
+                db_addr,
+                col_name,
+                docs,
+            } => Some((
+                db_addr.clone(),
+                col_name.to_string(),
+                docs.iter().map(|(id, _)| *id).collect(),
+            )),
+        }
+    }
+}
+
+///
+/// the writes of a mutation to the storage engine which are committed in one batch after
+/// all its bodies have been applied to the doc store. the later bodies read the values
+/// written by the earlier ones from the pending values, so nothing but the doc store has
+/// to be compensated when a body fails
+///
+#[derive(Default)]
+struct PendingWrites {
+    batch: WriteBatch,
+    // the values by the column family and the key and none for the deleted key
+    values: BTreeMap<(String, Vec<u8>), Option<Vec<u8>>>,
+    // the changes of the doc counts by the database and the collection
+    doc_counts: HashMap<(String, String), i64>,
+    // the doc orders of the databases before the doc ids were allocated
+    doc_orders: HashMap<String, i64>,
+    // the collections created by the mutation
+    collections: Vec<(String, String)>,
+    // the buckets of the state tree with the changed documents
+    state_buckets: BTreeSet<u16>,
+    undo_records: Vec<UndoRecord>,
+}
+
+impl PendingWrites {
+    fn put(&mut self, se: &StorageEngine, cf: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        let cf_handle = se
+            .cf_handle(cf)
+            .ok_or(DB3Error::WriteStoreError("cf is not found".to_string()))?;
+        self.batch.put_cf(&cf_handle, key, value);
+        self.values
+            .insert((cf.to_string(), key.to_vec()), Some(value.to_vec()));
+        Ok(())
+    }
+
+    fn delete(&mut self, se: &StorageEngine, cf: &str, key: &[u8]) -> Result<()> {
+        let cf_handle = se
+            .cf_handle(cf)
+            .ok_or(DB3Error::WriteStoreError("cf is not found".to_string()))?;
+        self.batch.delete_cf(&cf_handle, key);
+        self.values.insert((cf.to_string(), key.to_vec()), None);
+        Ok(())
+    }
+
+    /// get the pending value of the key or the one in the storage engine
+    fn get(&self, se: &StorageEngine, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.values.get(&(cf.to_string(), key.to_vec())) {
+            return Ok(value.clone());
+        }
+        let cf_handle = se
+            .cf_handle(cf)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        se.get_cf(&cf_handle, key)
+            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))
+    }
+
+    /// get the entries with the prefix in the storage engine merged with the pending values
+    fn get_with_prefix(
+        &self,
+        se: &StorageEngine,
+        cf: &str,
+        prefix: &[u8],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let cf_handle = se
+            .cf_handle(cf)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mut entries: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        let mut it: DBRawIterator = se.prefix_iterator_cf(&cf_handle, prefix).into();
+        while it.valid() {
+            match (it.key(), it.value()) {
+                (Some(k), Some(v)) if k.starts_with(prefix) => {
+                    entries.insert(k.to_vec(), v.to_vec());
+                }
+                _ => break,
+            }
+            it.next();
+        }
+        let pending_values = self
+            .values
+            .range((cf.to_string(), prefix.to_vec())..)
+            .take_while(|((name, k), _)| name.as_str() == cf && k.starts_with(prefix));
+        for ((_, k), v) in pending_values {
+            match v {
+                Some(v) => entries.insert(k.to_vec(), v.to_vec()),
+                None => entries.remove(k),
+            };
+        }
+        Ok(entries.into_iter().collect())
+    }
+
+    fn add_doc_count(&mut self, db_addr: &DB3Address, col_name: &str, count: i64) {
+        *self
+            .doc_counts
+            .entry((db_addr.to_hex(), col_name.to_string()))
+            .or_insert(0) += count;
+    }
+
+    /// add the writes to the batch which is written without the pending writes
+    fn write_into(&self, se: &StorageEngine, batch: &mut WriteBatch) -> Result<()> {
+        for ((cf, key), value) in self.values.iter() {
+            let cf_handle = se
+                .cf_handle(cf.as_str())
+                .ok_or(DB3Error::WriteStoreError("cf is not found".to_string()))?;
+            match value {
+                Some(value) => batch.put_cf(&cf_handle, key, value),
+                None => batch.delete_cf(&cf_handle, key),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the role of an address on a collection
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AccessRole {
+    None,
+    Reader,
+    // everyone is a contributor of the collection without acl and can only change
+    // its own documents
+    Contributor,
+    Writer,
+    Owner,
+}
+
 #[derive(Clone)]
 pub struct DBStoreV2 {
     config: DBStoreV2Config,
     se: Arc<StorageEngine>,
     doc_store: Arc<DocStore>,
     db_state: Arc<CHashMap<String, DatabaseState>>,
+    // the nodes of the state tree are shared by the mutations
+    state_tree_lock: Arc<Mutex<()>>,
 }
 
 impl DBStoreV2 {
@@ -106,6 +330,13 @@ impl DBStoreV2 {
                     config.db_owner_store_cf_name.as_str(),
                     STATE_CF,
                     BLOCK_STATE_CF,
+                    DOC_NAME_CF,
+                    DOC_EXPIRY_CF,
+                    DOC_HISTORY_CF,
+                    DOC_UNIQUE_CF,
+                    COLLECTION_DOC_CF,
+                    SKIPPED_MUTATION_CF,
+                    STATE_TREE_CF,
                 ],
             )
             .map_err(|e| {
@@ -124,8 +355,14 @@ impl DBStoreV2 {
             se,
             doc_store,
             db_state: Arc::new(CHashMap::new()),
+            state_tree_lock: Arc::new(Mutex::new(())),
         })
     }
+    /// the doc store is required to resolve the documents matched by a query
+    pub fn is_doc_store_enabled(&self) -> bool {
+        self.config.enable_doc_store
+    }
+
     pub fn flush(&self) -> Result<()> {
         self.se
             .flush()
@@ -300,6 +537,69 @@ impl DBStoreV2 {
         self.put_entry(BLOCK_STATE_CF, BLOCK_STATE_KEY.as_ref(), state)
     }
 
+    ///
+    /// record the mutation which fails to be applied and move the block state over it in
+    /// one batch, so the following mutations are not blocked by it
+    ///
+    pub fn skip_mutation(&self, block: u64, order: u32, reason: &str) -> Result<()> {
+        let skipped_mutation_cf_handle = self
+            .se
+            .cf_handle(SKIPPED_MUTATION_CF)
+            .ok_or(DB3Error::WriteStoreError("cf is not found".to_string()))?;
+        let block_state_cf_handle = self
+            .se
+            .cf_handle(BLOCK_STATE_CF)
+            .ok_or(DB3Error::WriteStoreError("cf is not found".to_string()))?;
+        let mut buf = BytesMut::with_capacity(1024);
+        BlockState { block, order }
+            .encode(&mut buf)
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        let key = [block.to_be_bytes().as_ref(), order.to_be_bytes().as_ref()].concat();
+        let mut batch = WriteBatch::default();
+        batch.put_cf(&skipped_mutation_cf_handle, key, reason.as_bytes());
+        batch.put_cf(
+            &block_state_cf_handle,
+            BLOCK_STATE_KEY.as_bytes(),
+            buf.freeze().as_ref(),
+        );
+        self.se
+            .write(batch)
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))
+    }
+
+    /// the block, order and the failure reason of the skipped mutations
+    pub fn get_skipped_mutations(&self) -> Result<Vec<(u64, u32, String)>> {
+        let skipped_mutation_cf_handle = self
+            .se
+            .cf_handle(SKIPPED_MUTATION_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mut it = self.se.raw_iterator_cf(&skipped_mutation_cf_handle);
+        it.seek_to_first();
+        let mut mutations = vec![];
+        while it.valid() {
+            if let (Some(k), Some(v)) = (it.key(), it.value()) {
+                if k.len() != 12 {
+                    return Err(DB3Error::ReadStoreError(
+                        "invalid skipped mutation key".to_string(),
+                    ));
+                }
+                let block = u64::from_be_bytes(
+                    <[u8; 8]>::try_from(&k[0..8])
+                        .map_err(|e| DB3Error::ReadStoreError(format!("invalid block {e}")))?,
+                );
+                let order = u32::from_be_bytes(
+                    <[u8; 4]>::try_from(&k[8..12])
+                        .map_err(|e| DB3Error::ReadStoreError(format!("invalid order {e}")))?,
+                );
+                let reason = String::from_utf8(v.to_vec())
+                    .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+                mutations.push((block, order, reason));
+            }
+            it.next();
+        }
+        Ok(mutations)
+    }
+
     fn recover_from_state(&self, address: &DB3Address) -> Result<Option<DatabaseStatePersistence>> {
         self.get_entry::<DatabaseStatePersistence>(STATE_CF, address.as_ref())
     }
@@ -348,17 +648,42 @@ impl DBStoreV2 {
     fn update_db_state_for_delete_docs(&self, db_addr: &str, col: &str, count: u64) {
         if let Some(mut write_guard) = self.db_state.get_mut(db_addr) {
             let database_state = write_guard.deref_mut();
-            database_state.total_doc_count = database_state.total_doc_count - count;
+            database_state.total_doc_count = database_state.total_doc_count.saturating_sub(count);
+            if let Some(collection_state) = database_state.collection_state.get_mut(col) {
+                collection_state.total_doc_count =
+                    collection_state.total_doc_count.saturating_sub(count);
+            }
+        }
+    }
+
+    fn update_db_state_for_drop_collection(&self, db_addr: &str, col: &str) {
+        if let Some(mut write_guard) = self.db_state.get_mut(db_addr) {
+            let database_state = write_guard.deref_mut();
+            if let Some(collection_state) = database_state.collection_state.remove(col) {
+                database_state.total_doc_count = database_state
+                    .total_doc_count
+                    .saturating_sub(collection_state.total_doc_count);
+            }
+        }
+    }
+
+    fn update_db_state_for_add_doc_count(&self, db_addr: &str, col: &str, count: u64) {
+        if let Some(mut write_guard) = self.db_state.get_mut(db_addr) {
+            let database_state = write_guard.deref_mut();
+            database_state.total_doc_count = database_state.total_doc_count + count;
             if let Some(collection_state) = database_state.collection_state.get_mut(col) {
-                collection_state.total_doc_count = collection_state.total_doc_count - count;
+                collection_state.total_doc_count = collection_state.total_doc_count + count;
             }
         }
     }
 
+    ///
+    /// allocate the ids of the docs to add and move the doc order of the database. the
+    /// doc counts are changed when the writes of the docs are committed
+    ///
     fn update_db_state_for_add_docs(
         &self,
         db_addr_hex: &str,
-        col: &str,
         doc_count: usize,
         doc_ids: Option<&Vec<i64>>,
     ) -> Result<Option<Vec<i64>>> {
@@ -386,11 +711,6 @@ impl DBStoreV2 {
                 }
                 database_state.doc_order = database_state.doc_order + doc_count as i64;
             }
-            database_state.total_doc_count = database_state.total_doc_count + doc_count as u64;
-            if let Some(collection_state) = database_state.collection_state.get_mut(col) {
-                collection_state.total_doc_count =
-                    collection_state.total_doc_count + doc_count as u64;
-            }
             Ok(Some(ids))
         } else {
             Ok(None)
@@ -549,28 +869,72 @@ impl DBStoreV2 {
 
     fn save_collection_internal(
         &self,
+        pending: &mut PendingWrites,
         sender: &DB3Address,
         db_addr: &DB3Address,
         name: &str,
         indexes: &Vec<Index>,
+        schema: Option<&CollectionSchema>,
+        ttl: u64,
+        keep_history: bool,
     ) -> Result<()> {
+        collection_key::check_collection_name(name)?;
+        Self::check_indexes(indexes)?;
+        if let Some(schema) = schema {
+            doc_schema::check_schema(schema)?;
+        }
+        if self.get_database(db_addr)?.is_none() {
+            return Err(DB3Error::ReadStoreError(
+                "fail to find database".to_string(),
+            ));
+        }
+        let col = Collection {
+            name: name.to_string(),
+            index_fields: indexes.to_vec(),
+            sender: sender.as_ref().to_vec(),
+            schema: schema.cloned(),
+            acl: None,
+            ttl,
+            keep_history,
+        };
+        let ck = collection_key::build_collection_key(db_addr, name)?;
+        let mut buf = BytesMut::with_capacity(1024);
+        col.encode(&mut buf)
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        pending.put(
+            &self.se,
+            self.config.collection_store_cf_name.as_str(),
+            ck.as_ref(),
+            buf.freeze().as_ref(),
+        )?;
+        pending
+            .collections
+            .push((db_addr.to_hex(), name.to_string()));
+        if self.config.enable_doc_store {
+            pending.undo_records.push(UndoRecord::AddCollection {
+                db_addr: db_addr.clone(),
+                col_name: name.to_string(),
+            });
+            self.doc_store
+                .add_index(db_addr, name, indexes)
+                .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        }
+        Ok(())
+    }
+
+    fn put_collection(&self, db_addr: &DB3Address, col: &Collection) -> Result<()> {
         let db = self.get_database(db_addr)?;
         if db.is_none() {
             return Err(DB3Error::ReadStoreError(
                 "fail to find database".to_string(),
             ));
         }
-        let ck = collection_key::build_collection_key(db_addr, name)?;
+        let ck = collection_key::build_collection_key(db_addr, col.name.as_str())?;
         let collection_store_cf_handle = self
             .se
             .cf_handle(self.config.collection_store_cf_name.as_str())
             .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
         let ck_ref: &[u8] = ck.as_ref();
-        let col = Collection {
-            name: name.to_string(),
-            index_fields: indexes.to_vec(),
-            sender: sender.as_ref().to_vec(),
-        };
         let mut buf = BytesMut::with_capacity(1024);
         col.encode(&mut buf)
             .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
@@ -580,12 +944,35 @@ impl DBStoreV2 {
         self.se
             .write(batch)
             .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
-        let db_addr_hex = db_addr.to_hex();
-        self.update_db_state_for_new_collection(db_addr_hex.as_str(), name);
-        if self.config.enable_doc_store {
-            self.doc_store
-                .add_index(db_addr, name, indexes)
-                .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        Ok(())
+    }
+
+    ///
+    /// check the paths of indexes are unique and every compound index has at least two
+    /// distinct fields
+    ///
+    fn check_indexes(indexes: &Vec<Index>) -> Result<()> {
+        let mut path_set: HashSet<&str> = HashSet::new();
+        for index in indexes {
+            if index.path.is_empty() || !path_set.insert(index.path.as_str()) {
+                return Err(DB3Error::InvalidKeyPathError(format!(
+                    "the index path {} is empty or duplicated",
+                    index.path
+                )));
+            }
+            if index.fields.is_empty() {
+                continue;
+            }
+            let field_set: HashSet<&str> = index.fields.iter().map(|f| f.path.as_str()).collect();
+            if index.fields.len() < 2
+                || field_set.len() != index.fields.len()
+                || field_set.contains("")
+            {
+                return Err(DB3Error::InvalidKeyPathError(format!(
+                    "the compound index {} requires at least two distinct fields",
+                    index.path
+                )));
+            }
         }
         Ok(())
     }
@@ -595,21 +982,58 @@ impl DBStoreV2 {
         sender: &DB3Address,
         db_addr: &DB3Address,
         collection: &CollectionMutation,
+        block: u64,
+        order: u32,
+        idx: u16,
+    ) -> Result<()> {
+        let mut pending = PendingWrites::default();
+        let result = self.create_collection_internal(
+            &mut pending,
+            sender,
+            db_addr,
+            collection,
+            block,
+            order,
+            idx,
+        );
+        self.commit_or_abort(pending, result)
+    }
+
+    fn create_collection_internal(
+        &self,
+        pending: &mut PendingWrites,
+        sender: &DB3Address,
+        db_addr: &DB3Address,
+        collection: &CollectionMutation,
         _block: u64,
         _order: u32,
         _idx: u16,
     ) -> Result<()> {
-        if self.is_db_collection_exist(db_addr, collection.collection_name.as_str())? {
+        let ck =
+            collection_key::build_collection_key(db_addr, collection.collection_name.as_str())?;
+        // the collection may be created by an earlier body of the mutation
+        if pending
+            .get(
+                &self.se,
+                self.config.collection_store_cf_name.as_str(),
+                ck.as_ref(),
+            )?
+            .is_some()
+        {
             return Err(DB3Error::CollectionAlreadyExist(
                 collection.collection_name.to_string(),
                 db_addr.to_hex(),
             ));
         }
         self.save_collection_internal(
+            pending,
             sender,
             db_addr,
             collection.collection_name.as_str(),
             &collection.index_fields,
+            collection.schema.as_ref(),
+            collection.ttl,
+            collection.keep_history,
         )
     }
 
@@ -662,6 +1086,22 @@ impl DBStoreV2 {
         }
     }
 
+    ///
+    /// the state root is the root of the state tree over the hashes of the documents. it is
+    /// updated with the mutations, so it is got without scanning the documents
+    ///
+    pub fn get_state_root(&self) -> Result<Vec<u8>> {
+        let cf_handle = self
+            .se
+            .cf_handle(STATE_TREE_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let value = self
+            .se
+            .get_cf(&cf_handle, StateNodeKey(0, 0).encode()?)
+            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+        Ok(value.unwrap_or_else(|| vec![0; merkle::HASH_LENGTH]))
+    }
+
     pub fn get_database_state(&self, db_addr: &DB3Address) -> Option<DatabaseStateProto> {
         let db_addr_hex = db_addr.to_hex();
         if let Some(guard) = self.db_state.get(db_addr_hex.as_str()) {
@@ -684,173 +1124,514 @@ impl DBStoreV2 {
         docs: &Vec<String>,
         doc_ids: &Vec<i64>,
     ) -> Result<()> {
-        if !self.is_db_collection_exist(db_addr, col_name)? {
-            return Err(DB3Error::CollectionNotFound(
-                col_name.to_string(),
-                db_addr.to_hex(),
-            ));
-        }
-        self.verify_docs_ownership(sender, db_addr, doc_ids)?;
-        if self.config.enable_doc_store {
-            //TODO add id-> owner mapping to control the permissions
-            self.doc_store.patch_docs(db_addr, col_name, docs, &doc_ids)
-        } else {
-            Ok(())
-        }
+        let mut pending = PendingWrites::default();
+        let result = self.update_docs_internal(
+            &mut pending,
+            db_addr,
+            sender,
+            col_name,
+            docs,
+            doc_ids,
+            &[],
+            false,
+        );
+        self.commit_or_abort(pending, result)
     }
 
-    pub fn query_docs(
+    ///
+    /// merge the docs into the stored ones or replace the stored ones with them
+    ///
+    fn update_docs_internal(
         &self,
+        pending: &mut PendingWrites,
         db_addr: &DB3Address,
+        sender: &DB3Address,
         col_name: &str,
-        query: &Query,
-    ) -> Result<(Vec<Document>, u64)> {
+        docs: &Vec<String>,
+        doc_ids: &Vec<i64>,
+        preconditions: &[Precondition],
+        replace: bool,
+    ) -> Result<()> {
         if !self.is_db_collection_exist(db_addr, col_name)? {
             return Err(DB3Error::CollectionNotFound(
                 col_name.to_string(),
                 db_addr.to_hex(),
             ));
         }
+        // the merge patch only has the changed fields
+        self.check_docs_schema(db_addr, col_name, docs, !replace)?;
+        self.check_preconditions(pending, db_addr, doc_ids, preconditions)?;
+        self.verify_docs_permission(pending, sender, db_addr, col_name, doc_ids)?;
+        let revisions = self.get_existing_doc_revisions(pending, db_addr, doc_ids)?;
+        let unique_values =
+            self.check_unique_keys(pending, db_addr, col_name, docs, doc_ids, !replace)?;
+        pending.undo_records.push(UndoRecord::UpdateDocs {
+            db_addr: db_addr.clone(),
+            col_name: col_name.to_string(),
+            docs: self.get_docs_for_undo(db_addr, col_name, doc_ids)?,
+        });
         if self.config.enable_doc_store {
-            let (result, count) = self.doc_store.execute_query(db_addr, col_name, query)?;
-            let mut documents = vec![];
-            for (id, doc) in result {
-                documents.push(Document { id, doc })
+            //TODO add id-> owner mapping to control the permissions
+            if replace {
+                self.doc_store
+                    .add_str_docs(db_addr, col_name, docs, doc_ids)?;
+            } else {
+                self.doc_store
+                    .patch_docs(db_addr, col_name, docs, &doc_ids)?;
             }
-            Ok((documents, count))
-        } else {
-            Ok((vec![], 0))
         }
+        // the owner of the doc is kept if it's changed by the writer of the collection
+        let new_revisions: Vec<(i64, DB3Address, u64)> = revisions
+            .iter()
+            .map(|(id, owner, revision)| (*id, owner.clone(), revision + 1))
+            .collect();
+        self.put_doc_revisions_and_unique_values(
+            pending,
+            db_addr,
+            col_name,
+            &new_revisions,
+            &unique_values,
+        )
     }
 
-    pub fn delete_docs(
+    ///
+    /// apply the field transforms to the docs. the doc store is only enabled on the indexer
+    /// which holds the docs, so the others only check the docs and bump their revisions.
+    /// the values are computed by the indexer when it applies the same mutation with the
+    /// server time kept in the mutation header
+    ///
+    fn transform_docs_internal(
         &self,
+        pending: &mut PendingWrites,
         db_addr: &DB3Address,
         sender: &DB3Address,
         col_name: &str,
         doc_ids: &Vec<i64>,
+        transforms: &[DocumentTransform],
+        preconditions: &[Precondition],
+        server_time: i64,
     ) -> Result<()> {
-        if !self.is_db_collection_exist(db_addr, col_name)? {
-            return Err(DB3Error::CollectionNotFound(
-                col_name.to_string(),
-                db_addr.to_hex(),
-            ));
+        let collection =
+            self.get_collection(db_addr, col_name)?
+                .ok_or(DB3Error::CollectionNotFound(
+                    col_name.to_string(),
+                    db_addr.to_hex(),
+                ))?;
+        // the unique values are kept by every node but the transformed value depends on the
+        // document which only the indexer holds
+        let unique_fields = Self::get_unique_fields(&collection.index_fields);
+        for field_transform in transforms.iter().flat_map(|t| t.field_transforms.iter()) {
+            let field = field_transform
+                .field_path
+                .split('.')
+                .next()
+                .unwrap_or_default();
+            if unique_fields.iter().any(|f| f.as_str() == field) {
+                return Err(DB3Error::InvalidMutationError(format!(
+                    "the unique field {field} can not be transformed"
+                )));
+            }
         }
-        self.verify_docs_ownership(sender, db_addr, doc_ids)?;
-        if self.config.enable_doc_store {
-            //TODO add id-> owner mapping to control the permissions
-            self.doc_store.delete_docs(db_addr, col_name, doc_ids)?;
+        let mut patches: Vec<String> = vec![];
+        for (id, transform) in doc_ids.iter().zip(transforms.iter()) {
+            // the patch on the empty doc is only used to check the schema because its fields
+            // and value types never depend on the stored doc
+            let doc = if self.config.enable_doc_store {
+                match self.doc_store.get_doc(db_addr, col_name, *id)? {
+                    Some(doc) => serde_json::from_str(doc.as_str())
+                        .map_err(|e| DB3Error::InvalidJson(format!("{e}")))?,
+                    None => serde_json::Value::Object(serde_json::Map::new()),
+                }
+            } else {
+                serde_json::Value::Object(serde_json::Map::new())
+            };
+            let patch =
+                doc_transform::transform_doc(&doc, &transform.field_transforms, server_time)?;
+            patches.push(patch.to_string());
         }
-        let db_addr_hex = db_addr.to_hex();
-        self.update_db_state_for_delete_docs(db_addr_hex.as_str(), col_name, doc_ids.len() as u64);
-        self.delete_doc_ids_from_owner_store(db_addr, doc_ids)
+        self.update_docs_internal(
+            pending,
+            db_addr,
+            sender,
+            col_name,
+            &patches,
+            doc_ids,
+            preconditions,
+            false,
+        )
     }
 
-    pub fn add_docs(
+    ///
+    /// replace the docs whose keys exist and add the others with their keys. the inserted
+    /// doc ids are returned
+    ///
+    fn upsert_docs_internal(
         &self,
+        pending: &mut PendingWrites,
         db_addr: &DB3Address,
         sender: &DB3Address,
         col_name: &str,
         docs: &Vec<String>,
+        keys: &[String],
+        preconditions: &[Precondition],
         given_doc_ids: Option<&Vec<i64>>,
     ) -> Result<Vec<i64>> {
+        if keys.len() != docs.len() || keys.iter().any(|key| key.is_empty()) {
+            return Err(DB3Error::InvalidMutationError(format!(
+                "the upsert requires a key for every document but got {} keys for {} documents",
+                keys.len(),
+                docs.len()
+            )));
+        }
+        if !preconditions.is_empty() && preconditions.len() != docs.len() {
+            return Err(DB3Error::InvalidMutationError(format!(
+                "preconditions size {} not equal to documents size {}",
+                preconditions.len(),
+                docs.len()
+            )));
+        }
+        if keys.iter().collect::<HashSet<_>>().len() != keys.len() {
+            return Err(DB3Error::InvalidMutationError(
+                "the keys of the upsert are duplicated".to_string(),
+            ));
+        }
         if !self.is_db_collection_exist(db_addr, col_name)? {
             return Err(DB3Error::CollectionNotFound(
                 col_name.to_string(),
                 db_addr.to_hex(),
             ));
         }
-        let db_addr_hex = db_addr.to_hex();
-        let doc_ids = self.update_db_state_for_add_docs(
-            db_addr_hex.as_str(),
-            col_name,
-            docs.len(),
-            given_doc_ids,
-        )?;
-        if let Some(all_doc_ids) = doc_ids {
-            self.create_doc_ownership(sender, db_addr, &all_doc_ids)?;
-            // add db+id-> owner mapping to control the permissions
-            if self.config.enable_doc_store {
-                self.doc_store
-                    .add_str_docs(db_addr, col_name, docs, &all_doc_ids)?;
+        let (mut replaced_docs, mut replaced_ids) = (vec![], vec![]);
+        let (mut new_docs, mut new_keys) = (vec![], vec![]);
+        for (i, (doc, key)) in docs.iter().zip(keys.iter()).enumerate() {
+            let id = self.get_doc_id_by_key_internal(pending, db_addr, col_name, key.as_str())?;
+            if let Some(precondition) = preconditions.get(i) {
+                let revision = match id {
+                    Some(id) => self.get_doc_revisions(pending, db_addr, &[id])?[0],
+                    None => None,
+                };
+                Self::check_precondition(key.as_str(), precondition, revision)?;
+            }
+            match id {
+                Some(id) => {
+                    replaced_docs.push(doc.to_string());
+                    replaced_ids.push(id);
+                }
+                None => {
+                    new_docs.push(doc.to_string());
+                    new_keys.push(key.to_string());
+                }
             }
-            return Ok(all_doc_ids);
         }
-        Ok(vec![])
+        if !replaced_ids.is_empty() {
+            self.update_docs_internal(
+                pending,
+                db_addr,
+                sender,
+                col_name,
+                &replaced_docs,
+                &replaced_ids,
+                &[],
+                true,
+            )?;
+        }
+        if new_docs.is_empty() {
+            return Ok(vec![]);
+        }
+        self.add_docs_internal(
+            pending,
+            db_addr,
+            sender,
+            col_name,
+            &new_docs,
+            given_doc_ids,
+            &new_keys,
+        )
     }
 
-    /// verify if the collection exists in the given db
-    pub fn is_db_collection_exist(&self, db_addr: &DB3Address, col_name: &str) -> Result<bool> {
-        let ck = collection_key::build_collection_key(db_addr, col_name)?;
-        let collection_store_cf_handle = self
-            .se
-            .cf_handle(self.config.collection_store_cf_name.as_str())
-            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
-        let ck_ref: &[u8] = ck.as_ref();
-        let value = self
-            .se
-            .get_cf(&collection_store_cf_handle, ck_ref)
-            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
-        Ok(value.is_some())
+    ///
+    /// check the keys of the docs to add. the empty key means the doc has no key
+    ///
+    fn check_doc_keys(
+        &self,
+        pending: &PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        keys: &[String],
+    ) -> Result<()> {
+        let mut key_set: HashSet<&str> = HashSet::new();
+        for key in keys.iter().filter(|key| !key.is_empty()) {
+            if !key_set.insert(key.as_str()) {
+                return Err(DB3Error::InvalidMutationError(format!(
+                    "the document key {key} is duplicated"
+                )));
+            }
+            if self
+                .get_doc_id_by_key_internal(pending, db_addr, col_name, key.as_str())?
+                .is_some()
+            {
+                return Err(DB3Error::DocumentKeyAlreadyExist(
+                    key.to_string(),
+                    col_name.to_string(),
+                ));
+            }
+        }
+        Ok(())
     }
 
-    /// clean doc ids that are not in the collection
-    pub fn delete_doc_ids_from_owner_store(
+    pub fn get_doc_id_by_key(
         &self,
         db_addr: &DB3Address,
-        doc_ids: &Vec<i64>,
-    ) -> Result<()> {
-        let doc_owner_store_cf_handle = self
-            .se
-            .cf_handle(self.config.doc_owner_store_cf_name.as_str())
-            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
-        let mut batch = WriteBatch::default();
+        col_name: &str,
+        key: &str,
+    ) -> Result<Option<i64>> {
+        self.get_doc_id_by_key_internal(&PendingWrites::default(), db_addr, col_name, key)
+    }
+
+    fn get_doc_id_by_key_internal(
+        &self,
+        pending: &PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        key: &str,
+    ) -> Result<Option<i64>> {
+        let name_key = DbDocNameKeyV2(db_addr, col_name, key).encode()?;
+        match pending.get(&self.se, DOC_NAME_CF, name_key.as_ref())? {
+            Some(v) => Ok(Some(i64::from_be_bytes(
+                <[u8; 8]>::try_from(v.as_slice())
+                    .map_err(|e| DB3Error::ReadStoreError(format!("invalid doc id {e}")))?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// get the keys of the docs and the docs without key are skipped
+    fn get_doc_keys(
+        &self,
+        pending: &PendingWrites,
+        db_addr: &DB3Address,
+        doc_ids: &[i64],
+    ) -> Result<Vec<(i64, String)>> {
+        let mut keys = vec![];
         for id in doc_ids {
             let db_doc_key = DbDocKeyV2(db_addr, *id).encode()?;
-            batch.delete_cf(&doc_owner_store_cf_handle, &db_doc_key);
+            if let Some(v) = pending.get(&self.se, DOC_NAME_CF, db_doc_key.as_ref())? {
+                let key =
+                    String::from_utf8(v).map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+                keys.push((*id, key));
+            }
+        }
+        Ok(keys)
+    }
+
+    fn put_doc_keys(
+        &self,
+        pending: &mut PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        keys: &[(i64, String)],
+    ) -> Result<()> {
+        for (id, key) in keys {
+            let name_key = DbDocNameKeyV2(db_addr, col_name, key.as_str()).encode()?;
+            let db_doc_key = DbDocKeyV2(db_addr, *id).encode()?;
+            pending.put(&self.se, DOC_NAME_CF, name_key.as_ref(), &id.to_be_bytes())?;
+            pending.put(&self.se, DOC_NAME_CF, db_doc_key.as_ref(), key.as_bytes())?;
         }
-        self.se
-            .write(batch)
-            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
         Ok(())
     }
 
-    /// verify the ownership of the doc ids
-    pub fn verify_docs_ownership(
+    fn delete_doc_keys(
         &self,
-        sender: &DB3Address,
+        pending: &mut PendingWrites,
         db_addr: &DB3Address,
-        doc_ids: &Vec<i64>,
+        col_name: &str,
+        keys: &[(i64, String)],
     ) -> Result<()> {
-        let doc_owner_store_cf_handle = self
-            .se
-            .cf_handle(self.config.doc_owner_store_cf_name.as_str())
-            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        for (id, key) in keys {
+            let name_key = DbDocNameKeyV2(db_addr, col_name, key.as_str()).encode()?;
+            let db_doc_key = DbDocKeyV2(db_addr, *id).encode()?;
+            pending.delete(&self.se, DOC_NAME_CF, name_key.as_ref())?;
+            pending.delete(&self.se, DOC_NAME_CF, db_doc_key.as_ref())?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// check the preconditions of the docs. the preconditions are either empty or one
+    /// for every doc id
+    ///
+    fn check_preconditions(
+        &self,
+        pending: &PendingWrites,
+        db_addr: &DB3Address,
+        doc_ids: &[i64],
+        preconditions: &[Precondition],
+    ) -> Result<()> {
+        if preconditions.is_empty() {
+            return Ok(());
+        }
+        if preconditions.len() != doc_ids.len() {
+            return Err(DB3Error::InvalidMutationError(format!(
+                "preconditions size {} not equal to doc ids size {}",
+                preconditions.len(),
+                doc_ids.len()
+            )));
+        }
+        let revisions = self.get_doc_revisions(pending, db_addr, doc_ids)?;
+        for ((id, precondition), revision) in doc_ids
+            .iter()
+            .zip(preconditions.iter())
+            .zip(revisions.into_iter())
+        {
+            Self::check_precondition(id.to_string().as_str(), precondition, revision)?;
+        }
+        Ok(())
+    }
+
+    /// check the precondition against the revision of the doc and None means the doc does not exist
+    fn check_precondition(
+        doc: &str,
+        precondition: &Precondition,
+        revision: Option<u64>,
+    ) -> Result<()> {
+        let failure = match (&precondition.condition_type, revision) {
+            (Some(ConditionType::Exists(true)), None)
+            | (Some(ConditionType::Revision(_)), None) => Some(format!("doc {doc} does not exist")),
+            (Some(ConditionType::Exists(false)), Some(_)) => Some(format!("doc {doc} exists")),
+            (Some(ConditionType::Revision(expected)), Some(current)) if *expected != current => {
+                Some(format!(
+                    "the revision of doc {doc} is {current} but {expected} is expected"
+                ))
+            }
+            _ => None,
+        };
+        match failure {
+            Some(msg) => Err(DB3Error::PreconditionFailed(msg)),
+            None => Ok(()),
+        }
+    }
+
+    ///
+    /// the value of the doc owner store is the owner address followed by the big endian
+    /// revision of the doc. the value without the revision is written by the old version
+    /// and its revision is 1
+    ///
+    fn encode_doc_owner_value(owner: &DB3Address, revision: u64) -> Vec<u8> {
+        let mut value = owner.as_ref().to_vec();
+        value.extend_from_slice(&revision.to_be_bytes());
+        value
+    }
+
+    fn decode_doc_owner_value(value: &[u8]) -> Result<(&[u8], u64)> {
+        if value.len() == DB3_ADDRESS_LENGTH {
+            return Ok((value, 1));
+        }
+        if value.len() != DB3_ADDRESS_LENGTH + 8 {
+            return Err(DB3Error::ReadStoreError(format!(
+                "invalid doc owner value with length {}",
+                value.len()
+            )));
+        }
+        let mut revision_bytes = [0u8; 8];
+        revision_bytes.copy_from_slice(&value[DB3_ADDRESS_LENGTH..]);
+        Ok((
+            &value[..DB3_ADDRESS_LENGTH],
+            u64::from_be_bytes(revision_bytes),
+        ))
+    }
+
+    /// get the owners and revisions of the docs and None for the doc which does not exist
+    fn get_doc_owners(
+        &self,
+        pending: &PendingWrites,
+        db_addr: &DB3Address,
+        doc_ids: &[i64],
+    ) -> Result<Vec<Option<(DB3Address, u64)>>> {
+        let mut owners = vec![];
         for id in doc_ids {
-            let db_doc_key = DbDocKeyV2(db_addr, *id).encode().unwrap();
-            let value = self
-                .se
-                .get_cf(&doc_owner_store_cf_handle, db_doc_key)
-                .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
-            if let Some(owner) = value {
-                if owner != sender.as_ref() {
-                    return Err(DB3Error::OwnerVerifyFailed(format!(
-                        "doc owner is not the sender"
-                    )));
+            let db_doc_key = DbDocKeyV2(db_addr, *id).encode()?;
+            let value = pending.get(
+                &self.se,
+                self.config.doc_owner_store_cf_name.as_str(),
+                db_doc_key.as_ref(),
+            )?;
+            match value {
+                Some(v) => {
+                    let (owner, revision) = Self::decode_doc_owner_value(v.as_ref())?;
+                    let owner = DB3Address::try_from(owner)
+                        .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+                    owners.push(Some((owner, revision)));
                 }
-            } else {
-                return Err(DB3Error::OwnerVerifyFailed(format!("doc id is not found")));
+                None => owners.push(None),
             }
         }
+        Ok(owners)
+    }
+
+    /// get the revisions of the docs and None for the doc which does not exist
+    fn get_doc_revisions(
+        &self,
+        pending: &PendingWrites,
+        db_addr: &DB3Address,
+        doc_ids: &[i64],
+    ) -> Result<Vec<Option<u64>>> {
+        Ok(self
+            .get_doc_owners(pending, db_addr, doc_ids)?
+            .into_iter()
+            .map(|owner| owner.map(|(_, revision)| revision))
+            .collect())
+    }
+
+    fn get_existing_doc_revisions(
+        &self,
+        pending: &PendingWrites,
+        db_addr: &DB3Address,
+        doc_ids: &[i64],
+    ) -> Result<Vec<(i64, DB3Address, u64)>> {
+        let owners = self.get_doc_owners(pending, db_addr, doc_ids)?;
+        Ok(doc_ids
+            .iter()
+            .zip(owners.into_iter())
+            .filter_map(|(id, owner)| owner.map(|(owner, revision)| (*id, owner, revision)))
+            .collect())
+    }
+
+    ///
+    /// write the owners with revisions, the collection doc keys and the unique values of
+    /// the docs
+    ///
+    fn put_doc_revisions_and_unique_values(
+        &self,
+        pending: &mut PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        revisions: &[(i64, DB3Address, u64)],
+        unique_values: &[(i64, String, Option<String>)],
+    ) -> Result<()> {
+        self.put_doc_unique_values(pending, db_addr, col_name, unique_values)?;
+        for (id, owner, revision) in revisions {
+            pending.put(
+                &self.se,
+                self.config.doc_owner_store_cf_name.as_str(),
+                DbDocKeyV2(db_addr, *id).encode()?.as_ref(),
+                Self::encode_doc_owner_value(owner, *revision).as_ref(),
+            )?;
+            pending.put(
+                &self.se,
+                COLLECTION_DOC_CF,
+                CollectionDocKey(db_addr, col_name, *id).encode()?.as_ref(),
+                b"",
+            )?;
+        }
         Ok(())
     }
 
-    pub fn get_doc(
+    pub fn query_docs(
         &self,
         db_addr: &DB3Address,
         col_name: &str,
-        doc_id: i64,
-    ) -> Result<Option<Document>> {
+        query: &Query,
+    ) -> Result<(Vec<Document>, u64)> {
         if !self.is_db_collection_exist(db_addr, col_name)? {
             return Err(DB3Error::CollectionNotFound(
                 col_name.to_string(),
@@ -858,539 +1639,3139 @@ impl DBStoreV2 {
             ));
         }
         if self.config.enable_doc_store {
-            let doc = self.doc_store.get_doc(db_addr, col_name, doc_id)?;
-            if let Some(d) = doc {
-                Ok(Some(Document { id: doc_id, doc: d }))
-            } else {
-                Ok(None)
+            let (result, count) = self.doc_store.execute_query(db_addr, col_name, query)?;
+            let doc_ids: Vec<i64> = result.iter().map(|(id, _)| *id).collect();
+            let pending = PendingWrites::default();
+            let revisions = self.get_doc_revisions(&pending, db_addr, &doc_ids)?;
+            let keys: HashMap<i64, String> = self
+                .get_doc_keys(&pending, db_addr, &doc_ids)?
+                .into_iter()
+                .collect();
+            let mut documents = vec![];
+            for ((id, doc), revision) in result.into_iter().zip(revisions.into_iter()) {
+                documents.push(Document {
+                    id,
+                    doc,
+                    revision: revision.unwrap_or(0),
+                    key: keys.get(&id).cloned().unwrap_or_default(),
+                })
             }
+            Ok((documents, count))
         } else {
-            Ok(None)
+            Ok((vec![], 0))
         }
     }
 
-    pub fn get_doc_key_from_doc_id(&self, doc_id: i64) -> Result<Vec<u8>> {
-        let doc_owner_store_cf_handle = self
-            .se
-            .cf_handle(self.config.doc_owner_store_cf_name.as_str())
-            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
-        let value = self
-            .se
-            .get_cf(&doc_owner_store_cf_handle, doc_id.to_be_bytes().as_ref())
-            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
-        match value {
-            Some(v) => Ok(v),
-            None => {
-                return Err(DB3Error::ReadStoreError(format!(
-                    "doc owner key not found for doc id {}",
-                    doc_id
-                )))
-            }
-        }
-    }
-    /// create db+id-> owner mapping to control the permissions
-    pub fn create_doc_ownership(
+    ///
+    /// run the structured query and return the documents, the count and the opaque cursor
+    /// for the next page. the cursor is empty if the query has no order or there is no more page
+    ///
+    pub fn query_docs_by_structured_query(
         &self,
-        sender: &DB3Address,
         db_addr: &DB3Address,
-        doc_ids: &Vec<i64>,
-    ) -> Result<()> {
-        let doc_owner_store_cf_handle = self
-            .se
-            .cf_handle(self.config.doc_owner_store_cf_name.as_str())
-            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
-        let mut batch = WriteBatch::default();
-        for id in doc_ids {
-            let db_doc_key = DbDocKeyV2(db_addr, *id);
-            let encoded_db_doc_key = db_doc_key.encode()?;
-            batch.put_cf(
-                &doc_owner_store_cf_handle,
-                &encoded_db_doc_key,
-                sender.as_ref(),
-            );
+        query: &StructuredQuery,
+        cursor: &str,
+    ) -> Result<(Vec<Document>, u64, String)> {
+        let col_name = query.collection_name.as_str();
+        let collection =
+            self.get_collection(db_addr, col_name)?
+                .ok_or(DB3Error::CollectionNotFound(
+                    col_name.to_string(),
+                    db_addr.to_hex(),
+                ))?;
+        let page_cursor = if cursor.is_empty() {
+            None
+        } else {
+            Some(query_plan::decode_page_cursor(cursor)?)
+        };
+        // the order fields are kept in the projected documents, so the cursor of the next
+        // page can be built from the returned documents
+        let mut projected_query = query.clone();
+        if let Some(projection) = projected_query.select.as_mut() {
+            if !projection.fields.is_empty() {
+                for order in query.order_by.iter() {
+                    if !projection.fields.contains(&order.field) {
+                        projection.fields.push(order.field.to_string());
+                    }
+                }
+            }
         }
-        self.se
-            .write(batch)
-            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
-        Ok(())
+        let page_limit = match &query.limit {
+            Some(limit) if !query.order_by.is_empty() && limit.limit > 0 => limit.limit as usize,
+            _ => {
+                let plan = QueryPlanner::new(&collection)
+                    .build_with_cursor(&projected_query, page_cursor.as_ref())?;
+                debug!(
+                    "compile the structured query to {} with parameters len {}",
+                    plan.query_str.as_str(),
+                    plan.parameters.len()
+                );
+                let (documents, count) = self.query_docs(db_addr, col_name, &plan)?;
+                return Ok((documents, count, "".to_string()));
+            }
+        };
+        let documents = self.query_page_docs(
+            db_addr,
+            &collection,
+            &projected_query,
+            page_cursor.as_ref(),
+            page_limit,
+        )?;
+        let next_cursor = if documents.len() == page_limit {
+            Self::build_next_page_cursor(&query.order_by, &documents)?
+        } else {
+            "".to_string()
+        };
+        let count = documents.len() as u64;
+        Ok((documents, count, next_cursor))
     }
 
-    pub fn create_event_database(
+    ///
+    /// query a page of the documents in the order of the order fields and then the doc id.
+    /// ejdb can neither sort nor filter on the doc id, so the documents with the same order
+    /// values as the page cursor or the last document of the page are queried as a group
+    /// and sorted by their ids
+    ///
+    fn query_page_docs(
         &self,
-        sender: &DB3Address,
-        mutation: &EventDatabaseMutation,
-        nonce: u64,
-        network_id: u64,
-        block: u64,
-        order: u32,
-    ) -> Result<DbId> {
-        let db_addr = DbId::from((sender, nonce, network_id));
-        let db_store_cf_handle = self
-            .se
-            .cf_handle(self.config.db_store_cf_name.as_str())
-            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
-        let db_owner_store_cf_handle = self
-            .se
-            .cf_handle(self.config.db_owner_store_cf_name.as_str())
-            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
-        let db_owner = DbOwnerKey(sender, block, order);
-        let db_owner_encoded_key = db_owner.encode()?;
-        //TODO check the name
-        let database = EventDatabase {
-            address: db_addr.as_ref().to_vec(),
-            sender: sender.as_ref().to_vec(),
-            desc: mutation.desc.to_string(),
-            contract_address: mutation.contract_address.to_string(),
-            ttl: mutation.ttl,
-            events_json_abi: mutation.events_json_abi.to_string(),
-            evm_node_url: mutation.evm_node_url.to_string(),
-            start_block: mutation.start_block,
-        };
-        let database_msg = DatabaseMessage {
-            database: Some(database_message::Database::EventDb(database)),
+        db_addr: &DB3Address,
+        collection: &Collection,
+        query: &StructuredQuery,
+        page_cursor: Option<&PageCursor>,
+        page_limit: usize,
+    ) -> Result<Vec<Document>> {
+        let mut documents = match page_cursor {
+            Some(cursor) => self
+                .query_order_group(db_addr, collection, query, &cursor.values)?
+                .into_iter()
+                .filter(|doc| doc.id > cursor.id)
+                .take(page_limit)
+                .collect(),
+            None => vec![],
         };
-        let mut buf = BytesMut::with_capacity(1024);
-        database_msg
-            .encode(&mut buf)
-            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
-        let buf = buf.freeze();
-        let mut batch = WriteBatch::default();
-        batch.put_cf(&db_store_cf_handle, db_addr.as_ref(), buf.as_ref());
-        batch.put_cf(
-            &db_owner_store_cf_handle,
-            &db_owner_encoded_key,
-            db_addr.as_ref(),
+        if documents.len() == page_limit {
+            return Ok(documents);
+        }
+        let rest_limit = page_limit - documents.len();
+        let mut rest_query = query.clone();
+        rest_query.limit = Some(Limit {
+            limit: rest_limit as i32,
+        });
+        let plan = QueryPlanner::new(collection).build_with_cursor(&rest_query, page_cursor)?;
+        debug!(
+            "compile the structured query to {} with parameters len {}",
+            plan.query_str.as_str(),
+            plan.parameters.len()
         );
-        self.se
-            .write(batch)
-            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
-
-        let db_addr_hex = db_addr.to_hex();
-        self.update_db_state_for_add_db(db_addr_hex.as_str());
-        for (idx, cm) in mutation.tables.iter().enumerate() {
-            self.create_collection(sender, db_addr.address(), cm, block, order, idx as u16)?;
+        let (mut rest, _) = self.query_docs(db_addr, collection.name.as_str(), &plan)?;
+        let mut values = rest
+            .iter()
+            .map(|doc| Self::get_doc_order_values(doc, &query.order_by))
+            .collect::<Result<Vec<_>>>()?;
+        // the limit cuts the documents with the last values in any order, so they are
+        // replaced by the ones with the smallest ids
+        if let Some(Some(last_values)) = values.last().cloned() {
+            if rest.len() == rest_limit {
+                let group_start = values
+                    .iter()
+                    .rposition(|v| v.as_ref() != Some(&last_values))
+                    .map(|i| i + 1)
+                    .unwrap_or(0);
+                rest.truncate(group_start);
+                values.truncate(group_start);
+                let group = self.query_order_group(db_addr, collection, query, &last_values)?;
+                for doc in group.into_iter().take(rest_limit - group_start) {
+                    rest.push(doc);
+                    values.push(Some(last_values.clone()));
+                }
+            }
         }
-        if self.config.enable_doc_store {
-            self.doc_store
-                .create_database(db_addr.address())
-                .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        let mut group_start = 0;
+        for i in 1..=rest.len() {
+            if i == rest.len() || values[i] != values[group_start] {
+                rest[group_start..i].sort_by_key(|doc| doc.id);
+                group_start = i;
+            }
         }
-        Ok(db_addr)
+        documents.extend(rest);
+        Ok(documents)
     }
 
-    pub fn delete_event_db(&self, sender: &DB3Address, db_addr: &DB3Address) -> Result<()> {
-        match self.get_event_db(db_addr)? {
-            Some(database) => {
-                let sender_ref: &[u8] = database.sender.as_ref();
-                if sender_ref != sender.as_ref() {
-                    return Err(DB3Error::DatabasePermissionDenied());
-                }
-                self.delete_event_db_internal(sender, db_addr)?;
-            }
-            None => return Err(DB3Error::DatabaseNotFound(db_addr.to_hex())),
-        }
-        Ok(())
+    fn query_order_group(
+        &self,
+        db_addr: &DB3Address,
+        collection: &Collection,
+        query: &StructuredQuery,
+        values: &[Value],
+    ) -> Result<Vec<Document>> {
+        let plan = QueryPlanner::new(collection).build_order_group(query, values)?;
+        let (mut documents, _) = self.query_docs(db_addr, collection.name.as_str(), &plan)?;
+        documents.sort_by_key(|doc| doc.id);
+        Ok(documents)
     }
 
-    // make sure the db is event db
-    fn delete_event_db_internal(&self, owner: &DB3Address, db_addr: &DB3Address) -> Result<()> {
-        let cf_handle = self
-            .se
-            .cf_handle(self.config.db_owner_store_cf_name.as_str())
-            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
-        let mut it: DBRawIterator = self.se.prefix_iterator_cf(&cf_handle, owner).into();
-        let mut batch = WriteBatch::default();
-        while it.valid() {
-            if let Some(k) = it.key() {
-                if &k[0..owner.as_ref().len()] != owner.as_ref() {
-                    break;
-                }
-            } else {
-                break;
-            }
-            if let Some(v) = it.value() {
-                let addr = DB3Address::try_from(v)
-                    .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
-                if addr.as_ref() == db_addr.as_ref() {
-                    if let Some(key_ref) = it.key() {
-                        batch.delete_cf(&cf_handle, key_ref);
-                    }
-                    break;
-                }
-            }
-            it.next();
+    pub fn aggregate_docs(
+        &self,
+        db_addr: &DB3Address,
+        query: &StructuredQuery,
+        aggregations: &[Aggregation],
+        group_by: &str,
+    ) -> Result<Vec<AggregationResult>> {
+        let col_name = query.collection_name.as_str();
+        let collection =
+            self.get_collection(db_addr, col_name)?
+                .ok_or(DB3Error::CollectionNotFound(
+                    col_name.to_string(),
+                    db_addr.to_hex(),
+                ))?;
+        if !self.config.enable_doc_store {
+            return Err(DB3Error::QueryDocumentError(
+                "the aggregation can not be run without the doc store".to_string(),
+            ));
         }
-        let db_store_cf_handle = self
-            .se
-            .cf_handle(self.config.db_store_cf_name.as_str())
-            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
-        batch.delete_cf(&db_store_cf_handle, db_addr);
-        self.se
-            .write(batch)
-            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
-        Ok(())
+        let count_only = query.limit.is_none()
+            && group_by.is_empty()
+            && aggregations
+                .iter()
+                .all(|aggregation| aggregation.op() == AggregationOp::Count);
+        if count_only {
+            // the count is pushed down to ejdb without loading the documents
+            let plan = QueryPlanner::new(&collection).build_count(query)?;
+            let (_, count) = self.doc_store.execute_query(db_addr, col_name, &plan)?;
+            return Ok(doc_aggregator::count_results(aggregations, count as i64));
+        }
+        // the matched documents are folded in memory, so the query is capped to one more
+        // document than the scan max limit to detect the overflow
+        let max_docs = self.config.scan_max_limit;
+        let mut capped_query = query.clone();
+        capped_query.limit = match &query.limit {
+            Some(limit) if limit.limit <= max_docs as i32 => Some(limit.clone()),
+            _ => Some(Limit {
+                limit: max_docs as i32 + 1,
+            }),
+        };
+        let plan = QueryPlanner::new(&collection).build_aggregation(
+            &capped_query,
+            aggregations,
+            group_by,
+        )?;
+        debug!(
+            "compile the aggregation query to {} with parameters len {}",
+            plan.query_str.as_str(),
+            plan.parameters.len()
+        );
+        self.doc_store.execute_aggregation(
+            db_addr,
+            col_name,
+            &plan,
+            aggregations,
+            group_by,
+            max_docs,
+        )
     }
 
-    pub fn create_predefined_doc_database(
+    fn get_doc_order_values(doc: &Document, order_by: &[Order]) -> Result<Option<Vec<Value>>> {
+        let doc: serde_json::Value = serde_json::from_str(doc.doc.as_str())
+            .map_err(|e| DB3Error::InvalidJson(format!("{e}")))?;
+        Ok(query_plan::order_values_from_json(&doc, order_by))
+    }
+
+    ///
+    /// build the cursor of the next page from the order values and the id of the last document
+    ///
+    fn build_next_page_cursor(order_by: &[Order], documents: &[Document]) -> Result<String> {
+        match documents.last() {
+            Some(doc) => match Self::get_doc_order_values(doc, order_by)? {
+                Some(values) => Ok(query_plan::encode_page_cursor(&PageCursor {
+                    values,
+                    id: doc.id,
+                })),
+                None => Ok("".to_string()),
+            },
+            None => Ok("".to_string()),
+        }
+    }
+
+    pub fn delete_docs(
         &self,
+        db_addr: &DB3Address,
         sender: &DB3Address,
-        db_addr: &DbId,
-        desc: &str,
-        block: u64,
-        order: u32,
+        col_name: &str,
+        doc_ids: &Vec<i64>,
     ) -> Result<()> {
-        if let Ok(Some(_)) = self.get_database(db_addr.address()) {
-            return Err(DB3Error::DatabaseAlreadyExist(db_addr.to_hex()));
+        let mut pending = PendingWrites::default();
+        let result =
+            self.delete_docs_internal(&mut pending, db_addr, sender, col_name, doc_ids, &[]);
+        self.commit_or_abort(pending, result)
+    }
+
+    fn delete_docs_internal(
+        &self,
+        pending: &mut PendingWrites,
+        db_addr: &DB3Address,
+        sender: &DB3Address,
+        col_name: &str,
+        doc_ids: &Vec<i64>,
+        preconditions: &[Precondition],
+    ) -> Result<()> {
+        if !self.is_db_collection_exist(db_addr, col_name)? {
+            return Err(DB3Error::CollectionNotFound(
+                col_name.to_string(),
+                db_addr.to_hex(),
+            ));
         }
-        let db_store_cf_handle = self
-            .se
-            .cf_handle(self.config.db_store_cf_name.as_str())
-            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
-        let db_owner_store_cf_handle = self
-            .se
-            .cf_handle(self.config.db_owner_store_cf_name.as_str())
-            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
-        let db_owner = DbOwnerKey(sender, block, order);
-        let db_owner_encoded_key = db_owner.encode()?;
-        let database = DocumentDatabase {
-            address: db_addr.as_ref().to_vec(),
-            sender: sender.as_ref().to_vec(),
-            desc: desc.to_string(),
-        };
-        let database_msg = DatabaseMessage {
-            database: Some(database_message::Database::DocDb(database)),
-        };
-        let mut buf = BytesMut::with_capacity(1024);
-        database_msg
-            .encode(&mut buf)
-            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
-        let buf = buf.freeze();
-        let mut batch = WriteBatch::default();
-        batch.put_cf(&db_store_cf_handle, db_addr.as_ref(), buf.as_ref());
-        batch.put_cf(
-            &db_owner_store_cf_handle,
-            &db_owner_encoded_key,
-            db_addr.as_ref(),
-        );
-        self.se
-            .write(batch)
-            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
-        let db_addr_hex = db_addr.to_hex();
-        self.update_db_state_for_add_db(db_addr_hex.as_str());
+        self.check_preconditions(pending, db_addr, doc_ids, preconditions)?;
+        self.verify_docs_permission(pending, sender, db_addr, col_name, doc_ids)?;
+        self.remove_docs_internal(pending, db_addr, col_name, doc_ids)
+    }
+
+    ///
+    /// remove the documents from the doc store, the owner store, the key store and the
+    /// expiry store without checking the permissions. only the existing documents are counted
+    ///
+    fn remove_docs_internal(
+        &self,
+        pending: &mut PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_ids: &Vec<i64>,
+    ) -> Result<()> {
+        let revisions = self.get_existing_doc_revisions(pending, db_addr, doc_ids)?;
+        let keys = self.get_doc_keys(pending, db_addr, doc_ids)?;
+        pending.undo_records.push(UndoRecord::DeleteDocs {
+            db_addr: db_addr.clone(),
+            col_name: col_name.to_string(),
+            docs: self.get_docs_for_undo(db_addr, col_name, doc_ids)?,
+        });
         if self.config.enable_doc_store {
-            self.doc_store
-                .create_database(db_addr.address())
-                .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+            //TODO add id-> owner mapping to control the permissions
+            self.doc_store.delete_docs(db_addr, col_name, doc_ids)?;
         }
+        self.delete_doc_ids_from_owner_store(pending, db_addr, col_name, doc_ids)?;
+        self.delete_doc_keys(pending, db_addr, col_name, &keys)?;
+        self.replace_doc_unique_values(pending, db_addr, col_name, doc_ids, &[])?;
+        self.delete_doc_expiry(pending, db_addr, doc_ids)?;
+        pending.add_doc_count(db_addr, col_name, -(revisions.len() as i64));
         Ok(())
     }
 
-    pub fn create_doc_database(
+    pub fn add_docs(
         &self,
+        db_addr: &DB3Address,
         sender: &DB3Address,
-        mutation: &DocumentDatabaseMutation,
-        nonce: u64,
-        network_id: u64,
-        block: u64,
-        order: u32,
-    ) -> Result<DbId> {
-        let db_addr = DbId::from((sender, nonce, network_id));
-        self.create_predefined_doc_database(
-            sender,
-            &db_addr,
-            mutation.db_desc.as_str(),
-            block,
-            order,
-        )?;
-        Ok(db_addr)
+        col_name: &str,
+        docs: &Vec<String>,
+        given_doc_ids: Option<&Vec<i64>>,
+    ) -> Result<Vec<i64>> {
+        let mut pending = PendingWrites::default();
+        let result = self
+            .add_docs_internal(
+                &mut pending,
+                db_addr,
+                sender,
+                col_name,
+                docs,
+                given_doc_ids,
+                &[],
+            )
+            .and_then(|doc_ids| {
+                // the documents added without a mutation expire from the local time
+                let ttl = self.get_doc_ttl(db_addr, col_name)?;
+                if ttl > 0 && !doc_ids.is_empty() {
+                    let expire_at = times::get_current_time_in_millis() + ttl * 1000;
+                    self.put_doc_expiry(&mut pending, db_addr, col_name, &doc_ids, expire_at)?;
+                }
+                Ok(doc_ids)
+            });
+        self.commit_or_abort(pending, result)
     }
 
-    fn add_index<'a>(
+    fn add_docs_internal(
         &self,
+        pending: &mut PendingWrites,
         db_addr: &DB3Address,
-        col: &str,
-        indexes: &Vec<Index>,
         sender: &DB3Address,
-    ) -> Result<()> {
-        let db = self.get_database(db_addr)?;
-        if db.is_none() {
-            return Err(DB3Error::DatabaseNotFound(db_addr.to_hex()));
+        col_name: &str,
+        docs: &Vec<String>,
+        given_doc_ids: Option<&Vec<i64>>,
+        keys: &[String],
+    ) -> Result<Vec<i64>> {
+        if !self.is_db_collection_exist(db_addr, col_name)? {
+            return Err(DB3Error::CollectionNotFound(
+                col_name.to_string(),
+                db_addr.to_hex(),
+            ));
         }
-        match self.get_collection(db_addr, col)? {
-            Some(collection) => {
-                let collection_sender: &[u8] = collection.sender.as_ref();
-                if collection_sender != sender.as_ref() {
-                    return Err(DB3Error::CollectionPermissionDenied());
-                }
-                let path_set: HashSet<&String> =
-                    collection.index_fields.iter().map(|x| &x.path).collect();
-                let exist_paths: Vec<&String> = indexes
-                    .iter()
-                    .filter(|x| path_set.contains(&x.path))
-                    .map(|x| &x.path)
-                    .collect();
-                if exist_paths.len() > 0 {
-                    return Err(DB3Error::InvalidKeyPathError(format!(
-                        "the index paths {:?} exist",
-                        exist_paths
-                    )));
+        if !keys.is_empty() && keys.len() != docs.len() {
+            return Err(DB3Error::InvalidMutationError(format!(
+                "keys size {} not equal to documents size {}",
+                keys.len(),
+                docs.len()
+            )));
+        }
+        self.check_write_permission(db_addr, col_name, sender)?;
+        self.check_docs_schema(db_addr, col_name, docs, false)?;
+        self.check_doc_keys(pending, db_addr, col_name, keys)?;
+        let db_addr_hex = db_addr.to_hex();
+        // the doc order is restored if the writes are aborted
+        if let Some(state) = self.get_database_state(db_addr) {
+            pending
+                .doc_orders
+                .entry(db_addr_hex.to_string())
+                .or_insert(state.doc_order);
+        }
+        let doc_ids = match self.update_db_state_for_add_docs(
+            db_addr_hex.as_str(),
+            docs.len(),
+            given_doc_ids,
+        )? {
+            Some(doc_ids) => doc_ids,
+            None => return Ok(vec![]),
+        };
+        let unique_values =
+            self.check_unique_keys(pending, db_addr, col_name, docs, &doc_ids, false)?;
+        pending.undo_records.push(UndoRecord::AddDocs {
+            db_addr: db_addr.clone(),
+            col_name: col_name.to_string(),
+            doc_ids: doc_ids.to_vec(),
+        });
+        if self.config.enable_doc_store {
+            self.doc_store
+                .add_str_docs(db_addr, col_name, docs, &doc_ids)?;
+        }
+        // add db+id-> owner mapping to control the permissions
+        let revisions: Vec<(i64, DB3Address, u64)> =
+            doc_ids.iter().map(|id| (*id, sender.clone(), 1)).collect();
+        self.put_doc_revisions_and_unique_values(
+            pending,
+            db_addr,
+            col_name,
+            &revisions,
+            &unique_values,
+        )?;
+        let doc_keys: Vec<(i64, String)> = doc_ids
+            .iter()
+            .zip(keys.iter())
+            .filter(|(_, key)| !key.is_empty())
+            .map(|(id, key)| (*id, key.to_string()))
+            .collect();
+        self.put_doc_keys(pending, db_addr, col_name, &doc_keys)?;
+        pending.add_doc_count(db_addr, col_name, doc_ids.len() as i64);
+        Ok(doc_ids)
+    }
+
+    fn get_docs_for_undo(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_ids: &[i64],
+    ) -> Result<Vec<(i64, String)>> {
+        let mut docs = vec![];
+        if self.config.enable_doc_store {
+            for id in doc_ids {
+                if let Some(doc) = self.doc_store.get_doc(db_addr, col_name, *id)? {
+                    docs.push((*id, doc));
                 }
-                let new_indexes = [collection.index_fields, indexes.clone()].concat();
-                self.save_collection_internal(sender, db_addr, col, &new_indexes)?;
-                Ok(())
             }
-            None => Err(DB3Error::CollectionNotFound(
-                col.to_string(),
-                db_addr.to_hex(),
-            )),
         }
+        Ok(docs)
     }
 
-    pub fn apply_mutation(
-        &self,
-        action: MutationAction,
-        dm: Mutation,
-        address: &DB3Address,
-        network: u64,
-        nonce: u64,
-        block: u64,
-        order: u32,
-        doc_ids_map: &HashMap<String, Vec<i64>>,
-    ) -> Result<Vec<ExtraItem>> {
-        let mut items: Vec<ExtraItem> = Vec::new();
-        match action {
-            MutationAction::DeleteEventDb => {
-                for body in dm.bodies {
-                    if let Some(Body::DeleteEventDatabaseMutation(ref _del_mutation)) = &body.body {
-                        let db_address_ref: &[u8] = body.db_address.as_ref();
-                        let db_addr = DB3Address::try_from(db_address_ref)?;
-                        self.delete_event_db(address, &db_addr)?;
-                        let item = ExtraItem {
-                            key: "db_addr".to_string(),
-                            value: db_addr.to_hex(),
-                        };
-                        items.push(item)
+    ///
+    /// commit the pending writes in one batch if all the changes succeed. otherwise the
+    /// pending writes are dropped, the doc store writes are compensated and the allocated
+    /// doc ids are released
+    ///
+    fn commit_or_abort<T>(&self, mut pending: PendingWrites, result: Result<T>) -> Result<T> {
+        let result = result.and_then(|value| {
+            // the nodes of the state tree are updated and written by one mutation at a time
+            let _guard = self
+                .state_tree_lock
+                .lock()
+                .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+            self.update_state_tree(&mut pending)?;
+            self.se
+                .write(std::mem::take(&mut pending.batch))
+                .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+            Ok(value)
+        });
+        match result {
+            Ok(value) => {
+                for (db_addr_hex, col_name) in pending.collections.iter() {
+                    self.update_db_state_for_new_collection(
+                        db_addr_hex.as_str(),
+                        col_name.as_str(),
+                    );
+                }
+                for ((db_addr_hex, col_name), count) in pending.doc_counts.iter() {
+                    if *count >= 0 {
+                        self.update_db_state_for_add_doc_count(
+                            db_addr_hex.as_str(),
+                            col_name.as_str(),
+                            *count as u64,
+                        );
+                    } else {
+                        self.update_db_state_for_delete_docs(
+                            db_addr_hex.as_str(),
+                            col_name.as_str(),
+                            count.unsigned_abs(),
+                        );
                     }
-                    break;
                 }
+                Ok(value)
+            }
+            Err(e) => {
+                warn!(
+                    "roll back {} doc store changes for error {e}",
+                    pending.undo_records.len()
+                );
+                for record in pending.undo_records.into_iter().rev() {
+                    self.rollback(record);
+                }
+                for (db_addr_hex, doc_order) in pending.doc_orders.into_iter() {
+                    if let Some(mut write_guard) = self.db_state.get_mut(db_addr_hex.as_str()) {
+                        write_guard.deref_mut().doc_order = doc_order;
+                    }
+                }
+                Err(e)
             }
+        }
+    }
 
-            MutationAction::AddIndex => {
-                for body in dm.bodies {
-                    if let Some(Body::AddIndexMutation(ref add_index_mutation)) = &body.body {
-                        let db_address_ref: &[u8] = body.db_address.as_ref();
-                        let db_addr = DB3Address::try_from(db_address_ref)?;
-                        self.add_index(
-                            &db_addr,
-                            add_index_mutation.collection_name.as_str(),
-                            &add_index_mutation.index_fields,
-                            address,
-                        )?;
-                        let item = ExtraItem {
-                            key: "collection".to_string(),
-                            value: add_index_mutation.collection_name.to_string(),
-                        };
-                        items.push(item);
-                        info!(
-                            "add index to collection {} done",
-                            add_index_mutation.collection_name.as_str()
-                        );
-                        break;
+    ///
+    /// revert the doc store write of a mutation body. the rollback is best effort and the
+    /// failures are only logged because the original error will be returned
+    ///
+    fn rollback(&self, record: UndoRecord) {
+        if !self.config.enable_doc_store {
+            return;
+        }
+        let result = match record {
+            UndoRecord::AddCollection { db_addr, col_name } => {
+                self.doc_store.drop_collection(&db_addr, col_name.as_str())
+            }
+            UndoRecord::AddDocs {
+                db_addr,
+                col_name,
+                doc_ids,
+            } => {
+                for id in doc_ids.iter() {
+                    // the doc may not have been written
+                    if let Err(e) = self.doc_store.delete_docs(&db_addr, &col_name, &vec![*id]) {
+                        debug!("skip deleting doc {} for {e}", id);
                     }
                 }
+                Ok(())
             }
+            UndoRecord::UpdateDocs {
+                db_addr,
+                col_name,
+                docs,
+            }
+            | UndoRecord::DeleteDocs {
+                db_addr,
+                col_name,
+                docs,
+            } => self.put_docs_for_undo(&db_addr, col_name.as_str(), &docs),
+        };
+        if let Err(e) = result {
+            warn!("fail to roll back the mutation body for error {e}");
+        }
+    }
 
-            MutationAction::MintCollection => {
-                for body in dm.bodies {
-                    if let Some(Body::MintCollectionMutation(ref mint_col_mutation)) = &body.body {
-                        let sender = DB3Address::try_from(mint_col_mutation.sender.as_str())?;
-                        let db_addr = DB3Address::try_from(mint_col_mutation.db_addr.as_str())?;
-                        if self.is_db_collection_exist(&db_addr, mint_col_mutation.name.as_str())? {
-                            return Err(DB3Error::CollectionAlreadyExist(
-                                mint_col_mutation.name.to_string(),
-                                db_addr.to_hex(),
-                            ));
+    fn put_docs_for_undo(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        docs: &[(i64, String)],
+    ) -> Result<()> {
+        if docs.is_empty() {
+            return Ok(());
+        }
+        let (ids, doc_strs): (Vec<i64>, Vec<String>) = docs.iter().cloned().unzip();
+        self.doc_store
+            .add_str_docs(db_addr, col_name, &doc_strs, &ids)
+    }
+
+    ///
+    /// check the unique keys of the documents against each other and the stored unique
+    /// values, which are kept by every node with or without the doc store. the value of a
+    /// unique key is the json array of its field values and the one of a compound index
+    /// is unique on the tuple of all its fields. the document with the same doc id is not
+    /// a conflict because it will be overwritten. the changes of the unique values are
+    /// returned and a none value removes the value of the key, the field missing in a
+    /// patch keeps its value
+    ///
+    fn check_unique_keys(
+        &self,
+        pending: &PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        docs: &[String],
+        doc_ids: &[i64],
+        is_patch: bool,
+    ) -> Result<Vec<(i64, String, Option<String>)>> {
+        let collection =
+            self.get_collection(db_addr, col_name)?
+                .ok_or(DB3Error::CollectionNotFound(
+                    col_name.to_string(),
+                    db_addr.to_hex(),
+                ))?;
+        let unique_keys = Self::get_unique_keys(&collection.index_fields);
+        if unique_keys.is_empty() {
+            return Ok(vec![]);
+        }
+        let json_docs = docs
+            .iter()
+            .map(|doc| {
+                serde_json::from_str::<serde_json::Value>(doc.as_str())
+                    .map_err(|e| DB3Error::InvalidJson(format!("{e}")))
+            })
+            .collect::<Result<Vec<serde_json::Value>>>()?;
+        let old_values: HashMap<(i64, String), String> = if is_patch {
+            self.get_doc_unique_values(pending, db_addr, doc_ids)?
+                .into_iter()
+                .map(|(id, key, value)| ((id, key), value))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        let mut unique_values = vec![];
+        for (key, fields) in unique_keys.iter() {
+            let mut seen_values: HashMap<String, i64> = HashMap::new();
+            for (doc, id) in json_docs.iter().zip(doc_ids.iter()) {
+                if is_patch && fields.iter().all(|field| doc.get(field.as_str()).is_none()) {
+                    continue;
+                }
+                // the field missing in the patch keeps its old value
+                let mut values = match old_values.get(&(*id, key.to_string())) {
+                    Some(old_value) => Self::decode_unique_value(old_value)?,
+                    None => vec![serde_json::Value::Null; fields.len()],
+                };
+                for (value, field) in values.iter_mut().zip(fields.iter()) {
+                    match doc.get(field.as_str()) {
+                        // the field with a non scalar value is not indexed
+                        Some(v) if query_plan::scalar_value_from_json(v).is_some() => {
+                            *value = v.clone()
                         }
-                        self.save_collection_internal(
-                            &sender,
-                            &db_addr,
-                            mint_col_mutation.name.as_str(),
-                            &vec![],
-                        )?;
-                        info!(
-                            "add collection with db_addr {}, collection_name: {}, from owner {}",
-                            db_addr.to_hex().as_str(),
-                            mint_col_mutation.name.as_str(),
-                            sender.to_hex().as_str()
-                        );
-                        let item = ExtraItem {
-                            key: "collection".to_string(),
-                            value: mint_col_mutation.name.to_string(),
-                        };
-                        items.push(item);
-                        break;
+                        None if is_patch => {}
+                        _ => *value = serde_json::Value::Null,
                     }
                 }
-            }
-            MutationAction::MintDocumentDb => {
-                for body in dm.bodies {
-                    if let Some(Body::MintDocDatabaseMutation(ref min_doc_db_mutation)) = &body.body
+                if values.iter().all(|v| v.is_null()) {
+                    unique_values.push((*id, key.to_string(), None));
+                    continue;
+                }
+                let value_key = serde_json::Value::Array(values).to_string();
+                // the document without all the fields of the key is not indexed but its
+                // values are kept for the next patch
+                if Self::is_indexed_unique_value(&value_key) {
+                    if let Some(conflict_id) = seen_values.get(&value_key) {
+                        return Err(DB3Error::DuplicateUniqueKey(key.to_string(), *conflict_id));
+                    }
+                    seen_values.insert(value_key.to_string(), *id);
+                    if let Some(conflict_id) = self
+                        .get_doc_id_by_unique_value(pending, db_addr, col_name, key, &value_key)?
                     {
-                        let sender = DB3Address::try_from(min_doc_db_mutation.sender.as_str())?;
-                        let db_id =
-                            DbId::from(DB3Address::try_from(min_doc_db_mutation.db_addr.as_str())?);
-                        self.create_predefined_doc_database(
-                            &sender,
-                            &db_id,
-                            min_doc_db_mutation.desc.as_str(),
-                            block,
-                            order,
-                        )
-                        .map_err(|e| {
-                            DB3Error::ApplyMutationError(format!(
-                                "fail to create predefined database {e}"
-                            ))
-                        })?;
-                        let db_id_hex = db_id.to_hex();
-                        info!(
-                            "mint database with addr {} from owner {}",
-                            db_id_hex.as_str(),
-                            address.to_hex().as_str()
-                        );
-                        let item = ExtraItem {
-                            key: "db_addr".to_string(),
-                            value: db_id_hex,
-                        };
-                        items.push(item);
-                        break;
+                        if conflict_id != *id {
+                            return Err(DB3Error::DuplicateUniqueKey(key.to_string(), conflict_id));
+                        }
                     }
                 }
+                unique_values.push((*id, key.to_string(), Some(value_key)));
             }
-            MutationAction::CreateEventDb => {
-                for body in dm.bodies {
-                    if let Some(Body::EventDatabaseMutation(ref mutation)) = &body.body {
-                        let db_id = self
-                            .create_event_database(address, mutation, nonce, network, block, order)
-                            .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
-                        let db_id_hex = db_id.to_hex();
-                        info!(
-                            "add database with addr {} from owner {}",
-                            db_id_hex.as_str(),
-                            address.to_hex().as_str()
-                        );
-                        let item = ExtraItem {
-                            key: "db_addr".to_string(),
-                            value: db_id_hex,
-                        };
-                        items.push(item);
-                        break;
-                    }
+        }
+        Ok(unique_values)
+    }
+
+    ///
+    /// the unique keys with their fields. the key of a single field index is named by the
+    /// field and the key of a compound unique index is named by the index path
+    ///
+    fn get_unique_keys(indexes: &[Index]) -> Vec<(String, Vec<String>)> {
+        let mut unique_keys: Vec<(String, Vec<String>)> = vec![];
+        for index in indexes.iter() {
+            let keys = if !index.fields.is_empty() && index.index_type() == IndexType::UniqueKey {
+                let fields = index
+                    .fields
+                    .iter()
+                    .map(|f| f.path.trim_start_matches('/').to_string())
+                    .collect();
+                vec![(index.path.to_string(), fields)]
+            } else {
+                doc_store::index_fields(index)
+                    .iter()
+                    .filter(|f| f.index_type() == IndexType::UniqueKey)
+                    .map(|f| {
+                        let field = f.path.trim_start_matches('/').to_string();
+                        (field.to_string(), vec![field])
+                    })
+                    .collect()
+            };
+            for key in keys {
+                if !unique_keys.iter().any(|(name, _)| name == &key.0) {
+                    unique_keys.push(key);
                 }
             }
-            MutationAction::CreateDocumentDb => {
-                for body in dm.bodies {
-                    if let Some(Body::DocDatabaseMutation(ref doc_db_mutation)) = &body.body {
-                        let db_id = self
-                            .create_doc_database(
-                                address,
-                                doc_db_mutation,
-                                nonce,
-                                network,
-                                block,
-                                order,
-                            )
-                            .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
-                        let db_id_hex = db_id.to_hex();
-                        info!(
-                            "add database with addr {} from owner {}",
-                            db_id_hex.as_str(),
-                            address.to_hex().as_str()
-                        );
-                        let item = ExtraItem {
-                            key: "db_addr".to_string(),
-                            value: db_id_hex,
-                        };
-                        items.push(item);
-                        break;
-                    }
+        }
+        unique_keys
+    }
+
+    /// the fields of all the unique keys
+    fn get_unique_fields(indexes: &[Index]) -> Vec<String> {
+        let mut unique_fields: Vec<String> = vec![];
+        for (_, fields) in Self::get_unique_keys(indexes) {
+            for field in fields {
+                if !unique_fields.contains(&field) {
+                    unique_fields.push(field);
                 }
             }
-            MutationAction::AddCollection => {
-                for (i, body) in dm.bodies.iter().enumerate() {
-                    let db_address_ref: &[u8] = body.db_address.as_ref();
-                    let db_addr = DB3Address::try_from(db_address_ref)
-                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
-                    if let Some(Body::CollectionMutation(ref col_mutation)) = &body.body {
-                        self.create_collection(
-                            address,
-                            &db_addr,
-                            col_mutation,
-                            block,
-                            order,
-                            i as u16,
-                        )
-                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
-                        info!(
-                            "add collection with db_addr {}, collection_name: {}, from owner {}",
-                            db_addr.to_hex().as_str(),
-                            col_mutation.collection_name.as_str(),
-                            address.to_hex().as_str()
-                        );
-                        let item = ExtraItem {
-                            key: "collection".to_string(),
-                            value: col_mutation.collection_name.to_string(),
-                        };
-                        items.push(item);
-                    }
+        }
+        unique_fields
+    }
+
+    fn decode_unique_value(value: &str) -> Result<Vec<serde_json::Value>> {
+        serde_json::from_str::<Vec<serde_json::Value>>(value)
+            .map_err(|e| DB3Error::ReadStoreError(format!("invalid unique value {e}")))
+    }
+
+    /// the value is indexed only if all the fields of the unique key have values
+    fn is_indexed_unique_value(value: &str) -> bool {
+        Self::decode_unique_value(value).map_or(false, |values| values.iter().all(|v| !v.is_null()))
+    }
+
+    fn get_doc_id_by_unique_value(
+        &self,
+        pending: &PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Option<i64>> {
+        let value_key = DocUniqueValueKey(db_addr, col_name, field, value).encode()?;
+        match pending.get(&self.se, DOC_UNIQUE_CF, value_key.as_ref())? {
+            Some(v) => Ok(Some(i64::from_be_bytes(
+                <[u8; 8]>::try_from(v.as_slice())
+                    .map_err(|e| DB3Error::ReadStoreError(format!("invalid doc id {e}")))?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// get the values of the unique fields of the docs
+    fn get_doc_unique_values(
+        &self,
+        pending: &PendingWrites,
+        db_addr: &DB3Address,
+        doc_ids: &[i64],
+    ) -> Result<Vec<(i64, String, String)>> {
+        let mut unique_values = vec![];
+        for id in doc_ids {
+            let prefix = DocUniqueFieldKey::build_prefix(db_addr, Some(*id));
+            for (k, v) in pending.get_with_prefix(&self.se, DOC_UNIQUE_CF, prefix.as_ref())? {
+                let field = DocUniqueFieldKey::decode_field(k.as_ref(), &prefix)?;
+                let value =
+                    String::from_utf8(v).map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+                unique_values.push((*id, field.to_string(), value));
+            }
+        }
+        Ok(unique_values)
+    }
+
+    ///
+    /// put the changes of the unique values and the old value of the field is removed if
+    /// it's changed
+    ///
+    fn put_doc_unique_values(
+        &self,
+        pending: &mut PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        unique_values: &[(i64, String, Option<String>)],
+    ) -> Result<()> {
+        for (id, field, value) in unique_values {
+            let field_key = DocUniqueFieldKey(db_addr, *id, field.as_str()).encode()?;
+            let old_value = pending.get(&self.se, DOC_UNIQUE_CF, field_key.as_ref())?;
+            if let Some(old_value) = old_value {
+                let old_value = String::from_utf8(old_value)
+                    .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+                if value.as_ref() != Some(&old_value) && Self::is_indexed_unique_value(&old_value) {
+                    pending.delete(
+                        &self.se,
+                        DOC_UNIQUE_CF,
+                        DocUniqueValueKey(db_addr, col_name, field, old_value.as_str())
+                            .encode()?
+                            .as_ref(),
+                    )?;
                 }
             }
-            MutationAction::AddDocument => {
-                for (i, body) in dm.bodies.iter().enumerate() {
-                    let db_address_ref: &[u8] = body.db_address.as_ref();
-                    let db_addr = DB3Address::try_from(db_address_ref)
-                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
-                    if let Some(Body::DocumentMutation(ref doc_mutation)) = &body.body {
-                        let mut docs = Vec::<String>::new();
-                        for buf in doc_mutation.documents.iter() {
-                            let document = bytes_to_bson_document(buf.clone())
-                                .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
-                            docs.push(document.to_string());
-                        }
-                        let ids = self
-                            .add_docs(
-                                &db_addr,
-                                address,
-                                doc_mutation.collection_name.as_str(),
-                                &docs,
-                                doc_ids_map.get(i.to_string().as_str()),
-                            )
-                            .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
-                        debug!(
-                                    "add documents with db_addr {}, collection_name: {}, from owner {}, document size: {}",
-                                    db_addr.to_hex().as_str(),
-                                    doc_mutation.collection_name.as_str(),
-                                    address.to_hex().as_str(),
-                                    ids.len()
-                                );
-                        // return document keys
-                        for id in ids {
-                            let item = ExtraItem {
-                                key: "document".to_string(),
-                                value: id.to_string(),
-                            };
-                            items.push(item);
-                        }
+            match value {
+                Some(value) => {
+                    if Self::is_indexed_unique_value(value) {
+                        pending.put(
+                            &self.se,
+                            DOC_UNIQUE_CF,
+                            DocUniqueValueKey(db_addr, col_name, field, value.as_str())
+                                .encode()?
+                                .as_ref(),
+                            &id.to_be_bytes(),
+                        )?;
                     }
+                    pending.put(
+                        &self.se,
+                        DOC_UNIQUE_CF,
+                        field_key.as_ref(),
+                        value.as_bytes(),
+                    )?;
                 }
+                None => pending.delete(&self.se, DOC_UNIQUE_CF, field_key.as_ref())?,
             }
-            MutationAction::UpdateDocument => {
-                for (_i, body) in dm.bodies.iter().enumerate() {
-                    let db_address_ref: &[u8] = body.db_address.as_ref();
-                    let db_addr = DB3Address::try_from(db_address_ref)
-                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
-                    if let Some(Body::DocumentMutation(ref doc_mutation)) = &body.body {
-                        if doc_mutation.documents.len() != doc_mutation.ids.len() {
-                            let msg = format!(
+        }
+        Ok(())
+    }
+
+    ///
+    /// replace all the unique values of the docs with the given ones
+    ///
+    fn replace_doc_unique_values(
+        &self,
+        pending: &mut PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_ids: &[i64],
+        unique_values: &[(i64, String, String)],
+    ) -> Result<()> {
+        for (id, field, value) in self.get_doc_unique_values(pending, db_addr, doc_ids)? {
+            if Self::is_indexed_unique_value(value.as_str()) {
+                pending.delete(
+                    &self.se,
+                    DOC_UNIQUE_CF,
+                    DocUniqueValueKey(db_addr, col_name, field.as_str(), value.as_str())
+                        .encode()?
+                        .as_ref(),
+                )?;
+            }
+            pending.delete(
+                &self.se,
+                DOC_UNIQUE_CF,
+                DocUniqueFieldKey(db_addr, id, field.as_str())
+                    .encode()?
+                    .as_ref(),
+            )?;
+        }
+        for (id, field, value) in unique_values {
+            if Self::is_indexed_unique_value(value) {
+                pending.put(
+                    &self.se,
+                    DOC_UNIQUE_CF,
+                    DocUniqueValueKey(db_addr, col_name, field.as_str(), value.as_str())
+                        .encode()?
+                        .as_ref(),
+                    &id.to_be_bytes(),
+                )?;
+            }
+            pending.put(
+                &self.se,
+                DOC_UNIQUE_CF,
+                DocUniqueFieldKey(db_addr, *id, field.as_str())
+                    .encode()?
+                    .as_ref(),
+                value.as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// delete the unique values of the key or all the keys in the collection and the
+    /// mappings from the documents of the collection to them
+    ///
+    fn delete_doc_unique_values_of_collection(
+        &self,
+        batch: &mut WriteBatch,
+        db_addr: &DB3Address,
+        col_name: &str,
+        key: Option<&str>,
+    ) -> Result<()> {
+        let doc_unique_cf_handle = self
+            .se
+            .cf_handle(DOC_UNIQUE_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let prefix = match key {
+            Some(key) => DocUniqueValueKey::build_field_prefix(db_addr, col_name, key)?,
+            None => DocUniqueValueKey::build_prefix(db_addr, Some(col_name))?,
+        };
+        self.delete_entries_with_prefix(batch, DOC_UNIQUE_CF, prefix.as_ref())?;
+        // the document whose key is not indexed still keeps its values, so the mappings
+        // are listed by the collection doc keys
+        let collection_doc_cf_handle = self
+            .se
+            .cf_handle(COLLECTION_DOC_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let prefix = CollectionDocKey::build_prefix(db_addr, Some(col_name))?;
+        let mut it: DBRawIterator = self
+            .se
+            .prefix_iterator_cf(&collection_doc_cf_handle, &prefix)
+            .into();
+        while it.valid() {
+            match it.key() {
+                Some(k) if k.starts_with(&prefix) => {
+                    let id = CollectionDocKey::decode_id(k, &prefix)?;
+                    match key {
+                        Some(key) => batch.delete_cf(
+                            &doc_unique_cf_handle,
+                            DocUniqueFieldKey(db_addr, id, key).encode()?,
+                        ),
+                        None => self.delete_entries_with_prefix(
+                            batch,
+                            DOC_UNIQUE_CF,
+                            DocUniqueFieldKey::build_prefix(db_addr, Some(id)).as_ref(),
+                        )?,
+                    }
+                }
+                _ => break,
+            }
+            it.next();
+        }
+        Ok(())
+    }
+
+    /// verify if the collection exists in the given db
+    pub fn is_db_collection_exist(&self, db_addr: &DB3Address, col_name: &str) -> Result<bool> {
+        let ck = collection_key::build_collection_key(db_addr, col_name)?;
+        let collection_store_cf_handle = self
+            .se
+            .cf_handle(self.config.collection_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let ck_ref: &[u8] = ck.as_ref();
+        let value = self
+            .se
+            .get_cf(&collection_store_cf_handle, ck_ref)
+            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+        Ok(value.is_some())
+    }
+
+    /// clean doc ids that are not in the collection
+    fn delete_doc_ids_from_owner_store(
+        &self,
+        pending: &mut PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_ids: &Vec<i64>,
+    ) -> Result<()> {
+        for id in doc_ids {
+            pending.delete(
+                &self.se,
+                self.config.doc_owner_store_cf_name.as_str(),
+                DbDocKeyV2(db_addr, *id).encode()?.as_ref(),
+            )?;
+            pending.delete(
+                &self.se,
+                COLLECTION_DOC_CF,
+                CollectionDocKey(db_addr, col_name, *id).encode()?.as_ref(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// verify the ownership of the doc ids
+    pub fn verify_docs_ownership(
+        &self,
+        sender: &DB3Address,
+        db_addr: &DB3Address,
+        doc_ids: &Vec<i64>,
+    ) -> Result<()> {
+        self.verify_docs_ownership_internal(&PendingWrites::default(), sender, db_addr, doc_ids)
+    }
+
+    fn verify_docs_ownership_internal(
+        &self,
+        pending: &PendingWrites,
+        sender: &DB3Address,
+        db_addr: &DB3Address,
+        doc_ids: &Vec<i64>,
+    ) -> Result<()> {
+        for owner in self.get_doc_owners(pending, db_addr, doc_ids)? {
+            match owner {
+                Some((owner, _)) if owner != *sender => {
+                    return Err(DB3Error::OwnerVerifyFailed(format!(
+                        "doc owner is not the sender"
+                    )));
+                }
+                Some(_) => {}
+                None => return Err(DB3Error::OwnerVerifyFailed(format!("doc id is not found"))),
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// resolve the ids of the documents matched by the query and owned by the sender.
+    /// the query matching more than scan_max_limit documents is rejected and the recorded
+    /// ids are used when the mutation is replayed
+    ///
+    fn match_docs_by_query(
+        &self,
+        pending: &PendingWrites,
+        db_addr: &DB3Address,
+        sender: &DB3Address,
+        query: &StructuredQuery,
+        recorded_doc_ids: Option<&Vec<i64>>,
+    ) -> Result<Vec<i64>> {
+        if let Some(doc_ids) = recorded_doc_ids {
+            return Ok(doc_ids.clone());
+        }
+        if !self.config.enable_doc_store {
+            return Err(DB3Error::InvalidMutationError(
+                "the documents matched by the query can not be resolved without the doc store"
+                    .to_string(),
+            ));
+        }
+        let col_name = query.collection_name.as_str();
+        let collection =
+            self.get_collection(db_addr, col_name)?
+                .ok_or(DB3Error::CollectionNotFound(
+                    col_name.to_string(),
+                    db_addr.to_hex(),
+                ))?;
+        // the query without a limit is checked with one more document than the scan max
+        // limit, so the matched documents are never truncated silently
+        let max_limit = self.config.scan_max_limit as i32;
+        let mut match_query = query.clone();
+        match_query.limit = match &query.limit {
+            Some(limit) if limit.limit > 0 && limit.limit <= max_limit => Some(limit.clone()),
+            _ => Some(Limit {
+                limit: max_limit + 1,
+            }),
+        };
+        let plan = QueryPlanner::new(&collection).build(&match_query)?;
+        let (result, _) = self.doc_store.execute_query(db_addr, col_name, &plan)?;
+        if result.len() > max_limit as usize {
+            return Err(DB3Error::InvalidMutationError(format!(
+                "the query matches more than {max_limit} documents, set a limit and repeat the mutation"
+            )));
+        }
+        let matched_ids: Vec<i64> = result.into_iter().map(|(id, _)| id).collect();
+        let owners = self.get_doc_owners(pending, db_addr, &matched_ids)?;
+        Ok(matched_ids
+            .into_iter()
+            .zip(owners.into_iter())
+            .filter(|(_, owner)| matches!(owner, Some((owner, _)) if owner == sender))
+            .map(|(id, _)| id)
+            .collect())
+    }
+
+    pub fn get_doc(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_id: i64,
+    ) -> Result<Option<Document>> {
+        if !self.is_db_collection_exist(db_addr, col_name)? {
+            return Err(DB3Error::CollectionNotFound(
+                col_name.to_string(),
+                db_addr.to_hex(),
+            ));
+        }
+        if self.config.enable_doc_store {
+            let doc = self.doc_store.get_doc(db_addr, col_name, doc_id)?;
+            if let Some(d) = doc {
+                let pending = PendingWrites::default();
+                let revision = self.get_doc_revisions(&pending, db_addr, &[doc_id])?[0];
+                let key = self
+                    .get_doc_keys(&pending, db_addr, &[doc_id])?
+                    .pop()
+                    .map(|(_, key)| key)
+                    .unwrap_or_default();
+                Ok(Some(Document {
+                    id: doc_id,
+                    doc: d,
+                    revision: revision.unwrap_or(0),
+                    key,
+                }))
+            } else {
+                Ok(None)
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn get_doc_by_key(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        key: &str,
+    ) -> Result<Option<Document>> {
+        match self.get_doc_id_by_key(db_addr, col_name, key)? {
+            Some(id) => self.get_doc(db_addr, col_name, id),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_doc_key_from_doc_id(&self, doc_id: i64) -> Result<Vec<u8>> {
+        let doc_owner_store_cf_handle = self
+            .se
+            .cf_handle(self.config.doc_owner_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let value = self
+            .se
+            .get_cf(&doc_owner_store_cf_handle, doc_id.to_be_bytes().as_ref())
+            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+        match value {
+            Some(v) => Ok(v),
+            None => {
+                return Err(DB3Error::ReadStoreError(format!(
+                    "doc owner key not found for doc id {}",
+                    doc_id
+                )))
+            }
+        }
+    }
+    /// create db+id-> owner mapping to control the permissions
+    pub fn create_doc_ownership(
+        &self,
+        sender: &DB3Address,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_ids: &Vec<i64>,
+    ) -> Result<()> {
+        // the revision of a new doc starts from 1
+        let revisions: Vec<(i64, DB3Address, u64)> =
+            doc_ids.iter().map(|id| (*id, sender.clone(), 1)).collect();
+        let mut pending = PendingWrites::default();
+        let result = self.put_doc_revisions_and_unique_values(
+            &mut pending,
+            db_addr,
+            col_name,
+            &revisions,
+            &[],
+        );
+        self.commit_or_abort(pending, result)
+    }
+
+    pub fn create_event_database(
+        &self,
+        sender: &DB3Address,
+        mutation: &EventDatabaseMutation,
+        nonce: u64,
+        network_id: u64,
+        block: u64,
+        order: u32,
+    ) -> Result<DbId> {
+        let db_addr = DbId::from((sender, nonce, network_id));
+        let db_store_cf_handle = self
+            .se
+            .cf_handle(self.config.db_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let db_owner_store_cf_handle = self
+            .se
+            .cf_handle(self.config.db_owner_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let db_owner = DbOwnerKey(sender, block, order);
+        let db_owner_encoded_key = db_owner.encode()?;
+        //TODO check the name
+        let database = EventDatabase {
+            address: db_addr.as_ref().to_vec(),
+            sender: sender.as_ref().to_vec(),
+            desc: mutation.desc.to_string(),
+            contract_address: mutation.contract_address.to_string(),
+            ttl: mutation.ttl,
+            events_json_abi: mutation.events_json_abi.to_string(),
+            evm_node_url: mutation.evm_node_url.to_string(),
+            start_block: mutation.start_block,
+        };
+        let database_msg = DatabaseMessage {
+            database: Some(database_message::Database::EventDb(database)),
+        };
+        let mut buf = BytesMut::with_capacity(1024);
+        database_msg
+            .encode(&mut buf)
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        let buf = buf.freeze();
+        let mut batch = WriteBatch::default();
+        batch.put_cf(&db_store_cf_handle, db_addr.as_ref(), buf.as_ref());
+        batch.put_cf(
+            &db_owner_store_cf_handle,
+            &db_owner_encoded_key,
+            db_addr.as_ref(),
+        );
+        self.se
+            .write(batch)
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+
+        let db_addr_hex = db_addr.to_hex();
+        self.update_db_state_for_add_db(db_addr_hex.as_str());
+        let mut pending = PendingWrites::default();
+        let result = mutation
+            .tables
+            .iter()
+            .enumerate()
+            .try_for_each(|(idx, cm)| {
+                self.create_collection_internal(
+                    &mut pending,
+                    sender,
+                    db_addr.address(),
+                    cm,
+                    block,
+                    order,
+                    idx as u16,
+                )
+            });
+        self.commit_or_abort(pending, result)?;
+        if self.config.enable_doc_store {
+            self.doc_store
+                .create_database(db_addr.address())
+                .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        }
+        Ok(db_addr)
+    }
+
+    pub fn delete_event_db(&self, sender: &DB3Address, db_addr: &DB3Address) -> Result<()> {
+        match self.get_event_db(db_addr)? {
+            Some(database) => {
+                let sender_ref: &[u8] = database.sender.as_ref();
+                if sender_ref != sender.as_ref() {
+                    return Err(DB3Error::DatabasePermissionDenied());
+                }
+                let mut batch = WriteBatch::default();
+                self.delete_doc_expiry_of_collection(&mut batch, db_addr, None)?;
+                self.se
+                    .write(batch)
+                    .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+                self.delete_db_internal(sender, db_addr)?;
+            }
+            None => return Err(DB3Error::DatabaseNotFound(db_addr.to_hex())),
+        }
+        Ok(())
+    }
+
+    /// get the owner key of the database which is used to list the databases of the owner
+    fn get_db_owner_key(
+        &self,
+        owner: &DB3Address,
+        db_addr: &DB3Address,
+    ) -> Result<Option<Vec<u8>>> {
+        let cf_handle = self
+            .se
+            .cf_handle(self.config.db_owner_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mut it: DBRawIterator = self.se.prefix_iterator_cf(&cf_handle, owner).into();
+        while it.valid() {
+            if let Some(k) = it.key() {
+                if &k[0..owner.as_ref().len()] != owner.as_ref() {
+                    break;
+                }
+            } else {
+                break;
+            }
+            if let Some(v) = it.value() {
+                let addr = DB3Address::try_from(v)
+                    .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+                if addr.as_ref() == db_addr.as_ref() {
+                    return Ok(it.key().map(|k| k.to_vec()));
+                }
+            }
+            it.next();
+        }
+        Ok(None)
+    }
+
+    // delete the owner key and the entry of the database
+    fn delete_db_internal(&self, owner: &DB3Address, db_addr: &DB3Address) -> Result<()> {
+        let cf_handle = self
+            .se
+            .cf_handle(self.config.db_owner_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mut batch = WriteBatch::default();
+        if let Some(key) = self.get_db_owner_key(owner, db_addr)? {
+            batch.delete_cf(&cf_handle, key);
+        }
+        let db_store_cf_handle = self
+            .se
+            .cf_handle(self.config.db_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        batch.delete_cf(&db_store_cf_handle, db_addr);
+        self.se
+            .write(batch)
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        Ok(())
+    }
+
+    fn get_database_owner(&self, db_addr: &DB3Address) -> Result<Vec<u8>> {
+        match self.get_database(db_addr)? {
+            Some(DatabaseMessage {
+                database: Some(database_message::Database::DocDb(db)),
+            }) => Ok(db.sender),
+            Some(DatabaseMessage {
+                database: Some(database_message::Database::EventDb(db)),
+            }) => Ok(db.sender),
+            _ => Err(DB3Error::DatabaseNotFound(db_addr.to_hex())),
+        }
+    }
+
+    fn delete_entries_with_prefix(
+        &self,
+        batch: &mut WriteBatch,
+        cf: &str,
+        prefix: &[u8],
+    ) -> Result<()> {
+        let cf_handle = self
+            .se
+            .cf_handle(cf)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mut it: DBRawIterator = self.se.prefix_iterator_cf(&cf_handle, prefix).into();
+        while it.valid() {
+            match it.key() {
+                Some(k) if k.starts_with(prefix) => batch.delete_cf(&cf_handle, k),
+                _ => break,
+            }
+            it.next();
+        }
+        Ok(())
+    }
+
+    ///
+    /// drop the collection with its indexes, document keys and owners. only the owners
+    /// of the database and the collection can do it
+    ///
+    pub fn drop_collection(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        sender: &DB3Address,
+    ) -> Result<()> {
+        let db_owner = self.get_database_owner(db_addr)?;
+        let collection =
+            self.get_collection(db_addr, col_name)?
+                .ok_or(DB3Error::CollectionNotFound(
+                    col_name.to_string(),
+                    db_addr.to_hex(),
+                ))?;
+        if db_owner.as_slice() != sender.as_ref() && collection.sender.as_slice() != sender.as_ref()
+        {
+            return Err(DB3Error::CollectionPermissionDenied());
+        }
+        let collection_store_cf_handle = self
+            .se
+            .cf_handle(self.config.collection_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let doc_name_cf_handle = self
+            .se
+            .cf_handle(DOC_NAME_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mut batch = WriteBatch::default();
+        let ck = collection_key::build_collection_key(db_addr, col_name)?;
+        batch.delete_cf(&collection_store_cf_handle, ck);
+        // delete both the key to id and the id to key mappings of the documents
+        let prefix = DbDocNameKeyV2::build_prefix(db_addr, Some(col_name))?;
+        let mut it: DBRawIterator = self
+            .se
+            .prefix_iterator_cf(&doc_name_cf_handle, &prefix)
+            .into();
+        while it.valid() {
+            match (it.key(), it.value()) {
+                (Some(k), Some(v)) if k.starts_with(&prefix) => {
+                    let id =
+                        i64::from_be_bytes(<[u8; 8]>::try_from(v).map_err(|e| {
+                            DB3Error::ReadStoreError(format!("invalid doc id {e}"))
+                        })?);
+                    batch.delete_cf(&doc_name_cf_handle, k);
+                    batch.delete_cf(&doc_name_cf_handle, DbDocKeyV2(db_addr, id).encode()?);
+                }
+                _ => break,
+            }
+            it.next();
+        }
+        // the owners of the documents are listed by the collection doc keys because the
+        // doc store is not enabled on every node
+        let doc_owner_store_cf_handle = self
+            .se
+            .cf_handle(self.config.doc_owner_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let collection_doc_cf_handle = self
+            .se
+            .cf_handle(COLLECTION_DOC_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let prefix = CollectionDocKey::build_prefix(db_addr, Some(col_name))?;
+        let mut it: DBRawIterator = self
+            .se
+            .prefix_iterator_cf(&collection_doc_cf_handle, &prefix)
+            .into();
+        while it.valid() {
+            match it.key() {
+                Some(k) if k.starts_with(&prefix) => {
+                    let id = CollectionDocKey::decode_id(k, &prefix)?;
+                    batch.delete_cf(&collection_doc_cf_handle, k);
+                    batch.delete_cf(
+                        &doc_owner_store_cf_handle,
+                        DbDocKeyV2(db_addr, id).encode()?,
+                    );
+                }
+                _ => break,
+            }
+            it.next();
+        }
+        self.delete_doc_expiry_of_collection(&mut batch, db_addr, Some(col_name))?;
+        self.delete_entries_with_prefix(
+            &mut batch,
+            DOC_HISTORY_CF,
+            DocHistoryKey::build_prefix(db_addr, Some(col_name))?.as_ref(),
+        )?;
+        self.delete_doc_unique_values_of_collection(&mut batch, db_addr, col_name, None)?;
+        self.write_with_doc_state_removed(batch, db_addr, Some(col_name))?;
+        self.update_db_state_for_drop_collection(db_addr.to_hex().as_str(), col_name);
+        if self.config.enable_doc_store {
+            self.doc_store.drop_collection(db_addr, col_name)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// rename the collection of the document database and keep its documents, indexes
+    /// and document keys. only the owners of the database and the collection can do it
+    ///
+    pub fn rename_collection(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        new_name: &str,
+        sender: &DB3Address,
+    ) -> Result<()> {
+        let db_owner = match self.get_database(db_addr)? {
+            Some(DatabaseMessage {
+                database: Some(database_message::Database::DocDb(db)),
+            }) => db.sender,
+            // the collections of the event database are bound to the contract events
+            Some(_) => {
+                return Err(DB3Error::InvalidMutationError(
+                    "the database is not a document database".to_string(),
+                ));
+            }
+            None => return Err(DB3Error::DatabaseNotFound(db_addr.to_hex())),
+        };
+        let collection =
+            self.get_collection(db_addr, col_name)?
+                .ok_or(DB3Error::CollectionNotFound(
+                    col_name.to_string(),
+                    db_addr.to_hex(),
+                ))?;
+        if db_owner.as_slice() != sender.as_ref() && collection.sender.as_slice() != sender.as_ref()
+        {
+            return Err(DB3Error::CollectionPermissionDenied());
+        }
+        collection_key::check_collection_name(new_name)?;
+        if new_name == col_name {
+            return Err(DB3Error::InvalidCollectionNameError(
+                "the new name is the same as the current name".to_string(),
+            ));
+        }
+        if self.is_db_collection_exist(db_addr, new_name)? {
+            return Err(DB3Error::CollectionAlreadyExist(
+                new_name.to_string(),
+                db_addr.to_hex(),
+            ));
+        }
+        let new_ck = collection_key::build_collection_key(db_addr, new_name)?;
+        let collection_store_cf_handle = self
+            .se
+            .cf_handle(self.config.collection_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let doc_name_cf_handle = self
+            .se
+            .cf_handle(DOC_NAME_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mut batch = WriteBatch::default();
+        let mut buf = BytesMut::with_capacity(1024);
+        Collection {
+            name: new_name.to_string(),
+            ..collection
+        }
+        .encode(&mut buf)
+        .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        batch.delete_cf(
+            &collection_store_cf_handle,
+            collection_key::build_collection_key(db_addr, col_name)?,
+        );
+        batch.put_cf(&collection_store_cf_handle, new_ck, buf.freeze().as_ref());
+        // the collection name is a part of the key to id mappings of the documents
+        let prefix = DbDocNameKeyV2::build_prefix(db_addr, Some(col_name))?;
+        let mut it: DBRawIterator = self
+            .se
+            .prefix_iterator_cf(&doc_name_cf_handle, &prefix)
+            .into();
+        while it.valid() {
+            match (it.key(), it.value()) {
+                (Some(k), Some(v)) if k.starts_with(&prefix) => {
+                    let key = std::str::from_utf8(&k[prefix.len()..])
+                        .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+                    let new_key = DbDocNameKeyV2(db_addr, new_name, key).encode()?;
+                    batch.delete_cf(&doc_name_cf_handle, k);
+                    batch.put_cf(&doc_name_cf_handle, new_key, v);
+                }
+                _ => break,
+            }
+            it.next();
+        }
+        // the collection name is a part of the collection doc keys
+        let collection_doc_cf_handle = self
+            .se
+            .cf_handle(COLLECTION_DOC_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let prefix = CollectionDocKey::build_prefix(db_addr, Some(col_name))?;
+        let mut it: DBRawIterator = self
+            .se
+            .prefix_iterator_cf(&collection_doc_cf_handle, &prefix)
+            .into();
+        while it.valid() {
+            match (it.key(), it.value()) {
+                (Some(k), Some(v)) if k.starts_with(&prefix) => {
+                    let id = CollectionDocKey::decode_id(k, &prefix)?;
+                    batch.delete_cf(&collection_doc_cf_handle, k);
+                    batch.put_cf(
+                        &collection_doc_cf_handle,
+                        CollectionDocKey(db_addr, new_name, id).encode()?,
+                        v,
+                    );
+                }
+                _ => break,
+            }
+            it.next();
+        }
+        // the expiry of the documents keeps the collection name to delete them
+        let doc_expiry_cf_handle = self
+            .se
+            .cf_handle(DOC_EXPIRY_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        for (id, expire_at, name) in self.get_doc_expiry_of_db(db_addr)? {
+            if name.as_str() != col_name {
+                continue;
+            }
+            batch.put_cf(
+                &doc_expiry_cf_handle,
+                DocExpiryKey(expire_at, db_addr, id).encode()?,
+                new_name.as_bytes(),
+            );
+            batch.put_cf(
+                &doc_expiry_cf_handle,
+                DocExpiryIndexKey(db_addr, id).encode()?,
+                Self::encode_doc_expiry_value(expire_at, new_name),
+            );
+        }
+        // the collection name is a part of the keys of the document versions
+        let doc_history_cf_handle = self
+            .se
+            .cf_handle(DOC_HISTORY_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let prefix = DocHistoryKey::build_prefix(db_addr, Some(col_name))?;
+        let mut it: DBRawIterator = self
+            .se
+            .prefix_iterator_cf(&doc_history_cf_handle, &prefix)
+            .into();
+        while it.valid() {
+            match (it.key(), it.value()) {
+                (Some(k), Some(v)) if k.starts_with(&prefix) => {
+                    let (id, block, order) = DocHistoryKey::decode(k, &prefix)?;
+                    let new_key = DocHistoryKey(db_addr, new_name, id, block, order).encode()?;
+                    batch.delete_cf(&doc_history_cf_handle, k);
+                    batch.put_cf(&doc_history_cf_handle, new_key, v);
+                }
+                _ => break,
+            }
+            it.next();
+        }
+        // the collection name is a part of the keys of the unique values
+        let doc_unique_cf_handle = self
+            .se
+            .cf_handle(DOC_UNIQUE_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let prefix = DocUniqueValueKey::build_prefix(db_addr, Some(col_name))?;
+        let new_prefix = DocUniqueValueKey::build_prefix(db_addr, Some(new_name))?;
+        let mut it: DBRawIterator = self
+            .se
+            .prefix_iterator_cf(&doc_unique_cf_handle, &prefix)
+            .into();
+        while it.valid() {
+            match (it.key(), it.value()) {
+                (Some(k), Some(v)) if k.starts_with(&prefix) => {
+                    let new_key = [new_prefix.as_slice(), &k[prefix.len()..]].concat();
+                    batch.delete_cf(&doc_unique_cf_handle, k);
+                    batch.put_cf(&doc_unique_cf_handle, new_key, v);
+                }
+                _ => break,
+            }
+            it.next();
+        }
+        self.se
+            .write(batch)
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        if let Some(mut write_guard) = self.db_state.get_mut(db_addr.to_hex().as_str()) {
+            let database_state = write_guard.deref_mut();
+            if let Some(collection_state) = database_state.collection_state.remove(col_name) {
+                database_state
+                    .collection_state
+                    .insert(new_name.to_string(), collection_state);
+            }
+        }
+        if self.config.enable_doc_store {
+            self.doc_store
+                .rename_collection(db_addr, col_name, new_name)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// transfer the database and the collections created by the current owner to the new
+    /// owner. only the current owner of the database can do it
+    ///
+    pub fn transfer_database(
+        &self,
+        db_addr: &DB3Address,
+        new_owner: &DB3Address,
+        sender: &DB3Address,
+        block: u64,
+        order: u32,
+    ) -> Result<()> {
+        let mut database = self
+            .get_database(db_addr)?
+            .ok_or(DB3Error::DatabaseNotFound(db_addr.to_hex()))?;
+        match database.database.as_mut() {
+            Some(database_message::Database::DocDb(db))
+                if db.sender.as_slice() == sender.as_ref() =>
+            {
+                db.sender = new_owner.as_ref().to_vec();
+            }
+            Some(database_message::Database::EventDb(db))
+                if db.sender.as_slice() == sender.as_ref() =>
+            {
+                db.sender = new_owner.as_ref().to_vec();
+            }
+            _ => return Err(DB3Error::DatabasePermissionDenied()),
+        }
+        let db_owner_store_cf_handle = self
+            .se
+            .cf_handle(self.config.db_owner_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let db_store_cf_handle = self
+            .se
+            .cf_handle(self.config.db_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let collection_store_cf_handle = self
+            .se
+            .cf_handle(self.config.collection_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mut batch = WriteBatch::default();
+        if let Some(key) = self.get_db_owner_key(sender, db_addr)? {
+            batch.delete_cf(&db_owner_store_cf_handle, key);
+        }
+        batch.put_cf(
+            &db_owner_store_cf_handle,
+            DbOwnerKey(new_owner, block, order).encode()?,
+            db_addr.as_ref(),
+        );
+        let mut buf = BytesMut::with_capacity(1024);
+        database
+            .encode(&mut buf)
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        batch.put_cf(&db_store_cf_handle, db_addr.as_ref(), buf.freeze().as_ref());
+        let collections = self.get_entries_with_prefix::<Collection>(
+            db_addr.as_ref(),
+            self.config.collection_store_cf_name.as_str(),
+        )?;
+        for collection in collections {
+            if collection.sender.as_slice() != sender.as_ref() {
+                continue;
+            }
+            let ck = collection_key::build_collection_key(db_addr, collection.name.as_str())?;
+            let mut buf = BytesMut::with_capacity(1024);
+            Collection {
+                sender: new_owner.as_ref().to_vec(),
+                ..collection
+            }
+            .encode(&mut buf)
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+            batch.put_cf(&collection_store_cf_handle, ck, buf.freeze().as_ref());
+        }
+        self.se
+            .write(batch)
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))
+    }
+
+    ///
+    /// delete the document database with all its collections, documents and the owner
+    /// key. only the owner of the database can do it
+    ///
+    pub fn delete_doc_db(&self, sender: &DB3Address, db_addr: &DB3Address) -> Result<()> {
+        match self.get_database(db_addr)? {
+            Some(DatabaseMessage {
+                database: Some(database_message::Database::DocDb(database)),
+            }) => {
+                if database.sender.as_slice() != sender.as_ref() {
+                    return Err(DB3Error::DatabasePermissionDenied());
+                }
+            }
+            Some(_) => {
+                return Err(DB3Error::InvalidMutationError(
+                    "the database is not a document database".to_string(),
+                ));
+            }
+            None => return Err(DB3Error::DatabaseNotFound(db_addr.to_hex())),
+        }
+        let mut batch = WriteBatch::default();
+        self.delete_entries_with_prefix(
+            &mut batch,
+            self.config.collection_store_cf_name.as_str(),
+            db_addr.as_ref(),
+        )?;
+        let doc_prefix = DbDocKeyV2::build_prefix(db_addr);
+        self.delete_entries_with_prefix(
+            &mut batch,
+            self.config.doc_owner_store_cf_name.as_str(),
+            doc_prefix.as_ref(),
+        )?;
+        self.delete_entries_with_prefix(&mut batch, DOC_NAME_CF, doc_prefix.as_ref())?;
+        self.delete_entries_with_prefix(
+            &mut batch,
+            COLLECTION_DOC_CF,
+            CollectionDocKey::build_prefix(db_addr, None)?.as_ref(),
+        )?;
+        self.delete_entries_with_prefix(
+            &mut batch,
+            DOC_NAME_CF,
+            DbDocNameKeyV2::build_prefix(db_addr, None)?.as_ref(),
+        )?;
+        let state_cf_handle = self
+            .se
+            .cf_handle(STATE_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        batch.delete_cf(&state_cf_handle, db_addr);
+        self.delete_doc_expiry_of_collection(&mut batch, db_addr, None)?;
+        self.delete_entries_with_prefix(
+            &mut batch,
+            DOC_HISTORY_CF,
+            DocHistoryKey::build_prefix(db_addr, None)?.as_ref(),
+        )?;
+        self.delete_entries_with_prefix(
+            &mut batch,
+            DOC_UNIQUE_CF,
+            DocUniqueValueKey::build_prefix(db_addr, None)?.as_ref(),
+        )?;
+        self.delete_entries_with_prefix(
+            &mut batch,
+            DOC_UNIQUE_CF,
+            DocUniqueFieldKey::build_prefix(db_addr, None).as_ref(),
+        )?;
+        self.write_with_doc_state_removed(batch, db_addr, None)?;
+        self.delete_db_internal(sender, db_addr)?;
+        self.db_state.remove(db_addr.to_hex().as_str());
+        if self.config.enable_doc_store {
+            self.doc_store.drop_database(db_addr)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// the ttl in seconds of the documents in the collection. the ttl of the collection
+    /// takes precedence over the ttl of the event database
+    ///
+    fn get_doc_ttl(&self, db_addr: &DB3Address, col_name: &str) -> Result<u64> {
+        if let Some(collection) = self.get_collection(db_addr, col_name)? {
+            if collection.ttl > 0 {
+                return Ok(collection.ttl);
+            }
+        }
+        Ok(self
+            .get_event_db(db_addr)?
+            .map_or(0, |database| database.ttl))
+    }
+
+    fn encode_doc_expiry_value(expire_at: u64, col_name: &str) -> Vec<u8> {
+        let mut value = expire_at.to_be_bytes().to_vec();
+        value.extend_from_slice(col_name.as_bytes());
+        value
+    }
+
+    fn decode_doc_expiry_value(value: &[u8]) -> Result<(u64, &str)> {
+        if value.len() < 8 {
+            return Err(DB3Error::ReadStoreError(
+                "invalid doc expiry value".to_string(),
+            ));
+        }
+        let expire_at = u64::from_be_bytes(
+            <[u8; 8]>::try_from(&value[..8])
+                .map_err(|e| DB3Error::ReadStoreError(format!("invalid expiry time {e}")))?,
+        );
+        let col_name = std::str::from_utf8(&value[8..])
+            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+        Ok((expire_at, col_name))
+    }
+
+    ///
+    /// record the expiry time in millis of the documents which are deleted once they
+    /// have expired
+    ///
+    fn put_doc_expiry(
+        &self,
+        pending: &mut PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_ids: &[i64],
+        expire_at: u64,
+    ) -> Result<()> {
+        for id in doc_ids {
+            pending.put(
+                &self.se,
+                DOC_EXPIRY_CF,
+                DocExpiryKey(expire_at, db_addr, *id).encode()?.as_ref(),
+                col_name.as_bytes(),
+            )?;
+            pending.put(
+                &self.se,
+                DOC_EXPIRY_CF,
+                DocExpiryIndexKey(db_addr, *id).encode()?.as_ref(),
+                Self::encode_doc_expiry_value(expire_at, col_name).as_ref(),
+            )?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// record the expiry of the documents added by the mutation. the server time is
+    /// generated by the storage node and replayed from the doc ids map by the indexer
+    /// and the recover, so all the replicas expire the documents at the same time
+    ///
+    fn put_doc_expiry_for_new_docs(
+        &self,
+        pending: &mut PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_ids: &[i64],
+        doc_ids_map: &HashMap<String, Vec<i64>>,
+        items: &mut Vec<ExtraItem>,
+    ) -> Result<()> {
+        let ttl = self.get_doc_ttl(db_addr, col_name)?;
+        if ttl == 0 || doc_ids.is_empty() {
+            return Ok(());
+        }
+        let server_time = Self::get_server_time(doc_ids_map, items);
+        self.put_doc_expiry(
+            pending,
+            db_addr,
+            col_name,
+            doc_ids,
+            server_time as u64 + ttl * 1000,
+        )
+    }
+
+    /// get the doc id, expiry time and collection name of the documents with expiry
+    fn get_doc_expiry(
+        &self,
+        pending: &PendingWrites,
+        db_addr: &DB3Address,
+        doc_ids: &[i64],
+    ) -> Result<Vec<(i64, u64, String)>> {
+        let mut doc_expiry = Vec::new();
+        for id in doc_ids {
+            let key = DocExpiryIndexKey(db_addr, *id).encode()?;
+            if let Some(value) = pending.get(&self.se, DOC_EXPIRY_CF, key.as_ref())? {
+                let (expire_at, col_name) = Self::decode_doc_expiry_value(value.as_ref())?;
+                doc_expiry.push((*id, expire_at, col_name.to_string()));
+            }
+        }
+        Ok(doc_expiry)
+    }
+
+    /// get the doc id, expiry time and collection name of all the documents in the database
+    fn get_doc_expiry_of_db(&self, db_addr: &DB3Address) -> Result<Vec<(i64, u64, String)>> {
+        let doc_expiry_cf_handle = self
+            .se
+            .cf_handle(DOC_EXPIRY_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let prefix = DocExpiryIndexKey::build_prefix(db_addr);
+        let mut it: DBRawIterator = self
+            .se
+            .prefix_iterator_cf(&doc_expiry_cf_handle, &prefix)
+            .into();
+        let mut doc_expiry = Vec::new();
+        while it.valid() {
+            match (it.key(), it.value()) {
+                (Some(k), Some(v)) if k.starts_with(&prefix) => {
+                    let id = DocExpiryIndexKey::decode_id(k)?;
+                    let (expire_at, col_name) = Self::decode_doc_expiry_value(v)?;
+                    doc_expiry.push((id, expire_at, col_name.to_string()));
+                }
+                _ => break,
+            }
+            it.next();
+        }
+        Ok(doc_expiry)
+    }
+
+    fn delete_doc_expiry(
+        &self,
+        pending: &mut PendingWrites,
+        db_addr: &DB3Address,
+        doc_ids: &[i64],
+    ) -> Result<()> {
+        for (id, expire_at, _) in self.get_doc_expiry(pending, db_addr, doc_ids)? {
+            pending.delete(
+                &self.se,
+                DOC_EXPIRY_CF,
+                DocExpiryKey(expire_at, db_addr, id).encode()?.as_ref(),
+            )?;
+            pending.delete(
+                &self.se,
+                DOC_EXPIRY_CF,
+                DocExpiryIndexKey(db_addr, id).encode()?.as_ref(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// delete the expiry of the documents in the collection or in the database if the name is none
+    fn delete_doc_expiry_of_collection(
+        &self,
+        batch: &mut WriteBatch,
+        db_addr: &DB3Address,
+        col_name: Option<&str>,
+    ) -> Result<()> {
+        let doc_expiry_cf_handle = self
+            .se
+            .cf_handle(DOC_EXPIRY_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        for (id, expire_at, name) in self.get_doc_expiry_of_db(db_addr)? {
+            if col_name.map_or(false, |col_name| col_name != name.as_str()) {
+                continue;
+            }
+            batch.delete_cf(
+                &doc_expiry_cf_handle,
+                DocExpiryKey(expire_at, db_addr, id).encode()?,
+            );
+            batch.delete_cf(
+                &doc_expiry_cf_handle,
+                DocExpiryIndexKey(db_addr, id).encode()?,
+            );
+        }
+        Ok(())
+    }
+
+    ///
+    /// scan the documents expired at the time in millis in the order of the expiry time
+    /// and group them by the database and the collection
+    ///
+    fn scan_expired_docs<F>(
+        &self,
+        now: u64,
+        limit: usize,
+        mut filter: F,
+    ) -> Result<Vec<(DB3Address, String, Vec<i64>)>>
+    where
+        F: FnMut(&DB3Address) -> Result<bool>,
+    {
+        let doc_expiry_cf_handle = self
+            .se
+            .cf_handle(DOC_EXPIRY_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let prefix = EXPIRY_PREFIX.as_bytes();
+        let mut it: DBRawIterator = self
+            .se
+            .prefix_iterator_cf(&doc_expiry_cf_handle, prefix)
+            .into();
+        let mut matched_dbs: HashMap<DB3Address, bool> = HashMap::new();
+        let mut expired_docs: Vec<(DB3Address, String, Vec<i64>)> = Vec::new();
+        let mut count: usize = 0;
+        while it.valid() && count < limit {
+            let (k, v) = match (it.key(), it.value()) {
+                (Some(k), Some(v)) if k.starts_with(prefix) => (k, v),
+                _ => break,
+            };
+            let (expire_at, db_addr, id) = DocExpiryKey::decode(k)?;
+            if expire_at > now {
+                break;
+            }
+            let matched = match matched_dbs.get(&db_addr) {
+                Some(matched) => *matched,
+                None => {
+                    let matched = filter(&db_addr)?;
+                    matched_dbs.insert(db_addr, matched);
+                    matched
+                }
+            };
+            if matched {
+                let col_name =
+                    std::str::from_utf8(v).map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+                match expired_docs
+                    .iter_mut()
+                    .find(|(addr, name, _)| *addr == db_addr && name.as_str() == col_name)
+                {
+                    Some((_, _, ids)) => ids.push(id),
+                    None => expired_docs.push((db_addr, col_name.to_string(), vec![id])),
+                }
+                count += 1;
+            }
+            it.next();
+        }
+        Ok(expired_docs)
+    }
+
+    ///
+    /// get the documents of the document databases expired at the time in millis. the
+    /// storage node deletes them with the expire document mutation
+    ///
+    pub fn get_expired_docs(
+        &self,
+        now: u64,
+        limit: usize,
+    ) -> Result<Vec<(DB3Address, String, Vec<i64>)>> {
+        self.scan_expired_docs(now, limit, |db_addr| {
+            Ok(self.get_event_db(db_addr)?.is_none())
+        })
+    }
+
+    ///
+    /// get the collections of the event databases with their ttl in seconds. the events
+    /// are added by the indexer, so the storage node expires them with the empty ids
+    /// which the indexer resolves from its expiry store
+    ///
+    pub fn get_expiring_event_collections(&self) -> Result<Vec<(DB3Address, String, u64)>> {
+        let mut collections = Vec::new();
+        for database in self.get_all_event_db()? {
+            let db_address_ref: &[u8] = database.address.as_ref();
+            let db_addr = DB3Address::try_from(db_address_ref)?;
+            let (cols, _) = self.get_collection_of_database(&db_addr)?;
+            for col in cols {
+                let ttl = self.get_doc_ttl(&db_addr, col.name.as_str())?;
+                if ttl > 0 {
+                    collections.push((db_addr, col.name, ttl));
+                }
+            }
+        }
+        Ok(collections)
+    }
+
+    ///
+    /// delete the documents expired at the time in millis and clear their expiry. the
+    /// documents not expired yet are skipped, so the result only depends on the time
+    /// recorded in the mutation and everyone can send the mutation. the empty ids
+    /// mean all the documents of the collection expired at the time
+    ///
+    pub fn expire_docs(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_ids: &[i64],
+        now: u64,
+    ) -> Result<Vec<i64>> {
+        let mut pending = PendingWrites::default();
+        let result = self.expire_docs_internal(&mut pending, db_addr, col_name, doc_ids, now);
+        self.commit_or_abort(pending, result)
+    }
+
+    fn expire_docs_internal(
+        &self,
+        pending: &mut PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_ids: &[i64],
+        now: u64,
+    ) -> Result<Vec<i64>> {
+        let mut doc_ids = if doc_ids.is_empty() {
+            self.scan_expired_docs(now, usize::MAX, |addr| Ok(addr == db_addr))?
+                .into_iter()
+                .filter(|(_, name, _)| name.as_str() == col_name)
+                .flat_map(|(_, _, ids)| ids)
+                .collect()
+        } else {
+            doc_ids.to_vec()
+        };
+        doc_ids.sort();
+        doc_ids.dedup();
+        let expired_ids: Vec<i64> = self
+            .get_doc_expiry(pending, db_addr, &doc_ids)?
+            .into_iter()
+            .filter(|(_, expire_at, name)| *expire_at <= now && name.as_str() == col_name)
+            .map(|(id, _, _)| id)
+            .collect();
+        if expired_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        // the documents may have been deleted before they expire
+        let existing_ids: Vec<i64> = self
+            .get_existing_doc_revisions(pending, db_addr, &expired_ids)?
+            .into_iter()
+            .map(|(id, _, _)| id)
+            .collect();
+        let removed = !existing_ids.is_empty() && self.is_db_collection_exist(db_addr, col_name)?;
+        if removed {
+            self.remove_docs_internal(pending, db_addr, col_name, &existing_ids)?;
+        }
+        // the removed documents have cleared their expiry
+        let left_ids: Vec<i64> = expired_ids
+            .into_iter()
+            .filter(|id| !removed || !existing_ids.contains(id))
+            .collect();
+        self.delete_doc_expiry(pending, db_addr, &left_ids)?;
+        Ok(existing_ids)
+    }
+
+    ///
+    /// record the current versions of the documents at the block and the order of the
+    /// mutation if the collection keeps the history. the deleted documents are recorded
+    /// with an empty value. the doc store is only enabled on the indexer which holds
+    /// the docs, so the others keep no history
+    ///
+    fn put_doc_history(
+        &self,
+        pending: &mut PendingWrites,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_ids: &[i64],
+        block: u64,
+        order: u32,
+    ) -> Result<()> {
+        if !self.config.enable_doc_store || doc_ids.is_empty() {
+            return Ok(());
+        }
+        match self.get_collection(db_addr, col_name)? {
+            Some(collection) if collection.keep_history => {}
+            _ => return Ok(()),
+        }
+        let revisions = self.get_doc_revisions(pending, db_addr, doc_ids)?;
+        let keys: HashMap<i64, String> = self
+            .get_doc_keys(pending, db_addr, doc_ids)?
+            .into_iter()
+            .collect();
+        for (id, revision) in doc_ids.iter().zip(revisions.into_iter()) {
+            let history_key = DocHistoryKey(db_addr, col_name, *id, block, order).encode()?;
+            let doc = match revision {
+                Some(_) => self.doc_store.get_doc(db_addr, col_name, *id)?,
+                None => None,
+            };
+            match (doc, revision) {
+                (Some(doc), Some(revision)) => {
+                    let mut buf = BytesMut::with_capacity(1024);
+                    Document {
+                        id: *id,
+                        doc,
+                        revision,
+                        key: keys.get(id).cloned().unwrap_or_default(),
+                    }
+                    .encode(&mut buf)
+                    .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+                    pending.put(
+                        &self.se,
+                        DOC_HISTORY_CF,
+                        history_key.as_ref(),
+                        buf.freeze().as_ref(),
+                    )?;
+                }
+                _ => pending.put(&self.se, DOC_HISTORY_CF, history_key.as_ref(), b"")?,
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// record the versions and the hashes of the documents changed by the mutation after all
+    /// its bodies are applied, so the document changed by many bodies has one version and
+    /// one hash for the mutation
+    ///
+    fn put_changed_docs(
+        &self,
+        pending: &mut PendingWrites,
+        dm: &Mutation,
+        block: u64,
+        order: u32,
+    ) -> Result<()> {
+        let changed_docs: Vec<(DB3Address, String, Vec<i64>)> = pending
+            .undo_records
+            .iter()
+            .filter_map(|record| record.changed_docs())
+            .collect();
+        let mut state_docs: BTreeMap<DB3Address, BTreeSet<i64>> = BTreeMap::new();
+        for (db_addr, col_name, doc_ids) in changed_docs.iter() {
+            self.put_doc_history(pending, db_addr, col_name.as_str(), doc_ids, block, order)?;
+            state_docs
+                .entry(*db_addr)
+                .or_default()
+                .extend(doc_ids.iter().copied());
+        }
+        let mut data = merkle::hash(dm.encode_to_vec().as_ref()).to_vec();
+        data.extend_from_slice(block.to_be_bytes().as_ref());
+        data.extend_from_slice(order.to_be_bytes().as_ref());
+        let mutation_hash = merkle::hash(&data);
+        for (db_addr, doc_ids) in state_docs.iter() {
+            let doc_ids: Vec<i64> = doc_ids.iter().copied().collect();
+            self.put_doc_state(pending, db_addr, &doc_ids, Some(&mutation_hash))?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// chain the hash of the mutation into the hashes of the documents changed by it, so the
+    /// hash of a document commits to the mutations which produce its content as the storage
+    /// node holds no content without the doc store. the documents which no longer exist are
+    /// removed from the state tree
+    ///
+    fn put_doc_state(
+        &self,
+        pending: &mut PendingWrites,
+        db_addr: &DB3Address,
+        doc_ids: &[i64],
+        mutation_hash: Option<&merkle::Hash>,
+    ) -> Result<()> {
+        let revisions = match mutation_hash {
+            Some(_) => self.get_doc_revisions(pending, db_addr, doc_ids)?,
+            None => vec![None; doc_ids.len()],
+        };
+        for (id, revision) in doc_ids.iter().zip(revisions.into_iter()) {
+            let leaf_key = StateLeafKey(db_addr, *id);
+            let key = leaf_key.encode()?;
+            match (revision, mutation_hash) {
+                (Some(revision), Some(mutation_hash)) => {
+                    let mut data = pending
+                        .get(&self.se, STATE_TREE_CF, key.as_ref())?
+                        .unwrap_or_default();
+                    data.extend_from_slice(mutation_hash);
+                    data.extend_from_slice(revision.to_be_bytes().as_ref());
+                    pending.put(
+                        &self.se,
+                        STATE_TREE_CF,
+                        key.as_ref(),
+                        merkle::hash(&data).as_ref(),
+                    )?;
+                }
+                _ => pending.delete(&self.se, STATE_TREE_CF, key.as_ref())?,
+            }
+            pending.state_buckets.insert(leaf_key.bucket());
+        }
+        Ok(())
+    }
+
+    ///
+    /// update the buckets with the changed documents and the nodes on their paths to the
+    /// root of the state tree. the empty subtree has the zero hash
+    ///
+    fn update_state_tree(&self, pending: &mut PendingWrites) -> Result<()> {
+        let empty_hash: merkle::Hash = [0; merkle::HASH_LENGTH];
+        for bucket in std::mem::take(&mut pending.state_buckets) {
+            let prefix = StateLeafKey::build_prefix(bucket);
+            let leaves: Vec<merkle::Hash> = pending
+                .get_with_prefix(&self.se, STATE_TREE_CF, &prefix)?
+                .into_iter()
+                .map(|(k, v)| {
+                    let mut data = k[prefix.len()..].to_vec();
+                    data.extend_from_slice(&v);
+                    merkle::hash_leaf(&data)
+                })
+                .collect();
+            let mut hash = merkle::merkle_root(&leaves);
+            let mut index = bucket as u32;
+            self.put_state_node(pending, STATE_TREE_DEPTH, index, &hash)?;
+            for level in (0..STATE_TREE_DEPTH).rev() {
+                let sibling = self.get_state_node(pending, level + 1, index ^ 1)?;
+                if hash != empty_hash || sibling != empty_hash {
+                    hash = if index % 2 == 0 {
+                        merkle::hash_node(&hash, &sibling)
+                    } else {
+                        merkle::hash_node(&sibling, &hash)
+                    };
+                }
+                index /= 2;
+                self.put_state_node(pending, level, index, &hash)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_state_node(
+        &self,
+        pending: &PendingWrites,
+        level: u8,
+        index: u32,
+    ) -> Result<merkle::Hash> {
+        let key = StateNodeKey(level, index).encode()?;
+        match pending.get(&self.se, STATE_TREE_CF, key.as_ref())? {
+            Some(value) => <merkle::Hash>::try_from(value.as_slice())
+                .map_err(|e| DB3Error::ReadStoreError(format!("invalid state node {e}"))),
+            None => Ok([0; merkle::HASH_LENGTH]),
+        }
+    }
+
+    /// the nodes of the empty subtrees are not stored
+    fn put_state_node(
+        &self,
+        pending: &mut PendingWrites,
+        level: u8,
+        index: u32,
+        hash: &merkle::Hash,
+    ) -> Result<()> {
+        let key = StateNodeKey(level, index).encode()?;
+        if hash.iter().all(|b| *b == 0) {
+            pending.delete(&self.se, STATE_TREE_CF, key.as_ref())
+        } else {
+            pending.put(&self.se, STATE_TREE_CF, key.as_ref(), hash.as_ref())
+        }
+    }
+
+    ///
+    /// write the batch deleting the documents of the collection or the database if the name
+    /// is none, and remove the documents from the state tree with it
+    ///
+    fn write_with_doc_state_removed(
+        &self,
+        mut batch: WriteBatch,
+        db_addr: &DB3Address,
+        col_name: Option<&str>,
+    ) -> Result<()> {
+        let collection_doc_cf_handle = self
+            .se
+            .cf_handle(COLLECTION_DOC_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let prefix = CollectionDocKey::build_prefix(db_addr, col_name)?;
+        let mut it: DBRawIterator = self
+            .se
+            .prefix_iterator_cf(&collection_doc_cf_handle, &prefix)
+            .into();
+        let mut doc_ids = Vec::new();
+        while it.valid() {
+            match it.key() {
+                Some(k) if k.starts_with(&prefix) => {
+                    // the doc id is at the end of the keys of all the collections
+                    let col_prefix = &k[..k.len().saturating_sub(8)];
+                    doc_ids.push(CollectionDocKey::decode_id(k, col_prefix)?);
+                }
+                _ => break,
+            }
+            it.next();
+        }
+        let mut pending = PendingWrites::default();
+        self.put_doc_state(&mut pending, db_addr, &doc_ids, None)?;
+        let _guard = self
+            .state_tree_lock
+            .lock()
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        self.update_state_tree(&mut pending)?;
+        pending.write_into(&self.se, &mut batch)?;
+        self.se
+            .write(batch)
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))
+    }
+
+    fn decode_doc_history_value(value: &[u8]) -> Result<Option<Document>> {
+        if value.is_empty() {
+            return Ok(None);
+        }
+        Document::decode(value)
+            .map(Some)
+            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))
+    }
+
+    fn check_history_kept(&self, db_addr: &DB3Address, col_name: &str) -> Result<()> {
+        match self.get_collection(db_addr, col_name)? {
+            Some(collection) if collection.keep_history => Ok(()),
+            Some(_) => Err(DB3Error::HistoryNotKept(col_name.to_string())),
+            None => Err(DB3Error::CollectionNotFound(
+                col_name.to_string(),
+                db_addr.to_hex(),
+            )),
+        }
+    }
+
+    /// seek the last version of the document written in or before the block
+    fn seek_doc_version_as_of(
+        it: &mut DBRawIterator,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_id: i64,
+        block: u64,
+    ) -> Result<Option<Document>> {
+        let prefix = DocHistoryKey::build_doc_prefix(db_addr, col_name, doc_id)?;
+        it.seek_for_prev(DocHistoryKey(db_addr, col_name, doc_id, block, u32::MAX).encode()?);
+        match (it.key(), it.value()) {
+            (Some(k), Some(v)) if k.starts_with(&prefix) => Self::decode_doc_history_value(v),
+            _ => Ok(None),
+        }
+    }
+
+    ///
+    /// get the version of the document as of the block and None if the document did not
+    /// exist at that time
+    ///
+    pub fn get_doc_as_of(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_id: i64,
+        block: u64,
+    ) -> Result<Option<Document>> {
+        self.check_history_kept(db_addr, col_name)?;
+        let doc_history_cf_handle = self
+            .se
+            .cf_handle(DOC_HISTORY_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mut it: DBRawIterator = self.se.raw_iterator_cf(&doc_history_cf_handle);
+        Self::seek_doc_version_as_of(&mut it, db_addr, col_name, doc_id, block)
+    }
+
+    ///
+    /// get the versions of all the documents in the collection as of the block
+    ///
+    pub fn get_docs_as_of(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        block: u64,
+    ) -> Result<Vec<Document>> {
+        self.check_history_kept(db_addr, col_name)?;
+        let doc_history_cf_handle = self
+            .se
+            .cf_handle(DOC_HISTORY_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let prefix = DocHistoryKey::build_prefix(db_addr, Some(col_name))?;
+        let mut it: DBRawIterator = self.se.raw_iterator_cf(&doc_history_cf_handle);
+        let mut documents = vec![];
+        it.seek(&prefix);
+        // the versions of a document are ordered by the block and the order, so only the
+        // last one in or before the block of every document is read
+        while let Some(k) = it.key() {
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            let (id, _, _) = DocHistoryKey::decode(k, &prefix)?;
+            if let Some(document) =
+                Self::seek_doc_version_as_of(&mut it, db_addr, col_name, id, block)?
+            {
+                documents.push(document);
+            }
+            match id.checked_add(1) {
+                Some(next_id) => {
+                    it.seek(DocHistoryKey::build_doc_prefix(db_addr, col_name, next_id)?)
+                }
+                None => break,
+            }
+        }
+        Ok(documents)
+    }
+
+    ///
+    /// get the version of the document with the key as of the block. the key is resolved
+    /// with the current documents, and the versions in the collection are looked up only
+    /// if the key was held by a document deleted since then
+    ///
+    pub fn get_doc_by_key_as_of(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        key: &str,
+        block: u64,
+    ) -> Result<Option<Document>> {
+        if let Some(id) = self.get_doc_id_by_key(db_addr, col_name, key)? {
+            if let Some(document) = self.get_doc_as_of(db_addr, col_name, id, block)? {
+                return Ok(Some(document));
+            }
+        }
+        Ok(self
+            .get_docs_as_of(db_addr, col_name, block)?
+            .into_iter()
+            .find(|document| document.key.as_str() == key))
+    }
+
+    ///
+    /// run the query on the versions of the documents in the collection as of the block
+    ///
+    pub fn query_docs_as_of(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        query: &Query,
+        block: u64,
+    ) -> Result<(Vec<Document>, u64)> {
+        let snapshot = self.get_docs_as_of(db_addr, col_name, block)?;
+        if !self.config.enable_doc_store {
+            return Ok((vec![], 0));
+        }
+        let docs: Vec<(i64, String)> = snapshot
+            .iter()
+            .map(|document| (document.id, document.doc.to_string()))
+            .collect();
+        let (result, count) = self
+            .doc_store
+            .execute_query_on_docs(col_name, &docs, query)?;
+        let versions: HashMap<i64, Document> = snapshot
+            .into_iter()
+            .map(|document| (document.id, document))
+            .collect();
+        let documents = result
+            .into_iter()
+            .map(|(id, doc)| {
+                let version = versions.get(&id);
+                Document {
+                    id,
+                    doc,
+                    revision: version.map_or(0, |v| v.revision),
+                    key: version.map(|v| v.key.to_string()).unwrap_or_default(),
+                }
+            })
+            .collect();
+        Ok((documents, count))
+    }
+
+    pub fn create_predefined_doc_database(
+        &self,
+        sender: &DB3Address,
+        db_addr: &DbId,
+        desc: &str,
+        block: u64,
+        order: u32,
+    ) -> Result<()> {
+        if let Ok(Some(_)) = self.get_database(db_addr.address()) {
+            return Err(DB3Error::DatabaseAlreadyExist(db_addr.to_hex()));
+        }
+        let db_store_cf_handle = self
+            .se
+            .cf_handle(self.config.db_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let db_owner_store_cf_handle = self
+            .se
+            .cf_handle(self.config.db_owner_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let db_owner = DbOwnerKey(sender, block, order);
+        let db_owner_encoded_key = db_owner.encode()?;
+        let database = DocumentDatabase {
+            address: db_addr.as_ref().to_vec(),
+            sender: sender.as_ref().to_vec(),
+            desc: desc.to_string(),
+            acl: None,
+        };
+        let database_msg = DatabaseMessage {
+            database: Some(database_message::Database::DocDb(database)),
+        };
+        let mut buf = BytesMut::with_capacity(1024);
+        database_msg
+            .encode(&mut buf)
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        let buf = buf.freeze();
+        let mut batch = WriteBatch::default();
+        batch.put_cf(&db_store_cf_handle, db_addr.as_ref(), buf.as_ref());
+        batch.put_cf(
+            &db_owner_store_cf_handle,
+            &db_owner_encoded_key,
+            db_addr.as_ref(),
+        );
+        self.se
+            .write(batch)
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        let db_addr_hex = db_addr.to_hex();
+        self.update_db_state_for_add_db(db_addr_hex.as_str());
+        if self.config.enable_doc_store {
+            self.doc_store
+                .create_database(db_addr.address())
+                .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        }
+        Ok(())
+    }
+
+    pub fn create_doc_database(
+        &self,
+        sender: &DB3Address,
+        mutation: &DocumentDatabaseMutation,
+        nonce: u64,
+        network_id: u64,
+        block: u64,
+        order: u32,
+    ) -> Result<DbId> {
+        let db_addr = DbId::from((sender, nonce, network_id));
+        self.create_predefined_doc_database(
+            sender,
+            &db_addr,
+            mutation.db_desc.as_str(),
+            block,
+            order,
+        )?;
+        Ok(db_addr)
+    }
+
+    fn add_index<'a>(
+        &self,
+        db_addr: &DB3Address,
+        col: &str,
+        indexes: &Vec<Index>,
+        sender: &DB3Address,
+    ) -> Result<()> {
+        let db = self.get_database(db_addr)?;
+        if db.is_none() {
+            return Err(DB3Error::DatabaseNotFound(db_addr.to_hex()));
+        }
+        match self.get_collection(db_addr, col)? {
+            Some(collection) => {
+                let collection_sender: &[u8] = collection.sender.as_ref();
+                if collection_sender != sender.as_ref() {
+                    return Err(DB3Error::CollectionPermissionDenied());
+                }
+                let path_set: HashSet<&String> =
+                    collection.index_fields.iter().map(|x| &x.path).collect();
+                let exist_paths: Vec<&String> = indexes
+                    .iter()
+                    .filter(|x| path_set.contains(&x.path))
+                    .map(|x| &x.path)
+                    .collect();
+                if exist_paths.len() > 0 {
+                    return Err(DB3Error::InvalidKeyPathError(format!(
+                        "the index paths {:?} exist",
+                        exist_paths
+                    )));
+                }
+                // the unique values are recorded when the documents are written, so the
+                // unique key can not be added to a collection with documents
+                let unique_keys = Self::get_unique_keys(&collection.index_fields);
+                let has_docs = self
+                    .get_collection_state(db_addr, col)
+                    .map_or(false, |state| state.total_doc_count > 0);
+                if let Some((key, _)) = Self::get_unique_keys(indexes)
+                    .iter()
+                    .find(|(key, _)| has_docs && !unique_keys.iter().any(|(k, _)| k == key))
+                {
+                    return Err(DB3Error::InvalidKeyPathError(format!(
+                        "the unique index {key} can only be added to an empty collection"
+                    )));
+                }
+                let new_indexes = [collection.index_fields, indexes.clone()].concat();
+                Self::check_indexes(&new_indexes)?;
+                self.put_collection(
+                    db_addr,
+                    &Collection {
+                        index_fields: new_indexes,
+                        ..collection
+                    },
+                )?;
+                if self.config.enable_doc_store {
+                    self.doc_store
+                        .add_index(db_addr, col, indexes)
+                        .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+                }
+                Ok(())
+            }
+            None => Err(DB3Error::CollectionNotFound(
+                col.to_string(),
+                db_addr.to_hex(),
+            )),
+        }
+    }
+
+    fn drop_index(
+        &self,
+        db_addr: &DB3Address,
+        col: &str,
+        paths: &Vec<String>,
+        sender: &DB3Address,
+    ) -> Result<()> {
+        let db = self.get_database(db_addr)?;
+        if db.is_none() {
+            return Err(DB3Error::DatabaseNotFound(db_addr.to_hex()));
+        }
+        match self.get_collection(db_addr, col)? {
+            Some(collection) => {
+                let collection_sender: &[u8] = collection.sender.as_ref();
+                if collection_sender != sender.as_ref() {
+                    return Err(DB3Error::CollectionPermissionDenied());
+                }
+                let path_set: HashSet<&String> =
+                    collection.index_fields.iter().map(|x| &x.path).collect();
+                let missing_paths: Vec<&String> =
+                    paths.iter().filter(|x| !path_set.contains(x)).collect();
+                if missing_paths.len() > 0 || paths.is_empty() {
+                    return Err(DB3Error::InvalidKeyPathError(format!(
+                        "the index paths {:?} do not exist",
+                        missing_paths
+                    )));
+                }
+                let (dropped_indexes, left_indexes): (Vec<Index>, Vec<Index>) = collection
+                    .index_fields
+                    .into_iter()
+                    .partition(|x| paths.contains(&x.path));
+                self.put_collection(
+                    db_addr,
+                    &Collection {
+                        index_fields: left_indexes.clone(),
+                        ..collection
+                    },
+                )?;
+                // the values of the key which is no longer unique are removed
+                let left_unique_keys = Self::get_unique_keys(&left_indexes);
+                let mut batch = WriteBatch::default();
+                for (key, _) in Self::get_unique_keys(&dropped_indexes) {
+                    if !left_unique_keys.iter().any(|(k, _)| k == &key) {
+                        self.delete_doc_unique_values_of_collection(
+                            &mut batch,
+                            db_addr,
+                            col,
+                            Some(key.as_str()),
+                        )?;
+                    }
+                }
+                self.se
+                    .write(batch)
+                    .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+                if self.config.enable_doc_store {
+                    // the field still used by the other indexes must be kept in the doc store
+                    let left_fields: Vec<IndexField> = left_indexes
+                        .iter()
+                        .flat_map(doc_store::index_fields)
+                        .collect();
+                    let mut dropped_fields: Vec<IndexField> = vec![];
+                    for field in dropped_indexes.iter().flat_map(doc_store::index_fields) {
+                        if !left_fields.contains(&field) && !dropped_fields.contains(&field) {
+                            dropped_fields.push(field);
+                        }
+                    }
+                    self.doc_store
+                        .drop_index(db_addr, col, &dropped_fields)
+                        .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+                }
+                Ok(())
+            }
+            None => Err(DB3Error::CollectionNotFound(
+                col.to_string(),
+                db_addr.to_hex(),
+            )),
+        }
+    }
+
+    ///
+    /// replace the schema of the collection and the schema is removed if it's none. only
+    /// the sender of the collection can update it
+    ///
+    fn update_collection_schema(
+        &self,
+        db_addr: &DB3Address,
+        col: &str,
+        schema: Option<&CollectionSchema>,
+        sender: &DB3Address,
+    ) -> Result<()> {
+        let db = self.get_database(db_addr)?;
+        if db.is_none() {
+            return Err(DB3Error::DatabaseNotFound(db_addr.to_hex()));
+        }
+        match self.get_collection(db_addr, col)? {
+            Some(collection) => {
+                let collection_sender: &[u8] = collection.sender.as_ref();
+                if collection_sender != sender.as_ref() {
+                    return Err(DB3Error::CollectionPermissionDenied());
+                }
+                if let Some(schema) = schema {
+                    doc_schema::check_schema(schema)?;
+                }
+                self.put_collection(
+                    db_addr,
+                    &Collection {
+                        schema: schema.cloned(),
+                        ..collection
+                    },
+                )
+            }
+            None => Err(DB3Error::CollectionNotFound(
+                col.to_string(),
+                db_addr.to_hex(),
+            )),
+        }
+    }
+
+    ///
+    /// get the role of the address on the collection and whether everyone can read the
+    /// collection. the acl of the collection overrides the acl of the database
+    ///
+    pub fn get_access_role(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        address: Option<&DB3Address>,
+    ) -> Result<(AccessRole, bool)> {
+        let (db_owner, db_acl) = match self.get_database(db_addr)? {
+            Some(DatabaseMessage {
+                database: Some(database_message::Database::DocDb(db)),
+            }) => (db.sender, db.acl),
+            Some(DatabaseMessage {
+                database: Some(database_message::Database::EventDb(db)),
+            }) => (db.sender, None),
+            _ => return Err(DB3Error::DatabaseNotFound(db_addr.to_hex())),
+        };
+        let collection =
+            self.get_collection(db_addr, col_name)?
+                .ok_or(DB3Error::CollectionNotFound(
+                    col_name.to_string(),
+                    db_addr.to_hex(),
+                ))?;
+        let acl = collection.acl.or(db_acl);
+        let public_read = acl.as_ref().map_or(true, |acl| acl.public_read);
+        let address: &[u8] = match address {
+            Some(address) => address.as_ref(),
+            None => return Ok((AccessRole::None, public_read)),
+        };
+        let role = if address == db_owner.as_slice() || address == collection.sender.as_slice() {
+            AccessRole::Owner
+        } else {
+            match &acl {
+                None => AccessRole::Contributor,
+                Some(acl) if acl.writers.iter().any(|w| w.as_slice() == address) => {
+                    AccessRole::Writer
+                }
+                Some(acl) if acl.readers.iter().any(|r| r.as_slice() == address) => {
+                    AccessRole::Reader
+                }
+                Some(_) => AccessRole::None,
+            }
+        };
+        Ok((role, public_read))
+    }
+
+    /// check the reader can read the documents of the collection
+    pub fn check_read_permission(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        reader: Option<&DB3Address>,
+    ) -> Result<()> {
+        let (role, public_read) = self.get_access_role(db_addr, col_name, reader)?;
+        if public_read || role >= AccessRole::Reader {
+            Ok(())
+        } else {
+            Err(DB3Error::ReadPermissionDenied(col_name.to_string()))
+        }
+    }
+
+    fn check_write_permission(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        sender: &DB3Address,
+    ) -> Result<AccessRole> {
+        let (role, _) = self.get_access_role(db_addr, col_name, Some(sender))?;
+        if role >= AccessRole::Contributor {
+            Ok(role)
+        } else {
+            Err(DB3Error::CollectionPermissionDenied())
+        }
+    }
+
+    /// the owners and writers of the collection can change all the documents
+    fn verify_docs_permission(
+        &self,
+        pending: &PendingWrites,
+        sender: &DB3Address,
+        db_addr: &DB3Address,
+        col_name: &str,
+        doc_ids: &Vec<i64>,
+    ) -> Result<()> {
+        if self.check_write_permission(db_addr, col_name, sender)? < AccessRole::Writer {
+            return self.verify_docs_ownership_internal(pending, sender, db_addr, doc_ids);
+        }
+        if self
+            .get_doc_owners(pending, db_addr, doc_ids)?
+            .iter()
+            .any(|owner| owner.is_none())
+        {
+            return Err(DB3Error::OwnerVerifyFailed(format!("doc id is not found")));
+        }
+        Ok(())
+    }
+
+    ///
+    /// change the acl of the database if the collection name is empty, otherwise the acl
+    /// of the collection. the acl of the collection can be changed by the owners of the
+    /// database and the collection
+    ///
+    fn update_access_control(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        acl: Option<&AccessControl>,
+        sender: &DB3Address,
+    ) -> Result<()> {
+        if let Some(acl) = acl {
+            if acl
+                .writers
+                .iter()
+                .chain(acl.readers.iter())
+                .any(|address| address.len() != DB3_ADDRESS_LENGTH)
+            {
+                return Err(DB3Error::InvalidMutationError(
+                    "the acl contains an invalid address".to_string(),
+                ));
+            }
+        }
+        let mut database = match self.get_database(db_addr)? {
+            Some(DatabaseMessage {
+                database: Some(database_message::Database::DocDb(db)),
+            }) => db,
+            Some(_) => {
+                return Err(DB3Error::InvalidMutationError(
+                    "the acl is only supported by the document database".to_string(),
+                ));
+            }
+            None => return Err(DB3Error::DatabaseNotFound(db_addr.to_hex())),
+        };
+        let is_db_owner = database.sender.as_slice() == sender.as_ref();
+        if col_name.is_empty() {
+            if !is_db_owner {
+                return Err(DB3Error::DatabasePermissionDenied());
+            }
+            database.acl = acl.cloned();
+            self.put_entry::<DatabaseMessage>(
+                self.config.db_store_cf_name.as_str(),
+                db_addr.as_ref(),
+                DatabaseMessage {
+                    database: Some(database_message::Database::DocDb(database)),
+                },
+            )
+        } else {
+            let collection =
+                self.get_collection(db_addr, col_name)?
+                    .ok_or(DB3Error::CollectionNotFound(
+                        col_name.to_string(),
+                        db_addr.to_hex(),
+                    ))?;
+            if !is_db_owner && collection.sender.as_slice() != sender.as_ref() {
+                return Err(DB3Error::CollectionPermissionDenied());
+            }
+            self.put_collection(
+                db_addr,
+                &Collection {
+                    acl: acl.cloned(),
+                    ..collection
+                },
+            )
+        }
+    }
+
+    /// validate the json documents against the schema of the collection
+    fn check_docs_schema(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        docs: &[String],
+        partial: bool,
+    ) -> Result<()> {
+        let schema = match self.get_collection(db_addr, col_name)? {
+            Some(Collection {
+                schema: Some(schema),
+                ..
+            }) => schema,
+            _ => return Ok(()),
+        };
+        for doc in docs {
+            let value: serde_json::Value = serde_json::from_str(doc.as_str())
+                .map_err(|e| DB3Error::InvalidDocumentBytes(format!("{e}")))?;
+            doc_schema::validate_doc(&schema, &value, partial)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// keep the errors which the client can handle and wrap the others
+    ///
+    fn to_apply_mutation_error(e: DB3Error) -> DB3Error {
+        match e {
+            DB3Error::PreconditionFailed(_)
+            | DB3Error::DuplicateUniqueKey(_, _)
+            | DB3Error::DocumentKeyAlreadyExist(_, _)
+            | DB3Error::DocumentSchemaMismatch(_) => e,
+            _ => DB3Error::ApplyMutationError(format!("{e}")),
+        }
+    }
+
+    ///
+    /// the errors returned by applying the mutation which fail it on every node
+    ///
+    pub fn is_apply_mutation_error(e: &DB3Error) -> bool {
+        matches!(
+            e,
+            DB3Error::ApplyMutationError(_)
+                | DB3Error::PreconditionFailed(_)
+                | DB3Error::DuplicateUniqueKey(_, _)
+                | DB3Error::DocumentKeyAlreadyExist(_, _)
+                | DB3Error::DocumentSchemaMismatch(_)
+        )
+    }
+
+    ///
+    /// the server time is generated by the storage node once for a mutation and replayed
+    /// from the doc ids map by the indexer and the recover
+    ///
+    fn get_server_time(doc_ids_map: &HashMap<String, Vec<i64>>, items: &mut Vec<ExtraItem>) -> i64 {
+        if let Some(t) = doc_ids_map.get(SERVER_TIME_KEY).and_then(|t| t.first()) {
+            return *t;
+        }
+        if let Some(t) = items
+            .iter()
+            .find(|item| item.key == SERVER_TIME_KEY)
+            .and_then(|item| item.value.parse::<i64>().ok())
+        {
+            return t;
+        }
+        let t = times::get_current_time_in_millis() as i64;
+        items.push(ExtraItem {
+            key: SERVER_TIME_KEY.to_string(),
+            value: t.to_string(),
+        });
+        t
+    }
+
+    ///
+    /// apply the bodies of the document mutation to the doc store and collect their
+    /// writes to the storage engine in `pending`
+    ///
+    fn apply_bodies(
+        &self,
+        pending: &mut PendingWrites,
+        action: MutationAction,
+        dm: &Mutation,
+        address: &DB3Address,
+        block: u64,
+        order: u32,
+        doc_ids_map: &HashMap<String, Vec<i64>>,
+        items: &mut Vec<ExtraItem>,
+    ) -> Result<()> {
+        match action {
+            MutationAction::AddCollection => {
+                for (i, body) in dm.bodies.iter().enumerate() {
+                    let db_address_ref: &[u8] = body.db_address.as_ref();
+                    let db_addr = DB3Address::try_from(db_address_ref)
+                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                    if let Some(Body::CollectionMutation(ref col_mutation)) = &body.body {
+                        self.create_collection_internal(
+                            pending,
+                            address,
+                            &db_addr,
+                            col_mutation,
+                            block,
+                            order,
+                            i as u16,
+                        )
+                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                        info!(
+                            "add collection with db_addr {}, collection_name: {}, from owner {}",
+                            db_addr.to_hex().as_str(),
+                            col_mutation.collection_name.as_str(),
+                            address.to_hex().as_str()
+                        );
+                        let item = ExtraItem {
+                            key: "collection".to_string(),
+                            value: col_mutation.collection_name.to_string(),
+                        };
+                        items.push(item);
+                    }
+                }
+            }
+            MutationAction::AddDocument => {
+                for (i, body) in dm.bodies.iter().enumerate() {
+                    let db_address_ref: &[u8] = body.db_address.as_ref();
+                    let db_addr = DB3Address::try_from(db_address_ref)
+                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                    if let Some(Body::DocumentMutation(ref doc_mutation)) = &body.body {
+                        let mut docs = Vec::<String>::new();
+                        for buf in doc_mutation.documents.iter() {
+                            let document = bytes_to_bson_document(buf.clone())
+                                .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                            docs.push(bson_document_into_json_str(&document));
+                        }
+                        let ids = self
+                            .add_docs_internal(
+                                pending,
+                                &db_addr,
+                                address,
+                                doc_mutation.collection_name.as_str(),
+                                &docs,
+                                doc_ids_map.get(i.to_string().as_str()),
+                                &doc_mutation.keys,
+                            )
+                            .map_err(Self::to_apply_mutation_error)?;
+                        self.put_doc_expiry_for_new_docs(
+                            pending,
+                            &db_addr,
+                            doc_mutation.collection_name.as_str(),
+                            &ids,
+                            doc_ids_map,
+                            items,
+                        )
+                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                        debug!(
+                                    "add documents with db_addr {}, collection_name: {}, from owner {}, document size: {}",
+                                    db_addr.to_hex().as_str(),
+                                    doc_mutation.collection_name.as_str(),
+                                    address.to_hex().as_str(),
+                                    ids.len()
+                                );
+                        // return document keys
+                        for id in ids {
+                            let item = ExtraItem {
+                                key: format!("{NEW_DOCS_KEY_PREFIX}{i}"),
+                                value: id.to_string(),
+                            };
+                            items.push(item);
+                        }
+                    }
+                }
+            }
+            MutationAction::UpsertDocument => {
+                for (i, body) in dm.bodies.iter().enumerate() {
+                    let db_address_ref: &[u8] = body.db_address.as_ref();
+                    let db_addr = DB3Address::try_from(db_address_ref)
+                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                    if let Some(Body::DocumentMutation(ref doc_mutation)) = &body.body {
+                        let mut docs = Vec::<String>::new();
+                        for buf in doc_mutation.documents.iter() {
+                            let document = bytes_to_bson_document(buf.clone())
+                                .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                            docs.push(bson_document_into_json_str(&document));
+                        }
+                        let ids = self
+                            .upsert_docs_internal(
+                                pending,
+                                &db_addr,
+                                address,
+                                doc_mutation.collection_name.as_str(),
+                                &docs,
+                                &doc_mutation.keys,
+                                &doc_mutation.preconditions,
+                                doc_ids_map.get(i.to_string().as_str()),
+                            )
+                            .map_err(Self::to_apply_mutation_error)?;
+                        self.put_doc_expiry_for_new_docs(
+                            pending,
+                            &db_addr,
+                            doc_mutation.collection_name.as_str(),
+                            &ids,
+                            doc_ids_map,
+                            items,
+                        )
+                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                        debug!(
+                            "upsert documents with db_addr {}, collection_name: {}, from owner {}, new document size: {}",
+                            db_addr.to_hex().as_str(),
+                            doc_mutation.collection_name.as_str(),
+                            address.to_hex().as_str(),
+                            ids.len()
+                        );
+                        // only the ids of the inserted documents are returned
+                        for id in ids {
+                            let item = ExtraItem {
+                                key: format!("{NEW_DOCS_KEY_PREFIX}{i}"),
+                                value: id.to_string(),
+                            };
+                            items.push(item);
+                        }
+                    }
+                }
+            }
+            MutationAction::UpdateDocument => {
+                for (_i, body) in dm.bodies.iter().enumerate() {
+                    let db_address_ref: &[u8] = body.db_address.as_ref();
+                    let db_addr = DB3Address::try_from(db_address_ref)
+                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                    if let Some(Body::DocumentMutation(ref doc_mutation)) = &body.body {
+                        if doc_mutation.documents.len() != doc_mutation.ids.len() {
+                            let msg = format!(
                                 "doc ids size {} not equal to documents size {}",
                                 doc_mutation.ids.len(),
                                 doc_mutation.documents.len()
@@ -1398,159 +4779,2060 @@ impl DBStoreV2 {
                             warn!("{}", msg.as_str());
                             return Err(DB3Error::ApplyMutationError(msg));
                         }
+                        if !doc_mutation.masks.is_empty()
+                            && doc_mutation.masks.len() != doc_mutation.ids.len()
+                        {
+                            let msg = format!(
+                                "doc ids size {} not equal to masks size {}",
+                                doc_mutation.ids.len(),
+                                doc_mutation.masks.len()
+                            );
+                            warn!("{}", msg.as_str());
+                            return Err(DB3Error::ApplyMutationError(msg));
+                        }
                         let mut docs = Vec::<String>::new();
-                        for buf in doc_mutation.documents.iter() {
-                            let document = bytes_to_bson_document(buf.clone())
+                        for (i, buf) in doc_mutation.documents.iter().enumerate() {
+                            let mut document = bytes_to_bson_document(buf.clone())
                                 .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
-                            let doc_str = document.to_string();
+                            // the empty mask merges the whole document
+                            if let Some(mask) = doc_mutation.masks.get(i) {
+                                if !mask.fields.is_empty() {
+                                    document = bson_document_with_mask(&document, &mask.fields)
+                                        .map_err(|e| {
+                                            DB3Error::ApplyMutationError(format!("{e}"))
+                                        })?;
+                                }
+                            }
+                            let doc_str = bson_document_into_json_str(&document);
                             debug!("update document: {}", doc_str);
                             docs.push(doc_str);
                         }
-                        self.update_docs(
+                        self.update_docs_internal(
+                            pending,
+                            &db_addr,
+                            address,
+                            doc_mutation.collection_name.as_str(),
+                            &docs,
+                            &doc_mutation.ids,
+                            &doc_mutation.preconditions,
+                            false,
+                        )
+                        .map_err(Self::to_apply_mutation_error)?;
+                        info!(
+                            "update documents with db_addr {}, collection_name: {}, from owner {}",
+                            db_addr.to_hex().as_str(),
+                            doc_mutation.collection_name.as_str(),
+                            address.to_hex().as_str()
+                        );
+                    }
+                }
+            }
+            MutationAction::DeleteDocument => {
+                for (_i, body) in dm.bodies.iter().enumerate() {
+                    let db_address_ref: &[u8] = body.db_address.as_ref();
+                    let db_addr = DB3Address::try_from(db_address_ref)
+                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                    if let Some(Body::DocumentMutation(ref doc_mutation)) = &body.body {
+                        self.delete_docs_internal(
+                            pending,
+                            &db_addr,
+                            address,
+                            doc_mutation.collection_name.as_str(),
+                            &doc_mutation.ids,
+                            &doc_mutation.preconditions,
+                        )
+                        .map_err(Self::to_apply_mutation_error)?;
+                        info!(
+                            "delete documents with db_addr {}, collection_name: {}, from owner {}",
+                            db_addr.to_hex().as_str(),
+                            doc_mutation.collection_name.as_str(),
+                            address.to_hex().as_str()
+                        );
+                    }
+                }
+            }
+            MutationAction::TransformDocument => {
+                let server_time = Self::get_server_time(doc_ids_map, items);
+                for body in dm.bodies.iter() {
+                    let db_address_ref: &[u8] = body.db_address.as_ref();
+                    let db_addr = DB3Address::try_from(db_address_ref)
+                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                    if let Some(Body::DocumentTransformMutation(ref transform_mutation)) =
+                        &body.body
+                    {
+                        if transform_mutation.transforms.len() != transform_mutation.ids.len() {
+                            let msg = format!(
+                                "doc ids size {} not equal to transforms size {}",
+                                transform_mutation.ids.len(),
+                                transform_mutation.transforms.len()
+                            );
+                            warn!("{}", msg.as_str());
+                            return Err(DB3Error::ApplyMutationError(msg));
+                        }
+                        for transform in transform_mutation.transforms.iter() {
+                            doc_transform::check_transforms(&transform.field_transforms)
+                                .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                        }
+                        self.transform_docs_internal(
+                            pending,
+                            &db_addr,
+                            address,
+                            transform_mutation.collection_name.as_str(),
+                            &transform_mutation.ids,
+                            &transform_mutation.transforms,
+                            &transform_mutation.preconditions,
+                            server_time,
+                        )
+                        .map_err(Self::to_apply_mutation_error)?;
+                        info!(
+                            "transform documents with db_addr {}, collection_name: {}, from owner {}",
+                            db_addr.to_hex().as_str(),
+                            transform_mutation.collection_name.as_str(),
+                            address.to_hex().as_str()
+                        );
+                    }
+                }
+            }
+            MutationAction::DeleteDocumentByQuery | MutationAction::UpdateDocumentByQuery => {
+                for (i, body) in dm.bodies.iter().enumerate() {
+                    let db_address_ref: &[u8] = body.db_address.as_ref();
+                    let db_addr = DB3Address::try_from(db_address_ref)
+                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                    if let Some(Body::DocumentQueryMutation(ref query_mutation)) = &body.body {
+                        let query =
+                            query_mutation
+                                .query
+                                .as_ref()
+                                .ok_or(DB3Error::ApplyMutationError(
+                                    "the query is required".to_string(),
+                                ))?;
+                        let col_name = query.collection_name.as_str();
+                        // the matched ids are resolved by the storage node and replayed from
+                        // the doc ids map by the indexer and the recover
+                        let matched_key = format!("{MATCHED_DOCS_KEY_PREFIX}{i}");
+                        let doc_ids = self
+                            .match_docs_by_query(
+                                pending,
+                                &db_addr,
+                                address,
+                                query,
+                                doc_ids_map.get(matched_key.as_str()),
+                            )
+                            .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                        let result = if action == MutationAction::DeleteDocumentByQuery {
+                            self.delete_docs_internal(
+                                pending,
+                                &db_addr,
+                                address,
+                                col_name,
+                                &doc_ids,
+                                &[],
+                            )
+                        } else {
+                            let mut document =
+                                bytes_to_bson_document(query_mutation.document.clone())
+                                    .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                            if let Some(mask) = &query_mutation.mask {
+                                if !mask.fields.is_empty() {
+                                    document = bson_document_with_mask(&document, &mask.fields)
+                                        .map_err(|e| {
+                                            DB3Error::ApplyMutationError(format!("{e}"))
+                                        })?;
+                                }
+                            }
+                            let doc_str = bson_document_into_json_str(&document);
+                            self.update_docs_internal(
+                                pending,
+                                &db_addr,
+                                address,
+                                col_name,
+                                &vec![doc_str; doc_ids.len()],
+                                &doc_ids,
+                                &[],
+                                false,
+                            )
+                        };
+                        result.map_err(Self::to_apply_mutation_error)?;
+                        info!(
+                            "{} {} documents by query with db_addr {}, collection_name: {}, from owner {}",
+                            action.as_str_name(),
+                            doc_ids.len(),
+                            db_addr.to_hex().as_str(),
+                            col_name,
+                            address.to_hex().as_str()
+                        );
+                        items.push(ExtraItem {
+                            key: matched_key,
+                            value: doc_ids
+                                .iter()
+                                .map(|id| id.to_string())
+                                .collect::<Vec<String>>()
+                                .join(","),
+                        });
+                    }
+                }
+            }
+            MutationAction::ExpireDocument => {
+                // the documents are expired at the server time of the mutation
+                let server_time = Self::get_server_time(doc_ids_map, items);
+                for (i, body) in dm.bodies.iter().enumerate() {
+                    if let Some(Body::ExpireDocumentMutation(ref expire_mutation)) = &body.body {
+                        let db_address_ref: &[u8] = body.db_address.as_ref();
+                        let db_addr = DB3Address::try_from(db_address_ref)
+                            .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                        let ids = self
+                            .expire_docs_internal(
+                                pending,
+                                &db_addr,
+                                expire_mutation.collection_name.as_str(),
+                                &expire_mutation.ids,
+                                server_time as u64,
+                            )
+                            .map_err(Self::to_apply_mutation_error)?;
+                        info!(
+                            "expire {} documents with db_addr {}, collection_name: {}",
+                            ids.len(),
+                            db_addr.to_hex().as_str(),
+                            expire_mutation.collection_name.as_str()
+                        );
+                        items.push(ExtraItem {
+                            key: format!("{EXPIRED_DOCS_KEY_PREFIX}{i}"),
+                            value: ids
+                                .iter()
+                                .map(|id| id.to_string())
+                                .collect::<Vec<String>>()
+                                .join(","),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    ///
+    /// the addresses of the databases which the mutation targets. the address of the
+    /// created database is taken from the items of the applied mutation
+    ///
+    pub fn get_db_addrs_of_mutation(dm: &Mutation, items: &[ExtraItem]) -> Vec<DB3Address> {
+        let mut addrs: BTreeSet<DB3Address> = BTreeSet::new();
+        for body in dm.bodies.iter() {
+            if let Ok(addr) = DB3Address::try_from(body.db_address.as_slice()) {
+                addrs.insert(addr);
+            }
+            if let Some(Body::MintCollectionMutation(ref mint_col_mutation)) = &body.body {
+                if let Ok(addr) = DB3Address::try_from(mint_col_mutation.db_addr.as_str()) {
+                    addrs.insert(addr);
+                }
+            }
+        }
+        for item in items.iter().filter(|item| item.key == "db_addr") {
+            if let Ok(addr) = DB3Address::try_from(item.value.as_str()) {
+                addrs.insert(addr);
+            }
+        }
+        addrs.into_iter().collect()
+    }
+
+    pub fn apply_mutation(
+        &self,
+        action: MutationAction,
+        dm: Mutation,
+        address: &DB3Address,
+        network: u64,
+        nonce: u64,
+        block: u64,
+        order: u32,
+        doc_ids_map: &HashMap<String, Vec<i64>>,
+    ) -> Result<Vec<ExtraItem>> {
+        let mut items: Vec<ExtraItem> = Vec::new();
+        match action {
+            MutationAction::DeleteEventDb => {
+                for body in dm.bodies {
+                    if let Some(Body::DeleteEventDatabaseMutation(ref _del_mutation)) = &body.body {
+                        let db_address_ref: &[u8] = body.db_address.as_ref();
+                        let db_addr = DB3Address::try_from(db_address_ref)?;
+                        self.delete_event_db(address, &db_addr)?;
+                        let item = ExtraItem {
+                            key: "db_addr".to_string(),
+                            value: db_addr.to_hex(),
+                        };
+                        items.push(item)
+                    }
+                    break;
+                }
+            }
+
+            MutationAction::DeleteDocumentDb => {
+                for body in dm.bodies {
+                    if let Some(Body::DeleteDocumentDatabaseMutation(ref _del_mutation)) =
+                        &body.body
+                    {
+                        let db_address_ref: &[u8] = body.db_address.as_ref();
+                        let db_addr = DB3Address::try_from(db_address_ref)?;
+                        self.delete_doc_db(address, &db_addr)?;
+                        info!(
+                            "delete database {} from owner {} done",
+                            db_addr.to_hex().as_str(),
+                            address.to_hex().as_str()
+                        );
+                        let item = ExtraItem {
+                            key: "db_addr".to_string(),
+                            value: db_addr.to_hex(),
+                        };
+                        items.push(item);
+                        break;
+                    }
+                }
+            }
+
+            MutationAction::DropCollection => {
+                for body in dm.bodies {
+                    if let Some(Body::DropCollectionMutation(ref drop_col_mutation)) = &body.body {
+                        let db_address_ref: &[u8] = body.db_address.as_ref();
+                        let db_addr = DB3Address::try_from(db_address_ref)?;
+                        self.drop_collection(
                             &db_addr,
+                            drop_col_mutation.collection_name.as_str(),
                             address,
-                            doc_mutation.collection_name.as_str(),
-                            &docs,
-                            &doc_mutation.ids,
-                        )
-                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                        )?;
+                        let item = ExtraItem {
+                            key: "collection".to_string(),
+                            value: drop_col_mutation.collection_name.to_string(),
+                        };
+                        items.push(item);
                         info!(
-                            "update documents with db_addr {}, collection_name: {}, from owner {}",
+                            "drop collection {} from db {} done",
+                            drop_col_mutation.collection_name.as_str(),
+                            db_addr.to_hex().as_str()
+                        );
+                        break;
+                    }
+                }
+            }
+
+            MutationAction::RenameCollection => {
+                for body in dm.bodies {
+                    if let Some(Body::RenameCollectionMutation(ref rename_mutation)) = &body.body {
+                        let db_address_ref: &[u8] = body.db_address.as_ref();
+                        let db_addr = DB3Address::try_from(db_address_ref)?;
+                        self.rename_collection(
+                            &db_addr,
+                            rename_mutation.collection_name.as_str(),
+                            rename_mutation.new_name.as_str(),
+                            address,
+                        )?;
+                        let item = ExtraItem {
+                            key: "collection".to_string(),
+                            value: rename_mutation.new_name.to_string(),
+                        };
+                        items.push(item);
+                        info!(
+                            "rename collection {} to {} in db {} done",
+                            rename_mutation.collection_name.as_str(),
+                            rename_mutation.new_name.as_str(),
+                            db_addr.to_hex().as_str()
+                        );
+                        break;
+                    }
+                }
+            }
+
+            MutationAction::TransferDatabase => {
+                for body in dm.bodies {
+                    if let Some(Body::TransferDatabaseMutation(ref transfer_mutation)) = &body.body
+                    {
+                        let db_address_ref: &[u8] = body.db_address.as_ref();
+                        let db_addr = DB3Address::try_from(db_address_ref)?;
+                        let new_owner_ref: &[u8] = transfer_mutation.new_owner.as_ref();
+                        let new_owner = DB3Address::try_from(new_owner_ref)?;
+                        self.transfer_database(&db_addr, &new_owner, address, block, order)?;
+                        info!(
+                            "transfer db {} from {} to {} done",
                             db_addr.to_hex().as_str(),
-                            doc_mutation.collection_name.as_str(),
+                            address.to_hex().as_str(),
+                            new_owner.to_hex().as_str()
+                        );
+                        let item = ExtraItem {
+                            key: "db_addr".to_string(),
+                            value: db_addr.to_hex(),
+                        };
+                        items.push(item);
+                        break;
+                    }
+                }
+            }
+
+            MutationAction::AddIndex => {
+                for body in dm.bodies {
+                    if let Some(Body::AddIndexMutation(ref add_index_mutation)) = &body.body {
+                        let db_address_ref: &[u8] = body.db_address.as_ref();
+                        let db_addr = DB3Address::try_from(db_address_ref)?;
+                        self.add_index(
+                            &db_addr,
+                            add_index_mutation.collection_name.as_str(),
+                            &add_index_mutation.index_fields,
+                            address,
+                        )?;
+                        let item = ExtraItem {
+                            key: "collection".to_string(),
+                            value: add_index_mutation.collection_name.to_string(),
+                        };
+                        items.push(item);
+                        info!(
+                            "add index to collection {} done",
+                            add_index_mutation.collection_name.as_str()
+                        );
+                        break;
+                    }
+                }
+            }
+
+            MutationAction::DropIndex => {
+                for body in dm.bodies {
+                    if let Some(Body::DropIndexMutation(ref drop_index_mutation)) = &body.body {
+                        let db_address_ref: &[u8] = body.db_address.as_ref();
+                        let db_addr = DB3Address::try_from(db_address_ref)?;
+                        self.drop_index(
+                            &db_addr,
+                            drop_index_mutation.collection_name.as_str(),
+                            &drop_index_mutation.paths,
+                            address,
+                        )?;
+                        let item = ExtraItem {
+                            key: "collection".to_string(),
+                            value: drop_index_mutation.collection_name.to_string(),
+                        };
+                        items.push(item);
+                        info!(
+                            "drop index from collection {} done",
+                            drop_index_mutation.collection_name.as_str()
+                        );
+                        break;
+                    }
+                }
+            }
+
+            MutationAction::UpdateCollectionSchema => {
+                for body in dm.bodies {
+                    if let Some(Body::UpdateCollectionSchemaMutation(ref schema_mutation)) =
+                        &body.body
+                    {
+                        let db_address_ref: &[u8] = body.db_address.as_ref();
+                        let db_addr = DB3Address::try_from(db_address_ref)?;
+                        self.update_collection_schema(
+                            &db_addr,
+                            schema_mutation.collection_name.as_str(),
+                            schema_mutation.schema.as_ref(),
+                            address,
+                        )?;
+                        let item = ExtraItem {
+                            key: "collection".to_string(),
+                            value: schema_mutation.collection_name.to_string(),
+                        };
+                        items.push(item);
+                        info!(
+                            "update the schema of collection {} done",
+                            schema_mutation.collection_name.as_str()
+                        );
+                        break;
+                    }
+                }
+            }
+            MutationAction::UpdateAccessControl => {
+                for body in dm.bodies {
+                    if let Some(Body::AccessControlMutation(ref acl_mutation)) = &body.body {
+                        let db_address_ref: &[u8] = body.db_address.as_ref();
+                        let db_addr = DB3Address::try_from(db_address_ref)?;
+                        self.update_access_control(
+                            &db_addr,
+                            acl_mutation.collection_name.as_str(),
+                            acl_mutation.acl.as_ref(),
+                            address,
+                        )?;
+                        info!(
+                            "update the acl of collection {} in db {} done",
+                            acl_mutation.collection_name.as_str(),
+                            db_addr.to_hex().as_str()
+                        );
+                        break;
+                    }
+                }
+            }
+            MutationAction::MintCollection => {
+                for body in dm.bodies {
+                    if let Some(Body::MintCollectionMutation(ref mint_col_mutation)) = &body.body {
+                        let sender = DB3Address::try_from(mint_col_mutation.sender.as_str())?;
+                        let db_addr = DB3Address::try_from(mint_col_mutation.db_addr.as_str())?;
+                        if self.is_db_collection_exist(&db_addr, mint_col_mutation.name.as_str())? {
+                            return Err(DB3Error::CollectionAlreadyExist(
+                                mint_col_mutation.name.to_string(),
+                                db_addr.to_hex(),
+                            ));
+                        }
+                        let mut pending = PendingWrites::default();
+                        let result = self.save_collection_internal(
+                            &mut pending,
+                            &sender,
+                            &db_addr,
+                            mint_col_mutation.name.as_str(),
+                            &vec![],
+                            None,
+                            0,
+                            false,
+                        );
+                        self.commit_or_abort(pending, result)?;
+                        info!(
+                            "add collection with db_addr {}, collection_name: {}, from owner {}",
+                            db_addr.to_hex().as_str(),
+                            mint_col_mutation.name.as_str(),
+                            sender.to_hex().as_str()
+                        );
+                        let item = ExtraItem {
+                            key: "collection".to_string(),
+                            value: mint_col_mutation.name.to_string(),
+                        };
+                        items.push(item);
+                        break;
+                    }
+                }
+            }
+            MutationAction::MintDocumentDb => {
+                for body in dm.bodies {
+                    if let Some(Body::MintDocDatabaseMutation(ref min_doc_db_mutation)) = &body.body
+                    {
+                        let sender = DB3Address::try_from(min_doc_db_mutation.sender.as_str())?;
+                        let db_id =
+                            DbId::from(DB3Address::try_from(min_doc_db_mutation.db_addr.as_str())?);
+                        self.create_predefined_doc_database(
+                            &sender,
+                            &db_id,
+                            min_doc_db_mutation.desc.as_str(),
+                            block,
+                            order,
+                        )
+                        .map_err(|e| {
+                            DB3Error::ApplyMutationError(format!(
+                                "fail to create predefined database {e}"
+                            ))
+                        })?;
+                        let db_id_hex = db_id.to_hex();
+                        info!(
+                            "mint database with addr {} from owner {}",
+                            db_id_hex.as_str(),
+                            address.to_hex().as_str()
+                        );
+                        let item = ExtraItem {
+                            key: "db_addr".to_string(),
+                            value: db_id_hex,
+                        };
+                        items.push(item);
+                        break;
+                    }
+                }
+            }
+            MutationAction::CreateEventDb => {
+                for body in dm.bodies {
+                    if let Some(Body::EventDatabaseMutation(ref mutation)) = &body.body {
+                        let db_id = self
+                            .create_event_database(address, mutation, nonce, network, block, order)
+                            .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                        let db_id_hex = db_id.to_hex();
+                        info!(
+                            "add database with addr {} from owner {}",
+                            db_id_hex.as_str(),
+                            address.to_hex().as_str()
+                        );
+                        let item = ExtraItem {
+                            key: "db_addr".to_string(),
+                            value: db_id_hex,
+                        };
+                        items.push(item);
+                        break;
+                    }
+                }
+            }
+            MutationAction::CreateDocumentDb => {
+                for body in dm.bodies {
+                    if let Some(Body::DocDatabaseMutation(ref doc_db_mutation)) = &body.body {
+                        let db_id = self
+                            .create_doc_database(
+                                address,
+                                doc_db_mutation,
+                                nonce,
+                                network,
+                                block,
+                                order,
+                            )
+                            .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
+                        let db_id_hex = db_id.to_hex();
+                        info!(
+                            "add database with addr {} from owner {}",
+                            db_id_hex.as_str(),
                             address.to_hex().as_str()
                         );
+                        let item = ExtraItem {
+                            key: "db_addr".to_string(),
+                            value: db_id_hex,
+                        };
+                        items.push(item);
+                        break;
                     }
                 }
             }
-            MutationAction::DeleteDocument => {
-                for (_i, body) in dm.bodies.iter().enumerate() {
-                    let db_address_ref: &[u8] = body.db_address.as_ref();
-                    let db_addr = DB3Address::try_from(db_address_ref)
-                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
-                    if let Some(Body::DocumentMutation(ref doc_mutation)) = &body.body {
-                        self.delete_docs(
-                            &db_addr,
-                            address,
-                            doc_mutation.collection_name.as_str(),
-                            &doc_mutation.ids,
+            MutationAction::AddCollection
+            | MutationAction::AddDocument
+            | MutationAction::UpdateDocument
+            | MutationAction::DeleteDocument
+            | MutationAction::TransformDocument
+            | MutationAction::UpsertDocument
+            | MutationAction::DeleteDocumentByQuery
+            | MutationAction::UpdateDocumentByQuery
+            | MutationAction::ExpireDocument => {
+                // all the bodies of the mutation are applied or none of them
+                let mut pending = PendingWrites::default();
+                let result = self
+                    .apply_bodies(
+                        &mut pending,
+                        action,
+                        &dm,
+                        address,
+                        block,
+                        order,
+                        doc_ids_map,
+                        &mut items,
+                    )
+                    .and_then(|_| self.put_changed_docs(&mut pending, &dm, block, order));
+                self.commit_or_abort(pending, result)?;
+            }
+        };
+        self.store_block_state(BlockState { block, order })?;
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db3_proto::db3_database_v2_proto::structured_query::value::ValueType;
+    use db3_proto::db3_database_v2_proto::structured_query::Direction;
+    use db3_proto::db3_database_v2_proto::structured_query::Value;
+    use db3_proto::db3_database_v2_proto::{FieldSchema, FieldType, IndexType};
+    use db3_proto::db3_mutation_v2_proto::field_transform::TransformType;
+    use db3_proto::db3_mutation_v2_proto::mutation::BodyWrapper;
+    use db3_proto::db3_mutation_v2_proto::{
+        AccessControlMutation, DocumentMask, DocumentMutation, DocumentQueryMutation,
+        DocumentTransformMutation, ExpireDocumentMutation, FieldTransform, ServerValue,
+        UpdateCollectionSchemaMutation,
+    };
+    use tempdir::TempDir;
+
+    fn new_store_config(name: &str, enable_doc_store: bool) -> (TempDir, DBStoreV2Config) {
+        let tmp_dir_path = TempDir::new(name).expect("create temp dir");
+        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
+        let doc_store_conf = if enable_doc_store {
+            DocStoreConfig {
+                db_root_path: real_path.to_string(),
+                in_memory_db_handle_limit: 16,
+            }
+        } else {
+            DocStoreConfig::default()
+        };
+        let config = DBStoreV2Config {
+            db_path: real_path,
+            db_store_cf_name: "db".to_string(),
+            doc_store_cf_name: "doc".to_string(),
+            collection_store_cf_name: "cf2".to_string(),
+            index_store_cf_name: "index".to_string(),
+            doc_owner_store_cf_name: "doc_owner".to_string(),
+            db_owner_store_cf_name: "db_owner".to_string(),
+            scan_max_limit: 50,
+            enable_doc_store,
+            doc_store_conf,
+            doc_start_id: 1000,
+        };
+        (tmp_dir_path, config)
+    }
+
+    #[test]
+    fn test_new_db_store() {
+        let (_tmp_dir_path, config) = new_store_config("new_db_store_path", false);
+        let result = DBStoreV2::new(config);
+        assert_eq!(result.is_ok(), true);
+    }
+    #[test]
+    fn test_collection_test() {
+        let (_tmp_dir_path, config) = new_store_config("new_database", false);
+        let result = DBStoreV2::new(config);
+        assert_eq!(result.is_ok(), true);
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db3_store = result.unwrap();
+        let result = db3_store.create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1);
+        assert!(result.is_ok());
+        let db_id = result.unwrap();
+        if let Ok(Some(db)) = db3_store.get_database(db_id.address()) {
+            if let Some(database_message::Database::DocDb(doc_db)) = db.database {
+                assert_eq!("test_desc", doc_db.desc.as_str());
+            }
+        } else {
+            assert!(false);
+        }
+        let collection = CollectionMutation {
+            index_fields: vec![],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let col_state = db3_store.get_collection_state(&db_id.address(), "col1");
+        assert!(col_state.is_none());
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_ok());
+        let col_state = db3_store.get_collection_state(&db_id.address(), "col1");
+        assert!(col_state.is_some());
+        let result = db3_store.get_collection(db_id.address(), "col1");
+        if let Ok(Some(_c)) = result {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        if let Ok((cl, states)) = db3_store.get_collection_of_database(db_id.address()) {
+            assert_eq!(cl.len(), 1);
+            assert_eq!(states.len(), 1);
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn test_add_and_drop_index() {
+        let (_tmp_dir_path, config) = new_store_config("new_database", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_ok());
+        let field = |path: &str| IndexField {
+            path: path.to_string(),
+            index_type: IndexType::StringKey.into(),
+        };
+        let compound_index = Index {
+            path: "name_city".to_string(),
+            index_type: IndexType::UniqueKey.into(),
+            fields: vec![field("/name"), field("/city")],
+        };
+        let bad_index = Index {
+            path: "name_name".to_string(),
+            index_type: IndexType::UniqueKey.into(),
+            fields: vec![field("/name"), field("/name")],
+        };
+        let result =
+            db3_store.add_index(db_id.address(), "col1", &vec![bad_index], &DB3Address::ZERO);
+        assert!(matches!(result, Err(DB3Error::InvalidKeyPathError(_))));
+        let result = db3_store.add_index(
+            db_id.address(),
+            "col1",
+            &vec![compound_index.clone()],
+            &DB3Address::ZERO,
+        );
+        assert!(result.is_ok());
+        let result = db3_store.add_index(
+            db_id.address(),
+            "col1",
+            &vec![compound_index.clone()],
+            &DB3Address::ZERO,
+        );
+        assert!(result.is_err());
+        let col = db3_store
+            .get_collection(db_id.address(), "col1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(vec![compound_index], col.index_fields);
+        let paths = vec!["name".to_string()];
+        let result = db3_store.drop_index(db_id.address(), "col1", &paths, &DB3Address::ZERO);
+        assert!(matches!(result, Err(DB3Error::InvalidKeyPathError(_))));
+        let paths = vec!["name_city".to_string()];
+        let result = db3_store.drop_index(db_id.address(), "col1", &paths, &DB3Address::ZERO);
+        assert!(result.is_ok());
+        let col = db3_store
+            .get_collection(db_id.address(), "col1")
+            .unwrap()
+            .unwrap();
+        assert!(col.index_fields.is_empty());
+    }
+
+    #[test]
+    fn test_unique_key_test() {
+        let (_tmp_dir_path, config) = new_store_config("new_database", true);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![Index {
+                path: "/name".to_string(),
+                index_type: IndexType::UniqueKey.into(),
+                fields: vec![],
+            }],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_ok());
+        let docs = vec![r#"{"name":"a"}"#.to_string(), r#"{"name":"a"}"#.to_string()];
+        let result = db3_store.add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None);
+        assert!(matches!(
+            result,
+            Err(DB3Error::DuplicateUniqueKey(ref field, 1)) if field == "name"
+        ));
+        let db_state = db3_store.get_database_state(db_id.address()).unwrap();
+        assert_eq!(0, db_state.total_doc_count);
+        assert_eq!(0, db_state.doc_order);
+        let docs = vec![r#"{"name":"a"}"#.to_string(), r#"{"city":"b"}"#.to_string()];
+        let ids = db3_store
+            .add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None)
+            .unwrap();
+        assert_eq!(vec![1, 2], ids);
+        let docs = vec![r#"{"name":"a"}"#.to_string()];
+        let result = db3_store.add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None);
+        assert!(matches!(
+            result,
+            Err(DB3Error::DuplicateUniqueKey(ref field, 1)) if field == "name"
+        ));
+        let result =
+            db3_store.update_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, &vec![2]);
+        assert!(matches!(
+            result,
+            Err(DB3Error::DuplicateUniqueKey(ref field, 1)) if field == "name"
+        ));
+        let result =
+            db3_store.update_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, &vec![1]);
+        assert!(result.is_ok());
+        let db_state = db3_store.get_database_state(db_id.address()).unwrap();
+        assert_eq!(2, db_state.total_doc_count);
+    }
+
+    #[test]
+    fn test_unique_key_without_doc_store_test() {
+        let (_tmp_dir_path, config) = new_store_config("new_database", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![Index {
+                path: "/name".to_string(),
+                index_type: IndexType::UniqueKey.into(),
+                fields: vec![],
+            }],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_ok());
+        let docs = vec![r#"{"name":"a"}"#.to_string(), r#"{"name":"b"}"#.to_string()];
+        let ids = db3_store
+            .add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None)
+            .unwrap();
+        assert_eq!(vec![1, 2], ids);
+        // the unique values are kept without the doc store
+        let docs = vec![r#"{"name":"a"}"#.to_string()];
+        let result = db3_store.add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None);
+        assert!(matches!(
+            result,
+            Err(DB3Error::DuplicateUniqueKey(ref field, 1)) if field == "name"
+        ));
+        // the old value is released by the update
+        let patches = vec![r#"{"name":"c"}"#.to_string()];
+        assert!(db3_store
+            .update_docs(
+                db_id.address(),
+                &DB3Address::ZERO,
+                "col1",
+                &patches,
+                &vec![1]
+            )
+            .is_ok());
+        let result = db3_store.add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None);
+        assert!(result.is_ok());
+        let docs = vec![r#"{"name":"c"}"#.to_string()];
+        let result = db3_store.add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None);
+        assert!(matches!(
+            result,
+            Err(DB3Error::DuplicateUniqueKey(ref field, 1)) if field == "name"
+        ));
+        // the value is released by the delete
+        assert!(db3_store
+            .delete_docs(db_id.address(), &DB3Address::ZERO, "col1", &vec![1])
+            .is_ok());
+        let result = db3_store.add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None);
+        assert!(result.is_ok());
+        // the unique index can not be added to the collection with documents
+        let result = db3_store.add_index(
+            db_id.address(),
+            "col1",
+            &vec![Index {
+                path: "/city".to_string(),
+                index_type: IndexType::UniqueKey.into(),
+                fields: vec![],
+            }],
+            &DB3Address::ZERO,
+        );
+        assert!(matches!(result, Err(DB3Error::InvalidKeyPathError(_))));
+        // the values are dropped with the index
+        assert!(db3_store
+            .drop_index(
+                db_id.address(),
+                "col1",
+                &vec!["/name".to_string()],
+                &DB3Address::ZERO
+            )
+            .is_ok());
+        assert!(db3_store
+            .get_doc_unique_values(&PendingWrites::default(), db_id.address(), &[2, 3, 4])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_compound_unique_key_test() {
+        let (_tmp_dir_path, config) = new_store_config("compound_unique_key", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let field = |path: &str| IndexField {
+            path: path.to_string(),
+            index_type: IndexType::StringKey.into(),
+        };
+        let collection = CollectionMutation {
+            index_fields: vec![Index {
+                path: "name_city".to_string(),
+                index_type: IndexType::UniqueKey.into(),
+                fields: vec![field("/name"), field("/city")],
+            }],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_ok());
+        // the key is unique on the tuple of the fields
+        let docs = vec![
+            r#"{"name":"a", "city":"x"}"#.to_string(),
+            r#"{"name":"a", "city":"y"}"#.to_string(),
+            r#"{"name":"a"}"#.to_string(),
+        ];
+        let ids = db3_store
+            .add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None)
+            .unwrap();
+        assert_eq!(vec![1, 2, 3], ids);
+        let docs = vec![r#"{"name":"a", "city":"x"}"#.to_string()];
+        let result = db3_store.add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None);
+        assert!(matches!(
+            result,
+            Err(DB3Error::DuplicateUniqueKey(ref key, 1)) if key == "name_city"
+        ));
+        // the patch is checked with the kept value of the other field
+        let patches = vec![r#"{"city":"x"}"#.to_string()];
+        let result = db3_store.update_docs(
+            db_id.address(),
+            &DB3Address::ZERO,
+            "col1",
+            &patches,
+            &vec![3],
+        );
+        assert!(matches!(
+            result,
+            Err(DB3Error::DuplicateUniqueKey(ref key, 1)) if key == "name_city"
+        ));
+        let patches = vec![r#"{"city":"z"}"#.to_string()];
+        assert!(db3_store
+            .update_docs(
+                db_id.address(),
+                &DB3Address::ZERO,
+                "col1",
+                &patches,
+                &vec![3]
+            )
+            .is_ok());
+        assert_eq!(
+            vec![(3, "name_city".to_string(), r#"["a","z"]"#.to_string())],
+            db3_store
+                .get_doc_unique_values(&PendingWrites::default(), db_id.address(), &[3])
+                .unwrap()
+        );
+        let docs = vec![r#"{"name":"a", "city":"z"}"#.to_string()];
+        let result = db3_store.add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None);
+        assert!(matches!(
+            result,
+            Err(DB3Error::DuplicateUniqueKey(ref key, 3)) if key == "name_city"
+        ));
+    }
+
+    #[test]
+    fn test_apply_mutation_rollback() {
+        let (_tmp_dir_path, config) = new_store_config("new_database", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_ok());
+        let doc_body = |col_name: &str, ids: Vec<i64>| BodyWrapper {
+            body: Some(Body::DocumentMutation(DocumentMutation {
+                collection_name: col_name.to_string(),
+                documents: vec![
+                    db3_base::bson_util::json_str_to_bson_bytes(r#"{"name":"a"}"#)
+                        .unwrap();
+                    ids.len().max(1)
+                ],
+                ids,
+                masks: vec![],
+                preconditions: vec![],
+                keys: vec![],
+            })),
+            db_address: db_id.address().as_ref().to_vec(),
+        };
+        let apply = |action: MutationAction, bodies: Vec<BodyWrapper>| {
+            let dm = Mutation {
+                action: action.into(),
+                bodies,
+            };
+            db3_store.apply_mutation(action, dm, &DB3Address::ZERO, 1, 1, 2, 1, &HashMap::new())
+        };
+        // the second body targets a missing collection so the first one is rolled back
+        let result = apply(
+            MutationAction::AddDocument,
+            vec![doc_body("col1", vec![]), doc_body("col2", vec![])],
+        );
+        assert!(result.is_err());
+        let db_state = db3_store.get_database_state(db_id.address()).unwrap();
+        assert_eq!(0, db_state.total_doc_count);
+        assert_eq!(0, db_state.doc_order);
+        assert!(db3_store
+            .verify_docs_ownership(&DB3Address::ZERO, db_id.address(), &vec![1])
+            .is_err());
+
+        let result = apply(MutationAction::AddDocument, vec![doc_body("col1", vec![])]);
+        assert!(result.is_ok());
+        let result = apply(
+            MutationAction::DeleteDocument,
+            vec![doc_body("col1", vec![1]), doc_body("col1", vec![5])],
+        );
+        assert!(result.is_err());
+        let db_state = db3_store.get_database_state(db_id.address()).unwrap();
+        assert_eq!(1, db_state.total_doc_count);
+        assert!(db3_store
+            .verify_docs_ownership(&DB3Address::ZERO, db_id.address(), &vec![1])
+            .is_ok());
+        // the second body sees the doc deleted by the first one before the commit
+        let result = apply(
+            MutationAction::DeleteDocument,
+            vec![doc_body("col1", vec![1]), doc_body("col1", vec![1])],
+        );
+        assert!(result.is_err());
+        let db_state = db3_store.get_database_state(db_id.address()).unwrap();
+        assert_eq!(1, db_state.total_doc_count);
+        assert!(db3_store
+            .verify_docs_ownership(&DB3Address::ZERO, db_id.address(), &vec![1])
+            .is_ok());
+
+        let col_body = |col_name: &str| BodyWrapper {
+            body: Some(Body::CollectionMutation(CollectionMutation {
+                index_fields: vec![],
+                collection_name: col_name.to_string(),
+                schema: None,
+                ttl: 0,
+                keep_history: false,
+            })),
+            db_address: db_id.address().as_ref().to_vec(),
+        };
+        let result = apply(
+            MutationAction::AddCollection,
+            vec![col_body("col2"), col_body("col1")],
+        );
+        assert!(result.is_err());
+        assert!(db3_store
+            .get_collection(db_id.address(), "col2")
+            .unwrap()
+            .is_none());
+        assert!(db3_store
+            .get_collection_state(db_id.address(), "col2")
+            .is_none());
+    }
+
+    #[test]
+    fn test_doc_revision_and_precondition() {
+        let (_tmp_dir_path, config) = new_store_config("precondition", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_ok());
+        let docs = vec![r#"{"name":"a"}"#.to_string(), r#"{"name":"b"}"#.to_string()];
+        let ids = db3_store
+            .add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None)
+            .unwrap();
+        assert_eq!(
+            vec![Some(1), Some(1), None],
+            db3_store
+                .get_doc_revisions(
+                    &PendingWrites::default(),
+                    db_id.address(),
+                    &[ids[0], ids[1], 100]
+                )
+                .unwrap()
+        );
+        let precondition = |condition_type: ConditionType| Precondition {
+            condition_type: Some(condition_type),
+        };
+        let doc_body = |ids: Vec<i64>, preconditions: Vec<Precondition>| BodyWrapper {
+            body: Some(Body::DocumentMutation(DocumentMutation {
+                collection_name: "col1".to_string(),
+                documents: vec![
+                    db3_base::bson_util::json_str_to_bson_bytes(r#"{"name":"c"}"#)
+                        .unwrap();
+                    ids.len()
+                ],
+                ids,
+                masks: vec![],
+                preconditions,
+                keys: vec![],
+            })),
+            db_address: db_id.address().as_ref().to_vec(),
+        };
+        let apply = |action: MutationAction, bodies: Vec<BodyWrapper>| {
+            let dm = Mutation {
+                action: action.into(),
+                bodies,
+            };
+            db3_store.apply_mutation(action, dm, &DB3Address::ZERO, 1, 1, 2, 1, &HashMap::new())
+        };
+        // the update without preconditions bumps the revision
+        let result = apply(
+            MutationAction::UpdateDocument,
+            vec![doc_body(vec![ids[0]], vec![])],
+        );
+        assert!(result.is_ok());
+        assert_eq!(
+            vec![Some(2), Some(1)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
+        // a stale revision fails the whole mutation
+        let result = apply(
+            MutationAction::UpdateDocument,
+            vec![
+                doc_body(vec![ids[1]], vec![precondition(ConditionType::Revision(1))]),
+                doc_body(vec![ids[0]], vec![precondition(ConditionType::Revision(1))]),
+            ],
+        );
+        assert!(matches!(result, Err(DB3Error::PreconditionFailed(_))));
+        assert_eq!(
+            vec![Some(2), Some(1)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
+        let result = apply(
+            MutationAction::UpdateDocument,
+            vec![doc_body(
+                ids.clone(),
+                vec![
+                    precondition(ConditionType::Revision(2)),
+                    precondition(ConditionType::Exists(true)),
+                ],
+            )],
+        );
+        assert!(result.is_ok());
+        assert_eq!(
+            vec![Some(3), Some(2)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
+        // the size of the preconditions must match the ids
+        let result = apply(
+            MutationAction::DeleteDocument,
+            vec![doc_body(
+                ids.clone(),
+                vec![precondition(ConditionType::Exists(true))],
+            )],
+        );
+        assert!(result.is_err());
+        let result = apply(
+            MutationAction::DeleteDocument,
+            vec![doc_body(
+                vec![ids[0]],
+                vec![precondition(ConditionType::Exists(false))],
+            )],
+        );
+        assert!(matches!(result, Err(DB3Error::PreconditionFailed(_))));
+        // the deleted docs keep their revisions after the rollback
+        let result = apply(
+            MutationAction::DeleteDocument,
+            vec![
+                doc_body(vec![ids[0]], vec![precondition(ConditionType::Revision(3))]),
+                doc_body(vec![ids[1]], vec![precondition(ConditionType::Revision(1))]),
+            ],
+        );
+        assert!(matches!(result, Err(DB3Error::PreconditionFailed(_))));
+        assert_eq!(
+            vec![Some(3), Some(2)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
+        let result = apply(
+            MutationAction::DeleteDocument,
+            vec![doc_body(
+                vec![ids[0]],
+                vec![precondition(ConditionType::Revision(3))],
+            )],
+        );
+        assert!(result.is_ok());
+        assert_eq!(
+            vec![None, Some(2)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_update_doc_with_mask() {
+        let (_tmp_dir_path, config) = new_store_config("update_mask", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_ok());
+        let docs = vec![r#"{"name":"a"}"#.to_string()];
+        let ids = db3_store
+            .add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None)
+            .unwrap();
+        let apply = |masks: Vec<DocumentMask>| {
+            let dm = Mutation {
+                action: MutationAction::UpdateDocument.into(),
+                bodies: vec![BodyWrapper {
+                    body: Some(Body::DocumentMutation(DocumentMutation {
+                        collection_name: "col1".to_string(),
+                        documents: vec![db3_base::bson_util::json_str_to_bson_bytes(
+                            r#"{"name":"b"}"#,
                         )
-                        .map_err(|e| DB3Error::ApplyMutationError(format!("{e}")))?;
-                        info!(
-                            "delete documents with db_addr {}, collection_name: {}, from owner {}",
-                            db_addr.to_hex().as_str(),
-                            doc_mutation.collection_name.as_str(),
-                            address.to_hex().as_str()
-                        );
-                    }
-                }
-            }
+                        .unwrap()],
+                        ids: ids.clone(),
+                        masks,
+                        preconditions: vec![],
+                        keys: vec![],
+                    })),
+                    db_address: db_id.address().as_ref().to_vec(),
+                }],
+            };
+            db3_store.apply_mutation(
+                MutationAction::UpdateDocument,
+                dm,
+                &DB3Address::ZERO,
+                1,
+                1,
+                2,
+                1,
+                &HashMap::new(),
+            )
         };
-        self.store_block_state(BlockState { block, order })?;
-        Ok(items)
+        let mask = |fields: Vec<&str>| DocumentMask {
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+        };
+        assert!(apply(vec![]).is_ok());
+        assert!(apply(vec![mask(vec![])]).is_ok());
+        assert!(apply(vec![mask(vec!["name", "age"])]).is_ok());
+        assert!(apply(vec![mask(vec!["__name__"])]).is_err());
+        assert!(apply(vec![mask(vec!["name"]), mask(vec!["name"])]).is_err());
+        assert_eq!(
+            vec![Some(4)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempdir::TempDir;
+    #[test]
+    fn test_transform_doc() {
+        let (_tmp_dir_path, config) = new_store_config("transform_doc", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![Index {
+                path: "/name".to_string(),
+                index_type: IndexType::UniqueKey.into(),
+                fields: vec![],
+            }],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_ok());
+        let docs = vec![r#"{"count":1}"#.to_string()];
+        let ids = db3_store
+            .add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None)
+            .unwrap();
+        let apply = |transforms: Vec<DocumentTransform>, doc_ids_map: HashMap<String, Vec<i64>>| {
+            let dm = Mutation {
+                action: MutationAction::TransformDocument.into(),
+                bodies: vec![BodyWrapper {
+                    body: Some(Body::DocumentTransformMutation(DocumentTransformMutation {
+                        collection_name: "col1".to_string(),
+                        ids: ids.clone(),
+                        transforms,
+                        preconditions: vec![],
+                    })),
+                    db_address: db_id.address().as_ref().to_vec(),
+                }],
+            };
+            db3_store.apply_mutation(
+                MutationAction::TransformDocument,
+                dm,
+                &DB3Address::ZERO,
+                1,
+                1,
+                2,
+                1,
+                &doc_ids_map,
+            )
+        };
+        let transform = |transform_type: TransformType| DocumentTransform {
+            field_transforms: vec![FieldTransform {
+                field_path: "count".to_string(),
+                transform_type: Some(transform_type),
+            }],
+        };
+        // the server time is generated on the first apply
+        let items = apply(
+            vec![transform(TransformType::SetToServerValue(
+                ServerValue::RequestTime.into(),
+            ))],
+            HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(1, items.len());
+        assert_eq!(SERVER_TIME_KEY, items[0].key.as_str());
+        // and replayed from the doc ids map
+        let items = apply(
+            vec![transform(TransformType::SetToServerValue(
+                ServerValue::RequestTime.into(),
+            ))],
+            HashMap::from([(SERVER_TIME_KEY.to_string(), vec![1000])]),
+        )
+        .unwrap();
+        assert!(items.is_empty());
+        assert_eq!(
+            vec![Some(3)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
+        assert!(apply(vec![], HashMap::new()).is_err());
+        let result = apply(
+            vec![transform(TransformType::Increment(Value {
+                value_type: Some(ValueType::StringValue("1".to_string())),
+            }))],
+            HashMap::new(),
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            vec![Some(3)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
+        // the unique field can not be transformed
+        let result = apply(
+            vec![DocumentTransform {
+                field_transforms: vec![FieldTransform {
+                    field_path: "name".to_string(),
+                    transform_type: Some(TransformType::SetToServerValue(
+                        ServerValue::RequestTime.into(),
+                    )),
+                }],
+            }],
+            HashMap::new(),
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            vec![Some(3)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
+    }
 
     #[test]
-    fn test_new_db_store() {
-        let tmp_dir_path = TempDir::new("new_db_store_path").expect("create temp dir");
-        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
-        let config = DBStoreV2Config {
-            db_path: real_path,
-            db_store_cf_name: "db".to_string(),
-            doc_store_cf_name: "doc".to_string(),
-            collection_store_cf_name: "cf2".to_string(),
-            index_store_cf_name: "index".to_string(),
-            doc_owner_store_cf_name: "doc_owner".to_string(),
-            db_owner_store_cf_name: "db_owner".to_string(),
-            scan_max_limit: 50,
-            enable_doc_store: false,
-            doc_store_conf: DocStoreConfig::default(),
-            doc_start_id: 1000,
+    fn test_update_and_delete_doc_by_query() {
+        let (_tmp_dir_path, config) = new_store_config("doc_query_mutation", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
         };
-        let result = DBStoreV2::new(config);
-        assert_eq!(result.is_ok(), true);
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_ok());
+        let docs = vec![
+            r#"{"name":"a"}"#.to_string(),
+            r#"{"name":"b"}"#.to_string(),
+            r#"{"name":"c"}"#.to_string(),
+        ];
+        let ids = db3_store
+            .add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None)
+            .unwrap();
+        let query_body = || BodyWrapper {
+            body: Some(Body::DocumentQueryMutation(DocumentQueryMutation {
+                query: Some(StructuredQuery {
+                    collection_name: "col1".to_string(),
+                    select: None,
+                    r#where: None,
+                    order_by: vec![],
+                    limit: None,
+                    start_at: None,
+                    end_at: None,
+                }),
+                document: db3_base::bson_util::json_str_to_bson_bytes(r#"{"name":"d"}"#).unwrap(),
+                mask: None,
+            })),
+            db_address: db_id.address().as_ref().to_vec(),
+        };
+        let apply = |action: MutationAction, doc_ids_map: &HashMap<String, Vec<i64>>| {
+            let dm = Mutation {
+                action: action.into(),
+                bodies: vec![query_body()],
+            };
+            db3_store.apply_mutation(action, dm, &DB3Address::ZERO, 1, 1, 2, 1, doc_ids_map)
+        };
+        // the matched ids can not be resolved without the doc store
+        let result = apply(MutationAction::UpdateDocumentByQuery, &HashMap::new());
+        assert!(result.is_err());
+        // the matched ids recorded by the storage node are replayed
+        let doc_ids_map = HashMap::from([("matched_0".to_string(), vec![ids[0], ids[1]])]);
+        let items = apply(MutationAction::UpdateDocumentByQuery, &doc_ids_map).unwrap();
+        assert_eq!(
+            vec![ExtraItem {
+                key: "matched_0".to_string(),
+                value: format!("{},{}", ids[0], ids[1]),
+            }],
+            items
+        );
+        assert_eq!(
+            vec![Some(2), Some(2), Some(1)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
+        let doc_ids_map = HashMap::from([("matched_0".to_string(), vec![ids[1], 100])]);
+        let result = apply(MutationAction::DeleteDocumentByQuery, &doc_ids_map);
+        assert!(result.is_err());
+        let doc_ids_map = HashMap::from([("matched_0".to_string(), vec![ids[1]])]);
+        let result = apply(MutationAction::DeleteDocumentByQuery, &doc_ids_map);
+        assert!(result.is_ok());
+        assert_eq!(
+            vec![Some(2), None, Some(1)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
+        // nothing is matched
+        let doc_ids_map = HashMap::from([("matched_0".to_string(), vec![])]);
+        let result = apply(MutationAction::DeleteDocumentByQuery, &doc_ids_map);
+        assert!(result.is_ok());
+        let db_state = db3_store.get_database_state(db_id.address()).unwrap();
+        assert_eq!(2, db_state.total_doc_count);
+        // the aggregation can not be run without the doc store
+        let aggregations = vec![Aggregation {
+            op: AggregationOp::Count.into(),
+            field: "".to_string(),
+        }];
+        let query = StructuredQuery {
+            collection_name: "col1".to_string(),
+            select: None,
+            r#where: None,
+            order_by: vec![],
+            limit: None,
+            start_at: None,
+            end_at: None,
+        };
+        let result = db3_store.aggregate_docs(db_id.address(), &query, &aggregations, "");
+        assert!(matches!(result, Err(DB3Error::QueryDocumentError(_))));
     }
+
     #[test]
-    fn test_collection_test() {
-        let tmp_dir_path = TempDir::new("new_database").expect("create temp dir");
-        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
-        let config = DBStoreV2Config {
-            db_path: real_path,
-            db_store_cf_name: "db".to_string(),
-            doc_store_cf_name: "doc".to_string(),
-            collection_store_cf_name: "cf2".to_string(),
-            index_store_cf_name: "index".to_string(),
-            doc_owner_store_cf_name: "doc_owner".to_string(),
-            db_owner_store_cf_name: "db_owner".to_string(),
-            scan_max_limit: 50,
-            enable_doc_store: false,
-            doc_store_conf: DocStoreConfig::default(),
-            doc_start_id: 1000,
+    fn test_match_docs_by_query_max_limit() {
+        let (_tmp_dir_path, mut config) = new_store_config("doc_query_max_limit", true);
+        config.scan_max_limit = 2;
+        let db3_store = DBStoreV2::new(config).unwrap();
+        assert!(db3_store.is_doc_store_enabled());
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
         };
-        let result = DBStoreV2::new(config);
-        assert_eq!(result.is_ok(), true);
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_ok());
+        let docs = vec![
+            r#"{"name":"a"}"#.to_string(),
+            r#"{"name":"b"}"#.to_string(),
+            r#"{"name":"c"}"#.to_string(),
+        ];
+        let ids = db3_store
+            .add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None)
+            .unwrap();
+        let query = |limit: Option<Limit>| StructuredQuery {
+            collection_name: "col1".to_string(),
+            select: None,
+            r#where: None,
+            order_by: vec![],
+            limit,
+            start_at: None,
+            end_at: None,
+        };
+        // three documents are matched and the scan max limit is two
+        let result = db3_store.match_docs_by_query(
+            &PendingWrites::default(),
+            db_id.address(),
+            &DB3Address::ZERO,
+            &query(None),
+            None,
+        );
+        assert!(result.is_err());
+        let result = db3_store.match_docs_by_query(
+            &PendingWrites::default(),
+            db_id.address(),
+            &DB3Address::ZERO,
+            &query(Some(Limit { limit: 3 })),
+            None,
+        );
+        assert!(result.is_err());
+        let doc_ids = db3_store
+            .match_docs_by_query(
+                &PendingWrites::default(),
+                db_id.address(),
+                &DB3Address::ZERO,
+                &query(Some(Limit { limit: 2 })),
+                None,
+            )
+            .unwrap();
+        assert_eq!(2, doc_ids.len());
+        assert!(doc_ids.iter().all(|id| ids.contains(id)));
+    }
+
+    #[test]
+    fn test_query_docs_by_page_cursor() {
+        let (_tmp_dir_path, config) = new_store_config("doc_query_page_cursor", true);
+        let db3_store = DBStoreV2::new(config).unwrap();
         let db_m = DocumentDatabaseMutation {
             db_desc: "test_desc".to_string(),
         };
-        let db3_store = result.unwrap();
-        let result = db3_store.create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1);
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![Index {
+                path: "/age".to_string(),
+                index_type: IndexType::Int64Key.into(),
+                fields: vec![],
+            }],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
         assert!(result.is_ok());
-        let db_id = result.unwrap();
-        if let Ok(Some(db)) = db3_store.get_database(db_id.address()) {
-            if let Some(database_message::Database::DocDb(doc_db)) = db.database {
-                assert_eq!("test_desc", doc_db.desc.as_str());
+        let docs: Vec<String> = [1, 1, 1, 2, 1]
+            .iter()
+            .map(|age| format!(r#"{{"age":{age}}}"#))
+            .collect();
+        let ids = db3_store
+            .add_docs(db_id.address(), &DB3Address::ZERO, "col1", &docs, None)
+            .unwrap();
+        let query = StructuredQuery {
+            collection_name: "col1".to_string(),
+            select: None,
+            r#where: None,
+            order_by: vec![Order {
+                field: "age".to_string(),
+                direction: Direction::Ascending.into(),
+            }],
+            limit: Some(Limit { limit: 2 }),
+            start_at: None,
+            end_at: None,
+        };
+        // the documents with the same age are paged in the order of their ids
+        let mut cursor = "".to_string();
+        let mut pages = vec![];
+        loop {
+            let (documents, count, next_cursor) = db3_store
+                .query_docs_by_structured_query(db_id.address(), &query, cursor.as_str())
+                .unwrap();
+            assert_eq!(documents.len() as u64, count);
+            pages.push(documents.iter().map(|doc| doc.id).collect::<Vec<i64>>());
+            if next_cursor.is_empty() {
+                break;
             }
-        } else {
-            assert!(false);
+            cursor = next_cursor;
         }
+        assert_eq!(
+            vec![vec![ids[0], ids[1]], vec![ids[2], ids[4]], vec![ids[3]]],
+            pages
+        );
+    }
+
+    #[test]
+    fn test_collection_schema() {
+        let (_tmp_dir_path, config) = new_store_config("collection_schema", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let field = |path: &str, field_type: FieldType, required: bool| FieldSchema {
+            path: path.to_string(),
+            field_type: field_type.into(),
+            required,
+        };
+        let schema = CollectionSchema {
+            fields: vec![
+                field("name", FieldType::String, true),
+                field("age", FieldType::Int64, false),
+            ],
+            strict: true,
+        };
+        let bad_schema = CollectionSchema {
+            fields: vec![field("name", FieldType::Unspecified, true)],
+            strict: false,
+        };
         let collection = CollectionMutation {
             index_fields: vec![],
             collection_name: "col1".to_string(),
+            schema: Some(bad_schema),
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_err());
+        let collection = CollectionMutation {
+            index_fields: vec![],
+            collection_name: "col1".to_string(),
+            schema: Some(schema),
+            ttl: 0,
+            keep_history: false,
         };
-        let col_state = db3_store.get_collection_state(&db_id.address(), "col1");
-        assert!(col_state.is_none());
         let result =
             db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
         assert!(result.is_ok());
-        let col_state = db3_store.get_collection_state(&db_id.address(), "col1");
-        assert!(col_state.is_some());
-        let result = db3_store.get_collection(db_id.address(), "col1");
-        if let Ok(Some(_c)) = result {
-            assert!(true);
-        } else {
-            assert!(false);
+        let add = |doc: &str| {
+            db3_store.add_docs(
+                db_id.address(),
+                &DB3Address::ZERO,
+                "col1",
+                &vec![doc.to_string()],
+                None,
+            )
+        };
+        let ids = add(r#"{"name":"a","age":1}"#).unwrap();
+        for doc in [
+            r#"{"age":1}"#,
+            r#"{"name":"a","age":"1"}"#,
+            r#"{"name":"a","x":1}"#,
+        ] {
+            assert!(matches!(add(doc), Err(DB3Error::DocumentSchemaMismatch(_))));
         }
-        if let Ok((cl, states)) = db3_store.get_collection_of_database(db_id.address()) {
-            assert_eq!(cl.len(), 1);
-            assert_eq!(states.len(), 1);
-        } else {
-            assert!(false);
+        assert!(db3_store
+            .update_docs(
+                db_id.address(),
+                &DB3Address::ZERO,
+                "col1",
+                &vec![r#"{"age":2}"#.to_string()],
+                &ids,
+            )
+            .is_ok());
+        assert!(db3_store
+            .update_docs(
+                db_id.address(),
+                &DB3Address::ZERO,
+                "col1",
+                &vec![r#"{"name":null}"#.to_string()],
+                &ids,
+            )
+            .is_err());
+        let schema_body = || BodyWrapper {
+            body: Some(Body::UpdateCollectionSchemaMutation(
+                UpdateCollectionSchemaMutation {
+                    collection_name: "col1".to_string(),
+                    schema: None,
+                },
+            )),
+            db_address: db_id.address().as_ref().to_vec(),
+        };
+        let apply = |sender: &DB3Address| {
+            let dm = Mutation {
+                action: MutationAction::UpdateCollectionSchema.into(),
+                bodies: vec![schema_body()],
+            };
+            db3_store.apply_mutation(
+                MutationAction::UpdateCollectionSchema,
+                dm,
+                sender,
+                1,
+                1,
+                2,
+                1,
+                &HashMap::new(),
+            )
+        };
+        // only the owner of the collection can change the schema
+        let other = DB3Address::try_from("0x1000000000000000000000000000000000000000").unwrap();
+        assert!(apply(&other).is_err());
+        assert!(apply(&DB3Address::ZERO).is_ok());
+        assert!(add(r#"{"x":1}"#).is_ok());
+    }
+
+    #[test]
+    fn test_access_control() {
+        let (_tmp_dir_path, config) = new_store_config("access_control", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_ok());
+        let alice = DB3Address::try_from("0x1000000000000000000000000000000000000000").unwrap();
+        let bob = DB3Address::try_from("0x2000000000000000000000000000000000000000").unwrap();
+        let carol = DB3Address::try_from("0x3000000000000000000000000000000000000000").unwrap();
+        let docs = vec![r#"{"name":"a"}"#.to_string()];
+        let add =
+            |sender: &DB3Address| db3_store.add_docs(db_id.address(), sender, "col1", &docs, None);
+        let update = |sender: &DB3Address, ids: &Vec<i64>| {
+            db3_store.update_docs(db_id.address(), sender, "col1", &docs, ids)
+        };
+        let set_acl = |sender: &DB3Address, col_name: &str, acl: Option<AccessControl>| {
+            let dm = Mutation {
+                action: MutationAction::UpdateAccessControl.into(),
+                bodies: vec![BodyWrapper {
+                    body: Some(Body::AccessControlMutation(AccessControlMutation {
+                        collection_name: col_name.to_string(),
+                        acl,
+                    })),
+                    db_address: db_id.address().as_ref().to_vec(),
+                }],
+            };
+            db3_store.apply_mutation(
+                MutationAction::UpdateAccessControl,
+                dm,
+                sender,
+                1,
+                1,
+                2,
+                1,
+                &HashMap::new(),
+            )
+        };
+        // everyone can write the collection without acl and change its own documents
+        let alice_ids = add(&alice).unwrap();
+        assert!(update(&bob, &alice_ids).is_err());
+        assert!(update(&DB3Address::ZERO, &alice_ids).is_ok());
+        // the owner of the doc is kept after the moderation
+        assert!(update(&alice, &alice_ids).is_ok());
+        assert_eq!(
+            vec![Some(3)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &alice_ids)
+                .unwrap()
+        );
+        assert!(db3_store
+            .check_read_permission(db_id.address(), "col1", None)
+            .is_ok());
+
+        let acl = AccessControl {
+            writers: vec![bob.as_ref().to_vec()],
+            readers: vec![carol.as_ref().to_vec()],
+            public_read: false,
+        };
+        assert!(set_acl(&bob, "col1", Some(acl.clone())).is_err());
+        assert!(set_acl(&DB3Address::ZERO, "col1", Some(acl)).is_ok());
+        assert!(matches!(
+            add(&alice),
+            Err(DB3Error::CollectionPermissionDenied())
+        ));
+        assert!(update(&alice, &alice_ids).is_err());
+        assert!(update(&bob, &alice_ids).is_ok());
+        assert!(add(&bob).is_ok());
+        assert!(add(&carol).is_err());
+        for (reader, readable) in [
+            (None, false),
+            (Some(&alice), false),
+            (Some(&bob), true),
+            (Some(&carol), true),
+            (Some(&DB3Address::ZERO), true),
+        ] {
+            assert_eq!(
+                readable,
+                db3_store
+                    .check_read_permission(db_id.address(), "col1", reader)
+                    .is_ok()
+            );
         }
+
+        // the acl of the database is used by the collection without acl
+        let db_acl = AccessControl {
+            writers: vec![],
+            readers: vec![],
+            public_read: true,
+        };
+        assert!(set_acl(&alice, "", Some(db_acl.clone())).is_err());
+        assert!(set_acl(&DB3Address::ZERO, "", Some(db_acl)).is_ok());
+        assert!(set_acl(&DB3Address::ZERO, "col1", None).is_ok());
+        assert!(add(&bob).is_err());
+        assert!(db3_store
+            .check_read_permission(db_id.address(), "col1", None)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_upsert_doc_by_key() {
+        let (_tmp_dir_path, config) = new_store_config("upsert_doc", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let result =
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1);
+        assert!(result.is_ok());
+        let doc_body = |col_name: &str,
+                        ids: Vec<i64>,
+                        keys: Vec<&str>,
+                        preconditions: Vec<Precondition>| BodyWrapper {
+            body: Some(Body::DocumentMutation(DocumentMutation {
+                collection_name: col_name.to_string(),
+                documents: vec![
+                    db3_base::bson_util::json_str_to_bson_bytes(r#"{"name":"a"}"#)
+                        .unwrap();
+                    keys.len().max(ids.len())
+                ],
+                ids,
+                masks: vec![],
+                preconditions,
+                keys: keys.iter().map(|k| k.to_string()).collect(),
+            })),
+            db_address: db_id.address().as_ref().to_vec(),
+        };
+        let apply = |action: MutationAction, bodies: Vec<BodyWrapper>| {
+            let dm = Mutation {
+                action: action.into(),
+                bodies,
+            };
+            db3_store.apply_mutation(action, dm, &DB3Address::ZERO, 1, 1, 2, 1, &HashMap::new())
+        };
+        let key_of = |key: &str| {
+            db3_store
+                .get_doc_id_by_key(db_id.address(), "col1", key)
+                .unwrap()
+        };
+        let items = apply(
+            MutationAction::UpsertDocument,
+            vec![doc_body("col1", vec![], vec!["a", "b"], vec![])],
+        )
+        .unwrap();
+        assert_eq!(2, items.len());
+        let id_a = key_of("a").unwrap();
+        assert_eq!(Some(id_a + 1), key_of("b"));
+        let result = apply(
+            MutationAction::AddDocument,
+            vec![doc_body("col1", vec![], vec!["", "a"], vec![])],
+        );
+        assert!(matches!(
+            result,
+            Err(DB3Error::DocumentKeyAlreadyExist(ref key, _)) if key == "a"
+        ));
+        // the doc of a is replaced and c is inserted
+        let items = apply(
+            MutationAction::UpsertDocument,
+            vec![doc_body("col1", vec![], vec!["a", "c"], vec![])],
+        )
+        .unwrap();
+        assert_eq!(1, items.len());
+        assert_eq!(Some(id_a), key_of("a"));
+        assert_eq!(
+            vec![Some(2)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &[id_a])
+                .unwrap()
+        );
+        let result = apply(
+            MutationAction::UpsertDocument,
+            vec![doc_body(
+                "col1",
+                vec![],
+                vec!["a"],
+                vec![Precondition {
+                    condition_type: Some(ConditionType::Exists(false)),
+                }],
+            )],
+        );
+        assert!(matches!(result, Err(DB3Error::PreconditionFailed(_))));
+        assert!(apply(
+            MutationAction::UpsertDocument,
+            vec![doc_body("col1", vec![], vec!["d", ""], vec![])],
+        )
+        .is_err());
+        // the inserted doc of d is rolled back with its key
+        let result = apply(
+            MutationAction::UpsertDocument,
+            vec![
+                doc_body("col1", vec![], vec!["d"], vec![]),
+                doc_body("col2", vec![], vec!["e"], vec![]),
+            ],
+        );
+        assert!(result.is_err());
+        assert_eq!(None, key_of("d"));
+        // the key is released after the doc is deleted
+        let result = apply(
+            MutationAction::DeleteDocument,
+            vec![doc_body("col1", vec![id_a], vec![], vec![])],
+        );
+        assert!(result.is_ok());
+        assert_eq!(None, key_of("a"));
+        let items = apply(
+            MutationAction::UpsertDocument,
+            vec![doc_body("col1", vec![], vec!["a"], vec![])],
+        )
+        .unwrap();
+        assert_eq!(1, items.len());
+        assert_ne!(Some(id_a), key_of("a"));
     }
 
     #[test]
     fn test_create_doc_db() {
-        let tmp_dir_path = TempDir::new("new_database").expect("create temp dir");
-        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
-        let config = DBStoreV2Config {
-            db_path: real_path,
-            db_store_cf_name: "db".to_string(),
-            doc_store_cf_name: "doc".to_string(),
-            collection_store_cf_name: "cf2".to_string(),
-            index_store_cf_name: "index".to_string(),
-            doc_owner_store_cf_name: "doc_owner".to_string(),
-            db_owner_store_cf_name: "db_owner".to_string(),
-            scan_max_limit: 50,
-            enable_doc_store: false,
-            doc_store_conf: DocStoreConfig::default(),
-            doc_start_id: 1000,
-        };
+        let (_tmp_dir_path, config) = new_store_config("new_database", false);
         let result = DBStoreV2::new(config);
         assert_eq!(result.is_ok(), true);
         let db_m = DocumentDatabaseMutation {
@@ -1583,21 +6865,7 @@ mod tests {
 
     #[test]
     fn event_db_smoke_test() {
-        let tmp_dir_path = TempDir::new("new_database").expect("create temp dir");
-        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
-        let config = DBStoreV2Config {
-            db_path: real_path,
-            db_store_cf_name: "db".to_string(),
-            doc_store_cf_name: "doc".to_string(),
-            collection_store_cf_name: "cf2".to_string(),
-            index_store_cf_name: "index".to_string(),
-            doc_owner_store_cf_name: "doc_owner".to_string(),
-            db_owner_store_cf_name: "db_owner".to_string(),
-            scan_max_limit: 50,
-            enable_doc_store: false,
-            doc_store_conf: DocStoreConfig::default(),
-            doc_start_id: 1000,
-        };
+        let (_tmp_dir_path, config) = new_store_config("new_database", false);
         let result = DBStoreV2::new(config);
         assert_eq!(result.is_ok(), true);
         let emutation = EventDatabaseMutation {
@@ -1625,22 +6893,394 @@ mod tests {
     }
 
     #[test]
-    fn test_increase_db_doc_order_ut() {
-        let tmp_dir_path = TempDir::new("new_database").expect("create temp dir");
-        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
-        let config = DBStoreV2Config {
-            db_path: real_path,
-            db_store_cf_name: "db".to_string(),
-            doc_store_cf_name: "doc".to_string(),
-            collection_store_cf_name: "cf2".to_string(),
-            index_store_cf_name: "index".to_string(),
-            doc_owner_store_cf_name: "doc_owner".to_string(),
-            db_owner_store_cf_name: "db_owner".to_string(),
-            scan_max_limit: 50,
-            enable_doc_store: false,
-            doc_store_conf: DocStoreConfig::default(),
-            doc_start_id: 1000,
+    fn test_drop_collection_and_delete_doc_db() {
+        let (_tmp_dir_path, config) = new_store_config("drop_collection", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        for name in ["col1", "col2"] {
+            let collection = CollectionMutation {
+                index_fields: vec![],
+                collection_name: name.to_string(),
+                schema: None,
+                ttl: 0,
+                keep_history: false,
+            };
+            assert!(db3_store
+                .create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1)
+                .is_ok());
+        }
+        let docs = vec![r#"{"name":"a"}"#.to_string(), r#"{"name":"b"}"#.to_string()];
+        let keys = vec!["a".to_string(), "".to_string()];
+        let mut pending = PendingWrites::default();
+        let result = db3_store.add_docs_internal(
+            &mut pending,
+            db_id.address(),
+            &DB3Address::ZERO,
+            "col1",
+            &docs,
+            None,
+            &keys,
+        );
+        let ids = db3_store.commit_or_abort(pending, result).unwrap();
+        let col2_ids = db3_store
+            .add_docs(db_id.address(), &DB3Address::ZERO, "col2", &docs, None)
+            .unwrap();
+        let state = db3_store.get_database_state(db_id.address()).unwrap();
+        assert_eq!(4, state.total_doc_count);
+        assert_eq!(2, state.total_col_count);
+
+        let bob = DB3Address::try_from("0x2000000000000000000000000000000000000000").unwrap();
+        assert!(matches!(
+            db3_store.drop_collection(db_id.address(), "col1", &bob),
+            Err(DB3Error::CollectionPermissionDenied())
+        ));
+        assert!(db3_store
+            .drop_collection(db_id.address(), "col1", &DB3Address::ZERO)
+            .is_ok());
+        assert!(!db3_store
+            .is_db_collection_exist(db_id.address(), "col1")
+            .unwrap());
+        assert_eq!(
+            None,
+            db3_store
+                .get_doc_id_by_key(db_id.address(), "col1", "a")
+                .unwrap()
+        );
+        assert!(db3_store
+            .get_doc_keys(&PendingWrites::default(), db_id.address(), &ids)
+            .unwrap()
+            .is_empty());
+        // the owners of the documents are dropped without the doc store
+        assert_eq!(
+            vec![None, None],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
+        assert_eq!(
+            vec![Some(1), Some(1)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &col2_ids)
+                .unwrap()
+        );
+        let state = db3_store.get_database_state(db_id.address()).unwrap();
+        assert_eq!(2, state.total_doc_count);
+        assert_eq!(1, state.total_col_count);
+        let (collections, _) = db3_store
+            .get_collection_of_database(db_id.address())
+            .unwrap();
+        assert_eq!(1, collections.len());
+
+        assert!(matches!(
+            db3_store.delete_doc_db(&bob, db_id.address()),
+            Err(DB3Error::DatabasePermissionDenied())
+        ));
+        assert!(db3_store
+            .delete_doc_db(&DB3Address::ZERO, db_id.address())
+            .is_ok());
+        assert!(db3_store.get_database(db_id.address()).unwrap().is_none());
+        assert!(db3_store.get_database_state(db_id.address()).is_none());
+        let (databases, _) = db3_store.get_database_of_owner(&DB3Address::ZERO).unwrap();
+        assert!(databases.is_empty());
+        assert!(db3_store
+            .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+            .unwrap()
+            .iter()
+            .all(|revision| revision.is_none()));
+        assert!(db3_store
+            .get_entries_with_prefix::<Collection>(db_id.address().as_ref(), "cf2")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_rename_collection_and_reuse_name() {
+        let (_tmp_dir_path, config) = new_store_config("rename_reuse_collection", true);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![Index {
+                path: "/name".to_string(),
+                index_type: IndexType::UniqueKey.into(),
+                fields: vec![],
+            }],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 10,
+            keep_history: true,
+        };
+        let create = || {
+            db3_store.create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1)
+        };
+        let add = |block: u64| {
+            let dm = Mutation {
+                action: MutationAction::AddDocument.into(),
+                bodies: vec![BodyWrapper {
+                    body: Some(Body::DocumentMutation(DocumentMutation {
+                        collection_name: "col1".to_string(),
+                        documents: vec![db3_base::bson_util::json_str_to_bson_bytes(
+                            r#"{"name":"a"}"#,
+                        )
+                        .unwrap()],
+                        ids: vec![],
+                        masks: vec![],
+                        preconditions: vec![],
+                        keys: vec!["k1".to_string()],
+                    })),
+                    db_address: db_id.address().as_ref().to_vec(),
+                }],
+            };
+            let items = db3_store
+                .apply_mutation(
+                    MutationAction::AddDocument,
+                    dm,
+                    &DB3Address::ZERO,
+                    1,
+                    block,
+                    block,
+                    1,
+                    &HashMap::from([(SERVER_TIME_KEY.to_string(), vec![1000])]),
+                )
+                .unwrap();
+            items
+                .iter()
+                .find(|item| item.key.starts_with(NEW_DOCS_KEY_PREFIX))
+                .map(|item| item.value.parse::<i64>().unwrap())
+                .unwrap()
+        };
+        assert!(matches!(
+            db3_store.create_collection(
+                &DB3Address::ZERO,
+                db_id.address(),
+                &CollectionMutation {
+                    collection_name: "".to_string(),
+                    ..collection.clone()
+                },
+                1,
+                1,
+                1
+            ),
+            Err(DB3Error::InvalidCollectionNameError(_))
+        ));
+        assert!(create().is_ok());
+        let old_id = add(2);
+        for new_name in ["", "col1", "a_very_long_collection_name"] {
+            assert!(matches!(
+                db3_store.rename_collection(db_id.address(), "col1", new_name, &DB3Address::ZERO),
+                Err(DB3Error::InvalidCollectionNameError(_))
+            ));
+        }
+        assert!(db3_store
+            .rename_collection(db_id.address(), "col1", "col2", &DB3Address::ZERO)
+            .is_ok());
+        // the old name is reused with the same key and the same unique value
+        assert!(create().is_ok());
+        let new_id = add(3);
+        assert_ne!(old_id, new_id);
+        assert_eq!(
+            Some(old_id),
+            db3_store
+                .get_doc_id_by_key(db_id.address(), "col2", "k1")
+                .unwrap()
+        );
+        assert_eq!(
+            Some(new_id),
+            db3_store
+                .get_doc_id_by_key(db_id.address(), "col1", "k1")
+                .unwrap()
+        );
+        let docs = vec![r#"{"name":"a"}"#.to_string()];
+        for (col_name, id) in [("col1", new_id), ("col2", old_id)] {
+            assert!(matches!(
+                db3_store.add_docs(db_id.address(), &DB3Address::ZERO, col_name, &docs, None),
+                Err(DB3Error::DuplicateUniqueKey(ref field, conflict_id))
+                    if field == "name" && conflict_id == id
+            ));
+            let versions = db3_store
+                .get_docs_as_of(db_id.address(), col_name, 3)
+                .unwrap();
+            assert_eq!(1, versions.len());
+            assert_eq!(id, versions[0].id);
+            let col_state = db3_store
+                .get_collection_state(db_id.address(), col_name)
+                .unwrap();
+            assert_eq!(1, col_state.total_doc_count);
+        }
+        let mut expired_docs: Vec<(String, Vec<i64>)> = db3_store
+            .get_expired_docs(11000, 10)
+            .unwrap()
+            .into_iter()
+            .map(|(_, col_name, ids)| (col_name, ids))
+            .collect();
+        expired_docs.sort();
+        assert_eq!(
+            vec![
+                ("col1".to_string(), vec![new_id]),
+                ("col2".to_string(), vec![old_id])
+            ],
+            expired_docs
+        );
+        // dropping the renamed collection keeps the documents of the reused name
+        assert!(db3_store
+            .drop_collection(db_id.address(), "col2", &DB3Address::ZERO)
+            .is_ok());
+        assert_eq!(
+            vec![None, Some(1)],
+            db3_store
+                .get_doc_revisions(
+                    &PendingWrites::default(),
+                    db_id.address(),
+                    &[old_id, new_id]
+                )
+                .unwrap()
+        );
+        assert_eq!(
+            1,
+            db3_store
+                .get_docs_as_of(db_id.address(), "col1", 3)
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            vec![(*db_id.address(), "col1".to_string(), vec![new_id])],
+            db3_store.get_expired_docs(11000, 10).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rename_collection_and_transfer_database() {
+        let (_tmp_dir_path, config) = new_store_config("rename_collection", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
         };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let bob = DB3Address::try_from("0x2000000000000000000000000000000000000000").unwrap();
+        for name in ["col1", "col2"] {
+            let collection = CollectionMutation {
+                index_fields: vec![Index {
+                    path: "/name".to_string(),
+                    index_type: IndexType::StringKey.into(),
+                    fields: vec![],
+                }],
+                collection_name: name.to_string(),
+                schema: None,
+                ttl: 0,
+                keep_history: false,
+            };
+            assert!(db3_store
+                .create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1)
+                .is_ok());
+        }
+        let docs = vec![r#"{"name":"a"}"#.to_string()];
+        let keys = vec!["a".to_string()];
+        let mut pending = PendingWrites::default();
+        let result = db3_store.add_docs_internal(
+            &mut pending,
+            db_id.address(),
+            &DB3Address::ZERO,
+            "col1",
+            &docs,
+            None,
+            &keys,
+        );
+        let ids = db3_store.commit_or_abort(pending, result).unwrap();
+
+        assert!(matches!(
+            db3_store.rename_collection(db_id.address(), "col1", "col3", &bob),
+            Err(DB3Error::CollectionPermissionDenied())
+        ));
+        assert!(matches!(
+            db3_store.rename_collection(db_id.address(), "col1", "col2", &DB3Address::ZERO),
+            Err(DB3Error::CollectionAlreadyExist(_, _))
+        ));
+        assert!(db3_store
+            .rename_collection(db_id.address(), "col1", "col3", &DB3Address::ZERO)
+            .is_ok());
+        assert!(!db3_store
+            .is_db_collection_exist(db_id.address(), "col1")
+            .unwrap());
+        let collection = db3_store
+            .get_collection(db_id.address(), "col3")
+            .unwrap()
+            .unwrap();
+        assert_eq!("col3", collection.name.as_str());
+        assert_eq!(1, collection.index_fields.len());
+        assert_eq!(
+            Some(ids[0]),
+            db3_store
+                .get_doc_id_by_key(db_id.address(), "col3", "a")
+                .unwrap()
+        );
+        assert_eq!(
+            None,
+            db3_store
+                .get_doc_id_by_key(db_id.address(), "col1", "a")
+                .unwrap()
+        );
+        let col_state = db3_store
+            .get_collection_state(db_id.address(), "col3")
+            .unwrap();
+        assert_eq!(1, col_state.total_doc_count);
+        assert!(db3_store
+            .get_collection_state(db_id.address(), "col1")
+            .is_none());
+
+        assert!(matches!(
+            db3_store.transfer_database(db_id.address(), &bob, &bob, 2, 1),
+            Err(DB3Error::DatabasePermissionDenied())
+        ));
+        assert!(db3_store
+            .transfer_database(db_id.address(), &bob, &DB3Address::ZERO, 2, 1)
+            .is_ok());
+        let (databases, _) = db3_store.get_database_of_owner(&DB3Address::ZERO).unwrap();
+        assert!(databases.is_empty());
+        let (databases, _) = db3_store.get_database_of_owner(&bob).unwrap();
+        assert_eq!(1, databases.len());
+        if let Some(database_message::Database::DocDb(db)) = &databases[0].database {
+            assert_eq!(bob.as_ref(), db.sender.as_slice());
+        } else {
+            assert!(false);
+        }
+        let collection = db3_store
+            .get_collection(db_id.address(), "col3")
+            .unwrap()
+            .unwrap();
+        assert_eq!(bob.as_ref(), collection.sender.as_slice());
+        // the previous owner can not change the database any more
+        assert!(db3_store
+            .rename_collection(db_id.address(), "col3", "col4", &DB3Address::ZERO)
+            .is_err());
+        assert!(db3_store
+            .rename_collection(db_id.address(), "col3", "col4", &bob)
+            .is_ok());
+        // the owners of the documents follow the renamed collection
+        assert!(db3_store
+            .drop_collection(db_id.address(), "col4", &bob)
+            .is_ok());
+        assert_eq!(
+            vec![None],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_increase_db_doc_order_ut() {
+        let (_tmp_dir_path, config) = new_store_config("new_database", false);
         let result = DBStoreV2::new(config);
         assert_eq!(result.is_ok(), true);
         let db_m = DocumentDatabaseMutation {
@@ -1653,28 +7293,14 @@ mod tests {
         let result = db3_store.create_doc_database(&DB3Address::ZERO, &db_m, 2, 1, 2, 1);
         assert!(result.is_ok());
         let result = db3_store
-            .update_db_state_for_add_docs(&db_id_1.address().to_hex(), "col1", 3, None)
+            .update_db_state_for_add_docs(&db_id_1.address().to_hex(), 3, None)
             .unwrap();
         assert_eq!(result, Some(vec![1, 2, 3]));
     }
 
     #[test]
     fn test_update_db_state_for_add_docs_with_given_doc_ids() {
-        let tmp_dir_path = TempDir::new("new_database").expect("create temp dir");
-        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
-        let config = DBStoreV2Config {
-            db_path: real_path,
-            db_store_cf_name: "db".to_string(),
-            doc_store_cf_name: "doc".to_string(),
-            collection_store_cf_name: "cf2".to_string(),
-            index_store_cf_name: "index".to_string(),
-            doc_owner_store_cf_name: "doc_owner".to_string(),
-            db_owner_store_cf_name: "db_owner".to_string(),
-            scan_max_limit: 50,
-            enable_doc_store: false,
-            doc_store_conf: DocStoreConfig::default(),
-            doc_start_id: 1000,
-        };
+        let (_tmp_dir_path, config) = new_store_config("new_database", false);
         let result = DBStoreV2::new(config);
         assert_eq!(result.is_ok(), true);
         let db_m = DocumentDatabaseMutation {
@@ -1688,29 +7314,15 @@ mod tests {
         assert!(result.is_ok());
         let doc_ids: Vec<i64> = vec![1, 2, 3];
         let result = db3_store
-            .update_db_state_for_add_docs(&db_id_1.address().to_hex(), "col1", 3, Some(&doc_ids))
+            .update_db_state_for_add_docs(&db_id_1.address().to_hex(), 3, Some(&doc_ids))
             .unwrap();
         assert_eq!(result, Some(vec![1, 2, 3]));
     }
 
     #[test]
     fn recover_and_store_block_state_ut() {
-        let tmp_dir_path = TempDir::new("recover_block_state_ut").expect("create temp dir");
-        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
+        let (_tmp_dir_path, config) = new_store_config("recover_block_state_ut", false);
         {
-            let config = DBStoreV2Config {
-                db_path: real_path.to_string(),
-                db_store_cf_name: "db".to_string(),
-                doc_store_cf_name: "doc".to_string(),
-                collection_store_cf_name: "cf2".to_string(),
-                index_store_cf_name: "index".to_string(),
-                doc_owner_store_cf_name: "doc_owner".to_string(),
-                db_owner_store_cf_name: "db_owner".to_string(),
-                scan_max_limit: 50,
-                enable_doc_store: false,
-                doc_store_conf: DocStoreConfig::default(),
-                doc_start_id: 1000,
-            };
             let result = DBStoreV2::new(config);
             assert_eq!(result.is_ok(), true);
             let db3_store = result.unwrap();
@@ -1726,29 +7338,48 @@ mod tests {
             // recover block state
             let block_state = db3_store.recover_block_state().unwrap();
             assert_eq!(block_state, Some(BlockState { block: 1, order: 2 }));
+
+            // the skipped mutation moves the block state over it
+            let res = db3_store.skip_mutation(1, 3, "collection not found");
+            assert!(res.is_ok());
+            let block_state = db3_store.recover_block_state().unwrap();
+            assert_eq!(block_state, Some(BlockState { block: 1, order: 3 }));
+            assert_eq!(
+                vec![(1, 3, "collection not found".to_string())],
+                db3_store.get_skipped_mutations().unwrap()
+            );
         }
     }
+
+    #[test]
+    fn test_is_apply_mutation_error() {
+        assert!(DBStoreV2::is_apply_mutation_error(
+            &DBStoreV2::to_apply_mutation_error(DB3Error::CollectionNotFound(
+                "col1".to_string(),
+                DB3Address::ZERO.to_hex()
+            ))
+        ));
+        assert!(DBStoreV2::is_apply_mutation_error(
+            &DB3Error::DuplicateUniqueKey("name".to_string(), 1)
+        ));
+        assert!(DBStoreV2::is_apply_mutation_error(
+            &DB3Error::PreconditionFailed("revision".to_string())
+        ));
+        // the errors out of applying the mutation are not skipped
+        assert!(!DBStoreV2::is_apply_mutation_error(
+            &DB3Error::WriteStoreError("invalid mutation body".to_string())
+        ));
+        assert!(!DBStoreV2::is_apply_mutation_error(
+            &DB3Error::ReadStoreError("cf is not found".to_string())
+        ));
+    }
     #[test]
     fn test_recover_db_state_with_persistence() {
-        let tmp_dir_path = TempDir::new("new_database").expect("create temp dir");
-        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
+        let (_tmp_dir_path, config) = new_store_config("new_database", false);
         let mut address: Vec<DB3Address> = Vec::new();
 
         {
-            let config = DBStoreV2Config {
-                db_path: real_path.to_string(),
-                db_store_cf_name: "db".to_string(),
-                doc_store_cf_name: "doc".to_string(),
-                collection_store_cf_name: "cf2".to_string(),
-                index_store_cf_name: "index".to_string(),
-                doc_owner_store_cf_name: "doc_owner".to_string(),
-                db_owner_store_cf_name: "db_owner".to_string(),
-                scan_max_limit: 50,
-                enable_doc_store: false,
-                doc_store_conf: DocStoreConfig::default(),
-                doc_start_id: 1000,
-            };
-            let result = DBStoreV2::new(config);
+            let result = DBStoreV2::new(config.clone());
             assert_eq!(result.is_ok(), true);
             let db_m = DocumentDatabaseMutation {
                 db_desc: "test_desc".to_string(),
@@ -1764,6 +7395,9 @@ mod tests {
             let collection = CollectionMutation {
                 index_fields: vec![],
                 collection_name: "col1".to_string(),
+                schema: None,
+                ttl: 0,
+                keep_history: false,
             };
 
             let result = db3_store.create_collection(
@@ -1801,20 +7435,7 @@ mod tests {
         }
 
         {
-            let config = DBStoreV2Config {
-                db_path: real_path,
-                db_store_cf_name: "db".to_string(),
-                doc_store_cf_name: "doc".to_string(),
-                collection_store_cf_name: "cf2".to_string(),
-                index_store_cf_name: "index".to_string(),
-                doc_owner_store_cf_name: "doc_owner".to_string(),
-                db_owner_store_cf_name: "db_owner".to_string(),
-                scan_max_limit: 50,
-                enable_doc_store: false,
-                doc_store_conf: DocStoreConfig::default(),
-                doc_start_id: 1000,
-            };
-            let result = DBStoreV2::new(config);
+            let result = DBStoreV2::new(config.clone());
             let db3_store = result.unwrap();
             let result = db3_store.recover_db_state();
             println!("{:?}", result);
@@ -1828,25 +7449,11 @@ mod tests {
 
     #[test]
     fn test_recover_db_state() {
-        let tmp_dir_path = TempDir::new("new_database").expect("create temp dir");
-        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
+        let (_tmp_dir_path, config) = new_store_config("new_database", false);
         let mut address: Vec<DB3Address> = Vec::new();
 
         {
-            let config = DBStoreV2Config {
-                db_path: real_path.to_string(),
-                db_store_cf_name: "db".to_string(),
-                doc_store_cf_name: "doc".to_string(),
-                collection_store_cf_name: "cf2".to_string(),
-                index_store_cf_name: "index".to_string(),
-                doc_owner_store_cf_name: "doc_owner".to_string(),
-                db_owner_store_cf_name: "db_owner".to_string(),
-                scan_max_limit: 50,
-                enable_doc_store: false,
-                doc_store_conf: DocStoreConfig::default(),
-                doc_start_id: 1000,
-            };
-            let result = DBStoreV2::new(config);
+            let result = DBStoreV2::new(config.clone());
             assert_eq!(result.is_ok(), true);
             let db_m = DocumentDatabaseMutation {
                 db_desc: "test_desc".to_string(),
@@ -1862,6 +7469,9 @@ mod tests {
             let collection = CollectionMutation {
                 index_fields: vec![],
                 collection_name: "col1".to_string(),
+                schema: None,
+                ttl: 0,
+                keep_history: false,
             };
 
             let result = db3_store.create_collection(
@@ -1897,20 +7507,7 @@ mod tests {
         }
 
         {
-            let config = DBStoreV2Config {
-                db_path: real_path,
-                db_store_cf_name: "db".to_string(),
-                doc_store_cf_name: "doc".to_string(),
-                collection_store_cf_name: "cf2".to_string(),
-                index_store_cf_name: "index".to_string(),
-                doc_owner_store_cf_name: "doc_owner".to_string(),
-                db_owner_store_cf_name: "db_owner".to_string(),
-                scan_max_limit: 50,
-                enable_doc_store: false,
-                doc_store_conf: DocStoreConfig::default(),
-                doc_start_id: 1000,
-            };
-            let result = DBStoreV2::new(config);
+            let result = DBStoreV2::new(config.clone());
             let db3_store = result.unwrap();
             let result = db3_store.recover_db_state();
             println!("{:?}", result);
@@ -1921,4 +7518,511 @@ mod tests {
             assert_eq!(database_state.doc_order, 1004);
         }
     }
+
+    #[test]
+    fn test_expire_docs() {
+        let (_tmp_dir_path, config) = new_store_config("expire_docs", false);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        for (name, ttl) in [("col1", 10), ("col2", 0)] {
+            let collection = CollectionMutation {
+                index_fields: vec![],
+                collection_name: name.to_string(),
+                schema: None,
+                ttl,
+                keep_history: false,
+            };
+            assert!(db3_store
+                .create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1)
+                .is_ok());
+        }
+        let add = |col_name: &str| {
+            let dm = Mutation {
+                action: MutationAction::AddDocument.into(),
+                bodies: vec![BodyWrapper {
+                    body: Some(Body::DocumentMutation(DocumentMutation {
+                        collection_name: col_name.to_string(),
+                        documents: vec![
+                            db3_base::bson_util::json_str_to_bson_bytes(
+                                r#"{"name":"a"}"#
+                            )
+                            .unwrap();
+                            2
+                        ],
+                        ids: vec![],
+                        masks: vec![],
+                        preconditions: vec![],
+                        keys: vec![],
+                    })),
+                    db_address: db_id.address().as_ref().to_vec(),
+                }],
+            };
+            db3_store.apply_mutation(
+                MutationAction::AddDocument,
+                dm,
+                &DB3Address::ZERO,
+                1,
+                1,
+                2,
+                1,
+                &HashMap::from([(SERVER_TIME_KEY.to_string(), vec![1000])]),
+            )
+        };
+        let items = add("col1").unwrap();
+        let ids: Vec<i64> = items
+            .iter()
+            .filter(|item| item.key.starts_with(NEW_DOCS_KEY_PREFIX))
+            .map(|item| item.value.parse::<i64>().unwrap())
+            .collect();
+        assert_eq!(2, ids.len());
+        // the documents without ttl never expire
+        assert!(add("col2").is_ok());
+        assert!(db3_store.get_expired_docs(10999, 10).unwrap().is_empty());
+        let expired_docs = db3_store.get_expired_docs(11000, 10).unwrap();
+        assert_eq!(1, expired_docs.len());
+        assert_eq!(expired_docs[0].0, *db_id.address());
+        assert_eq!("col1", expired_docs[0].1.as_str());
+        assert_eq!(ids, expired_docs[0].2);
+        assert_eq!(1, db3_store.get_expired_docs(11000, 1).unwrap()[0].2.len());
+
+        let expire = |server_time: i64| {
+            let dm = Mutation {
+                action: MutationAction::ExpireDocument.into(),
+                bodies: vec![BodyWrapper {
+                    body: Some(Body::ExpireDocumentMutation(ExpireDocumentMutation {
+                        collection_name: "col1".to_string(),
+                        ids: ids.clone(),
+                    })),
+                    db_address: db_id.address().as_ref().to_vec(),
+                }],
+            };
+            db3_store.apply_mutation(
+                MutationAction::ExpireDocument,
+                dm,
+                &DB3Address::ZERO,
+                1,
+                2,
+                3,
+                1,
+                &HashMap::from([(SERVER_TIME_KEY.to_string(), vec![server_time])]),
+            )
+        };
+        // the documents have not expired at the server time of the mutation
+        assert!(expire(10999).is_ok());
+        assert_eq!(
+            vec![Some(1), Some(1)],
+            db3_store
+                .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+                .unwrap()
+        );
+        assert!(expire(11000).is_ok());
+        assert!(db3_store
+            .get_doc_revisions(&PendingWrites::default(), db_id.address(), &ids)
+            .unwrap()
+            .iter()
+            .all(|revision| revision.is_none()));
+        assert!(db3_store.get_expired_docs(11000, 10).unwrap().is_empty());
+        let state = db3_store.get_database_state(db_id.address()).unwrap();
+        assert_eq!(2, state.total_doc_count);
+        // the deleted document clears its expiry
+        let ids: Vec<i64> = add("col1")
+            .unwrap()
+            .iter()
+            .filter(|item| item.key.starts_with(NEW_DOCS_KEY_PREFIX))
+            .map(|item| item.value.parse::<i64>().unwrap())
+            .collect();
+        assert_eq!(2, ids.len());
+        assert!(db3_store
+            .delete_docs(db_id.address(), &DB3Address::ZERO, "col1", &vec![ids[0]])
+            .is_ok());
+        assert_eq!(
+            vec![ids[1]],
+            db3_store.get_expired_docs(11000, 10).unwrap()[0].2
+        );
+        // the expiry is moved with the renamed collection
+        assert!(db3_store
+            .rename_collection(db_id.address(), "col1", "col3", &DB3Address::ZERO)
+            .is_ok());
+        assert_eq!(
+            "col3",
+            db3_store.get_expired_docs(11000, 10).unwrap()[0].1.as_str()
+        );
+        // the empty ids expire all the expired documents of the collection
+        let dm = Mutation {
+            action: MutationAction::ExpireDocument.into(),
+            bodies: vec![BodyWrapper {
+                body: Some(Body::ExpireDocumentMutation(ExpireDocumentMutation {
+                    collection_name: "col3".to_string(),
+                    ids: vec![],
+                })),
+                db_address: db_id.address().as_ref().to_vec(),
+            }],
+        };
+        let items = db3_store
+            .apply_mutation(
+                MutationAction::ExpireDocument,
+                dm,
+                &DB3Address::ZERO,
+                1,
+                3,
+                4,
+                1,
+                &HashMap::from([(SERVER_TIME_KEY.to_string(), vec![11000])]),
+            )
+            .unwrap();
+        assert!(items.iter().any(|item| item.value == ids[1].to_string()));
+        assert!(db3_store.get_expired_docs(11000, 10).unwrap().is_empty());
+        // the expiry is deleted with the database
+        assert!(add("col3").is_ok());
+        assert!(!db3_store.get_expired_docs(11000, 10).unwrap().is_empty());
+        assert!(db3_store
+            .delete_doc_db(&DB3Address::ZERO, db_id.address())
+            .is_ok());
+        assert!(db3_store.get_expired_docs(11000, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_state_root() {
+        let (_tmp_dir_path, config) = new_store_config("state_root", false);
+        let (_other_tmp_dir_path, other_config) = new_store_config("other_state_root", false);
+        let stores = [
+            DBStoreV2::new(config).unwrap(),
+            DBStoreV2::new(other_config).unwrap(),
+        ];
+        let empty_root = vec![0; merkle::HASH_LENGTH];
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let collection = CollectionMutation {
+            index_fields: vec![],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let mut db_addrs = vec![];
+        for store in stores.iter() {
+            assert_eq!(empty_root, store.get_state_root().unwrap());
+            let db_id = store
+                .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+                .unwrap();
+            assert!(store
+                .create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1)
+                .is_ok());
+            db_addrs.push(*db_id.address());
+        }
+        let db_addr = db_addrs[0];
+        let apply = |store: &DBStoreV2, action: MutationAction, ids: Vec<i64>, order: u32| {
+            let dm = Mutation {
+                action: action.into(),
+                bodies: vec![BodyWrapper {
+                    body: Some(Body::DocumentMutation(DocumentMutation {
+                        collection_name: "col1".to_string(),
+                        documents: vec![
+                            db3_base::bson_util::json_str_to_bson_bytes(
+                                r#"{"name":"a"}"#
+                            )
+                            .unwrap();
+                            ids.len().max(2)
+                        ],
+                        ids,
+                        masks: vec![],
+                        preconditions: vec![],
+                        keys: vec![],
+                    })),
+                    db_address: db_addr.as_ref().to_vec(),
+                }],
+            };
+            store
+                .apply_mutation(
+                    action,
+                    dm,
+                    &DB3Address::ZERO,
+                    1,
+                    order as u64,
+                    2,
+                    order,
+                    &HashMap::new(),
+                )
+                .unwrap()
+        };
+        // the stores applying the same mutations have the same state root
+        let mut ids = vec![];
+        for store in stores.iter() {
+            ids = apply(store, MutationAction::AddDocument, vec![], 1)
+                .iter()
+                .filter(|item| item.key.starts_with(NEW_DOCS_KEY_PREFIX))
+                .map(|item| item.value.parse::<i64>().unwrap())
+                .collect();
+        }
+        assert_eq!(2, ids.len());
+        let added_root = stores[0].get_state_root().unwrap();
+        assert_ne!(empty_root, added_root);
+        assert_eq!(added_root, stores[1].get_state_root().unwrap());
+        // the updated document changes the state root
+        apply(&stores[0], MutationAction::UpdateDocument, ids.clone(), 2);
+        let updated_root = stores[0].get_state_root().unwrap();
+        assert_ne!(added_root, updated_root);
+        apply(&stores[1], MutationAction::UpdateDocument, ids.clone(), 2);
+        assert_eq!(updated_root, stores[1].get_state_root().unwrap());
+        // the deleted documents are removed from the state tree
+        apply(&stores[0], MutationAction::DeleteDocument, vec![ids[0]], 3);
+        assert_ne!(updated_root, stores[0].get_state_root().unwrap());
+        apply(&stores[0], MutationAction::DeleteDocument, vec![ids[1]], 4);
+        assert_eq!(empty_root, stores[0].get_state_root().unwrap());
+        assert!(stores[1]
+            .drop_collection(&db_addr, "col1", &DB3Address::ZERO)
+            .is_ok());
+        assert_eq!(empty_root, stores[1].get_state_root().unwrap());
+    }
+
+    #[test]
+    fn test_doc_history() {
+        let (_tmp_dir_path, config) = new_store_config("doc_history", true);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        for (name, keep_history) in [("col1", true), ("col2", false)] {
+            let collection = CollectionMutation {
+                index_fields: vec![],
+                collection_name: name.to_string(),
+                schema: None,
+                ttl: 0,
+                keep_history,
+            };
+            assert!(db3_store
+                .create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1)
+                .is_ok());
+        }
+        let apply = |action: MutationAction, doc: Option<&str>, ids: Vec<i64>, block: u64| {
+            let dm = Mutation {
+                action: action.into(),
+                bodies: vec![BodyWrapper {
+                    body: Some(Body::DocumentMutation(DocumentMutation {
+                        collection_name: "col1".to_string(),
+                        documents: doc
+                            .iter()
+                            .map(|d| db3_base::bson_util::json_str_to_bson_bytes(d).unwrap())
+                            .collect(),
+                        ids,
+                        masks: vec![],
+                        preconditions: vec![],
+                        keys: vec![],
+                    })),
+                    db_address: db_id.address().as_ref().to_vec(),
+                }],
+            };
+            db3_store.apply_mutation(
+                action,
+                dm,
+                &DB3Address::ZERO,
+                1,
+                block,
+                block,
+                1,
+                &HashMap::new(),
+            )
+        };
+        let items = apply(
+            MutationAction::AddDocument,
+            Some(r#"{"name":"a"}"#),
+            vec![],
+            2,
+        )
+        .unwrap();
+        let id = items[0].value.parse::<i64>().unwrap();
+        assert!(apply(
+            MutationAction::UpdateDocument,
+            Some(r#"{"name":"b"}"#),
+            vec![id],
+            4
+        )
+        .is_ok());
+        assert!(apply(MutationAction::DeleteDocument, None, vec![id], 6).is_ok());
+
+        let name_as_of = |block: u64| {
+            db3_store
+                .get_doc_as_of(db_id.address(), "col1", id, block)
+                .unwrap()
+                .map(|document| {
+                    let value: serde_json::Value =
+                        serde_json::from_str(document.doc.as_str()).unwrap();
+                    (
+                        value["name"].as_str().unwrap().to_string(),
+                        document.revision,
+                    )
+                })
+        };
+        assert_eq!(None, name_as_of(1));
+        assert_eq!(Some(("a".to_string(), 1)), name_as_of(3));
+        assert_eq!(Some(("b".to_string(), 2)), name_as_of(4));
+        assert_eq!(None, name_as_of(6));
+        let query = Query {
+            query_str: "/[name = \"a\"]".to_string(),
+            parameters: vec![],
+        };
+        let (documents, count) = db3_store
+            .query_docs_as_of(db_id.address(), "col1", &query, 3)
+            .unwrap();
+        assert_eq!(1, count);
+        assert_eq!(id, documents[0].id);
+        let (_, count) = db3_store
+            .query_docs_as_of(db_id.address(), "col1", &query, 5)
+            .unwrap();
+        assert_eq!(0, count);
+        // the collection without history can't be read as of a block
+        assert!(matches!(
+            db3_store.get_doc_as_of(db_id.address(), "col2", id, 3),
+            Err(DB3Error::HistoryNotKept(_))
+        ));
+        // the history is moved with the renamed collection and deleted with it
+        assert!(db3_store
+            .rename_collection(db_id.address(), "col1", "col3", &DB3Address::ZERO)
+            .is_ok());
+        assert_eq!(
+            1,
+            db3_store
+                .get_docs_as_of(db_id.address(), "col3", 3)
+                .unwrap()
+                .len()
+        );
+        assert!(db3_store
+            .drop_collection(db_id.address(), "col3", &DB3Address::ZERO)
+            .is_ok());
+        let prefix = DocHistoryKey::build_prefix(db_id.address(), None).unwrap();
+        let cf_handle = db3_store.se.cf_handle(DOC_HISTORY_CF).unwrap();
+        let it: DBRawIterator = db3_store.se.prefix_iterator_cf(&cf_handle, &prefix).into();
+        assert!(!it.valid() || !it.key().unwrap().starts_with(&prefix));
+    }
+    #[test]
+    fn test_doc_by_key_as_of() {
+        let (_tmp_dir_path, config) = new_store_config("doc_by_key_as_of", true);
+        let db3_store = DBStoreV2::new(config).unwrap();
+        let db_m = DocumentDatabaseMutation {
+            db_desc: "test_desc".to_string(),
+        };
+        let db_id = db3_store
+            .create_doc_database(&DB3Address::ZERO, &db_m, 1, 1, 1, 1)
+            .unwrap();
+        let collection = CollectionMutation {
+            index_fields: vec![],
+            collection_name: "col1".to_string(),
+            schema: None,
+            ttl: 0,
+            keep_history: true,
+        };
+        assert!(db3_store
+            .create_collection(&DB3Address::ZERO, db_id.address(), &collection, 1, 1, 1)
+            .is_ok());
+        let apply = |action: MutationAction,
+                     doc: Option<&str>,
+                     ids: Vec<i64>,
+                     keys: Vec<String>,
+                     block: u64| {
+            let dm = Mutation {
+                action: action.into(),
+                bodies: vec![BodyWrapper {
+                    body: Some(Body::DocumentMutation(DocumentMutation {
+                        collection_name: "col1".to_string(),
+                        documents: doc
+                            .iter()
+                            .map(|d| db3_base::bson_util::json_str_to_bson_bytes(d).unwrap())
+                            .collect(),
+                        ids,
+                        masks: vec![],
+                        preconditions: vec![],
+                        keys,
+                    })),
+                    db_address: db_id.address().as_ref().to_vec(),
+                }],
+            };
+            db3_store
+                .apply_mutation(
+                    action,
+                    dm,
+                    &DB3Address::ZERO,
+                    1,
+                    block,
+                    block,
+                    1,
+                    &HashMap::new(),
+                )
+                .unwrap()
+                .iter()
+                .filter(|item| item.key.starts_with(NEW_DOCS_KEY_PREFIX))
+                .map(|item| item.value.parse::<i64>().unwrap())
+                .collect::<Vec<i64>>()
+        };
+        let add = |name: &str, key: &str, block: u64| {
+            apply(
+                MutationAction::AddDocument,
+                Some(format!(r#"{{"name":"{name}"}}"#).as_str()),
+                vec![],
+                vec![key.to_string()],
+                block,
+            )[0]
+        };
+        let first_id = add("a", "k1", 2);
+        add("c", "k2", 3);
+        apply(
+            MutationAction::UpdateDocument,
+            Some(r#"{"name":"b"}"#),
+            vec![first_id],
+            vec![],
+            4,
+        );
+        apply(
+            MutationAction::DeleteDocument,
+            None,
+            vec![first_id],
+            vec![],
+            6,
+        );
+        // the key of the deleted document is held by a new one
+        let second_id = add("d", "k1", 8);
+        assert_ne!(first_id, second_id);
+
+        let name_as_of = |key: &str, block: u64| {
+            db3_store
+                .get_doc_by_key_as_of(db_id.address(), "col1", key, block)
+                .unwrap()
+                .map(|document| {
+                    let value: serde_json::Value =
+                        serde_json::from_str(document.doc.as_str()).unwrap();
+                    value["name"].as_str().unwrap().to_string()
+                })
+        };
+        assert_eq!(None, name_as_of("k1", 1));
+        assert_eq!(Some("a".to_string()), name_as_of("k1", 3));
+        assert_eq!(Some("b".to_string()), name_as_of("k1", 5));
+        assert_eq!(None, name_as_of("k1", 7));
+        assert_eq!(Some("d".to_string()), name_as_of("k1", 8));
+        assert_eq!(Some("c".to_string()), name_as_of("k2", 8));
+        assert_eq!(None, name_as_of("k3", 8));
+
+        let ids_as_of = |block: u64| -> Vec<i64> {
+            db3_store
+                .get_docs_as_of(db_id.address(), "col1", block)
+                .unwrap()
+                .iter()
+                .map(|document| document.id)
+                .collect()
+        };
+        assert!(ids_as_of(1).is_empty());
+        assert_eq!(1, ids_as_of(2).len());
+        assert_eq!(2, ids_as_of(5).len());
+        assert!(!ids_as_of(7).contains(&first_id));
+        assert_eq!(1, ids_as_of(7).len());
+        assert!(ids_as_of(8).contains(&second_id));
+        assert_eq!(2, ids_as_of(8).len());
+    }
 }
@@ -0,0 +1,286 @@
+//
+// doc_aggregator.rs
+// Copyright (C) 2023 db3.network Author imotai <codego.me@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::query_plan;
+use db3_proto::db3_database_v2_proto::aggregation::Operator as AggregationOp;
+use db3_proto::db3_database_v2_proto::structured_query::value::ValueType;
+use db3_proto::db3_database_v2_proto::structured_query::Value;
+use db3_proto::db3_database_v2_proto::{Aggregation, AggregationResult};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Default)]
+struct Accumulator {
+    count: i64,
+    numeric_count: i64,
+    int_sum: Option<i64>,
+    double_sum: f64,
+    min: Option<(f64, ValueType)>,
+    max: Option<(f64, ValueType)>,
+}
+
+impl Accumulator {
+    fn add(&mut self, value: Option<&serde_json::Value>) {
+        self.count += 1;
+        let number = match value {
+            Some(serde_json::Value::Number(n)) => n,
+            _ => return,
+        };
+        let (f, value_type) = match (number.as_i64(), number.as_f64()) {
+            (Some(i), _) => (i as f64, ValueType::IntegerValue(i)),
+            (None, Some(f)) => (f, ValueType::DoubleValue(f)),
+            _ => return,
+        };
+        self.int_sum = match (self.numeric_count, self.int_sum, &value_type) {
+            (0, _, ValueType::IntegerValue(i)) => Some(*i),
+            (_, Some(sum), ValueType::IntegerValue(i)) => sum.checked_add(*i),
+            _ => None,
+        };
+        self.numeric_count += 1;
+        self.double_sum += f;
+        if self.min.as_ref().map_or(true, |(m, _)| f < *m) {
+            self.min = Some((f, value_type.clone()));
+        }
+        if self.max.as_ref().map_or(true, |(m, _)| f > *m) {
+            self.max = Some((f, value_type));
+        }
+    }
+
+    fn result(&self, op: AggregationOp) -> Value {
+        let value_type = match op {
+            AggregationOp::Count => Some(ValueType::IntegerValue(self.count)),
+            AggregationOp::Sum => match self.int_sum {
+                Some(sum) => Some(ValueType::IntegerValue(sum)),
+                None if self.numeric_count > 0 => Some(ValueType::DoubleValue(self.double_sum)),
+                None => Some(ValueType::IntegerValue(0)),
+            },
+            AggregationOp::Avg if self.numeric_count > 0 => Some(ValueType::DoubleValue(
+                self.double_sum / self.numeric_count as f64,
+            )),
+            AggregationOp::Min => self.min.as_ref().map(|(_, v)| v.clone()),
+            AggregationOp::Max => self.max.as_ref().map(|(_, v)| v.clone()),
+            _ => None,
+        };
+        Value { value_type }
+    }
+}
+
+struct Group {
+    group_value: Option<Value>,
+    accumulators: Vec<Accumulator>,
+}
+
+///
+/// DocAggregator folds the json documents into the results of the aggregations
+/// grouped by the value of the group by field
+///
+pub struct DocAggregator<'a> {
+    aggregations: &'a [Aggregation],
+    group_by: &'a str,
+    groups: BTreeMap<String, Group>,
+}
+
+impl<'a> DocAggregator<'a> {
+    pub fn new(aggregations: &'a [Aggregation], group_by: &'a str) -> Self {
+        Self {
+            aggregations,
+            group_by,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_doc(&mut self, doc: &serde_json::Value) {
+        let group_json = if self.group_by.is_empty() {
+            None
+        } else {
+            doc.get(self.group_by)
+        };
+        // the serialized json is used as the key to keep the output order deterministic
+        let key = group_json.map_or("".to_string(), |v| v.to_string());
+        let aggregation_count = self.aggregations.len();
+        let group = self.groups.entry(key).or_insert_with(|| Group {
+            group_value: group_json.and_then(query_plan::scalar_value_from_json),
+            accumulators: vec![Accumulator::default(); aggregation_count],
+        });
+        for (aggregation, accumulator) in
+            self.aggregations.iter().zip(group.accumulators.iter_mut())
+        {
+            accumulator.add(doc.get(aggregation.field.as_str()));
+        }
+    }
+
+    pub fn finish(self) -> Vec<AggregationResult> {
+        let aggregations = self.aggregations;
+        let mut groups: Vec<Group> = self.groups.into_values().collect();
+        if groups.is_empty() && self.group_by.is_empty() {
+            // the aggregations without group by always have one result
+            groups.push(Group {
+                group_value: None,
+                accumulators: vec![Accumulator::default(); aggregations.len()],
+            });
+        }
+        groups
+            .into_iter()
+            .map(|group| AggregationResult {
+                group_value: group.group_value,
+                values: aggregations
+                    .iter()
+                    .zip(group.accumulators.iter())
+                    .map(|(aggregation, accumulator)| accumulator.result(aggregation.op()))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+///
+/// the results of the aggregations without group by which only count the documents
+///
+pub fn count_results(aggregations: &[Aggregation], count: i64) -> Vec<AggregationResult> {
+    let accumulator = Accumulator {
+        count,
+        ..Default::default()
+    };
+    vec![AggregationResult {
+        group_value: None,
+        values: aggregations
+            .iter()
+            .map(|aggregation| accumulator.result(aggregation.op()))
+            .collect(),
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aggregation(op: AggregationOp, field: &str) -> Aggregation {
+        Aggregation {
+            op: op.into(),
+            field: field.to_string(),
+        }
+    }
+
+    fn value(value_type: ValueType) -> Value {
+        Value {
+            value_type: Some(value_type),
+        }
+    }
+
+    #[test]
+    fn doc_aggregator_test() {
+        let aggregations = vec![
+            aggregation(AggregationOp::Count, ""),
+            aggregation(AggregationOp::Sum, "amount"),
+            aggregation(AggregationOp::Avg, "amount"),
+            aggregation(AggregationOp::Min, "amount"),
+            aggregation(AggregationOp::Max, "amount"),
+        ];
+        let mut aggregator = DocAggregator::new(&aggregations, "");
+        for doc in [
+            r#"{"amount": 1}"#,
+            r#"{"amount": 4}"#,
+            r#"{"amount": "x"}"#,
+            r#"{"name": "a"}"#,
+        ] {
+            aggregator.add_doc(&serde_json::from_str(doc).unwrap());
+        }
+        let results = aggregator.finish();
+        assert_eq!(1, results.len());
+        assert_eq!(None, results[0].group_value);
+        assert_eq!(
+            vec![
+                value(ValueType::IntegerValue(4)),
+                value(ValueType::IntegerValue(5)),
+                value(ValueType::DoubleValue(2.5)),
+                value(ValueType::IntegerValue(1)),
+                value(ValueType::IntegerValue(4)),
+            ],
+            results[0].values
+        );
+    }
+
+    #[test]
+    fn doc_aggregator_group_by_test() {
+        let aggregations = vec![
+            aggregation(AggregationOp::Sum, "amount"),
+            aggregation(AggregationOp::Max, "amount"),
+        ];
+        let mut aggregator = DocAggregator::new(&aggregations, "city");
+        for doc in [
+            r#"{"city": "b", "amount": 1.5}"#,
+            r#"{"city": "a", "amount": 2}"#,
+            r#"{"city": "b", "amount": 2}"#,
+            r#"{"amount": 3}"#,
+        ] {
+            aggregator.add_doc(&serde_json::from_str(doc).unwrap());
+        }
+        let results = aggregator.finish();
+        assert_eq!(3, results.len());
+        assert_eq!(None, results[0].group_value);
+        assert_eq!(
+            Some(value(ValueType::StringValue("a".to_string()))),
+            results[1].group_value
+        );
+        assert_eq!(
+            vec![
+                value(ValueType::DoubleValue(3.5)),
+                value(ValueType::IntegerValue(2)),
+            ],
+            results[2].values
+        );
+    }
+
+    #[test]
+    fn doc_aggregator_empty_test() {
+        let aggregations = vec![
+            aggregation(AggregationOp::Count, ""),
+            aggregation(AggregationOp::Sum, "amount"),
+            aggregation(AggregationOp::Avg, "amount"),
+        ];
+        let results = DocAggregator::new(&aggregations, "").finish();
+        assert_eq!(1, results.len());
+        assert_eq!(
+            vec![
+                value(ValueType::IntegerValue(0)),
+                value(ValueType::IntegerValue(0)),
+                Value { value_type: None },
+            ],
+            results[0].values
+        );
+        assert!(DocAggregator::new(&aggregations, "city")
+            .finish()
+            .is_empty());
+    }
+
+    #[test]
+    fn count_results_test() {
+        let aggregations = vec![
+            aggregation(AggregationOp::Count, ""),
+            aggregation(AggregationOp::Count, ""),
+        ];
+        let results = count_results(&aggregations, 7);
+        assert_eq!(1, results.len());
+        assert_eq!(None, results[0].group_value);
+        assert_eq!(
+            vec![
+                value(ValueType::IntegerValue(7)),
+                value(ValueType::IntegerValue(7)),
+            ],
+            results[0].values
+        );
+    }
+}
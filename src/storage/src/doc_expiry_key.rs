@@ -0,0 +1,132 @@
+//
+// doc_expiry_key.rs
+// Copyright (C) 2023 db3.network Author imotai <codego.me@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use db3_crypto::db3_address::{DB3Address, DB3_ADDRESS_LENGTH};
+use db3_error::{DB3Error, Result};
+use std::fmt;
+pub const EXPIRY_PREFIX: &str = "/expiry/";
+const EXPIRY_DOC_PREFIX: &str = "/expiry_doc/";
+const KEY_LENGTH: usize = EXPIRY_PREFIX.len() + 8 + DB3_ADDRESS_LENGTH + 8;
+
+/// DocExpiryKey with the expiry time in millis, db address and doc id. the keys are
+/// ordered by the expiry time
+pub struct DocExpiryKey<'a>(pub u64, pub &'a DB3Address, pub i64);
+impl<'a> DocExpiryKey<'a> {
+    ///
+    /// encode the document expiry key
+    ///
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut encoded_key = EXPIRY_PREFIX.as_bytes().to_vec();
+        encoded_key.extend_from_slice(self.0.to_be_bytes().as_ref());
+        encoded_key.extend_from_slice(self.1.as_ref());
+        encoded_key.extend_from_slice(self.2.to_be_bytes().as_ref());
+        Ok(encoded_key)
+    }
+
+    ///
+    /// decode the expiry time, db address and doc id from the key
+    ///
+    pub fn decode(key: &[u8]) -> Result<(u64, DB3Address, i64)> {
+        if key.len() != KEY_LENGTH || !key.starts_with(EXPIRY_PREFIX.as_bytes()) {
+            return Err(DB3Error::KeyCodecError(
+                "invalid doc expiry key".to_string(),
+            ));
+        }
+        let start = EXPIRY_PREFIX.len();
+        let expire_at = u64::from_be_bytes(
+            <[u8; 8]>::try_from(&key[start..start + 8])
+                .map_err(|e| DB3Error::KeyCodecError(format!("get expiry time err {e}")))?,
+        );
+        let db_addr = DB3Address::try_from(&key[start + 8..start + 8 + DB3_ADDRESS_LENGTH])?;
+        let doc_id = i64::from_be_bytes(
+            <[u8; 8]>::try_from(&key[KEY_LENGTH - 8..KEY_LENGTH])
+                .map_err(|e| DB3Error::KeyCodecError(format!("get doc id err {e}")))?,
+        );
+        Ok((expire_at, db_addr, doc_id))
+    }
+}
+
+impl fmt::Display for DocExpiryKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}/{}",
+            EXPIRY_PREFIX,
+            self.0,
+            self.1.to_hex(),
+            self.2
+        )
+    }
+}
+
+/// DocExpiryIndexKey with db address and doc id which maps the document to its expiry
+pub struct DocExpiryIndexKey<'a>(pub &'a DB3Address, pub i64);
+impl<'a> DocExpiryIndexKey<'a> {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut encoded_key = Self::build_prefix(self.0);
+        encoded_key.extend_from_slice(self.1.to_be_bytes().as_ref());
+        Ok(encoded_key)
+    }
+
+    pub fn build_prefix(db_addr: &DB3Address) -> Vec<u8> {
+        let mut encoded_key = EXPIRY_DOC_PREFIX.as_bytes().to_vec();
+        encoded_key.extend_from_slice(db_addr.as_ref());
+        encoded_key
+    }
+
+    pub fn decode_id(key: &[u8]) -> Result<i64> {
+        let key_len = EXPIRY_DOC_PREFIX.len() + DB3_ADDRESS_LENGTH + 8;
+        if key.len() != key_len {
+            return Err(DB3Error::KeyCodecError(
+                "invalid doc expiry index key length".to_string(),
+            ));
+        }
+        Ok(i64::from_be_bytes(
+            <[u8; 8]>::try_from(&key[key_len - 8..key_len])
+                .map_err(|e| DB3Error::KeyCodecError(format!("get doc id err {e}")))?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_doc_expiry_key() {
+        let db_addr = DB3Address::from(&[1u8; DB3_ADDRESS_LENGTH]);
+        let encoded_key = DocExpiryKey(1000, &db_addr, 7).encode().unwrap();
+        assert_eq!(encoded_key.len(), KEY_LENGTH);
+        let (expire_at, addr, doc_id) = DocExpiryKey::decode(&encoded_key).unwrap();
+        assert_eq!(expire_at, 1000);
+        assert_eq!(addr, db_addr);
+        assert_eq!(doc_id, 7);
+        // the keys are ordered by the expiry time first
+        let later_key = DocExpiryKey(1001, &DB3Address::ZERO, 1).encode().unwrap();
+        assert!(encoded_key < later_key);
+        assert!(DocExpiryKey::decode(&encoded_key[1..]).is_err());
+    }
+
+    #[test]
+    fn test_doc_expiry_index_key() {
+        let db_addr = DB3Address::ZERO;
+        let encoded_key = DocExpiryIndexKey(&db_addr, 9).encode().unwrap();
+        assert!(encoded_key.starts_with(&DocExpiryIndexKey::build_prefix(&db_addr)));
+        assert_eq!(DocExpiryIndexKey::decode_id(&encoded_key).unwrap(), 9);
+        assert!(DocExpiryIndexKey::decode_id(&encoded_key[1..]).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//
+// doc_history_key.rs
+// Copyright (C) 2023 db3.network Author imotai <codego.me@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use db3_crypto::db3_address::DB3Address;
+use db3_error::{DB3Error, Result};
+use std::fmt;
+const DOC_HISTORY_PREFIX: &str = "/doc_history/";
+
+/// DocHistoryKey with db address, collection name, doc id, block and order. the versions
+/// of a document are ordered by the block and the order of the mutations
+pub struct DocHistoryKey<'a>(pub &'a DB3Address, pub &'a str, pub i64, pub u64, pub u32);
+impl<'a> DocHistoryKey<'a> {
+    ///
+    /// encode the document history key. the collection name is prefixed with its length
+    /// to keep the keys of different collections apart
+    ///
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut encoded_key = Self::build_doc_prefix(self.0, self.1, self.2)?;
+        encoded_key.extend_from_slice(self.3.to_be_bytes().as_ref());
+        encoded_key.extend_from_slice(self.4.to_be_bytes().as_ref());
+        Ok(encoded_key)
+    }
+
+    /// the prefix of the keys in the collection or in the database if the name is none
+    pub fn build_prefix(db_addr: &DB3Address, col_name: Option<&str>) -> Result<Vec<u8>> {
+        let mut encoded_key = DOC_HISTORY_PREFIX.as_bytes().to_vec();
+        encoded_key.extend_from_slice(db_addr.as_ref());
+        if let Some(col_name) = col_name {
+            let col_len = u16::try_from(col_name.len())
+                .map_err(|e| DB3Error::KeyCodecError(format!("invalid collection name {e}")))?;
+            encoded_key.extend_from_slice(col_len.to_be_bytes().as_ref());
+            encoded_key.extend_from_slice(col_name.as_bytes());
+        }
+        Ok(encoded_key)
+    }
+
+    /// the prefix of the versions of the document
+    pub fn build_doc_prefix(db_addr: &DB3Address, col_name: &str, doc_id: i64) -> Result<Vec<u8>> {
+        let mut encoded_key = Self::build_prefix(db_addr, Some(col_name))?;
+        encoded_key.extend_from_slice(doc_id.to_be_bytes().as_ref());
+        Ok(encoded_key)
+    }
+
+    ///
+    /// decode the doc id, block and order from the key with the given collection prefix
+    ///
+    pub fn decode(key: &[u8], col_prefix: &[u8]) -> Result<(i64, u64, u32)> {
+        if key.len() != col_prefix.len() + 8 + 8 + 4 || !key.starts_with(col_prefix) {
+            return Err(DB3Error::KeyCodecError(
+                "invalid doc history key".to_string(),
+            ));
+        }
+        let start = col_prefix.len();
+        let doc_id = i64::from_be_bytes(
+            <[u8; 8]>::try_from(&key[start..start + 8])
+                .map_err(|e| DB3Error::KeyCodecError(format!("get doc id err {e}")))?,
+        );
+        let block = u64::from_be_bytes(
+            <[u8; 8]>::try_from(&key[start + 8..start + 16])
+                .map_err(|e| DB3Error::KeyCodecError(format!("get block err {e}")))?,
+        );
+        let order = u32::from_be_bytes(
+            <[u8; 4]>::try_from(&key[start + 16..start + 20])
+                .map_err(|e| DB3Error::KeyCodecError(format!("get order err {e}")))?,
+        );
+        Ok((doc_id, block, order))
+    }
+}
+
+impl fmt::Display for DocHistoryKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}/{}/{}/{}",
+            DOC_HISTORY_PREFIX,
+            self.0.to_hex(),
+            self.1,
+            self.2,
+            self.3,
+            self.4
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_doc_history_key() {
+        let db_addr = DB3Address::ZERO;
+        let encoded_key = DocHistoryKey(&db_addr, "col", 1000, 7, 2).encode().unwrap();
+        let col_prefix = DocHistoryKey::build_prefix(&db_addr, Some("col")).unwrap();
+        let (doc_id, block, order) = DocHistoryKey::decode(&encoded_key, &col_prefix).unwrap();
+        assert_eq!(doc_id, 1000);
+        assert_eq!(block, 7);
+        assert_eq!(order, 2);
+        // the versions of a document are ordered by the block and the order
+        let later_key = DocHistoryKey(&db_addr, "col", 1000, 8, 0).encode().unwrap();
+        assert!(encoded_key < later_key);
+        let doc_prefix = DocHistoryKey::build_doc_prefix(&db_addr, "col", 1000).unwrap();
+        assert!(encoded_key.starts_with(&doc_prefix));
+        assert!(later_key.starts_with(&doc_prefix));
+        let other_col_prefix = DocHistoryKey::build_prefix(&db_addr, Some("cola")).unwrap();
+        assert!(DocHistoryKey::decode(&encoded_key, &other_col_prefix).is_err());
+        let db_prefix = DocHistoryKey::build_prefix(&db_addr, None).unwrap();
+        assert!(encoded_key.starts_with(&db_prefix));
+    }
+}
@@ -0,0 +1,281 @@
+//
+// doc_schema.rs
+// Copyright (C) 2023 db3.network Author imotai <codego.me@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use db3_base::bson_util::check_field_path;
+use db3_error::{DB3Error, Result};
+use db3_proto::db3_database_v2_proto::{CollectionSchema, FieldSchema, FieldType};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+
+///
+/// check the field paths of the schema are valid and unique and every field is
+/// declared inside an object field
+///
+pub fn check_schema(schema: &CollectionSchema) -> Result<()> {
+    let mut types: HashMap<&str, FieldType> = HashMap::new();
+    for field in schema.fields.iter() {
+        let path = field.path.as_str();
+        check_field_path(path)?;
+        let field_type = FieldType::from_i32(field.field_type).unwrap_or(FieldType::Unspecified);
+        if field_type == FieldType::Unspecified {
+            return Err(DB3Error::InvalidMutationError(format!(
+                "the type of field {path} is unspecified"
+            )));
+        }
+        if types.insert(path, field_type).is_some() {
+            return Err(DB3Error::InvalidMutationError(format!(
+                "the field {path} is duplicated in the schema"
+            )));
+        }
+    }
+    for field in schema.fields.iter() {
+        let path = field.path.as_str();
+        for (i, _) in path.match_indices('.') {
+            let parent = &path[..i];
+            match types.get(parent) {
+                Some(FieldType::Object) | None => {}
+                Some(_) => {
+                    return Err(DB3Error::InvalidMutationError(format!(
+                        "the field {path} is declared inside the field {parent} which is not an object"
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+///
+/// validate the json document against the schema. the merge patch of the update is
+/// validated with `partial`, so the missing required fields are allowed but deleting
+/// them with null is rejected
+///
+pub fn validate_doc(schema: &CollectionSchema, doc: &JsonValue, partial: bool) -> Result<()> {
+    for field in schema.fields.iter() {
+        validate_field(field, doc, partial)?;
+    }
+    if schema.strict {
+        check_undeclared_fields(schema, doc, "", partial)?;
+    }
+    Ok(())
+}
+
+fn validate_field(field: &FieldSchema, doc: &JsonValue, partial: bool) -> Result<()> {
+    let path = field.path.as_str();
+    let segments: Vec<&str> = path.split('.').collect();
+    let mut current = Some(doc);
+    let mut deleted = false;
+    for (i, segment) in segments.iter().enumerate() {
+        current = match current {
+            Some(JsonValue::Object(map)) => map.get(*segment),
+            Some(JsonValue::Null) => {
+                deleted = true;
+                None
+            }
+            Some(other) => {
+                return Err(DB3Error::DocumentSchemaMismatch(format!(
+                    "the field {} should be object but got {}",
+                    segments[..i].join("."),
+                    json_type_name(other)
+                )));
+            }
+            None => None,
+        };
+    }
+    match current {
+        Some(JsonValue::Null) | None => {
+            let deleted = deleted || current.is_some();
+            if field.required && (!partial || deleted) {
+                return Err(DB3Error::DocumentSchemaMismatch(format!(
+                    "the required field {path} is missing"
+                )));
+            }
+        }
+        Some(value) => {
+            let field_type = field.field_type();
+            let is_valid = match field_type {
+                FieldType::String => value.is_string(),
+                FieldType::Int64 => value.is_i64(),
+                FieldType::Double => value.is_number(),
+                FieldType::Bool => value.is_boolean(),
+                FieldType::Array => value.is_array(),
+                FieldType::Object => value.is_object(),
+                FieldType::Unspecified => true,
+            };
+            if !is_valid {
+                return Err(DB3Error::DocumentSchemaMismatch(format!(
+                    "the field {path} should be {} but got {}",
+                    field_type_name(field_type),
+                    json_type_name(value)
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// the field is checked recursively only if there are fields declared inside it
+fn check_undeclared_fields(
+    schema: &CollectionSchema,
+    doc: &JsonValue,
+    prefix: &str,
+    partial: bool,
+) -> Result<()> {
+    if let JsonValue::Object(map) = doc {
+        for (key, value) in map.iter() {
+            let path = if prefix.is_empty() {
+                key.to_string()
+            } else {
+                format!("{prefix}.{key}")
+            };
+            let child_prefix = format!("{path}.");
+            let has_children = schema
+                .fields
+                .iter()
+                .any(|f| f.path.starts_with(child_prefix.as_str()));
+            if has_children {
+                check_undeclared_fields(schema, value, path.as_str(), partial)?;
+            } else if !schema.fields.iter().any(|f| f.path == path) {
+                // deleting an undeclared field with the merge patch is allowed
+                if partial && value.is_null() {
+                    continue;
+                }
+                return Err(DB3Error::DocumentSchemaMismatch(format!(
+                    "the field {path} is not declared in the schema"
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn field_type_name(field_type: FieldType) -> &'static str {
+    match field_type {
+        FieldType::String => "string",
+        FieldType::Int64 => "int64",
+        FieldType::Double => "double",
+        FieldType::Bool => "bool",
+        FieldType::Array => "array",
+        FieldType::Object => "object",
+        FieldType::Unspecified => "unspecified",
+    }
+}
+
+fn json_type_name(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "bool",
+        JsonValue::Number(n) if n.is_i64() => "int64",
+        JsonValue::Number(_) => "double",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(path: &str, field_type: FieldType, required: bool) -> FieldSchema {
+        FieldSchema {
+            path: path.to_string(),
+            field_type: field_type.into(),
+            required,
+        }
+    }
+
+    fn schema(strict: bool) -> CollectionSchema {
+        CollectionSchema {
+            fields: vec![
+                field("name", FieldType::String, true),
+                field("age", FieldType::Int64, false),
+                field("profile.score", FieldType::Double, false),
+                field("tags", FieldType::Array, false),
+            ],
+            strict,
+        }
+    }
+
+    fn validate(doc: &str, strict: bool, partial: bool) -> Result<()> {
+        validate_doc(
+            &schema(strict),
+            &serde_json::from_str(doc).unwrap(),
+            partial,
+        )
+    }
+
+    #[test]
+    fn check_schema_test() {
+        assert!(check_schema(&schema(true)).is_ok());
+        for fields in [
+            vec![field("name", FieldType::Unspecified, false)],
+            vec![
+                field("name", FieldType::String, false),
+                field("name", FieldType::Int64, false),
+            ],
+            vec![
+                field("tags", FieldType::Array, false),
+                field("tags.name", FieldType::String, false),
+            ],
+            vec![field("__name__", FieldType::String, false)],
+        ] {
+            let schema = CollectionSchema {
+                fields,
+                strict: false,
+            };
+            assert!(check_schema(&schema).is_err());
+        }
+    }
+
+    #[test]
+    fn validate_doc_test() {
+        assert!(validate(
+            r#"{"name": "a", "age": 1, "profile": {"score": 1}, "x": 1}"#,
+            false,
+            false
+        )
+        .is_ok());
+        let err = validate(r#"{"name": "a", "profile": {"score": "1"}}"#, false, false);
+        assert_eq!(
+            "the document does not match the schema for error the field profile.score should be double but got string",
+            err.unwrap_err().to_string()
+        );
+        let err = validate(r#"{"name": "a", "profile": 1}"#, false, false);
+        assert!(
+            matches!(err, Err(DB3Error::DocumentSchemaMismatch(msg)) if msg == "the field profile should be object but got int64")
+        );
+        assert!(validate(r#"{"name": "a", "age": 1.5}"#, false, false).is_err());
+        assert!(validate(r#"{"age": 1}"#, false, false).is_err());
+        assert!(validate(r#"{"name": null}"#, false, false).is_err());
+        // strict schema
+        assert!(validate(r#"{"name": "a", "tags": [1, "x"]}"#, true, false).is_ok());
+        let err = validate(r#"{"name": "a", "profile": {"rank": 1}}"#, true, false);
+        assert!(
+            matches!(err, Err(DB3Error::DocumentSchemaMismatch(msg)) if msg == "the field profile.rank is not declared in the schema")
+        );
+    }
+
+    #[test]
+    fn validate_patch_test() {
+        assert!(validate(r#"{"age": 2}"#, true, true).is_ok());
+        assert!(validate(r#"{"age": null, "x": null}"#, true, true).is_ok());
+        assert!(validate(r#"{"age": "2"}"#, true, true).is_err());
+        assert!(validate(r#"{"name": null}"#, true, true).is_err());
+        assert!(validate(r#"{"x": 1}"#, true, true).is_err());
+    }
+}
@@ -15,18 +15,51 @@
 // limitations under the License.
 //
 
+use crate::doc_aggregator::DocAggregator;
 use db3_crypto::db3_address::DB3Address;
 use db3_error::{DB3Error, Result};
-use db3_proto::db3_database_v2_proto::{query_parameter, Index, Query};
+use db3_proto::db3_database_v2_proto::{
+    query_parameter, Aggregation, AggregationResult, Index, IndexField, Query,
+};
 use ejdb2::SetPlaceholder;
 use ejdb2::{EJDBQuery, EJDB};
 use moka::sync::Cache;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tracing::{info, warn};
 
 const EJDB_INDEX: [u8; 4] = [0x01u8, 0x04u8, 0x08u8, 0x10u8];
+// the sequence of the scratch databases for the queries on the given documents
+static SCRATCH_DB_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+///
+/// expand the index into the fields indexed by ejdb. ejdb has no compound index so
+/// every field of a compound index is indexed on its own. the query planner still
+/// requires the compound index for the query which sorts or ranges on several fields
+///
+pub fn index_fields(index: &Index) -> Vec<IndexField> {
+    if index.fields.is_empty() {
+        vec![IndexField {
+            path: index.path.to_string(),
+            index_type: index.index_type,
+        }]
+    } else {
+        index.fields.to_vec()
+    }
+}
+
+fn ejdb_index_mode(field: &IndexField) -> Result<u8> {
+    EJDB_INDEX
+        .get(field.index_type as usize)
+        .copied()
+        .ok_or(DB3Error::WriteStoreError(format!(
+            "the index type {} of path {} is invalid",
+            field.index_type, field.path
+        )))
+}
+
 #[derive(Clone)]
 pub struct DocStoreConfig {
     pub db_root_path: String,
@@ -101,6 +134,60 @@ impl DocStore {
         }
     }
 
+    ///
+    /// close the database and remove its files. the database is created again if it's
+    /// opened later
+    ///
+    pub fn drop_database(&self, db_addr: &DB3Address) -> Result<()> {
+        self.dbs.invalidate(&db_addr.as_ref().to_vec());
+        let mut path = PathBuf::new();
+        path.push(self.config.db_root_path.as_str());
+        path.push(db_addr.to_hex());
+        let wal_path = PathBuf::from(format!("{}-wal", path.display()));
+        for file in [path, wal_path] {
+            if file.exists() {
+                fs::remove_file(file.as_path())
+                    .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+            }
+        }
+        info!("drop database with address {} done", db_addr.to_hex());
+        Ok(())
+    }
+
+    pub fn drop_collection(&self, db_addr: &DB3Address, col_name: &str) -> Result<()> {
+        let db_opt = self.get_db_ref(db_addr);
+        if let Some(db) = db_opt {
+            db.remove_collection(col_name)
+                .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+            Ok(())
+        } else {
+            Err(DB3Error::WriteStoreError(format!(
+                "no database found with addr {}",
+                db_addr.to_hex()
+            )))
+        }
+    }
+
+    /// rename the collection and the documents and indexes are kept
+    pub fn rename_collection(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        new_name: &str,
+    ) -> Result<()> {
+        let db_opt = self.get_db_ref(db_addr);
+        if let Some(db) = db_opt {
+            db.rename_collection(col_name, new_name)
+                .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+            Ok(())
+        } else {
+            Err(DB3Error::WriteStoreError(format!(
+                "no database found with addr {}",
+                db_addr.to_hex()
+            )))
+        }
+    }
+
     pub fn add_index(&self, db_addr: &DB3Address, name: &str, indexes: &Vec<Index>) -> Result<()> {
         if indexes.len() > 0 {
             let key = db_addr.as_ref().to_vec();
@@ -114,14 +201,10 @@ impl DocStore {
                 }
             });
             if let Some(entry) = db_entry {
-                for field in indexes {
+                for field in indexes.iter().flat_map(index_fields) {
                     entry
                         .value()
-                        .ensure_index(
-                            name,
-                            field.path.as_str(),
-                            EJDB_INDEX[field.index_type as usize],
-                        )
+                        .ensure_index(name, field.path.as_str(), ejdb_index_mode(&field)?)
                         .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
                 }
             }
@@ -129,6 +212,27 @@ impl DocStore {
         Ok(())
     }
 
+    pub fn drop_index(
+        &self,
+        db_addr: &DB3Address,
+        name: &str,
+        fields: &Vec<IndexField>,
+    ) -> Result<()> {
+        let db_opt = self.get_db_ref(db_addr);
+        if let Some(db) = db_opt {
+            for field in fields {
+                db.remove_index(name, field.path.as_str(), ejdb_index_mode(field)?)
+                    .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+            }
+            Ok(())
+        } else {
+            Err(DB3Error::WriteStoreError(format!(
+                "no database found with addr {}",
+                db_addr.to_hex()
+            )))
+        }
+    }
+
     pub fn add_str_doc(&self, db_addr: &DB3Address, col_name: &str, doc: &str) -> Result<i64> {
         let db_opt = self.get_db_ref(db_addr);
         if let Some(db) = db_opt {
@@ -174,12 +278,7 @@ impl DocStore {
         }
     }
 
-    pub fn execute_query(
-        &self,
-        db_addr: &DB3Address,
-        col_name: &str,
-        query: &Query,
-    ) -> Result<(Vec<(i64, String)>, u64)> {
+    fn prepare_query(col_name: &str, query: &Query) -> Result<EJDBQuery> {
         let mut prepared_statement = EJDBQuery::new(col_name, query.query_str.as_str());
         prepared_statement
             .init()
@@ -204,6 +303,16 @@ impl DocStore {
                 _ => {}
             }
         }
+        Ok(prepared_statement)
+    }
+
+    pub fn execute_query(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        query: &Query,
+    ) -> Result<(Vec<(i64, String)>, u64)> {
+        let prepared_statement = Self::prepare_query(col_name, query)?;
         let db_opt = self.get_db_ref(db_addr);
         let mut result = Vec::<(i64, String)>::new();
         if let Some(db) = db_opt {
@@ -219,6 +328,87 @@ impl DocStore {
         }
     }
 
+    ///
+    /// execute the query on the given documents instead of the documents of a database.
+    /// the documents are written to a scratch database which is removed after the query
+    ///
+    pub fn execute_query_on_docs(
+        &self,
+        col_name: &str,
+        docs: &[(i64, String)],
+        query: &Query,
+    ) -> Result<(Vec<(i64, String)>, u64)> {
+        let prepared_statement = Self::prepare_query(col_name, query)?;
+        let mut path = PathBuf::new();
+        path.push(self.config.db_root_path.as_str());
+        path.push(format!(
+            "scratch_{}_{}",
+            std::process::id(),
+            SCRATCH_DB_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let path_str = path
+            .to_str()
+            .ok_or(DB3Error::ReadStoreError(
+                "invalid scratch db path".to_string(),
+            ))?
+            .to_string();
+        let mut db = EJDB::new();
+        db.open(path_str.as_str())
+            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+        let mut result = Vec::<(i64, String)>::new();
+        let count = docs
+            .iter()
+            .try_for_each(|(id, doc)| {
+                db.put(col_name, doc, *id)
+                    .map(|_| ())
+                    .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))
+            })
+            .and_then(|_| {
+                db.exec::<String>(&prepared_statement, &mut result)
+                    .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))
+            });
+        // close the scratch database before removing its files
+        drop(db);
+        let wal_path = PathBuf::from(format!("{}-wal", path_str));
+        for file in [path, wal_path] {
+            if file.exists() {
+                if let Err(e) = fs::remove_file(file.as_path()) {
+                    warn!("fail to remove scratch db {} for {e}", file.display());
+                }
+            }
+        }
+        Ok((result, count? as u64))
+    }
+
+    ///
+    /// execute the query and fold the matched documents into the aggregation results
+    /// without returning the documents. the query should be limited to max_docs + 1 and
+    /// an error is returned if it matches more than max_docs documents
+    ///
+    pub fn execute_aggregation(
+        &self,
+        db_addr: &DB3Address,
+        col_name: &str,
+        query: &Query,
+        aggregations: &[Aggregation],
+        group_by: &str,
+        max_docs: usize,
+    ) -> Result<Vec<AggregationResult>> {
+        let (docs, _) = self.execute_query(db_addr, col_name, query)?;
+        if docs.len() > max_docs {
+            return Err(DB3Error::QueryDocumentError(format!(
+                "the aggregation matches more than {max_docs} documents, narrow the filter or set a limit"
+            )));
+        }
+        let mut aggregator = DocAggregator::new(aggregations, group_by);
+        for (_, doc) in docs.iter() {
+            let value: serde_json::Value = serde_json::from_str(doc.as_str())
+                .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+            aggregator.add_doc(&value);
+        }
+        Ok(aggregator.finish())
+    }
+
     fn get_db_ref(&self, db_addr: &DB3Address) -> Option<Arc<EJDB>> {
         let key = db_addr.as_ref().to_vec();
         let add_addr_clone = db_addr.clone();
@@ -315,7 +505,17 @@ impl DocStore {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::query_plan::QueryPlanner;
+    use db3_proto::db3_database_v2_proto::aggregation::Operator as AggregationOp;
+    use db3_proto::db3_database_v2_proto::structured_query::composite_filter::Operator as CompositeOp;
+    use db3_proto::db3_database_v2_proto::structured_query::field_filter::Operator;
+    use db3_proto::db3_database_v2_proto::structured_query::filter::FilterType;
+    use db3_proto::db3_database_v2_proto::structured_query::value::ValueType;
+    use db3_proto::db3_database_v2_proto::structured_query::{
+        CompositeFilter, Direction, FieldFilter, Filter, NullValue, Order, Projection, Value,
+    };
     use db3_proto::db3_database_v2_proto::QueryParameter;
+    use db3_proto::db3_database_v2_proto::{Collection, StructuredQuery};
     use db3_proto::db3_database_v2_proto::{Index, IndexType};
     use tempdir::TempDir;
 
@@ -332,6 +532,7 @@ mod tests {
         let indexes = vec![Index {
             path: "/f1".to_string(),
             index_type: IndexType::StringKey.into(),
+            fields: vec![],
         }];
         let result = doc_store.add_index(&DB3Address::ZERO, "col1", &indexes);
         assert!(result.is_ok());
@@ -395,6 +596,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn doc_store_query_on_docs_test() {
+        let (doc_store, _) = prepare_the_dataset();
+        let docs = vec![
+            (1000, r#"{"f2":"f2", "f1":"f1"}"#.to_string()),
+            (1001, r#"{"f2":"f", "f1":"f1"}"#.to_string()),
+        ];
+        let query = Query {
+            query_str: "/[f2=\"f2\"]".to_string(),
+            parameters: vec![],
+        };
+        let (result, count) = doc_store
+            .execute_query_on_docs("col1", &docs, &query)
+            .unwrap();
+        assert_eq!(1, count);
+        assert_eq!(1000, result[0].0);
+        // the documents of the database are not changed
+        let (_, count) = doc_store
+            .execute_query(&DB3Address::ZERO, "col1", &query)
+            .unwrap();
+        assert_eq!(1, count);
+    }
+
     #[test]
     fn doc_store_project_count_test() {
         let (doc_store, _) = prepare_the_dataset();
@@ -410,6 +634,284 @@ mod tests {
         assert_eq!(docs.len(), 0);
     }
 
+    #[test]
+    fn doc_store_invalid_index_type_test() {
+        let (doc_store, _) = prepare_the_dataset();
+        let fields = vec![IndexField {
+            path: "/f1".to_string(),
+            index_type: 16,
+        }];
+        let result = doc_store.drop_index(&DB3Address::ZERO, "col1", &fields);
+        assert!(matches!(result, Err(DB3Error::WriteStoreError(_))));
+        let indexes = vec![Index {
+            path: "/f2".to_string(),
+            index_type: 16,
+            fields: vec![],
+        }];
+        let result = doc_store.add_index(&DB3Address::ZERO, "col1", &indexes);
+        assert!(matches!(result, Err(DB3Error::WriteStoreError(_))));
+    }
+
+    #[test]
+    fn doc_store_null_filter_test() {
+        let (doc_store, _) = prepare_the_dataset();
+        for doc_str in [
+            r#"{"f2":"n", "f1":"n", "f3":null}"#,
+            r#"{"f2":"v", "f1":"v", "f3":"v"}"#,
+        ] {
+            assert!(doc_store
+                .add_str_doc(&DB3Address::ZERO, "col1", doc_str)
+                .is_ok());
+        }
+        let collection = Collection {
+            name: "col1".to_string(),
+            index_fields: vec![Index {
+                path: "/f3".to_string(),
+                index_type: IndexType::StringKey.into(),
+                fields: vec![],
+            }],
+            sender: vec![],
+            schema: None,
+            acl: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let null_filter = |op: Operator| StructuredQuery {
+            select: None,
+            collection_name: "col1".to_string(),
+            r#where: Some(Filter {
+                filter_type: Some(FilterType::FieldFilter(FieldFilter {
+                    field: "f3".to_string(),
+                    op: op.into(),
+                    value: Some(Value {
+                        value_type: Some(ValueType::NullValue(NullValue::NullValue.into())),
+                    }),
+                })),
+            }),
+            limit: None,
+            order_by: vec![],
+            start_at: None,
+            end_at: None,
+        };
+        // the null filter matches the null field and the missing field
+        let query = QueryPlanner::new(&collection)
+            .build(&null_filter(Operator::Equal))
+            .unwrap();
+        let (docs, count) = doc_store
+            .execute_query(&DB3Address::ZERO, "col1", &query)
+            .unwrap();
+        assert_eq!(2, count);
+        let mut f1: Vec<String> = docs
+            .iter()
+            .map(|(_, doc)| {
+                let value: serde_json::Value = serde_json::from_str(doc.as_str()).unwrap();
+                value["f1"].as_str().unwrap().to_string()
+            })
+            .collect();
+        f1.sort();
+        assert_eq!(vec!["f1".to_string(), "n".to_string()], f1);
+        let query = QueryPlanner::new(&collection)
+            .build(&null_filter(Operator::NotEqual))
+            .unwrap();
+        let (_, count) = doc_store
+            .execute_query(&DB3Address::ZERO, "col1", &query)
+            .unwrap();
+        assert_eq!(1, count);
+    }
+
+    #[test]
+    fn doc_store_null_filter_in_composite_filter_test() {
+        let (doc_store, _) = prepare_the_dataset();
+        for doc_str in [
+            r#"{"f2":"n", "f1":"n", "f3":null}"#,
+            r#"{"f2":"v", "f1":"v", "f3":"v"}"#,
+        ] {
+            assert!(doc_store
+                .add_str_doc(&DB3Address::ZERO, "col1", doc_str)
+                .is_ok());
+        }
+        let string_index = |path: &str| Index {
+            path: path.to_string(),
+            index_type: IndexType::StringKey.into(),
+            fields: vec![],
+        };
+        let collection = Collection {
+            name: "col1".to_string(),
+            index_fields: vec![string_index("/f1"), string_index("/f3")],
+            sender: vec![],
+            schema: None,
+            acl: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let field_filter = |field: &str, value_type: ValueType| Filter {
+            filter_type: Some(FilterType::FieldFilter(FieldFilter {
+                field: field.to_string(),
+                op: Operator::Equal.into(),
+                value: Some(Value {
+                    value_type: Some(value_type),
+                }),
+            })),
+        };
+        let composite_filter = |op: CompositeOp, f1: &str| StructuredQuery {
+            select: None,
+            collection_name: "col1".to_string(),
+            r#where: Some(Filter {
+                filter_type: Some(FilterType::CompositeFilter(CompositeFilter {
+                    op: op.into(),
+                    filters: vec![
+                        field_filter("f3", ValueType::NullValue(NullValue::NullValue.into())),
+                        field_filter("f1", ValueType::StringValue(f1.to_string())),
+                    ],
+                })),
+            }),
+            limit: None,
+            order_by: vec![],
+            start_at: None,
+            end_at: None,
+        };
+        let count_of = |query: &StructuredQuery| {
+            let query = QueryPlanner::new(&collection).build(query).unwrap();
+            let (_, count) = doc_store
+                .execute_query(&DB3Address::ZERO, "col1", &query)
+                .unwrap();
+            count
+        };
+        // the missing field and the null field are matched in the and filter
+        assert_eq!(1, count_of(&composite_filter(CompositeOp::And, "f1")));
+        assert_eq!(1, count_of(&composite_filter(CompositeOp::And, "n")));
+        assert_eq!(0, count_of(&composite_filter(CompositeOp::And, "v")));
+        assert_eq!(3, count_of(&composite_filter(CompositeOp::Or, "v")));
+        assert_eq!(2, count_of(&composite_filter(CompositeOp::Or, "x")));
+    }
+
+    #[test]
+    fn doc_store_dashed_field_test() {
+        let (doc_store, _) = prepare_the_dataset();
+        let index = Index {
+            path: "/first-name".to_string(),
+            index_type: IndexType::StringKey.into(),
+            fields: vec![],
+        };
+        assert!(doc_store
+            .add_index(&DB3Address::ZERO, "col1", &vec![index.clone()])
+            .is_ok());
+        for doc_str in [
+            r#"{"first-name":"a", "f1":"a"}"#,
+            r#"{"first-name":"b", "f1":"b"}"#,
+            r#"{"first-name":"c", "f1":"c"}"#,
+        ] {
+            assert!(doc_store
+                .add_str_doc(&DB3Address::ZERO, "col1", doc_str)
+                .is_ok());
+        }
+        let collection = Collection {
+            name: "col1".to_string(),
+            index_fields: vec![index],
+            sender: vec![],
+            schema: None,
+            acl: None,
+            ttl: 0,
+            keep_history: false,
+        };
+        let query = StructuredQuery {
+            select: Some(Projection {
+                fields: vec!["first-name".to_string()],
+            }),
+            collection_name: "col1".to_string(),
+            r#where: Some(Filter {
+                filter_type: Some(FilterType::FieldFilter(FieldFilter {
+                    field: "first-name".to_string(),
+                    op: Operator::GreaterThan.into(),
+                    value: Some(Value {
+                        value_type: Some(ValueType::StringValue("a".to_string())),
+                    }),
+                })),
+            }),
+            limit: None,
+            order_by: vec![Order {
+                field: "first-name".to_string(),
+                direction: Direction::Descending.into(),
+            }],
+            start_at: None,
+            end_at: None,
+        };
+        let query = QueryPlanner::new(&collection).build(&query).unwrap();
+        let (docs, count) = doc_store
+            .execute_query(&DB3Address::ZERO, "col1", &query)
+            .unwrap();
+        assert_eq!(2, count);
+        let names: Vec<serde_json::Value> = docs
+            .iter()
+            .map(|(_, doc)| serde_json::from_str(doc.as_str()).unwrap())
+            .collect();
+        let right: Vec<serde_json::Value> = vec![
+            serde_json::from_str(r#"{"first-name":"c"}"#).unwrap(),
+            serde_json::from_str(r#"{"first-name":"b"}"#).unwrap(),
+        ];
+        assert_eq!(right, names);
+    }
+
+    #[test]
+    fn doc_store_aggregation_max_docs_test() {
+        let (doc_store, _) = prepare_the_dataset();
+        let doc_str = r#"{"f2":"f", "f1":"f"}"#;
+        assert!(doc_store
+            .add_str_doc(&DB3Address::ZERO, "col1", doc_str)
+            .is_ok());
+        let aggregations = vec![Aggregation {
+            op: AggregationOp::Count.into(),
+            field: "".to_string(),
+        }];
+        // the query is limited to max_docs + 1 by the caller
+        let query = Query {
+            query_str: "/* | limit 2".to_string(),
+            parameters: vec![],
+        };
+        assert!(matches!(
+            doc_store.execute_aggregation(&DB3Address::ZERO, "col1", &query, &aggregations, "", 1),
+            Err(DB3Error::QueryDocumentError(_))
+        ));
+        let results = doc_store
+            .execute_aggregation(&DB3Address::ZERO, "col1", &query, &aggregations, "", 2)
+            .unwrap();
+        assert_eq!(
+            Some(ValueType::IntegerValue(2)),
+            results[0].values[0].value_type
+        );
+    }
+
+    #[test]
+    fn doc_store_rename_collection_test() {
+        let (doc_store, id) = prepare_the_dataset();
+        assert!(doc_store
+            .rename_collection(&DB3Address::ZERO, "col1", "col2")
+            .is_ok());
+        assert!(doc_store.get_doc(&DB3Address::ZERO, "col1", id).is_err());
+        if let Ok(Some(value)) = doc_store.get_doc(&DB3Address::ZERO, "col2", id) {
+            let value: serde_json::Value = serde_json::from_str(value.as_str()).unwrap();
+            assert_eq!(value["f1"].as_str(), Some("f1"));
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn doc_store_drop_collection_test() {
+        let (doc_store, id) = prepare_the_dataset();
+        assert!(doc_store.drop_collection(&DB3Address::ZERO, "col1").is_ok());
+        let query = Query {
+            query_str: "/* | count".to_string(),
+            parameters: vec![],
+        };
+        let (_, count) = doc_store
+            .execute_query(&DB3Address::ZERO, "col1", &query)
+            .unwrap();
+        assert_eq!(count, 0);
+        assert!(doc_store.get_doc(&DB3Address::ZERO, "col1", id).is_err());
+        assert!(doc_store.drop_database(&DB3Address::ZERO).is_ok());
+    }
+
     #[test]
     fn doc_store_smoke_test() {
         let (doc_store, id) = prepare_the_dataset();
@@ -0,0 +1,314 @@
+//
+// doc_transform.rs
+// Copyright (C) 2023 db3.network Author imotai <codego.me@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use db3_base::bson_util::{check_field_path, json_value_from_proto_value};
+use db3_error::{DB3Error, Result};
+use db3_proto::db3_database_v2_proto::structured_query::value::ValueType;
+use db3_proto::db3_database_v2_proto::structured_query::{ArrayValue, Value};
+use db3_proto::db3_mutation_v2_proto::field_transform::TransformType;
+use db3_proto::db3_mutation_v2_proto::{FieldTransform, ServerValue};
+use serde_json::{Map, Value as JsonValue};
+use std::cmp::Ordering;
+
+///
+/// check the field paths and the operands of the transforms. the transforms which pass the
+/// check never fail to apply, so the indexer can replay them on any document
+///
+pub fn check_transforms(transforms: &[FieldTransform]) -> Result<()> {
+    for transform in transforms {
+        let path = transform.field_path.as_str();
+        check_field_path(path)?;
+        match &transform.transform_type {
+            Some(TransformType::Increment(value))
+            | Some(TransformType::Maximum(value))
+            | Some(TransformType::Minimum(value)) => {
+                let is_number = match value.value_type {
+                    Some(ValueType::IntegerValue(_)) => true,
+                    Some(ValueType::DoubleValue(d)) => d.is_finite(),
+                    _ => false,
+                };
+                if !is_number {
+                    return Err(DB3Error::InvalidMutationError(format!(
+                        "the operand of the transform on {path} must be a finite number"
+                    )));
+                }
+            }
+            Some(TransformType::AppendMissingElements(array))
+            | Some(TransformType::RemoveAllFromArray(array)) => {
+                array_from_proto(array)?;
+            }
+            Some(TransformType::SetToServerValue(server_value)) => {
+                if ServerValue::from_i32(*server_value) != Some(ServerValue::RequestTime) {
+                    return Err(DB3Error::InvalidMutationError(format!(
+                        "bad server value {server_value} of the transform on {path}"
+                    )));
+                }
+            }
+            None => {
+                return Err(DB3Error::InvalidMutationError(format!(
+                    "the transform on {path} is empty"
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+///
+/// apply the transforms to the json document in order and return the merge patch with
+/// the new values of the transformed fields
+///
+pub fn transform_doc(
+    doc: &JsonValue,
+    transforms: &[FieldTransform],
+    server_time: i64,
+) -> Result<JsonValue> {
+    let mut doc = doc.clone();
+    let mut patch = JsonValue::Object(Map::new());
+    for transform in transforms {
+        let segments: Vec<&str> = transform.field_path.split('.').collect();
+        let current = get_field(&doc, &segments);
+        let new_value = match &transform.transform_type {
+            Some(TransformType::Increment(value)) => {
+                let operand = number_from_proto(value)?;
+                match current {
+                    Some(c) if c.is_number() => add_numbers(c, &operand).unwrap_or(c.clone()),
+                    _ => operand,
+                }
+            }
+            Some(TransformType::Maximum(value)) => {
+                let operand = number_from_proto(value)?;
+                match current {
+                    Some(c) if c.is_number() && compare_numbers(c, &operand).is_ge() => c.clone(),
+                    _ => operand,
+                }
+            }
+            Some(TransformType::Minimum(value)) => {
+                let operand = number_from_proto(value)?;
+                match current {
+                    Some(c) if c.is_number() && compare_numbers(c, &operand).is_le() => c.clone(),
+                    _ => operand,
+                }
+            }
+            Some(TransformType::AppendMissingElements(array)) => {
+                let mut elements = array_field(current);
+                for element in array_from_proto(array)? {
+                    if !elements.contains(&element) {
+                        elements.push(element);
+                    }
+                }
+                JsonValue::Array(elements)
+            }
+            Some(TransformType::RemoveAllFromArray(array)) => {
+                let removed = array_from_proto(array)?;
+                let mut elements = array_field(current);
+                elements.retain(|element| !removed.contains(element));
+                JsonValue::Array(elements)
+            }
+            Some(TransformType::SetToServerValue(_)) => JsonValue::from(server_time),
+            None => {
+                return Err(DB3Error::InvalidMutationError(format!(
+                    "the transform on {} is empty",
+                    transform.field_path
+                )));
+            }
+        };
+        set_field(&mut doc, &segments, new_value.clone());
+        set_field(&mut patch, &segments, new_value);
+    }
+    Ok(patch)
+}
+
+fn number_from_proto(value: &Value) -> Result<JsonValue> {
+    let number = json_value_from_proto_value(value)?;
+    if number.is_number() {
+        Ok(number)
+    } else {
+        Err(DB3Error::InvalidMutationError(
+            "the operand of the transform must be a finite number".to_string(),
+        ))
+    }
+}
+
+fn array_from_proto(array: &ArrayValue) -> Result<Vec<JsonValue>> {
+    array
+        .values
+        .iter()
+        .map(json_value_from_proto_value)
+        .collect()
+}
+
+fn array_field(current: Option<&JsonValue>) -> Vec<JsonValue> {
+    match current {
+        Some(JsonValue::Array(elements)) => elements.clone(),
+        _ => vec![],
+    }
+}
+
+/// the integer addition saturates and None is returned if the double sum is not finite
+fn add_numbers(left: &JsonValue, right: &JsonValue) -> Option<JsonValue> {
+    if let (Some(l), Some(r)) = (left.as_i64(), right.as_i64()) {
+        return Some(JsonValue::from(l.saturating_add(r)));
+    }
+    let sum = left.as_f64()? + right.as_f64()?;
+    serde_json::Number::from_f64(sum).map(JsonValue::Number)
+}
+
+fn compare_numbers(left: &JsonValue, right: &JsonValue) -> Ordering {
+    if let (Some(l), Some(r)) = (left.as_i64(), right.as_i64()) {
+        return l.cmp(&r);
+    }
+    let (l, r) = (
+        left.as_f64().unwrap_or(f64::NAN),
+        right.as_f64().unwrap_or(f64::NAN),
+    );
+    l.partial_cmp(&r).unwrap_or(Ordering::Less)
+}
+
+fn get_field<'a>(doc: &'a JsonValue, segments: &[&str]) -> Option<&'a JsonValue> {
+    segments
+        .iter()
+        .try_fold(doc, |value, segment| value.get(*segment))
+}
+
+/// the value on the path which is not an object is replaced like the json merge patch
+fn set_field(doc: &mut JsonValue, segments: &[&str], value: JsonValue) {
+    if let Some((first, rest)) = segments.split_first() {
+        if !doc.is_object() {
+            *doc = JsonValue::Object(Map::new());
+        }
+        if let JsonValue::Object(map) = doc {
+            if rest.is_empty() {
+                map.insert(first.to_string(), value);
+            } else {
+                let child = map.entry(first.to_string()).or_insert(JsonValue::Null);
+                set_field(child, rest, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform(path: &str, transform_type: TransformType) -> FieldTransform {
+        FieldTransform {
+            field_path: path.to_string(),
+            transform_type: Some(transform_type),
+        }
+    }
+
+    fn value(value_type: ValueType) -> Value {
+        Value {
+            value_type: Some(value_type),
+        }
+    }
+
+    fn array(values: Vec<ValueType>) -> ArrayValue {
+        ArrayValue {
+            values: values.into_iter().map(value).collect(),
+        }
+    }
+
+    #[test]
+    fn transform_doc_test() {
+        let doc: JsonValue = serde_json::from_str(
+            r#"{"count": 1, "score": 2.5, "name": "a", "tags": ["x", "y"], "stat": {"max": 3}}"#,
+        )
+        .unwrap();
+        let transforms = vec![
+            transform(
+                "count",
+                TransformType::Increment(value(ValueType::IntegerValue(2))),
+            ),
+            transform(
+                "score",
+                TransformType::Increment(value(ValueType::IntegerValue(1))),
+            ),
+            transform(
+                "name",
+                TransformType::Increment(value(ValueType::IntegerValue(1))),
+            ),
+            transform(
+                "stat.max",
+                TransformType::Maximum(value(ValueType::IntegerValue(2))),
+            ),
+            transform(
+                "stat.min",
+                TransformType::Minimum(value(ValueType::DoubleValue(0.5))),
+            ),
+            transform(
+                "tags",
+                TransformType::AppendMissingElements(array(vec![
+                    ValueType::StringValue("y".to_string()),
+                    ValueType::StringValue("z".to_string()),
+                ])),
+            ),
+            transform(
+                "tags",
+                TransformType::RemoveAllFromArray(array(vec![ValueType::StringValue(
+                    "x".to_string(),
+                )])),
+            ),
+            transform(
+                "updated",
+                TransformType::SetToServerValue(ServerValue::RequestTime.into()),
+            ),
+        ];
+        assert!(check_transforms(&transforms).is_ok());
+        let patch = transform_doc(&doc, &transforms, 1000).unwrap();
+        let expected: JsonValue = serde_json::from_str(
+            r#"{"count": 3, "score": 3.5, "name": 1, "tags": ["y", "z"],
+            "stat": {"max": 3, "min": 0.5}, "updated": 1000}"#,
+        )
+        .unwrap();
+        assert_eq!(expected, patch);
+        let patch = transform_doc(
+            &doc,
+            &[transform(
+                "count",
+                TransformType::Increment(value(ValueType::IntegerValue(i64::MAX))),
+            )],
+            1000,
+        )
+        .unwrap();
+        assert_eq!(Some(&JsonValue::from(i64::MAX)), patch.get("count"));
+    }
+
+    #[test]
+    fn check_transforms_test() {
+        for transforms in [
+            vec![transform(
+                "count",
+                TransformType::Increment(value(ValueType::StringValue("1".to_string()))),
+            )],
+            vec![transform(
+                "count",
+                TransformType::Maximum(value(ValueType::DoubleValue(f64::NAN))),
+            )],
+            vec![transform("__count__", TransformType::SetToServerValue(1))],
+            vec![transform("count", TransformType::SetToServerValue(0))],
+            vec![FieldTransform {
+                field_path: "count".to_string(),
+                transform_type: None,
+            }],
+        ] {
+            assert!(check_transforms(&transforms).is_err());
+        }
+    }
+}
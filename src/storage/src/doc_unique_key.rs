@@ -0,0 +1,168 @@
+//
+// doc_unique_key.rs
+// Copyright (C) 2023 db3.network Author imotai <codego.me@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use db3_crypto::db3_address::DB3Address;
+use db3_error::{DB3Error, Result};
+use std::fmt;
+const UNIQUE_VALUE_PREFIX: &str = "/unique_value/";
+const UNIQUE_DOC_PREFIX: &str = "/unique_doc/";
+
+fn encode_name(encoded_key: &mut Vec<u8>, name: &str) -> Result<()> {
+    let name_len = u16::try_from(name.len())
+        .map_err(|e| DB3Error::KeyCodecError(format!("invalid name {name} {e}")))?;
+    encoded_key.extend_from_slice(name_len.to_be_bytes().as_ref());
+    encoded_key.extend_from_slice(name.as_bytes());
+    Ok(())
+}
+
+/// DocUniqueValueKey with db address, collection name, unique key and the json value of
+/// the key which maps the value of a unique key to the document id. the unique key is the
+/// field of a single field index or the path of a compound index
+pub struct DocUniqueValueKey<'a>(pub &'a DB3Address, pub &'a str, pub &'a str, pub &'a str);
+impl<'a> DocUniqueValueKey<'a> {
+    ///
+    /// encode the unique value key. the collection name and the unique key are prefixed
+    /// with their lengths to keep the keys of different collections and unique keys apart
+    ///
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut encoded_key = Self::build_field_prefix(self.0, self.1, self.2)?;
+        encoded_key.extend_from_slice(self.3.as_bytes());
+        Ok(encoded_key)
+    }
+
+    /// the prefix of the keys in the collection or in the database if the name is none
+    pub fn build_prefix(db_addr: &DB3Address, col_name: Option<&str>) -> Result<Vec<u8>> {
+        let mut encoded_key = UNIQUE_VALUE_PREFIX.as_bytes().to_vec();
+        encoded_key.extend_from_slice(db_addr.as_ref());
+        if let Some(col_name) = col_name {
+            encode_name(&mut encoded_key, col_name)?;
+        }
+        Ok(encoded_key)
+    }
+
+    /// the prefix of the keys of the unique key in the collection
+    pub fn build_field_prefix(
+        db_addr: &DB3Address,
+        col_name: &str,
+        field: &str,
+    ) -> Result<Vec<u8>> {
+        let mut encoded_key = Self::build_prefix(db_addr, Some(col_name))?;
+        encode_name(&mut encoded_key, field)?;
+        Ok(encoded_key)
+    }
+}
+
+impl fmt::Display for DocUniqueValueKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}/{}/{}",
+            UNIQUE_VALUE_PREFIX,
+            self.0.to_hex(),
+            self.1,
+            self.2,
+            self.3
+        )
+    }
+}
+
+/// DocUniqueFieldKey with db address, doc id and unique key which maps the document to
+/// the value of its unique key
+pub struct DocUniqueFieldKey<'a>(pub &'a DB3Address, pub i64, pub &'a str);
+impl<'a> DocUniqueFieldKey<'a> {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut encoded_key = Self::build_prefix(self.0, Some(self.1));
+        encoded_key.extend_from_slice(self.2.as_bytes());
+        Ok(encoded_key)
+    }
+
+    /// the prefix of the keys of the document or of the database if the id is none
+    pub fn build_prefix(db_addr: &DB3Address, id: Option<i64>) -> Vec<u8> {
+        let mut encoded_key = UNIQUE_DOC_PREFIX.as_bytes().to_vec();
+        encoded_key.extend_from_slice(db_addr.as_ref());
+        if let Some(id) = id {
+            encoded_key.extend_from_slice(id.to_be_bytes().as_ref());
+        }
+        encoded_key
+    }
+
+    /// decode the field from the key with the prefix of the document
+    pub fn decode_field<'b>(key: &'b [u8], prefix: &[u8]) -> Result<&'b str> {
+        if !key.starts_with(prefix) {
+            return Err(DB3Error::KeyCodecError(
+                "invalid doc unique field key".to_string(),
+            ));
+        }
+        std::str::from_utf8(&key[prefix.len()..])
+            .map_err(|e| DB3Error::KeyCodecError(format!("invalid field {e}")))
+    }
+}
+
+impl fmt::Display for DocUniqueFieldKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}/{}",
+            UNIQUE_DOC_PREFIX,
+            self.0.to_hex(),
+            self.1,
+            self.2
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_doc_unique_value_key() {
+        let db_addr = DB3Address::ZERO;
+        let encoded_key = DocUniqueValueKey(&db_addr, "col", "name", "\"a\"")
+            .encode()
+            .unwrap();
+        // the same bytes of field and value in different split are different keys
+        let other_key = DocUniqueValueKey(&db_addr, "col", "name\"", "a\"")
+            .encode()
+            .unwrap();
+        assert_ne!(encoded_key, other_key);
+        let field_prefix = DocUniqueValueKey::build_field_prefix(&db_addr, "col", "name").unwrap();
+        assert!(encoded_key.starts_with(&field_prefix));
+        assert!(!other_key.starts_with(&field_prefix));
+        let col_prefix = DocUniqueValueKey::build_prefix(&db_addr, Some("col")).unwrap();
+        assert!(other_key.starts_with(&col_prefix));
+        let other_col_key = DocUniqueValueKey(&db_addr, "cola", "name", "\"a\"")
+            .encode()
+            .unwrap();
+        assert!(!other_col_key.starts_with(&col_prefix));
+    }
+
+    #[test]
+    fn test_doc_unique_field_key() {
+        let db_addr = DB3Address::ZERO;
+        let encoded_key = DocUniqueFieldKey(&db_addr, 1, "name").encode().unwrap();
+        let prefix = DocUniqueFieldKey::build_prefix(&db_addr, Some(1));
+        assert_eq!(
+            "name",
+            DocUniqueFieldKey::decode_field(&encoded_key, &prefix).unwrap()
+        );
+        let other_prefix = DocUniqueFieldKey::build_prefix(&db_addr, Some(2));
+        assert!(DocUniqueFieldKey::decode_field(&encoded_key, &other_prefix).is_err());
+        let db_prefix = DocUniqueFieldKey::build_prefix(&db_addr, None);
+        assert!(encoded_key.starts_with(&db_prefix));
+    }
+}
@@ -16,14 +16,24 @@
 //
 #![feature(iter_intersperse)]
 pub mod ar_fs;
+pub mod collection_doc_key;
 pub mod collection_key;
 pub mod db_doc_key_v2;
+pub mod db_doc_name_key_v2;
 pub mod db_owner_key_v2;
 pub mod db_store_v2;
+pub mod doc_aggregator;
+pub mod doc_expiry_key;
+pub mod doc_history_key;
 pub mod doc_key_v2;
+pub mod doc_schema;
 pub mod doc_store;
+pub mod doc_transform;
+pub mod doc_unique_key;
 pub mod key_store;
 pub mod meta_store_client;
 pub mod mutation_store;
+pub mod query_plan;
 pub mod state_store;
+pub mod state_tree_key;
 pub mod system_store;
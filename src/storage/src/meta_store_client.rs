@@ -16,6 +16,7 @@
 //
 
 use arweave_rs::crypto::base64::Base64;
+use db3_crypto::merkle::Hash;
 use db3_error::{DB3Error, Result};
 use ethers::prelude::{LocalWallet, Signer};
 use ethers::{
@@ -175,23 +176,49 @@ impl MetaStoreClient {
         Ok(data_network.admin)
     }
 
-    pub async fn update_rollup_step(&self, ar_tx: &str, network: u64) -> Result<(U256, TxHash)> {
-        info!("update rollup step with tx {}, network: {}", ar_tx, network);
+    fn decode_ar_tx(ar_tx: &str) -> Result<[u8; 32]> {
         let b64: Base64 = Base64::from_str(ar_tx).map_err(|e| {
             DB3Error::StoreEventError(format!(
                 "fail to decode arweave tx from base64 for error {e}"
             ))
         })?;
-        let ar_tx_binary: [u8; 32] = b64.0.try_into().map_err(|_| {
+        b64.0.try_into().map_err(|_| {
             DB3Error::StoreEventError("fail to convert tx bytes to bytes32".to_string())
-        })?;
+        })
+    }
+
+    ///
+    /// get the mutation root anchored with the arweave tx of the rollup batch
+    ///
+    pub async fn get_mutation_root(&self, ar_tx: &str, network: u64) -> Result<Hash> {
+        let ar_tx_binary = Self::decode_ar_tx(ar_tx)?;
+        let store = DB3MetaStore::new(self.address, self.client.clone());
+        let network_id = U256::from(network);
+        store
+            .get_mutation_root(network_id, ar_tx_binary)
+            .call()
+            .await
+            .map_err(|e| DB3Error::StoreEventError(format!("{e}")))
+    }
+
+    ///
+    /// anchor the arweave tx and the mutation root of the rollup batch on the evm chain
+    ///
+    pub async fn update_rollup_step(
+        &self,
+        ar_tx: &str,
+        mutation_root: &Hash,
+        network: u64,
+    ) -> Result<(U256, TxHash)> {
+        info!("update rollup step with tx {}, network: {}", ar_tx, network);
+        let ar_tx_binary = Self::decode_ar_tx(ar_tx)?;
         let store = DB3MetaStore::new(self.address, self.client.clone());
         let network_id = U256::from(network);
         info!(
             "start update rollup step with tx {}, network: {}",
             ar_tx, network
         );
-        let tx = store.update_rollup_steps(network_id, ar_tx_binary);
+        let tx = store.update_rollup_steps(network_id, ar_tx_binary, *mutation_root);
         let tx_hash = match self.use_legacy_tx {
             true => {
                 let tx = tx.legacy();
@@ -283,13 +310,17 @@ mod tests {
         assert!(result.is_ok(), "register data network failed {:?}", result);
         sleep(TokioDuration::from_millis(5 * 1000)).await;
         let tx = "TY5SMaPPRk_TMvSDROaQWyc_WHyJrEL760-UhiNnHG4";
-        let result = client.update_rollup_step(tx, 2).await;
+        let mutation_root = [1; 32];
+        let result = client.update_rollup_step(tx, &mutation_root, 2).await;
         assert!(result.is_ok(), "update rollup step failed {:?}", result);
         sleep(TokioDuration::from_millis(5 * 1000)).await;
         let tx_ret = client.get_latest_arweave_tx(2).await;
         assert!(tx_ret.is_ok());
         let tx_remote = tx_ret.unwrap();
         assert_eq!(tx, tx_remote);
+        let root_ret = client.get_mutation_root(tx, 2).await;
+        assert!(root_ret.is_ok());
+        assert_eq!(mutation_root, root_ret.unwrap());
         let result = client.create_database(2, "test create db").await;
         assert!(result.is_ok(), "create database {:?}", result);
         sleep(TokioDuration::from_millis(10 * 1000)).await;
@@ -17,22 +17,53 @@
 
 use bytes::{Bytes, BytesMut};
 use db3_base::times;
-use db3_crypto::db3_address::DB3Address;
-use db3_crypto::id::TxId;
+use db3_crypto::db3_address::{DB3Address, DB3_ADDRESS_LENGTH};
+use db3_crypto::id::{TxId, TX_ID_LENGTH};
+use db3_crypto::merkle::{self, Hash};
 use db3_error::{DB3Error, Result};
 use db3_proto::db3_base_proto::MutationState;
-use db3_proto::db3_mutation_v2_proto::{MutationAction, MutationBody, MutationHeader};
-use db3_proto::db3_rollup_proto::{GcRecord as GCRecord, RollupRecord};
+use db3_proto::db3_mutation_v2_proto::{BlockHeader, MutationAction, MutationBody, MutationHeader};
+use db3_proto::db3_rollup_proto::{
+    GcRecord as GCRecord, MerkleProofNode, MutationProof, RollupRecord,
+};
 use ethers::types::U256;
 use prost::Message;
 use rocksdb::{DBWithThreadMode, MultiThreaded, Options, WriteBatch};
 use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
 use tracing::info;
 
 type StorageEngine = DBWithThreadMode<MultiThreaded>;
 const STATE_CF: &str = "STATE_CF";
+const BLOCK_HEADER_CF: &str = "BLOCK_HEADER_CF";
+// the ids of the rolled up mutations by the start block of the rollup record
+const ROLLUP_MUTATION_CF: &str = "ROLLUP_MUTATION_CF";
+// the start block of the rollup record and the index of the rolled up mutation by its id
+const MUTATION_ROLLUP_CF: &str = "MUTATION_ROLLUP_CF";
+// the secondary indexes of the mutations and the keys are prefixed by the index type.
+// the keys of the sender, database and action indexes end with the block and order of
+// the mutation and the value of the tx index is the block, order and the databases
+const MUTATION_INDEX_CF: &str = "MUTATION_INDEX_CF";
+const SENDER_INDEX_PREFIX: u8 = b's';
+const DB_INDEX_PREFIX: u8 = b'd';
+const DB_SENDER_INDEX_PREFIX: u8 = b'b';
+const ACTION_INDEX_PREFIX: u8 = b'a';
+const TX_INDEX_PREFIX: u8 = b't';
+const POSITION_LENGTH: usize = 12;
+// the mutations of the blocks before it were added before the indexes
+const INDEX_START_BLOCK_KEY: &str = "index_start_block";
+
+///
+/// the filter of the mutation headers. at least one of the fields should be set and the
+/// empty actions match all the actions
+///
+#[derive(Clone, Default)]
+pub struct MutationFilter {
+    pub sender: Option<DB3Address>,
+    pub db_addr: Option<DB3Address>,
+    pub actions: Vec<MutationAction>,
+}
 
 #[derive(Clone)]
 pub struct MutationStoreConfig {
@@ -85,6 +116,9 @@ pub struct MutationStore {
     rollup_cost: Arc<Mutex<MutationCost>>,
     total_rollup_raw_bytes: Arc<AtomicU64>,
     total_rollup_mutation_count: Arc<AtomicU64>,
+    // the mutations hold the read lock until they are added, so the block is produced
+    // with all its mutations
+    block_lock: Arc<RwLock<()>>,
 }
 
 impl MutationStore {
@@ -105,6 +139,10 @@ impl MutationStore {
                     config.gc_cf_name.as_str(),
                     config.block_state_cf_name.as_str(),
                     STATE_CF,
+                    BLOCK_HEADER_CF,
+                    ROLLUP_MUTATION_CF,
+                    MUTATION_ROLLUP_CF,
+                    MUTATION_INDEX_CF,
                 ],
             )
             .map_err(|e| {
@@ -130,6 +168,7 @@ impl MutationStore {
             })),
             total_rollup_raw_bytes: Arc::new(AtomicU64::new(0)),
             total_rollup_mutation_count: Arc::new(AtomicU64::new(0)),
+            block_lock: Arc::new(RwLock::new(())),
         })
     }
 
@@ -159,6 +198,23 @@ impl MutationStore {
                 _ => {}
             }
         }
+        let index_start_block = self
+            .se
+            .get_cf(&cf_handle, INDEX_START_BLOCK_KEY.as_bytes())
+            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+        if index_start_block.is_none() {
+            // the store is opened for the first time or upgraded from the one without the
+            // indexes, so the mutations from the current block are indexed
+            let block = self.get_current_block()?;
+            self.se
+                .put_cf(
+                    &cf_handle,
+                    INDEX_START_BLOCK_KEY.as_bytes(),
+                    block.to_be_bytes(),
+                )
+                .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+            info!("the mutations are indexed from block {}", block);
+        }
         self.recover_last_state()?;
         Ok(())
     }
@@ -278,6 +334,13 @@ impl MutationStore {
         self.get_last_record::<GCRecord>(self.config.gc_cf_name.as_str())
     }
 
+    ///
+    /// the mutations of the blocks before the watermark have been removed by the gc
+    ///
+    pub fn get_gc_watermark(&self) -> Result<u64> {
+        Ok(self.get_last_gc_record()?.map_or(0, |r| r.end_block))
+    }
+
     pub fn get_next_rollup_record(&self, start_block: u64) -> Result<Option<RollupRecord>> {
         let id = start_block.to_be_bytes();
         self.get_next_record::<RollupRecord>(self.config.rollup_store_cf_name.as_str(), &id)
@@ -288,12 +351,29 @@ impl MutationStore {
         self.get_record::<RollupRecord>(self.config.rollup_store_cf_name.as_str(), &id)
     }
 
-    pub fn add_rollup_record(&self, record: &RollupRecord) -> Result<()> {
+    ///
+    /// add the rollup record with the ids of the rolled up mutations by block and order.
+    /// the ids are kept after the gc of the mutations for the inclusion proofs
+    ///
+    pub fn add_rollup_record(&self, record: &RollupRecord, mutation_ids: &[TxId]) -> Result<()> {
+        if record.mutation_root.as_slice() != Self::compute_mutation_root(mutation_ids) {
+            return Err(DB3Error::WriteStoreError(
+                "the mutation root does not match the mutation ids".to_string(),
+            ));
+        }
         // validate the end block
         let rollup_cf_handle = self
             .se
             .cf_handle(self.config.rollup_store_cf_name.as_str())
             .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let rollup_mutation_cf_handle = self
+            .se
+            .cf_handle(ROLLUP_MUTATION_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mutation_rollup_cf_handle = self
+            .se
+            .cf_handle(MUTATION_ROLLUP_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
         let id = record.start_block.to_be_bytes();
         let mut buf = BytesMut::with_capacity(1024);
         record
@@ -303,6 +383,15 @@ impl MutationStore {
         let mut batch = WriteBatch::default();
         // store the rollup record
         batch.put_cf(&rollup_cf_handle, &id, buf.as_ref());
+        let mut ids: Vec<u8> = Vec::with_capacity(mutation_ids.len() * TX_ID_LENGTH);
+        for (index, tx_id) in mutation_ids.iter().enumerate() {
+            ids.extend_from_slice(tx_id.as_ref());
+            let mut position: Vec<u8> = Vec::with_capacity(12);
+            position.extend_from_slice(&id);
+            position.extend_from_slice(&(index as u32).to_be_bytes());
+            batch.put_cf(&mutation_rollup_cf_handle, tx_id.as_ref(), &position);
+        }
+        batch.put_cf(&rollup_mutation_cf_handle, &id, &ids);
         self.se
             .write(batch)
             .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
@@ -323,6 +412,64 @@ impl MutationStore {
         Ok(())
     }
 
+    ///
+    /// get the merkle path from the mutation id to the mutation root of the rollup record
+    /// which includes the mutation. it's none if the mutation has not been rolled up
+    ///
+    pub fn get_mutation_proof(&self, tx_id: &TxId) -> Result<Option<MutationProof>> {
+        let mutation_rollup_cf_handle = self
+            .se
+            .cf_handle(MUTATION_ROLLUP_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let position = match self
+            .se
+            .get_cf(&mutation_rollup_cf_handle, tx_id.as_ref())
+            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?
+        {
+            Some(v) if v.len() == 12 => v,
+            Some(_) => {
+                return Err(DB3Error::ReadStoreError(
+                    "invalid rollup position of mutation".to_string(),
+                ))
+            }
+            None => return Ok(None),
+        };
+        let start_block = u64::from_be_bytes(position[0..8].try_into().unwrap());
+        let index = u32::from_be_bytes(position[8..12].try_into().unwrap());
+        let record = self
+            .get_rollup_record(start_block)?
+            .ok_or(DB3Error::ReadStoreError(format!(
+                "rollup record with start block {start_block} is not found"
+            )))?;
+        let rollup_mutation_cf_handle = self
+            .se
+            .cf_handle(ROLLUP_MUTATION_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let ids = self
+            .se
+            .get_cf(&rollup_mutation_cf_handle, &start_block.to_be_bytes())
+            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?
+            .ok_or(DB3Error::ReadStoreError(format!(
+                "the mutation ids of rollup record with start block {start_block} are not found"
+            )))?;
+        let leaves: Vec<Hash> = ids.chunks(TX_ID_LENGTH).map(merkle::hash_leaf).collect();
+        let path = merkle::merkle_proof(&leaves, index as usize).ok_or(
+            DB3Error::ReadStoreError(format!("invalid index {index} of mutation")),
+        )?;
+        Ok(Some(MutationProof {
+            id: tx_id.to_hex(),
+            index,
+            path: path
+                .into_iter()
+                .map(|node| MerkleProofNode {
+                    hash: node.hash.to_vec(),
+                    is_left: node.is_left,
+                })
+                .collect(),
+            record: Some(record),
+        }))
+    }
+
     fn get_record<T>(&self, cf: &str, id: &[u8]) -> Result<Option<T>>
     where
         T: Message + std::default::Default,
@@ -463,6 +610,144 @@ impl MutationStore {
         }
     }
 
+    ///
+    /// hold the current block until the guard is dropped. the mutation holds it from
+    /// generating the block and order to adding the mutation
+    ///
+    pub fn hold_block(&self) -> Result<RwLockReadGuard<'_, ()>> {
+        self.block_lock
+            .read()
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))
+    }
+
+    ///
+    /// produce the current block with its header after the mutations holding it are
+    /// added and return the header. the state root is got when no mutation is applied
+    ///
+    pub fn produce_block<F>(&self, get_state_root: F) -> Result<BlockHeader>
+    where
+        F: FnOnce() -> Result<Vec<u8>>,
+    {
+        let _guard = self
+            .block_lock
+            .write()
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        let (block_id, mutation_count) = self.increase_block_return_last_state()?;
+        let ids = self.get_block_mutation_ids(block_id)?;
+        let parent_hash = match self.get_last_record::<BlockHeader>(BLOCK_HEADER_CF)? {
+            Some(parent) => parent.hash,
+            None => vec![],
+        };
+        let mut header = BlockHeader {
+            block_id,
+            parent_hash,
+            mutation_root: Self::compute_mutation_root(&ids).to_vec(),
+            state_root: get_state_root()?,
+            time: times::get_current_time_in_millis(),
+            mutation_count,
+            hash: vec![],
+        };
+        header.hash = Self::compute_block_hash(&header).to_vec();
+        self.add_record::<BlockHeader>(BLOCK_HEADER_CF, &block_id.to_be_bytes(), &header)?;
+        Ok(header)
+    }
+
+    ///
+    /// the merkle root over the ids of the mutations by order
+    ///
+    pub fn compute_mutation_root(ids: &[TxId]) -> Hash {
+        let leaves: Vec<Hash> = ids
+            .iter()
+            .map(|id| merkle::hash_leaf(id.as_ref()))
+            .collect();
+        merkle::merkle_root(&leaves)
+    }
+
+    ///
+    /// the hash over the fields of the block header except the hash
+    ///
+    pub fn compute_block_hash(header: &BlockHeader) -> Hash {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(&header.block_id.to_be_bytes());
+        for field in [
+            &header.parent_hash,
+            &header.mutation_root,
+            &header.state_root,
+        ] {
+            data.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            data.extend_from_slice(field);
+        }
+        data.extend_from_slice(&header.time.to_be_bytes());
+        data.extend_from_slice(&header.mutation_count.to_be_bytes());
+        merkle::hash(&data)
+    }
+
+    pub fn get_block_header(&self, block: u64) -> Result<Option<BlockHeader>> {
+        self.get_record::<BlockHeader>(BLOCK_HEADER_CF, &block.to_be_bytes())
+    }
+
+    ///
+    /// get the headers of the produced blocks in the range
+    ///
+    pub fn get_range_block_headers(
+        &self,
+        block_start: u64,
+        block_end: u64,
+    ) -> Result<Vec<BlockHeader>> {
+        if block_start >= block_end {
+            return Err(DB3Error::ReadStoreError("invalid block range".to_string()));
+        }
+        let cf_handle = self
+            .se
+            .cf_handle(BLOCK_HEADER_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mut it = self.se.raw_iterator_cf(&cf_handle);
+        it.seek(&block_start.to_be_bytes());
+        let end_id = block_end.to_be_bytes();
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        while it.valid() {
+            match (it.key(), it.value()) {
+                (Some(k), Some(v)) if k < end_id.as_ref() => {
+                    let header = BlockHeader::decode(v)
+                        .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+                    headers.push(header);
+                }
+                _ => break,
+            }
+            it.next();
+        }
+        Ok(headers)
+    }
+
+    ///
+    /// get the ids of the mutations in the block by order
+    ///
+    fn get_block_mutation_ids(&self, block: u64) -> Result<Vec<TxId>> {
+        let block_cf_handle = self
+            .se
+            .cf_handle(self.config.block_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mut it = self.se.raw_iterator_cf(&block_cf_handle);
+        let block_prefix = block.to_be_bytes();
+        it.seek(&block_prefix);
+        let mut ids: Vec<TxId> = Vec::new();
+        while it.valid() {
+            match (it.key(), it.value()) {
+                (Some(k), Some(v)) if k.starts_with(&block_prefix) => {
+                    let header = MutationHeader::decode(v)
+                        .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+                    ids.push(
+                        TxId::try_from_hex(header.id.as_str())
+                            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?,
+                    );
+                }
+                _ => break,
+            }
+            it.next();
+        }
+        Ok(ids)
+    }
+
     pub fn get_current_block(&self) -> Result<u64> {
         match self.block_state.lock() {
             Ok(state) => Ok(state.block),
@@ -504,6 +789,9 @@ impl MutationStore {
         mutations
             .iter()
             .for_each(|ref x| batch.delete_cf(&tx_cf_handle, x.0.id.as_str()));
+        for (header, _) in mutations.iter() {
+            self.delete_mutation_index(&mut batch, header)?;
+        }
         let block_cf_handle = self
             .se
             .cf_handle(self.config.block_store_cf_name.as_str())
@@ -567,6 +855,45 @@ impl MutationStore {
         Ok(mutations)
     }
 
+    ///
+    /// get the block id and the mutation count of the blocks in the range. the blocks
+    /// without mutation are skipped
+    ///
+    pub fn get_range_block_mutation_count(
+        &self,
+        block_start: u64,
+        block_end: u64,
+    ) -> Result<Vec<(u64, u32)>> {
+        if block_start >= block_end {
+            return Err(DB3Error::ReadStoreError("invalid block range".to_string()));
+        }
+        let block_cf_handle = self
+            .se
+            .cf_handle(self.config.block_store_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mut it = self.se.raw_iterator_cf(&block_cf_handle);
+        it.seek(&block_start.to_be_bytes());
+        let mut blocks: Vec<(u64, u32)> = Vec::new();
+        while it.valid() {
+            let block = match it.key() {
+                Some(k) if k.len() >= 8 => u64::from_be_bytes(
+                    <[u8; 8]>::try_from(&k[0..8])
+                        .map_err(|e| DB3Error::KeyCodecError(format!("get block id err {e}")))?,
+                ),
+                _ => break,
+            };
+            if block >= block_end {
+                break;
+            }
+            match blocks.last_mut() {
+                Some((last_block, count)) if *last_block == block => *count += 1,
+                _ => blocks.push((block, 1)),
+            }
+            it.next();
+        }
+        Ok(blocks)
+    }
+
     pub fn get_mutation_header(&self, block: u64, order: u32) -> Result<Option<MutationHeader>> {
         let mut encoded_id: Vec<u8> = Vec::new();
         encoded_id.extend_from_slice(&block.to_be_bytes());
@@ -589,6 +916,248 @@ impl MutationStore {
         }
     }
 
+    pub fn get_mutation_header_by_id(&self, tx_id: &TxId) -> Result<Option<MutationHeader>> {
+        let index_cf_handle = self
+            .se
+            .cf_handle(MUTATION_INDEX_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let value = self
+            .se
+            .get_cf(&index_cf_handle, &Self::encode_tx_index_key(tx_id))
+            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+        match value {
+            Some(v) if v.len() >= POSITION_LENGTH => {
+                let (block, order) = Self::decode_position(&v[..POSITION_LENGTH]);
+                self.get_mutation_header(block, order)
+            }
+            Some(_) => Err(DB3Error::ReadStoreError(
+                "invalid position of mutation".to_string(),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    ///
+    /// the mutations of the blocks before it have no index and can not be scanned by filter
+    ///
+    pub fn get_index_start_block(&self) -> Result<u64> {
+        let cf_handle = self
+            .se
+            .cf_handle(self.config.block_state_cf_name.as_str())
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let value = self
+            .se
+            .get_cf(&cf_handle, INDEX_START_BLOCK_KEY.as_bytes())
+            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+        match value {
+            Some(v) => Ok(u64::from_be_bytes(v.try_into().map_err(|_| {
+                DB3Error::KeyCodecError("invalid index start block".to_string())
+            })?)),
+            None => Ok(0),
+        }
+    }
+
+    ///
+    /// scan the mutation headers matched by the filter from the block and order. the
+    /// headers are ordered by block and order. at most scan max limit index keys are
+    /// scanned for every index, so the block and order to resume from are returned with
+    /// the headers if the scan is not finished
+    ///
+    pub fn scan_mutation_headers_by_filter(
+        &self,
+        filter: &MutationFilter,
+        start_block: u64,
+        start_order: u32,
+        limit: u32,
+    ) -> Result<(Vec<MutationHeader>, Option<(u64, u32)>)> {
+        if limit > self.config.scan_max_limit as u32 {
+            return Err(DB3Error::ReadStoreError(
+                "reach the scan max limit".to_string(),
+            ));
+        }
+        let actions: Vec<i32> = filter.actions.iter().map(|a| *a as i32).collect();
+        // the action index is used only if neither the sender nor the database is set
+        let (prefixes, match_action) = match (&filter.sender, &filter.db_addr) {
+            (Some(sender), Some(db_addr)) => {
+                let mut prefix = vec![DB_SENDER_INDEX_PREFIX];
+                prefix.extend_from_slice(db_addr.as_ref());
+                prefix.extend_from_slice(sender.as_ref());
+                (vec![prefix], !actions.is_empty())
+            }
+            (None, Some(db_addr)) => {
+                let mut prefix = vec![DB_INDEX_PREFIX];
+                prefix.extend_from_slice(db_addr.as_ref());
+                (vec![prefix], !actions.is_empty())
+            }
+            (Some(sender), None) => {
+                let mut prefix = vec![SENDER_INDEX_PREFIX];
+                prefix.extend_from_slice(sender.as_ref());
+                (vec![prefix], !actions.is_empty())
+            }
+            (None, None) if !actions.is_empty() => (
+                actions
+                    .iter()
+                    .map(|action| {
+                        let mut prefix = vec![ACTION_INDEX_PREFIX];
+                        prefix.extend_from_slice(&action.to_be_bytes());
+                        prefix
+                    })
+                    .collect(),
+                false,
+            ),
+            _ => {
+                return Err(DB3Error::ReadStoreError(
+                    "the filter of mutation headers is empty".to_string(),
+                ))
+            }
+        };
+        let index_cf_handle = self
+            .se
+            .cf_handle(MUTATION_INDEX_CF)
+            .ok_or(DB3Error::ReadStoreError("cf is not found".to_string()))?;
+        let mut headers: Vec<MutationHeader> = Vec::new();
+        // the min position to resume from of the indexes which are not scanned to the end
+        let mut next_position: Option<(u64, u32)> = None;
+        for prefix in prefixes.iter() {
+            let mut start_key = prefix.clone();
+            start_key.extend_from_slice(&Self::encode_position(start_block, start_order));
+            let mut it = self.se.raw_iterator_cf(&index_cf_handle);
+            it.seek(&start_key);
+            let mut count: u32 = 0;
+            let mut scanned: usize = 0;
+            while it.valid() {
+                let (block, order) = match it.key() {
+                    Some(k)
+                        if k.starts_with(prefix) && k.len() == prefix.len() + POSITION_LENGTH =>
+                    {
+                        Self::decode_position(&k[prefix.len()..])
+                    }
+                    _ => break,
+                };
+                if count >= limit || scanned >= self.config.scan_max_limit {
+                    next_position = match next_position {
+                        Some(position) if position <= (block, order) => Some(position),
+                        _ => Some((block, order)),
+                    };
+                    break;
+                }
+                scanned += 1;
+                // the header has been removed by the gc if it's not found
+                if let Some(header) = self.get_mutation_header(block, order)? {
+                    if !match_action || actions.contains(&header.action) {
+                        headers.push(header);
+                        count += 1;
+                    }
+                }
+                it.next();
+            }
+        }
+        // merge the headers of the action indexes and the headers after the position
+        // to resume from will be returned by the next scan
+        if prefixes.len() > 1 {
+            headers.sort_by_key(|header| (header.block_id, header.order_id));
+            if let Some(position) = next_position {
+                headers.retain(|header| (header.block_id, header.order_id) < position);
+            }
+            if headers.len() > limit as usize {
+                next_position = Some((
+                    headers[limit as usize].block_id,
+                    headers[limit as usize].order_id,
+                ));
+                headers.truncate(limit as usize);
+            }
+        }
+        Ok((headers, next_position))
+    }
+
+    fn encode_position(block: u64, order: u32) -> Vec<u8> {
+        let mut position: Vec<u8> = Vec::with_capacity(POSITION_LENGTH);
+        position.extend_from_slice(&block.to_be_bytes());
+        position.extend_from_slice(&order.to_be_bytes());
+        position
+    }
+
+    fn decode_position(position: &[u8]) -> (u64, u32) {
+        let block = u64::from_be_bytes(position[0..8].try_into().unwrap());
+        let order = u32::from_be_bytes(position[8..POSITION_LENGTH].try_into().unwrap());
+        (block, order)
+    }
+
+    fn encode_tx_index_key(tx_id: &TxId) -> Vec<u8> {
+        let mut key = vec![TX_INDEX_PREFIX];
+        key.extend_from_slice(tx_id.as_ref());
+        key
+    }
+
+    ///
+    /// the keys of the sender, database and action indexes of the mutation
+    ///
+    fn encode_index_keys(
+        sender: &DB3Address,
+        db_addrs: &[DB3Address],
+        action: i32,
+        block: u64,
+        order: u32,
+    ) -> Vec<Vec<u8>> {
+        let position = Self::encode_position(block, order);
+        let mut keys: Vec<Vec<u8>> = Vec::new();
+        let mut sender_key = vec![SENDER_INDEX_PREFIX];
+        sender_key.extend_from_slice(sender.as_ref());
+        sender_key.extend_from_slice(&position);
+        keys.push(sender_key);
+        let mut action_key = vec![ACTION_INDEX_PREFIX];
+        action_key.extend_from_slice(&action.to_be_bytes());
+        action_key.extend_from_slice(&position);
+        keys.push(action_key);
+        for db_addr in db_addrs {
+            let mut db_key = vec![DB_INDEX_PREFIX];
+            db_key.extend_from_slice(db_addr.as_ref());
+            db_key.extend_from_slice(&position);
+            keys.push(db_key);
+            let mut db_sender_key = vec![DB_SENDER_INDEX_PREFIX];
+            db_sender_key.extend_from_slice(db_addr.as_ref());
+            db_sender_key.extend_from_slice(sender.as_ref());
+            db_sender_key.extend_from_slice(&position);
+            keys.push(db_sender_key);
+        }
+        keys
+    }
+
+    fn delete_mutation_index(&self, batch: &mut WriteBatch, header: &MutationHeader) -> Result<()> {
+        let index_cf_handle = self
+            .se
+            .cf_handle(MUTATION_INDEX_CF)
+            .ok_or(DB3Error::WriteStoreError("cf is not found".to_string()))?;
+        let tx_id = TxId::try_from_hex(header.id.as_str())
+            .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
+        let tx_key = Self::encode_tx_index_key(&tx_id);
+        let value = self
+            .se
+            .get_cf(&index_cf_handle, &tx_key)
+            .map_err(|e| DB3Error::ReadStoreError(format!("{e}")))?;
+        // the mutations added before the indexes have no index
+        if let Some(v) = value {
+            let db_addrs = v
+                .get(POSITION_LENGTH..)
+                .unwrap_or_default()
+                .chunks(DB3_ADDRESS_LENGTH)
+                .map(DB3Address::try_from)
+                .collect::<Result<Vec<DB3Address>>>()?;
+            let sender = DB3Address::try_from(header.sender.as_slice())?;
+            for key in Self::encode_index_keys(
+                &sender,
+                &db_addrs,
+                header.action,
+                header.block_id,
+                header.order_id,
+            ) {
+                batch.delete_cf(&index_cf_handle, &key);
+            }
+            batch.delete_cf(&index_cf_handle, &tx_key);
+        }
+        Ok(())
+    }
+
     pub fn get_mutation(&self, tx_id: &TxId) -> Result<Option<MutationBody>> {
         self.get_record::<MutationBody>(self.config.tx_store_cf_name.as_str(), tx_id.as_ref())
     }
@@ -657,6 +1226,7 @@ impl MutationStore {
         order: u32,
         network: u64,
         action: MutationAction,
+        db_addrs: &[DB3Address],
     ) -> Result<(String, u64, u32)> {
         let tx_id = TxId::from((payload, signature.as_bytes()));
         let hex_id = tx_id.to_hex();
@@ -688,6 +1258,23 @@ impl MutationStore {
         batch.put_cf(&tx_cf_handle, &tx_id, buf.as_ref());
         // store the mutation header
         batch.put_cf(&block_cf_handle, &encoded_id, header_buf.as_ref());
+        // store the indexes of the mutation
+        let index_cf_handle = self
+            .se
+            .cf_handle(MUTATION_INDEX_CF)
+            .ok_or(DB3Error::WriteStoreError("cf is not found".to_string()))?;
+        for key in Self::encode_index_keys(sender, db_addrs, action.into(), block, order) {
+            batch.put_cf(&index_cf_handle, &key, b"");
+        }
+        let mut tx_index_value = encoded_id.clone();
+        db_addrs
+            .iter()
+            .for_each(|db_addr| tx_index_value.extend_from_slice(db_addr.as_ref()));
+        batch.put_cf(
+            &index_cf_handle,
+            &Self::encode_tx_index_key(&tx_id),
+            &tx_index_value,
+        );
         self.se
             .write(batch)
             .map_err(|e| DB3Error::WriteStoreError(format!("{e}")))?;
@@ -785,6 +1372,7 @@ mod tests {
                 order,
                 1,
                 MutationAction::CreateDocumentDb,
+                &[],
             );
             assert!(result.is_ok());
             if let Ok(headers) = store.scan_mutation_headers(0, 1) {
@@ -805,6 +1393,7 @@ mod tests {
                 order,
                 1,
                 MutationAction::CreateDocumentDb,
+                &[],
             );
             assert!(result.is_ok());
             if let Ok(headers) = store.scan_mutation_headers(0, 1) {
@@ -817,6 +1406,149 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_scan_mutation_headers_by_filter() {
+        let tmp_dir_path = TempDir::new("mutation_index").expect("create temp dir");
+        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
+        let config = MutationStoreConfig {
+            db_path: real_path,
+            block_store_cf_name: "cf1".to_string(),
+            tx_store_cf_name: "cf2".to_string(),
+            rollup_store_cf_name: "rf3".to_string(),
+            gc_cf_name: "gc".to_string(),
+            message_max_buffer: 4 * 1024,
+            scan_max_limit: 3,
+            block_state_cf_name: "block_state_cf".to_string(),
+        };
+        let store = MutationStore::new(config).unwrap();
+        let sender = DB3Address::ONE;
+        let other_sender = DB3Address::ZERO;
+        let db_addr = DB3Address::from(&[2u8; DB3_ADDRESS_LENGTH]);
+        let other_db_addr = DB3Address::from(&[3u8; DB3_ADDRESS_LENGTH]);
+        let mutations = vec![
+            (sender, vec![db_addr], MutationAction::AddDocument),
+            (other_sender, vec![db_addr], MutationAction::AddDocument),
+            (sender, vec![other_db_addr], MutationAction::AddCollection),
+            (sender, vec![db_addr], MutationAction::UpdateDocument),
+            (sender, vec![], MutationAction::CreateDocumentDb),
+        ];
+        let signature: &str = "0xasdasdsad";
+        let mut ids: Vec<String> = Vec::new();
+        for (i, (sender, db_addrs, action)) in mutations.iter().enumerate() {
+            let payload: Vec<u8> = vec![i as u8];
+            let (_id, block, order) = store
+                .generate_mutation_block_and_order(payload.as_ref(), signature)
+                .unwrap();
+            let (id, _, _) = store
+                .add_mutation(
+                    payload.as_ref(),
+                    signature,
+                    "",
+                    sender,
+                    1,
+                    block,
+                    order,
+                    1,
+                    *action,
+                    db_addrs,
+                )
+                .unwrap();
+            ids.push(id);
+        }
+        let scan = |filter: &MutationFilter,
+                    block: u64,
+                    order: u32,
+                    limit: u32|
+         -> (Vec<String>, Option<(u64, u32)>) {
+            let (headers, next_position) = store
+                .scan_mutation_headers_by_filter(filter, block, order, limit)
+                .unwrap();
+            (
+                headers.into_iter().map(|header| header.id).collect(),
+                next_position,
+            )
+        };
+        let position = |id: &String| -> (u64, u32) {
+            let tx_id = TxId::try_from_hex(id.as_str()).unwrap();
+            let header = store.get_mutation_header_by_id(&tx_id).unwrap().unwrap();
+            (header.block_id, header.order_id)
+        };
+        let filter = MutationFilter {
+            sender: Some(sender),
+            db_addr: Some(db_addr),
+            actions: vec![],
+        };
+        assert_eq!(
+            (vec![ids[0].clone(), ids[3].clone()], None),
+            scan(&filter, 0, 0, 3)
+        );
+        let filter = MutationFilter {
+            sender: None,
+            db_addr: Some(db_addr),
+            actions: vec![],
+        };
+        assert_eq!(
+            (ids[0..2].to_vec(), Some(position(&ids[3]))),
+            scan(&filter, 0, 0, 2)
+        );
+        let filter = MutationFilter {
+            sender: Some(sender),
+            db_addr: None,
+            actions: vec![MutationAction::AddDocument],
+        };
+        // the scanned index keys are capped by the scan max limit
+        assert_eq!(
+            (vec![ids[0].clone()], Some(position(&ids[4]))),
+            scan(&filter, 0, 0, 3)
+        );
+        let filter = MutationFilter {
+            sender: Some(sender),
+            db_addr: None,
+            actions: vec![MutationAction::CreateDocumentDb],
+        };
+        let (headers, next_position) = scan(&filter, 0, 0, 1);
+        assert!(headers.is_empty());
+        let (block, order) = next_position.unwrap();
+        assert_eq!((vec![ids[4].clone()], None), scan(&filter, block, order, 1));
+        let filter = MutationFilter {
+            sender: None,
+            db_addr: None,
+            actions: vec![MutationAction::AddCollection, MutationAction::AddDocument],
+        };
+        assert_eq!((ids[0..3].to_vec(), None), scan(&filter, 0, 0, 3));
+        assert_eq!(
+            (ids[0..2].to_vec(), Some(position(&ids[2]))),
+            scan(&filter, 0, 0, 2)
+        );
+        // paginate by block and order
+        let filter = MutationFilter {
+            sender: Some(sender),
+            db_addr: None,
+            actions: vec![],
+        };
+        let (headers, next_position) = scan(&filter, 0, 0, 2);
+        assert_eq!(vec![ids[0].clone(), ids[2].clone()], headers);
+        let (block, order) = next_position.unwrap();
+        assert_eq!(
+            (vec![ids[3].clone(), ids[4].clone()], None),
+            scan(&filter, block, order, 2)
+        );
+        assert!(store
+            .scan_mutation_headers_by_filter(&MutationFilter::default(), 0, 0, 3)
+            .is_err());
+        assert!(store
+            .scan_mutation_headers_by_filter(&filter, 0, 0, 4)
+            .is_err());
+        let tx_id = TxId::try_from_hex(ids[2].as_str()).unwrap();
+        let header = store.get_mutation_header_by_id(&tx_id).unwrap().unwrap();
+        assert_eq!(ids[2], header.id);
+        // the indexes are removed with the mutations
+        let (block, _) = store.increase_block_return_last_state().unwrap();
+        assert!(store.gc_range_mutation(0, block + 1).is_ok());
+        assert_eq!((vec![], None), scan(&filter, 0, 0, 3));
+        assert!(store.get_mutation_header_by_id(&tx_id).unwrap().is_none());
+    }
+
     #[test]
     fn test_add_and_get_rollup_record() {
         let tmp_dir_path = TempDir::new("rollup").expect("create temp dir");
@@ -852,8 +1584,9 @@ mod tests {
                 start_block: 1,
                 evm_cost: 1,
                 evm_tx: "".to_string(),
+                mutation_root: MutationStore::compute_mutation_root(&[]).to_vec(),
             };
-            let result = store.add_rollup_record(&record);
+            let result = store.add_rollup_record(&record, &[]);
             assert!(result.is_ok());
             let result = store.get_last_rollup_record();
             if let Ok(Some(r)) = result {
@@ -864,6 +1597,61 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_get_mutation_proof() {
+        let tmp_dir_path = TempDir::new("mutation_proof").expect("create temp dir");
+        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
+        let config = MutationStoreConfig {
+            db_path: real_path,
+            block_store_cf_name: "cf1".to_string(),
+            tx_store_cf_name: "cf2".to_string(),
+            rollup_store_cf_name: "rf3".to_string(),
+            gc_cf_name: "gc".to_string(),
+            message_max_buffer: 4 * 1024,
+            scan_max_limit: 50,
+            block_state_cf_name: "block_state_cf".to_string(),
+        };
+        let store = MutationStore::new(config).unwrap();
+        let ids: Vec<TxId> = (0..5u8).map(|i| TxId::from(&[i][..])).collect();
+        let mut record = RollupRecord {
+            end_block: 3,
+            raw_data_size: 10,
+            compress_data_size: 1,
+            processed_time: 1,
+            arweave_tx: "xx".to_string(),
+            time: 111,
+            mutation_count: 5,
+            cost: 11111,
+            start_block: 1,
+            evm_cost: 1,
+            evm_tx: "".to_string(),
+            mutation_root: vec![],
+        };
+        // the mutation root must match the mutation ids
+        assert!(store.add_rollup_record(&record, &ids).is_err());
+        let root = MutationStore::compute_mutation_root(&ids);
+        record.mutation_root = root.to_vec();
+        assert!(store.add_rollup_record(&record, &ids).is_ok());
+        for (index, id) in ids.iter().enumerate() {
+            let proof = store.get_mutation_proof(id).unwrap().unwrap();
+            assert_eq!(index as u32, proof.index);
+            assert_eq!(id.to_hex(), proof.id);
+            assert_eq!(Some(record.clone()), proof.record);
+            let path: Vec<merkle::ProofNode> = proof
+                .path
+                .iter()
+                .map(|node| merkle::ProofNode {
+                    hash: node.hash.as_slice().try_into().unwrap(),
+                    is_left: node.is_left,
+                })
+                .collect();
+            let leaf = merkle::hash_leaf(id.as_ref());
+            assert!(merkle::verify_proof(&leaf, &path, &root));
+        }
+        let missing = TxId::from(&[9][..]);
+        assert_eq!(None, store.get_mutation_proof(&missing).unwrap());
+    }
+
     #[test]
     fn test_range_mutations() {
         let tmp_dir_path = TempDir::new("range store path").expect("create temp dir");
@@ -902,6 +1690,7 @@ mod tests {
                 order,
                 1,
                 MutationAction::CreateDocumentDb,
+                &[],
             );
             assert!(result.is_ok());
             let result = store.get_range_mutations(0, 1);
@@ -915,6 +1704,141 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_range_block_mutation_count() {
+        let tmp_dir_path = TempDir::new("range block store path").expect("create temp dir");
+        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
+        let config = MutationStoreConfig {
+            db_path: real_path,
+            block_store_cf_name: "cf1".to_string(),
+            tx_store_cf_name: "cf2".to_string(),
+            rollup_store_cf_name: "rf3".to_string(),
+            gc_cf_name: "gc".to_string(),
+            message_max_buffer: 4 * 1024,
+            scan_max_limit: 50,
+            block_state_cf_name: "block_state_cf".to_string(),
+        };
+        let store = MutationStore::new(config).unwrap();
+        assert!(store.get_range_block_mutation_count(1, 1).is_err());
+        for (i, signature) in ["0x01", "0x02", "0x03"].iter().enumerate() {
+            let payload: Vec<u8> = vec![i as u8];
+            let (_id, block, order) = store
+                .generate_mutation_block_and_order(payload.as_ref(), signature)
+                .unwrap();
+            let result = store.add_mutation(
+                payload.as_ref(),
+                signature,
+                "",
+                &DB3Address::ZERO,
+                1,
+                block,
+                order,
+                1,
+                MutationAction::CreateDocumentDb,
+                &[],
+            );
+            assert!(result.is_ok());
+            // block 1 has no mutation
+            if i == 1 {
+                store.increase_block_return_last_state().unwrap();
+                store.increase_block_return_last_state().unwrap();
+            }
+        }
+        let blocks = store.get_range_block_mutation_count(0, 10).unwrap();
+        assert_eq!(vec![(0, 2), (2, 1)], blocks);
+        let blocks = store.get_range_block_mutation_count(1, 2).unwrap();
+        assert!(blocks.is_empty());
+        assert_eq!(0, store.get_gc_watermark().unwrap());
+        assert!(store.gc_range_mutation(0, 2).is_ok());
+        let record = GCRecord {
+            start_block: 0,
+            end_block: 2,
+            data_size: 0,
+            time: 0,
+            processed_time: 0,
+        };
+        assert!(store.add_gc_record(&record).is_ok());
+        assert_eq!(2, store.get_gc_watermark().unwrap());
+        let blocks = store.get_range_block_mutation_count(0, 10).unwrap();
+        assert_eq!(vec![(2, 1)], blocks);
+        // the index start block is kept after it's recorded by the first recovery
+        assert_eq!(0, store.get_index_start_block().unwrap());
+        assert!(store.recover().is_ok());
+        let index_start_block = store.get_index_start_block().unwrap();
+        assert_eq!(store.get_current_block().unwrap(), index_start_block);
+        store.increase_block_return_last_state().unwrap();
+        assert!(store.recover().is_ok());
+        assert_eq!(index_start_block, store.get_index_start_block().unwrap());
+    }
+
+    #[test]
+    fn test_produce_block() {
+        let tmp_dir_path = TempDir::new("produce block store path").expect("create temp dir");
+        let real_path = tmp_dir_path.path().to_str().unwrap().to_string();
+        let config = MutationStoreConfig {
+            db_path: real_path,
+            block_store_cf_name: "cf1".to_string(),
+            tx_store_cf_name: "cf2".to_string(),
+            rollup_store_cf_name: "rf3".to_string(),
+            gc_cf_name: "gc".to_string(),
+            message_max_buffer: 4 * 1024,
+            scan_max_limit: 50,
+            block_state_cf_name: "block_state_cf".to_string(),
+        };
+        let store = MutationStore::new(config).unwrap();
+        let mut ids: Vec<TxId> = Vec::new();
+        for signature in ["0x01", "0x02"] {
+            let payload: Vec<u8> = vec![1];
+            let _guard = store.hold_block().unwrap();
+            let (id, block, order) = store
+                .generate_mutation_block_and_order(payload.as_ref(), signature)
+                .unwrap();
+            ids.push(TxId::try_from_hex(id.as_str()).unwrap());
+            let result = store.add_mutation(
+                payload.as_ref(),
+                signature,
+                "",
+                &DB3Address::ZERO,
+                1,
+                block,
+                order,
+                1,
+                MutationAction::CreateDocumentDb,
+                &[],
+            );
+            assert!(result.is_ok());
+        }
+        let first = store.produce_block(|| Ok(vec![1])).unwrap();
+        assert_eq!(0, first.block_id);
+        assert_eq!(2, first.mutation_count);
+        assert!(first.parent_hash.is_empty());
+        assert_eq!(
+            MutationStore::compute_mutation_root(&ids).to_vec(),
+            first.mutation_root
+        );
+        assert_eq!(vec![1], first.state_root);
+        assert_eq!(
+            MutationStore::compute_block_hash(&first).to_vec(),
+            first.hash
+        );
+        let second = store.produce_block(|| Ok(vec![2])).unwrap();
+        assert_eq!(1, second.block_id);
+        assert_eq!(0, second.mutation_count);
+        assert_eq!(first.hash, second.parent_hash);
+        assert_eq!(vec![0; 32], second.mutation_root);
+        let headers = store.get_range_block_headers(0, 10).unwrap();
+        assert_eq!(vec![first.clone(), second], headers);
+        assert_eq!(Some(first), store.get_block_header(0).unwrap());
+        assert!(store.get_block_header(2).unwrap().is_none());
+        // the header is changed
+        let mut tampered = store.get_block_header(0).unwrap().unwrap();
+        tampered.mutation_count = 1;
+        assert_ne!(
+            MutationStore::compute_block_hash(&tampered).to_vec(),
+            tampered.hash
+        );
+    }
+
     #[test]
     fn test_add_mutation() {
         let tmp_dir_path = TempDir::new("add mutation store path").expect("create temp dir");
@@ -947,6 +1871,7 @@ mod tests {
                 order,
                 1,
                 MutationAction::CreateDocumentDb,
+                &[],
             );
             assert!(result.is_ok());
             if let Ok((id, block, order)) = result {
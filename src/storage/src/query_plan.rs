@@ -0,0 +1,1417 @@
+//
+// query_plan.rs
+// Copyright (C) 2023 db3.network Author imotai <codego.me@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use bson::Bson;
+use db3_base::bson_util;
+use db3_error::{DB3Error, Result};
+use db3_proto::db3_database_v2_proto::aggregation::Operator as AggregationOp;
+use db3_proto::db3_database_v2_proto::structured_query::composite_filter::Operator as CompositeOp;
+use db3_proto::db3_database_v2_proto::structured_query::field_filter::Operator;
+use db3_proto::db3_database_v2_proto::structured_query::filter::FilterType;
+use db3_proto::db3_database_v2_proto::structured_query::value::ValueType;
+use db3_proto::db3_database_v2_proto::structured_query::{
+    CompositeFilter, Direction, FieldFilter, Filter, Order, Projection, Value,
+};
+use db3_proto::db3_database_v2_proto::{
+    query_parameter, Aggregation, Collection, IndexType, PageCursor, Query, QueryParameter,
+    StructuredQuery,
+};
+use prost::Message;
+
+const MAX_ARRAY_FILTER_VALUES: usize = 10;
+
+///
+/// QueryPlanner validates a structured query against the indexes of the collection
+/// and compiles it into an ejdb query. all the string, int64 and bool values are
+/// bound with placeholders so the client values never become a part of the query string
+///
+pub struct QueryPlanner<'a> {
+    collection: &'a Collection,
+    parameters: Vec<QueryParameter>,
+    // only count the matched documents in ejdb
+    count_only: bool,
+}
+
+impl<'a> QueryPlanner<'a> {
+    pub fn new(collection: &'a Collection) -> Self {
+        Self {
+            collection,
+            parameters: Vec::new(),
+            count_only: false,
+        }
+    }
+
+    pub fn build(self, query: &StructuredQuery) -> Result<Query> {
+        self.build_with_cursor(query, None)
+    }
+
+    ///
+    /// build the query for the aggregations. only the fields used by the aggregations
+    /// are projected and the group by field must be indexed
+    ///
+    pub fn build_aggregation(
+        self,
+        query: &StructuredQuery,
+        aggregations: &[Aggregation],
+        group_by: &str,
+    ) -> Result<Query> {
+        if aggregations.is_empty() {
+            return Err(DB3Error::InvalidFilterValue(
+                "at least one aggregation is required".to_string(),
+            ));
+        }
+        let mut fields: Vec<String> = vec![];
+        if !group_by.is_empty() {
+            Self::check_field_name(group_by)?;
+            self.find_index(group_by)?;
+            fields.push(group_by.to_string());
+        }
+        for aggregation in aggregations {
+            match aggregation.op() {
+                AggregationOp::Count => continue,
+                AggregationOp::Unspecified => {
+                    return Err(DB3Error::InvalidFilterOp(
+                        "the aggregation operator is unspecified".to_string(),
+                    ));
+                }
+                _ => {}
+            }
+            Self::check_field_name(aggregation.field.as_str())?;
+            if !fields.contains(&aggregation.field) {
+                fields.push(aggregation.field.to_string());
+            }
+        }
+        let aggregation_query = StructuredQuery {
+            select: Some(Projection { fields }),
+            collection_name: query.collection_name.to_string(),
+            r#where: query.r#where.clone(),
+            limit: query.limit.clone(),
+            order_by: vec![],
+            start_at: None,
+            end_at: None,
+        };
+        self.build(&aggregation_query)
+    }
+
+    ///
+    /// build the query which returns the number of the matched documents without
+    /// loading them
+    ///
+    pub fn build_count(mut self, query: &StructuredQuery) -> Result<Query> {
+        self.count_only = true;
+        let count_query = StructuredQuery {
+            select: None,
+            collection_name: query.collection_name.to_string(),
+            r#where: query.r#where.clone(),
+            limit: None,
+            order_by: vec![],
+            start_at: None,
+            end_at: None,
+        };
+        self.build(&count_query)
+    }
+
+    ///
+    /// build the query with the page cursor returned by the last page. the page cursor
+    /// replaces the start_at of the query and the page starts after its values, the
+    /// documents with the same values are queried with `build_order_group`
+    ///
+    pub fn build_with_cursor(
+        mut self,
+        query: &StructuredQuery,
+        page_cursor: Option<&PageCursor>,
+    ) -> Result<Query> {
+        if query.collection_name != self.collection.name {
+            return Err(DB3Error::InvalidCollectionNameError(format!(
+                "the query targets collection {} but the plan is built for {}",
+                query.collection_name, self.collection.name
+            )));
+        }
+        let orders = self.build_orders(&query.order_by)?;
+        self.check_compound_index(query)?;
+        let has_cursor =
+            page_cursor.is_some() || query.start_at.is_some() || query.end_at.is_some();
+        let mut conditions = vec![];
+        if let Some(filter) = &query.r#where {
+            conditions.push(self.build_filter(filter, has_cursor)?);
+        }
+        if let Some(cursor) = page_cursor {
+            conditions.push(self.build_cursor_filter(&orders, &cursor.values, false, false)?);
+        } else if let Some(cursor) = &query.start_at {
+            conditions.push(self.build_cursor_filter(
+                &orders,
+                &cursor.values,
+                false,
+                cursor.before,
+            )?);
+        }
+        if let Some(cursor) = &query.end_at {
+            conditions.push(self.build_cursor_filter(
+                &orders,
+                &cursor.values,
+                true,
+                !cursor.before,
+            )?);
+        }
+        let mut query_str = if conditions.is_empty() {
+            "/*".to_string()
+        } else {
+            conditions.join(" and ")
+        };
+        query_str.push_str(Self::build_projection(query)?.as_str());
+        let mut options: Vec<String> = orders
+            .iter()
+            .map(|(field, desc)| {
+                let field = Self::node_name(field);
+                if *desc {
+                    format!("desc /{field}")
+                } else {
+                    format!("asc /{field}")
+                }
+            })
+            .collect();
+        if let Some(limit) = &query.limit {
+            if limit.limit < 0 {
+                return Err(DB3Error::InvalidFilterValue(format!(
+                    "the limit {} must be greater than or equal to zero",
+                    limit.limit
+                )));
+            }
+            options.push(format!("limit {}", limit.limit));
+        }
+        if self.count_only {
+            options.push("count".to_string());
+        }
+        if !options.is_empty() {
+            query_str.push_str(format!(" | {}", options.join(" ")).as_str());
+        }
+        Ok(Query {
+            query_str,
+            parameters: self.parameters,
+        })
+    }
+
+    ///
+    /// build the query of the documents with the given values of the order fields, so
+    /// the documents with the same values can be returned in the order of their ids
+    ///
+    pub fn build_order_group(mut self, query: &StructuredQuery, values: &[Value]) -> Result<Query> {
+        if query.collection_name != self.collection.name {
+            return Err(DB3Error::InvalidCollectionNameError(format!(
+                "the query targets collection {} but the plan is built for {}",
+                query.collection_name, self.collection.name
+            )));
+        }
+        let orders = self.build_orders(&query.order_by)?;
+        if values.len() != orders.len() {
+            return Err(DB3Error::InvalidFilterValue(format!(
+                "the order group requires {} values but {} are given",
+                orders.len(),
+                values.len()
+            )));
+        }
+        let mut conditions = vec![];
+        if let Some(filter) = &query.r#where {
+            conditions.push(self.build_filter(filter, true)?);
+        }
+        if let Some(cursor) = &query.end_at {
+            conditions.push(self.build_cursor_filter(
+                &orders,
+                &cursor.values,
+                true,
+                !cursor.before,
+            )?);
+        }
+        let mut comparisons = vec![];
+        for ((field, _), value) in orders.iter().zip(values.iter()) {
+            let value = value
+                .value_type
+                .as_ref()
+                .ok_or(DB3Error::InvalidFilterValue(format!(
+                    "the order value of field {field} is empty"
+                )))?;
+            let (_, index_type) = self.find_index(field)?;
+            Self::check_value_type(field, index_type, value)?;
+            let value_str = self.bind_value(field, value)?;
+            comparisons.push(format!("{} = {value_str}", Self::node_name(field)));
+        }
+        conditions.push(format!("/[{}]", comparisons.join(" and ")));
+        let mut query_str = conditions.join(" and ");
+        query_str.push_str(Self::build_projection(query)?.as_str());
+        Ok(Query {
+            query_str,
+            parameters: self.parameters,
+        })
+    }
+
+    fn build_projection(query: &StructuredQuery) -> Result<String> {
+        match &query.select {
+            Some(projection) if !projection.fields.is_empty() => {
+                let fields = projection
+                    .fields
+                    .iter()
+                    .map(|f| Self::check_field_name(f).map(|_| Self::node_name(f)))
+                    .collect::<Result<Vec<String>>>()?;
+                Ok(format!(" | /{{{}}}", fields.join(",")))
+            }
+            _ => Ok("".to_string()),
+        }
+    }
+
+    fn build_orders(&self, order_by: &[Order]) -> Result<Vec<(&'a str, bool)>> {
+        let mut orders = vec![];
+        for order in order_by {
+            Self::check_field_name(order.field.as_str())?;
+            let (field, _) = self.find_index(order.field.as_str())?;
+            let desc = order.direction() == Direction::Descending;
+            orders.push((field, desc));
+        }
+        Ok(orders)
+    }
+
+    ///
+    /// build the filter for a position in the ordered result set. the position (v1, v2)
+    /// of the order (f1 asc, f2 desc) starts after the documents
+    /// `f1 > v1 or (f1 = v1 and f2 < v2)` and the equal one is included if `inclusive` is true
+    ///
+    fn build_cursor_filter(
+        &mut self,
+        orders: &[(&str, bool)],
+        values: &[Value],
+        is_end: bool,
+        inclusive: bool,
+    ) -> Result<String> {
+        if values.is_empty() || values.len() > orders.len() {
+            return Err(DB3Error::InvalidFilterValue(format!(
+                "the cursor requires 1 to {} values but {} are given",
+                orders.len(),
+                values.len()
+            )));
+        }
+        let mut terms = vec![];
+        let term_count = if inclusive {
+            values.len() + 1
+        } else {
+            values.len()
+        };
+        for i in 0..term_count {
+            let mut comparisons = vec![];
+            for (j, value) in values.iter().enumerate().take(i + 1) {
+                let (field, desc) = orders[j];
+                let op_str = if j < i || i == values.len() {
+                    "="
+                } else if desc != is_end {
+                    "<"
+                } else {
+                    ">"
+                };
+                let value = value
+                    .value_type
+                    .as_ref()
+                    .ok_or(DB3Error::InvalidFilterValue(format!(
+                        "the cursor value of field {field} is empty"
+                    )))?;
+                let (_, index_type) = self.find_index(field)?;
+                Self::check_value_type(field, index_type, value)?;
+                let value_str = self.bind_value(field, value)?;
+                comparisons.push(format!("{} {op_str} {value_str}", Self::node_name(field)));
+            }
+            terms.push(format!("/[{}]", comparisons.join(" and ")));
+        }
+        if terms.len() == 1 {
+            Ok(terms.remove(0))
+        } else {
+            Ok(format!("({})", terms.join(" or ")))
+        }
+    }
+
+    fn build_filter(&mut self, filter: &Filter, nested: bool) -> Result<String> {
+        match &filter.filter_type {
+            Some(FilterType::FieldFilter(field_filter)) => self.build_field_filter(field_filter),
+            Some(FilterType::CompositeFilter(composite_filter)) => {
+                let filter_str = self.build_composite_filter(composite_filter)?;
+                if nested {
+                    Ok(format!("({filter_str})"))
+                } else {
+                    Ok(filter_str)
+                }
+            }
+            None => Err(DB3Error::InvalidFilterType("filter is empty".to_string())),
+        }
+    }
+
+    fn build_composite_filter(&mut self, composite_filter: &CompositeFilter) -> Result<String> {
+        if composite_filter.filters.is_empty() {
+            return Err(DB3Error::InvalidFilterType(
+                "composite filter requires at least one filter".to_string(),
+            ));
+        }
+        let op_str = match composite_filter.op() {
+            CompositeOp::And => " and ",
+            CompositeOp::Or => " or ",
+            CompositeOp::Unspecified => {
+                return Err(DB3Error::InvalidFilterOp(
+                    "the composite filter operator is unspecified".to_string(),
+                ));
+            }
+        };
+        let filters = composite_filter
+            .filters
+            .iter()
+            .map(|f| self.build_filter(f, true))
+            .collect::<Result<Vec<String>>>()?;
+        Ok(filters.join(op_str))
+    }
+
+    fn build_field_filter(&mut self, field_filter: &FieldFilter) -> Result<String> {
+        let field = field_filter.field.as_str();
+        Self::check_field_name(field)?;
+        let (_, index_type) = self.find_index(field)?;
+        let value = match &field_filter.value {
+            Some(Value {
+                value_type: Some(value_type),
+            }) => value_type,
+            _ => {
+                return Err(DB3Error::InvalidFilterValue(format!(
+                    "value is required for the filter on field {field}"
+                )));
+            }
+        };
+        // the membership operators are expanded to a list of comparisons in one
+        // ejdb filter so every value can still be bound with a placeholder
+        let (op_str, join_str) = match field_filter.op() {
+            Operator::LessThan => ("<", None),
+            Operator::LessThanOrEqual => ("<=", None),
+            Operator::GreaterThan => (">", None),
+            Operator::GreaterThanOrEqual => (">=", None),
+            Operator::Equal => ("=", None),
+            Operator::NotEqual => ("!=", None),
+            Operator::ArrayContains => ("ni", None),
+            Operator::In => ("=", Some(" or ")),
+            Operator::NotIn => ("!=", Some(" and ")),
+            Operator::ArrayContainsAny => ("ni", Some(" or ")),
+            Operator::Unspecified => {
+                return Err(DB3Error::InvalidFilterOp(format!(
+                    "the operator of the filter on field {field} is unspecified"
+                )));
+            }
+        };
+        match (join_str, value) {
+            (None, ValueType::MapValue(_)) if op_str != "=" && op_str != "!=" => {
+                Err(DB3Error::InvalidFilterOp(format!(
+                    "the map value of field {field} only supports the equality operators"
+                )))
+            }
+            (None, ValueType::NullValue(_)) if op_str == "=" => {
+                Ok(Self::null_equality_filter(field))
+            }
+            (None, _) => {
+                Self::check_value_type(field, index_type, value)?;
+                let value_str = self.bind_value(field, value)?;
+                Ok(format!("/[{} {op_str} {value_str}]", Self::node_name(field)))
+            }
+            (Some(join_str), ValueType::ArrayValue(array))
+                if !array.values.is_empty() && array.values.len() <= MAX_ARRAY_FILTER_VALUES =>
+            {
+                let mut conditions = vec![];
+                let mut has_null = false;
+                for item in array.values.iter() {
+                    let item_value = item.value_type.as_ref().ok_or(
+                        DB3Error::InvalidFilterValue(format!(
+                            "the array value of field {field} contains an empty value"
+                        )),
+                    )?;
+                    if let ValueType::NullValue(_) = item_value {
+                        match field_filter.op() {
+                            // the null is rewritten like the null equality
+                            Operator::In => {
+                                has_null = true;
+                                continue;
+                            }
+                            Operator::ArrayContainsAny => {
+                                return Err(DB3Error::InvalidFilterValue(format!(
+                                    "the array value of field {field} can not contain null"
+                                )));
+                            }
+                            _ => {}
+                        }
+                    }
+                    Self::check_value_type(field, index_type, item_value)?;
+                    let value_str = self.bind_value(field, item_value)?;
+                    conditions.push(format!("{} {op_str} {value_str}", Self::node_name(field)));
+                }
+                match (conditions.is_empty(), has_null) {
+                    (true, _) => Ok(Self::null_equality_filter(field)),
+                    (false, true) => Ok(format!(
+                        "(/[{}] or {})",
+                        conditions.join(join_str),
+                        Self::null_equality_filter(field)
+                    )),
+                    (false, false) => Ok(format!("/[{}]", conditions.join(join_str))),
+                }
+            }
+            (Some(_), _) => Err(DB3Error::InvalidFilterValue(format!(
+                "the filter on field {field} requires a non-empty array with at most {MAX_ARRAY_FILTER_VALUES} values"
+            ))),
+        }
+    }
+
+    /// ejdb never matches a filter on a missing field, so the null equality
+    /// is rewritten to the negation of the non null filter to match the
+    /// documents without the field too
+    fn null_equality_filter(field: &str) -> String {
+        format!("(/* and not /[{} != null])", Self::node_name(field))
+    }
+
+    fn bind_value(&mut self, field: &str, value: &ValueType) -> Result<String> {
+        let parameter = match value {
+            ValueType::BooleanValue(b) => query_parameter::Parameter::BoolValue(*b),
+            ValueType::IntegerValue(n) => query_parameter::Parameter::Int64Value(*n),
+            ValueType::StringValue(s) => query_parameter::Parameter::StrValue(s.to_string()),
+            ValueType::DoubleValue(d) => {
+                // the query parameter has no double type and a finite number
+                // is safe to be inlined
+                if !d.is_finite() {
+                    return Err(DB3Error::InvalidFilterValue(format!(
+                        "the double value of field {field} must be finite"
+                    )));
+                }
+                return Ok(format!("{d:?}"));
+            }
+            ValueType::NullValue(_) => return Ok("null".to_string()),
+            // the timestamp is stored as the milliseconds since epoch in the doc store
+            ValueType::TimestampValue(ts) => {
+                query_parameter::Parameter::Int64Value(bson_util::timestamp_to_millis(ts))
+            }
+            // the bytes is stored as the hex string in the doc store
+            ValueType::BytesValue(b) => query_parameter::Parameter::StrValue(hex::encode(b)),
+            ValueType::MapValue(_) => {
+                // the map is inlined as a json object with the keys sorted and all the
+                // strings in it are escaped by the json encoder
+                let doc = match bson_util::bson_value_from_proto_value(&Value {
+                    value_type: Some(value.clone()),
+                })? {
+                    Bson::Document(doc) => doc,
+                    _ => {
+                        return Err(DB3Error::InvalidFilterValue(format!(
+                            "invalid map value of field {field}"
+                        )));
+                    }
+                };
+                return Ok(bson_util::bson_document_into_json_str(&doc));
+            }
+            ValueType::ArrayValue(_) => {
+                return Err(DB3Error::InvalidFilterValue(format!(
+                    "array value is not supported by the operator on field {field}"
+                )));
+            }
+        };
+        self.parameters.push(QueryParameter {
+            name: field.to_string(),
+            parameter: Some(parameter),
+            idx: self.parameters.len() as i32,
+        });
+        Ok("?".to_string())
+    }
+
+    ///
+    /// find the index of the field and a field of the compound index is indexed too
+    ///
+    fn find_index(&self, field: &str) -> Result<(&'a str, IndexType)> {
+        let collection: &'a Collection = self.collection;
+        for index in collection.index_fields.iter() {
+            if index.fields.is_empty() {
+                if index.path.trim_start_matches('/') == field {
+                    return Ok((index.path.trim_start_matches('/'), index.index_type()));
+                }
+            } else if let Some(index_field) = index
+                .fields
+                .iter()
+                .find(|f| f.path.trim_start_matches('/') == field)
+            {
+                return Ok((
+                    index_field.path.trim_start_matches('/'),
+                    index_field.index_type(),
+                ));
+            }
+        }
+        Err(DB3Error::IndexNotFoundForFiledFilter(format!(
+            "field {} of collection {}",
+            field, self.collection.name
+        )))
+    }
+
+    ///
+    /// the query which sorts on the fields or uses a range filter together with the
+    /// filters on the other fields must be served by a compound index. the equality
+    /// fields must be the prefix of the compound index and the range and order fields
+    /// must follow them. the equality filters on different fields can use the single
+    /// field indexes and the not equal filters need no compound index
+    ///
+    fn check_compound_index(&self, query: &StructuredQuery) -> Result<()> {
+        let mut equality_fields: Vec<&str> = vec![];
+        let mut range_fields: Vec<&str> = vec![];
+        if let Some(filter) = &query.r#where {
+            Self::collect_conjunction_fields(filter, &mut equality_fields, &mut range_fields);
+        }
+        for order in query.order_by.iter() {
+            if !range_fields.contains(&order.field.as_str()) {
+                range_fields.push(order.field.as_str());
+            }
+        }
+        range_fields.retain(|field| !equality_fields.contains(field));
+        if range_fields.is_empty() || equality_fields.len() + range_fields.len() < 2 {
+            return Ok(());
+        }
+        let prefix_len = equality_fields.len();
+        let fields_len = prefix_len + range_fields.len();
+        let covered = self.collection.index_fields.iter().any(|index| {
+            let paths: Vec<&str> = index
+                .fields
+                .iter()
+                .map(|f| f.path.trim_start_matches('/'))
+                .collect();
+            paths.len() >= fields_len
+                && paths[..prefix_len]
+                    .iter()
+                    .all(|path| equality_fields.contains(path))
+                && paths[prefix_len..fields_len]
+                    .iter()
+                    .all(|path| range_fields.contains(path))
+        });
+        if covered {
+            Ok(())
+        } else {
+            Err(DB3Error::IndexNotFoundForFiledFilter(format!(
+                "a compound index starting with the fields {:?} and then {:?} of collection {} is required",
+                equality_fields, range_fields, self.collection.name
+            )))
+        }
+    }
+
+    fn collect_conjunction_fields<'b>(
+        filter: &'b Filter,
+        equality_fields: &mut Vec<&'b str>,
+        range_fields: &mut Vec<&'b str>,
+    ) {
+        match &filter.filter_type {
+            Some(FilterType::FieldFilter(field_filter)) => {
+                let field = field_filter.field.as_str();
+                let fields = match field_filter.op() {
+                    Operator::Equal
+                    | Operator::In
+                    | Operator::ArrayContains
+                    | Operator::ArrayContainsAny => equality_fields,
+                    Operator::LessThan
+                    | Operator::LessThanOrEqual
+                    | Operator::GreaterThan
+                    | Operator::GreaterThanOrEqual => range_fields,
+                    // the not equal filters select no range of an index and are checked
+                    // on the documents matched by the other filters
+                    Operator::NotEqual | Operator::NotIn | Operator::Unspecified => return,
+                };
+                if !fields.contains(&field) {
+                    fields.push(field);
+                }
+            }
+            // every branch of the or filter is checked with its own single field index
+            Some(FilterType::CompositeFilter(composite_filter))
+                if composite_filter.op() == CompositeOp::And =>
+            {
+                for filter in composite_filter.filters.iter() {
+                    Self::collect_conjunction_fields(filter, equality_fields, range_fields);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_value_type(field: &str, index_type: IndexType, value: &ValueType) -> Result<()> {
+        let matched = match (index_type, value) {
+            (_, ValueType::ArrayValue(_)) => false,
+            (IndexType::UniqueKey, _) => true,
+            (_, ValueType::NullValue(_)) => true,
+            (IndexType::Int64Key, ValueType::TimestampValue(_)) => true,
+            (IndexType::StringKey, ValueType::BytesValue(_)) => true,
+            (IndexType::StringKey, ValueType::StringValue(_)) => true,
+            (IndexType::Int64Key, ValueType::IntegerValue(_)) => true,
+            (IndexType::DoubleKey, ValueType::DoubleValue(_)) => true,
+            (IndexType::DoubleKey, ValueType::IntegerValue(_)) => true,
+            _ => false,
+        };
+        if matched {
+            Ok(())
+        } else {
+            Err(DB3Error::InvalidFilterType(format!(
+                "the value type of field {} does not match the index type {:?}",
+                field, index_type
+            )))
+        }
+    }
+
+    ///
+    /// the field name is a node name of the ejdb query and the one with the dash is
+    /// quoted so the dash is not parsed as a part of the query syntax
+    ///
+    fn node_name(field: &str) -> String {
+        if field.contains('-') {
+            format!("\"{field}\"")
+        } else {
+            field.to_string()
+        }
+    }
+
+    fn check_field_name(field: &str) -> Result<()> {
+        if !field.is_empty()
+            && field
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            Ok(())
+        } else {
+            Err(DB3Error::InvalidKeyPathError(format!(
+                "invalid field name {field}"
+            )))
+        }
+    }
+}
+
+pub fn encode_page_cursor(cursor: &PageCursor) -> String {
+    hex::encode(cursor.encode_to_vec())
+}
+
+pub fn decode_page_cursor(cursor: &str) -> Result<PageCursor> {
+    let buf = hex::decode(cursor)
+        .map_err(|e| DB3Error::InvalidFilterValue(format!("invalid cursor for {e}")))?;
+    PageCursor::decode(buf.as_ref())
+        .map_err(|e| DB3Error::InvalidFilterValue(format!("invalid cursor for {e}")))
+}
+
+///
+/// convert the scalar json value to the value of the structured query. None will be
+/// returned for null, array and object
+///
+pub fn scalar_value_from_json(value: &serde_json::Value) -> Option<Value> {
+    let value_type = match value {
+        serde_json::Value::Bool(b) => ValueType::BooleanValue(*b),
+        serde_json::Value::String(s) => ValueType::StringValue(s.to_string()),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => ValueType::IntegerValue(i),
+            None => ValueType::DoubleValue(n.as_f64()?),
+        },
+        _ => return None,
+    };
+    Some(Value {
+        value_type: Some(value_type),
+    })
+}
+
+///
+/// extract the values of the order by fields from the json document. None will be returned
+/// if any field is missing or not a scalar value
+///
+pub fn order_values_from_json(doc: &serde_json::Value, order_by: &[Order]) -> Option<Vec<Value>> {
+    order_by
+        .iter()
+        .map(|order| scalar_value_from_json(doc.get(order.field.as_str())?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db3_proto::db3_database_v2_proto::structured_query::{
+        ArrayValue, Limit, MapValue, NullValue, Timestamp,
+    };
+    use db3_proto::db3_database_v2_proto::{Cursor, Index, IndexField};
+
+    fn index_field(path: &str, index_type: IndexType) -> IndexField {
+        IndexField {
+            path: path.to_string(),
+            index_type: index_type.into(),
+        }
+    }
+
+    fn build_collection() -> Collection {
+        Collection {
+            name: "col1".to_string(),
+            index_fields: vec![
+                Index {
+                    path: "/name".to_string(),
+                    index_type: IndexType::StringKey.into(),
+                    fields: vec![],
+                },
+                Index {
+                    path: "/age".to_string(),
+                    index_type: IndexType::Int64Key.into(),
+                    fields: vec![],
+                },
+                Index {
+                    path: "/score".to_string(),
+                    index_type: IndexType::DoubleKey.into(),
+                    fields: vec![],
+                },
+                Index {
+                    path: "name_age_score".to_string(),
+                    index_type: IndexType::UniqueKey.into(),
+                    fields: vec![
+                        index_field("/name", IndexType::StringKey),
+                        index_field("/age", IndexType::Int64Key),
+                        index_field("/score", IndexType::DoubleKey),
+                    ],
+                },
+            ],
+            sender: vec![],
+            schema: None,
+            acl: None,
+            ttl: 0,
+            keep_history: false,
+        }
+    }
+
+    fn field_filter(field: &str, op: Operator, value: ValueType) -> Filter {
+        Filter {
+            filter_type: Some(FilterType::FieldFilter(FieldFilter {
+                field: field.to_string(),
+                op: op.into(),
+                value: Some(Value {
+                    value_type: Some(value),
+                }),
+            })),
+        }
+    }
+
+    fn build_query(filter: Option<Filter>) -> StructuredQuery {
+        StructuredQuery {
+            select: None,
+            collection_name: "col1".to_string(),
+            r#where: filter,
+            limit: None,
+            order_by: vec![],
+            start_at: None,
+            end_at: None,
+        }
+    }
+
+    #[test]
+    fn query_plan_without_filter_test() {
+        let collection = build_collection();
+        let mut query = build_query(None);
+        query.select = Some(Projection {
+            fields: vec!["name".to_string(), "age".to_string()],
+        });
+        query.limit = Some(Limit { limit: 10 });
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!("/* | /{name,age} | limit 10", plan.query_str.as_str());
+        let plan = QueryPlanner::new(&collection).build_count(&query).unwrap();
+        assert_eq!("/* | count", plan.query_str.as_str());
+        assert!(plan.parameters.is_empty());
+    }
+
+    #[test]
+    fn query_plan_field_filter_test() {
+        let collection = build_collection();
+        let query = build_query(Some(field_filter(
+            "name",
+            Operator::Equal,
+            ValueType::StringValue("\"] or /[age > 0".to_string()),
+        )));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!("/[name = ?]", plan.query_str.as_str());
+        assert_eq!(1, plan.parameters.len());
+        assert_eq!(0, plan.parameters[0].idx);
+        assert_eq!(
+            Some(query_parameter::Parameter::StrValue(
+                "\"] or /[age > 0".to_string()
+            )),
+            plan.parameters[0].parameter
+        );
+    }
+
+    #[test]
+    fn query_plan_composite_filter_test() {
+        let collection = build_collection();
+        let query = build_query(Some(Filter {
+            filter_type: Some(FilterType::CompositeFilter(CompositeFilter {
+                op: CompositeOp::And.into(),
+                filters: vec![
+                    field_filter("age", Operator::GreaterThan, ValueType::IntegerValue(10)),
+                    field_filter("score", Operator::LessThan, ValueType::DoubleValue(1.5)),
+                ],
+            })),
+        }));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!("/[age > ?] and /[score < 1.5]", plan.query_str.as_str());
+        assert_eq!(1, plan.parameters.len());
+    }
+
+    fn array_value(values: Vec<ValueType>) -> ValueType {
+        ValueType::ArrayValue(ArrayValue {
+            values: values
+                .into_iter()
+                .map(|v| Value {
+                    value_type: Some(v),
+                })
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn query_plan_or_filter_test() {
+        let collection = build_collection();
+        let query = build_query(Some(Filter {
+            filter_type: Some(FilterType::CompositeFilter(CompositeFilter {
+                op: CompositeOp::Or.into(),
+                filters: vec![
+                    field_filter(
+                        "name",
+                        Operator::Equal,
+                        ValueType::StringValue("a".to_string()),
+                    ),
+                    Filter {
+                        filter_type: Some(FilterType::CompositeFilter(CompositeFilter {
+                            op: CompositeOp::And.into(),
+                            filters: vec![
+                                field_filter(
+                                    "age",
+                                    Operator::GreaterThan,
+                                    ValueType::IntegerValue(1),
+                                ),
+                                field_filter("age", Operator::LessThan, ValueType::IntegerValue(9)),
+                            ],
+                        })),
+                    },
+                ],
+            })),
+        }));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!(
+            "/[name = ?] or (/[age > ?] and /[age < ?])",
+            plan.query_str.as_str()
+        );
+        assert_eq!(3, plan.parameters.len());
+        assert_eq!(2, plan.parameters[2].idx);
+    }
+
+    #[test]
+    fn query_plan_array_filter_test() {
+        let collection = build_collection();
+        let query = build_query(Some(field_filter(
+            "age",
+            Operator::In,
+            array_value(vec![ValueType::IntegerValue(1), ValueType::IntegerValue(2)]),
+        )));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!("/[age = ? or age = ?]", plan.query_str.as_str());
+        assert_eq!(2, plan.parameters.len());
+        let query = build_query(Some(field_filter(
+            "name",
+            Operator::NotIn,
+            array_value(vec![ValueType::StringValue("a".to_string())]),
+        )));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!("/[name != ?]", plan.query_str.as_str());
+        let query = build_query(Some(field_filter(
+            "score",
+            Operator::ArrayContainsAny,
+            array_value(vec![
+                ValueType::DoubleValue(1.0),
+                ValueType::DoubleValue(2.5),
+            ]),
+        )));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!("/[score ni 1.0 or score ni 2.5]", plan.query_str.as_str());
+        assert!(plan.parameters.is_empty());
+
+        let query = build_query(Some(field_filter("age", Operator::In, array_value(vec![]))));
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(result, Err(DB3Error::InvalidFilterValue(_))));
+        let query = build_query(Some(field_filter(
+            "age",
+            Operator::In,
+            array_value((0..11).map(ValueType::IntegerValue).collect()),
+        )));
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(result, Err(DB3Error::InvalidFilterValue(_))));
+        let query = build_query(Some(field_filter(
+            "age",
+            Operator::In,
+            array_value(vec![ValueType::StringValue("1".to_string())]),
+        )));
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(result, Err(DB3Error::InvalidFilterType(_))));
+        let query = build_query(Some(field_filter(
+            "age",
+            Operator::Equal,
+            array_value(vec![ValueType::IntegerValue(1)]),
+        )));
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(result, Err(DB3Error::InvalidFilterType(_))));
+    }
+
+    #[test]
+    fn query_plan_extended_value_test() {
+        let mut collection = build_collection();
+        collection.index_fields.push(Index {
+            path: "/meta".to_string(),
+            index_type: IndexType::UniqueKey.into(),
+            fields: vec![],
+        });
+        let query = build_query(Some(field_filter(
+            "name",
+            Operator::Equal,
+            ValueType::NullValue(NullValue::NullValue.into()),
+        )));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!("(/* and not /[name != null])", plan.query_str.as_str());
+        assert!(plan.parameters.is_empty());
+        let query = build_query(Some(field_filter(
+            "name",
+            Operator::NotEqual,
+            ValueType::NullValue(NullValue::NullValue.into()),
+        )));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!("/[name != null]", plan.query_str.as_str());
+        let query = build_query(Some(field_filter(
+            "name",
+            Operator::In,
+            array_value(vec![
+                ValueType::NullValue(NullValue::NullValue.into()),
+                ValueType::StringValue("a".to_string()),
+            ]),
+        )));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!(
+            "(/[name = ?] or (/* and not /[name != null]))",
+            plan.query_str.as_str()
+        );
+        assert_eq!(1, plan.parameters.len());
+        let query = build_query(Some(field_filter(
+            "name",
+            Operator::In,
+            array_value(vec![ValueType::NullValue(NullValue::NullValue.into())]),
+        )));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!("(/* and not /[name != null])", plan.query_str.as_str());
+        let query = build_query(Some(field_filter(
+            "name",
+            Operator::ArrayContainsAny,
+            array_value(vec![ValueType::NullValue(NullValue::NullValue.into())]),
+        )));
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(result, Err(DB3Error::InvalidFilterValue(_))));
+        let query = build_query(Some(field_filter(
+            "age",
+            Operator::GreaterThan,
+            ValueType::TimestampValue(Timestamp {
+                seconds: 1,
+                nanos: 2_000_000,
+            }),
+        )));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!("/[age > ?]", plan.query_str.as_str());
+        assert_eq!(
+            Some(query_parameter::Parameter::Int64Value(1002)),
+            plan.parameters[0].parameter
+        );
+        let query = build_query(Some(field_filter(
+            "name",
+            Operator::Equal,
+            ValueType::BytesValue(vec![1, 255]),
+        )));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!(
+            Some(query_parameter::Parameter::StrValue("01ff".to_string())),
+            plan.parameters[0].parameter
+        );
+        let mut fields = std::collections::HashMap::new();
+        fields.insert(
+            "b".to_string(),
+            Value {
+                value_type: Some(ValueType::StringValue("x\"]".to_string())),
+            },
+        );
+        fields.insert(
+            "a".to_string(),
+            Value {
+                value_type: Some(ValueType::IntegerValue(1)),
+            },
+        );
+        let map_value = ValueType::MapValue(MapValue { fields });
+        let query = build_query(Some(field_filter(
+            "meta",
+            Operator::Equal,
+            map_value.clone(),
+        )));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!(r#"/[meta = {"a":1,"b":"x\"]"}]"#, plan.query_str.as_str());
+
+        let query = build_query(Some(field_filter(
+            "meta",
+            Operator::GreaterThan,
+            map_value.clone(),
+        )));
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(result, Err(DB3Error::InvalidFilterOp(_))));
+        let query = build_query(Some(field_filter("name", Operator::Equal, map_value)));
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(result, Err(DB3Error::InvalidFilterType(_))));
+        let query = build_query(Some(field_filter(
+            "name",
+            Operator::Equal,
+            ValueType::TimestampValue(Timestamp::default()),
+        )));
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(result, Err(DB3Error::InvalidFilterType(_))));
+    }
+
+    fn and_filter(filters: Vec<Filter>) -> Filter {
+        Filter {
+            filter_type: Some(FilterType::CompositeFilter(CompositeFilter {
+                op: CompositeOp::And.into(),
+                filters,
+            })),
+        }
+    }
+
+    #[test]
+    fn query_plan_compound_index_test() {
+        let mut collection = build_collection();
+        collection.index_fields.truncate(3);
+        collection.index_fields.push(Index {
+            path: "city_age".to_string(),
+            index_type: IndexType::UniqueKey.into(),
+            fields: vec![
+                index_field("/city", IndexType::StringKey),
+                index_field("/age", IndexType::Int64Key),
+            ],
+        });
+        let city_filter = field_filter(
+            "city",
+            Operator::Equal,
+            ValueType::StringValue("a".to_string()),
+        );
+        let name_filter = field_filter(
+            "name",
+            Operator::Equal,
+            ValueType::StringValue("a".to_string()),
+        );
+        let age_filter = field_filter("age", Operator::GreaterThan, ValueType::IntegerValue(1));
+        let query = build_query(Some(and_filter(vec![
+            city_filter.clone(),
+            age_filter.clone(),
+        ])));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!("/[city = ?] and /[age > ?]", plan.query_str.as_str());
+        let query = build_query(Some(and_filter(vec![
+            name_filter.clone(),
+            field_filter("score", Operator::Equal, ValueType::DoubleValue(1.5)),
+        ])));
+        assert!(QueryPlanner::new(&collection).build(&query).is_ok());
+        let query = build_query(Some(Filter {
+            filter_type: Some(FilterType::CompositeFilter(CompositeFilter {
+                op: CompositeOp::Or.into(),
+                filters: vec![name_filter.clone(), age_filter.clone()],
+            })),
+        }));
+        assert!(QueryPlanner::new(&collection).build(&query).is_ok());
+        // the not equal filters need no compound index
+        let query = build_query(Some(and_filter(vec![
+            name_filter.clone(),
+            field_filter("score", Operator::NotEqual, ValueType::DoubleValue(2.0)),
+        ])));
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!("/[name = ?] and /[score != 2.0]", plan.query_str.as_str());
+        let query = build_query(Some(and_filter(vec![
+            age_filter.clone(),
+            field_filter(
+                "name",
+                Operator::NotIn,
+                array_value(vec![ValueType::StringValue("b".to_string())]),
+            ),
+        ])));
+        assert!(QueryPlanner::new(&collection).build(&query).is_ok());
+        let mut query = build_query(Some(city_filter));
+        query.order_by = vec![order("age", Direction::Ascending)];
+        assert!(QueryPlanner::new(&collection).build(&query).is_ok());
+
+        let query = build_query(Some(and_filter(vec![name_filter.clone(), age_filter])));
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(
+            result,
+            Err(DB3Error::IndexNotFoundForFiledFilter(_))
+        ));
+        let mut query = build_query(Some(name_filter));
+        query.order_by = vec![order("age", Direction::Ascending)];
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(
+            result,
+            Err(DB3Error::IndexNotFoundForFiledFilter(_))
+        ));
+        // the range field must follow the equality fields in the compound index
+        let age_filter = field_filter("age", Operator::Equal, ValueType::IntegerValue(1));
+        let mut query = build_query(Some(age_filter));
+        query.order_by = vec![order("city", Direction::Ascending)];
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(
+            result,
+            Err(DB3Error::IndexNotFoundForFiledFilter(_))
+        ));
+    }
+
+    fn order(field: &str, direction: Direction) -> Order {
+        Order {
+            field: field.to_string(),
+            direction: direction.into(),
+        }
+    }
+
+    #[test]
+    fn query_plan_order_by_test() {
+        let collection = build_collection();
+        let mut query = build_query(Some(field_filter(
+            "name",
+            Operator::Equal,
+            ValueType::StringValue("a".to_string()),
+        )));
+        query.order_by = vec![
+            order("age", Direction::Descending),
+            order("score", Direction::Unspecified),
+        ];
+        query.limit = Some(Limit { limit: 2 });
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!(
+            "/[name = ?] | desc /age asc /score limit 2",
+            plan.query_str.as_str()
+        );
+        query.order_by = vec![order("f1", Direction::Ascending)];
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(
+            result,
+            Err(DB3Error::IndexNotFoundForFiledFilter(_))
+        ));
+    }
+
+    #[test]
+    fn query_plan_cursor_test() {
+        let collection = build_collection();
+        let mut query = build_query(None);
+        query.order_by = vec![
+            order("age", Direction::Ascending),
+            order("name", Direction::Descending),
+        ];
+        query.start_at = Some(Cursor {
+            values: vec![
+                Value {
+                    value_type: Some(ValueType::IntegerValue(10)),
+                },
+                Value {
+                    value_type: Some(ValueType::StringValue("b".to_string())),
+                },
+            ],
+            before: false,
+        });
+        query.end_at = Some(Cursor {
+            values: vec![Value {
+                value_type: Some(ValueType::IntegerValue(20)),
+            }],
+            before: false,
+        });
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!(
+            "(/[age > ?] or /[age = ? and name < ?]) and (/[age < ?] or /[age = ?]) | asc /age desc /name",
+            plan.query_str.as_str()
+        );
+        assert_eq!(5, plan.parameters.len());
+        let page_cursor = PageCursor {
+            values: vec![Value {
+                value_type: Some(ValueType::IntegerValue(15)),
+            }],
+            id: 2,
+        };
+        let token = encode_page_cursor(&page_cursor);
+        let page_cursor = decode_page_cursor(token.as_str()).unwrap();
+        query.end_at = None;
+        query.limit = Some(Limit { limit: 10 });
+        let plan = QueryPlanner::new(&collection)
+            .build_with_cursor(&query, Some(&page_cursor))
+            .unwrap();
+        assert_eq!(
+            "/[age > ?] | asc /age desc /name limit 10",
+            plan.query_str.as_str()
+        );
+        assert_eq!(2, page_cursor.id);
+        let result = QueryPlanner::new(&collection).build_order_group(&query, &page_cursor.values);
+        assert!(matches!(result, Err(DB3Error::InvalidFilterValue(_))));
+        let mut values = page_cursor.values.clone();
+        values.push(Value {
+            value_type: Some(ValueType::StringValue("a".to_string())),
+        });
+        let plan = QueryPlanner::new(&collection)
+            .build_order_group(&query, &values)
+            .unwrap();
+        assert_eq!("/[age = ? and name = ?]", plan.query_str.as_str());
+        assert_eq!(2, plan.parameters.len());
+        assert!(decode_page_cursor("xyz").is_err());
+        query.start_at = Some(Cursor {
+            values: vec![
+                Value {
+                    value_type: Some(ValueType::IntegerValue(10)),
+                };
+                3
+            ],
+            before: true,
+        });
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(result, Err(DB3Error::InvalidFilterValue(_))));
+    }
+
+    #[test]
+    fn order_values_from_json_test() {
+        let doc: serde_json::Value =
+            serde_json::from_str(r#"{"name":"a", "age": 10, "score": 1.5}"#).unwrap();
+        let values = order_values_from_json(
+            &doc,
+            &[
+                order("name", Direction::Ascending),
+                order("age", Direction::Ascending),
+                order("score", Direction::Ascending),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                Value {
+                    value_type: Some(ValueType::StringValue("a".to_string()))
+                },
+                Value {
+                    value_type: Some(ValueType::IntegerValue(10))
+                },
+                Value {
+                    value_type: Some(ValueType::DoubleValue(1.5))
+                },
+            ],
+            values
+        );
+        assert!(order_values_from_json(&doc, &[order("f1", Direction::Ascending)]).is_none());
+    }
+
+    #[test]
+    fn query_plan_aggregation_test() {
+        let collection = build_collection();
+        let query = build_query(Some(field_filter(
+            "age",
+            Operator::GreaterThan,
+            ValueType::IntegerValue(1),
+        )));
+        let aggregations = vec![
+            Aggregation {
+                op: AggregationOp::Count.into(),
+                field: "".to_string(),
+            },
+            Aggregation {
+                op: AggregationOp::Sum.into(),
+                field: "amount".to_string(),
+            },
+            Aggregation {
+                op: AggregationOp::Max.into(),
+                field: "amount".to_string(),
+            },
+        ];
+        let plan = QueryPlanner::new(&collection)
+            .build_aggregation(&query, &aggregations, "name")
+            .unwrap();
+        assert_eq!("/[age > ?] | /{name,amount}", plan.query_str.as_str());
+        let result =
+            QueryPlanner::new(&collection).build_aggregation(&query, &aggregations, "amount");
+        assert!(matches!(
+            result,
+            Err(DB3Error::IndexNotFoundForFiledFilter(_))
+        ));
+        let result = QueryPlanner::new(&collection).build_aggregation(&query, &[], "");
+        assert!(matches!(result, Err(DB3Error::InvalidFilterValue(_))));
+    }
+
+    #[test]
+    fn query_plan_dashed_field_test() {
+        let mut collection = build_collection();
+        collection.index_fields.push(Index {
+            path: "/first-name".to_string(),
+            index_type: IndexType::StringKey.into(),
+            fields: vec![],
+        });
+        let mut query = build_query(Some(field_filter(
+            "first-name",
+            Operator::GreaterThan,
+            ValueType::StringValue("a".to_string()),
+        )));
+        query.order_by = vec![order("first-name", Direction::Descending)];
+        query.select = Some(Projection {
+            fields: vec!["first-name".to_string(), "age".to_string()],
+        });
+        let plan = QueryPlanner::new(&collection).build(&query).unwrap();
+        assert_eq!(
+            r#"/["first-name" > ?] | /{"first-name",age} | desc /"first-name""#,
+            plan.query_str.as_str()
+        );
+        assert_eq!("first-name", plan.parameters[0].name.as_str());
+    }
+
+    #[test]
+    fn query_plan_invalid_query_test() {
+        let collection = build_collection();
+        let query = build_query(Some(field_filter(
+            "f1",
+            Operator::Equal,
+            ValueType::StringValue("v".to_string()),
+        )));
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(
+            result,
+            Err(DB3Error::IndexNotFoundForFiledFilter(_))
+        ));
+        let query = build_query(Some(field_filter(
+            "age",
+            Operator::Equal,
+            ValueType::StringValue("10".to_string()),
+        )));
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(result, Err(DB3Error::InvalidFilterType(_))));
+        let query = build_query(Some(field_filter(
+            "name]",
+            Operator::Equal,
+            ValueType::StringValue("v".to_string()),
+        )));
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(result, Err(DB3Error::InvalidKeyPathError(_))));
+        // the dash is allowed in the field name
+        let query = build_query(Some(field_filter(
+            "first-name",
+            Operator::Equal,
+            ValueType::StringValue("v".to_string()),
+        )));
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(
+            result,
+            Err(DB3Error::IndexNotFoundForFiledFilter(_))
+        ));
+        let mut query = build_query(None);
+        query.limit = Some(Limit { limit: -1 });
+        let result = QueryPlanner::new(&collection).build(&query);
+        assert!(matches!(result, Err(DB3Error::InvalidFilterValue(_))));
+    }
+}
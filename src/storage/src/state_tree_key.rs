@@ -0,0 +1,103 @@
+//
+// state_tree_key.rs
+// Copyright (C) 2023 db3.network Author imotai <codego.me@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use db3_crypto::db3_address::DB3Address;
+use db3_crypto::merkle;
+use db3_error::Result;
+use std::fmt;
+const STATE_LEAF_PREFIX: &str = "/state_leaf/";
+const STATE_NODE_PREFIX: &str = "/state_node/";
+
+/// StateLeafKey with db address and doc id which maps the document to its hash. the keys
+/// are grouped by the bucket of the document
+pub struct StateLeafKey<'a>(pub &'a DB3Address, pub i64);
+impl<'a> StateLeafKey<'a> {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut encoded_key = Self::build_prefix(self.bucket());
+        encoded_key.extend_from_slice(self.0.as_ref());
+        encoded_key.extend_from_slice(self.1.to_be_bytes().as_ref());
+        Ok(encoded_key)
+    }
+
+    ///
+    /// the bucket of the document is the leaf of the state tree which holds it
+    ///
+    pub fn bucket(&self) -> u16 {
+        let mut data = self.0.as_ref().to_vec();
+        data.extend_from_slice(self.1.to_be_bytes().as_ref());
+        let hash = merkle::hash(&data);
+        u16::from_be_bytes([hash[0], hash[1]])
+    }
+
+    pub fn build_prefix(bucket: u16) -> Vec<u8> {
+        let mut encoded_key = STATE_LEAF_PREFIX.as_bytes().to_vec();
+        encoded_key.extend_from_slice(bucket.to_be_bytes().as_ref());
+        encoded_key
+    }
+}
+
+impl fmt::Display for StateLeafKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}/{}",
+            STATE_LEAF_PREFIX,
+            self.bucket(),
+            self.0.to_hex(),
+            self.1
+        )
+    }
+}
+
+/// StateNodeKey with the level and the index of the node in the state tree
+pub struct StateNodeKey(pub u8, pub u32);
+impl StateNodeKey {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut encoded_key = STATE_NODE_PREFIX.as_bytes().to_vec();
+        encoded_key.push(self.0);
+        encoded_key.extend_from_slice(self.1.to_be_bytes().as_ref());
+        Ok(encoded_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db3_crypto::db3_address::DB3_ADDRESS_LENGTH;
+
+    #[test]
+    fn test_state_leaf_key() {
+        let db_addr = DB3Address::from(&[1u8; DB3_ADDRESS_LENGTH]);
+        let key = StateLeafKey(&db_addr, 7);
+        let encoded_key = key.encode().unwrap();
+        assert!(encoded_key.starts_with(&StateLeafKey::build_prefix(key.bucket())));
+        assert_eq!(
+            encoded_key.len(),
+            STATE_LEAF_PREFIX.len() + 2 + DB3_ADDRESS_LENGTH + 8
+        );
+        // the bucket only depends on the document
+        assert_eq!(key.bucket(), StateLeafKey(&db_addr, 7).bucket());
+    }
+
+    #[test]
+    fn test_state_node_key() {
+        let root_key = StateNodeKey(0, 0).encode().unwrap();
+        let leaf_key = StateNodeKey(16, 1).encode().unwrap();
+        assert_ne!(root_key, leaf_key);
+        assert_eq!(root_key.len(), STATE_NODE_PREFIX.len() + 5);
+    }
+}